@@ -26,6 +26,78 @@ fn nibblepack8_varlen(c: &mut Criterion) {
 }
 
 
+// Packs blocks from realistic delta encoded data (small values, some zeroes) and full width values, to compare
+// the two ways nibble_pack8 can compute min leading/trailing zeros.  Run once as is for the default ORed version,
+// then again with `--features lane_min_zeros` for the per-lane version.
+fn nibblepack8_min_zeros(c: &mut Criterion) {
+    let mut blocks = Vec::new();
+    for &nonzeroes in [2, 4, 8, 16].iter() {
+        let inputs = make_nonzeroes_u64x64(nonzeroes);
+        for chunk in inputs.chunks_exact(8) {
+            let mut block = [0u64; 8];
+            block.copy_from_slice(chunk);
+            blocks.push(block);
+        }
+    }
+    blocks.push([0x0123_4567_89ab_cdefu64, 0, 3, 0, 0x0fed_cba9_8765_4321u64, 7, 0, 1]);
+
+    let name = if cfg!(feature = "lane_min_zeros") { "nibblepack8 min zeros per lane" }
+               else { "nibblepack8 min zeros ORed" };
+    let mut buf = [0u8; 1024];
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            blocks.iter().map(|block| nibblepacking::nibble_pack8(criterion::black_box(block), &mut buf, 0).unwrap())
+                  .sum::<usize>()
+        })
+    });
+}
+
+// Packs blocks at each even nibble width from 2 to 16 nibbles.  Widths of 2 to 8 nibbles use the loops specialized
+// for 1 to 4 bytes per value; run again with `--features generic_even_nibbles` to compare against the generic loop.
+fn nibblepack8_even_widths(c: &mut Criterion) {
+    let name = if cfg!(feature = "generic_even_nibbles") { "nibblepack8 even nibble widths generic" }
+               else { "nibblepack8 even nibble widths" };
+    let mut group = c.benchmark_group(name);
+    let mut buf = [0u8; 1024];
+    for &num_nibbles in [2u32, 4, 6, 8, 12, 16].iter() {
+        // Every value uses the top bit, so the block packs at exactly num_nibbles nibbles
+        let top_bit = 1u64 << (num_nibbles * 4 - 1);
+        let mut block = [0u64; 8];
+        for (i, n) in block.iter_mut().enumerate() {
+            *n = top_bit | (i as u64).wrapping_mul(0x0123_4567_89ab_cdef) >> (64 - num_nibbles * 4);
+        }
+        group.throughput(Throughput::Elements(8));
+        group.bench_with_input(BenchmarkId::from_parameter(num_nibbles), &block, |b, block| {
+            b.iter(|| nibblepacking::nibble_pack8(criterion::black_box(block), &mut buf, 0).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// Packs blocks with every pattern of zero and nonzero values, to compare the tiers for computing the nonzero
+// bitmask.  Run as is for SSE2 (AVX2 with `RUSTFLAGS="-C target-feature=+avx2"`), then again with
+// `--features scalar_nonzero_mask` for the scalar loop.
+fn nibblepack8_nonzero_mask(c: &mut Criterion) {
+    let blocks: Vec<[u64; 8]> = (0..=255u64).map(|pattern| {
+        let mut block = [0u64; 8];
+        for (i, n) in block.iter_mut().enumerate() {
+            if pattern & (1 << i) != 0 { *n = 0x100 + i as u64; }
+        }
+        block
+    }).collect();
+
+    let name = if cfg!(feature = "scalar_nonzero_mask") { "nibblepack8 nonzero mask scalar" }
+               else if cfg!(target_feature = "avx2") { "nibblepack8 nonzero mask avx2" }
+               else { "nibblepack8 nonzero mask sse2" };
+    let mut buf = [0u8; 1024];
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            blocks.iter().map(|block| nibblepacking::nibble_pack8(criterion::black_box(block), &mut buf, 0).unwrap())
+                  .sum::<usize>()
+        })
+    });
+}
+
 fn make_nonzeroes_u64x64(num_nonzeroes: usize) -> [u64; 64] {
     let mut inputs = [0u64; 64];
     for i in 1..=num_nonzeroes {
@@ -78,6 +150,162 @@ fn pack_delta_u64s_varlen(c: &mut Criterion) {
     }, &[2, 4, 8, 16]);
 }
 
+// Gauge-like values: sine wave jitter around a level, compared between clipping delta and zigzag delta
+fn gauge_u64x64() -> [u64; 64] {
+    let mut inputs = [0u64; 64];
+    for i in 0..64 {
+        inputs[i] = (100_000.0 + ((i as f32) * std::f32::consts::PI / 10.0).sin() * 1000.0) as u64;
+    }
+    inputs
+}
+
+fn pack_unpack_gauge_u64s(c: &mut Criterion) {
+    let inputs = gauge_u64x64();
+    let mut buf = [0u8; 1024];
+    let written = nibblepacking::pack_gauge(&inputs, &mut buf).unwrap();
+    assert!(written < inputs.len() * 4, "Gauge: {} u64s packed into {} bytes", inputs.len(), written);
+
+    c.bench_function("pack gauge u64s", |b| {
+        let mut buf = [0u8; 1024];
+        b.iter(|| {
+            nibblepacking::pack_gauge(&inputs, &mut buf).unwrap();
+        })
+    });
+
+    c.bench_function("unpack gauge u64s", |b| {
+        let mut sink = nibblepacking::ZigZagDeltaSink::new();
+        b.iter(|| {
+            sink.reset();
+            nibblepacking::unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+        })
+    });
+}
+
+// Skipping over blocks by walking only their headers, vs decoding every block to find where the next one starts
+fn walk_blocks_vs_unpack(c: &mut Criterion) {
+    let mut inputs = Vec::new();
+    for _ in 0..64 {
+        inputs.extend_from_slice(&increasing_nonzeroes_u64x64(16)[..]);
+    }
+    let mut buf = vec![0u8; inputs.len() * 8];
+    let written = nibblepacking::pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let num_blocks = inputs.len() / 8;
+
+    c.bench_function("walk block headers", |b| {
+        b.iter(|| nibblepacking::walk_blocks(&buf[..written], num_blocks, |_, _| {}).unwrap())
+    });
+
+    c.bench_function("unpack blocks to skip", |b| {
+        let mut sink = VecSink::<u64>::new();
+        b.iter(|| {
+            let mut slice = &buf[..written];
+            for _ in 0..num_blocks {
+                sink.reset();
+                slice = nibblepacking::nibble_unpack8(slice, &mut sink).unwrap();
+            }
+            slice.len()
+        })
+    });
+}
+
+// Fused decode + SIMD compare + branchless compress, against decoding everything then filtering with push
+fn decode_filter_collect_vs_push(c: &mut Criterion) {
+    use compressed_vec::filter::{decode_filter_collect, Comparison};
+
+    let inputs: Vec<u64> = (0..4096u64).map(|i| (i * 7919) % 1000).collect();
+    let packed = nibblepacking::pack_u64_to_vec(inputs.iter().cloned());
+    let mut group = c.benchmark_group("decode filter collect");
+    group.throughput(Throughput::Elements(inputs.len() as u64));
+    for &pct in [1u64, 10, 50].iter() {
+        let comparison = Comparison::LessThan(pct * 10);
+        group.bench_with_input(BenchmarkId::new("fused", pct), &comparison, |b, &comparison| {
+            b.iter(|| decode_filter_collect(&packed[..], inputs.len(), comparison).unwrap().len())
+        });
+        group.bench_with_input(BenchmarkId::new("decode then push", pct), &comparison, |b, &comparison| {
+            let mut sink = VecSink::<u64>::with_capacity(inputs.len());
+            b.iter(|| {
+                sink.reset();
+                nibblepacking::unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+                let mut out = Vec::new();
+                for &n in sink.as_slice() {
+                    if comparison.matches(n) { out.push(n); }
+                }
+                out.len()
+            })
+        });
+    }
+    group.finish();
+}
+
+// Compares bit-level Gorilla compression against NibblePacked XOR for a slowly changing f64 series
+fn pack_f64_gorilla_vs_xor(c: &mut Criterion) {
+    let inputs: Vec<f64> = (0..256).map(|i| 100.0 + ((i as f64) * std::f64::consts::PI / 64.0).sin().round() * 0.5)
+                                   .collect();
+    let mut buf = [0u8; 4096];
+    let gorilla_len = gorilla::pack_f64_gorilla(&inputs, &mut buf).unwrap();
+    let xor_len = nibblepacking::pack_f64_xor(inputs.iter().cloned(), &mut buf).unwrap();
+    // Both should need well under a byte per value for a series which mostly repeats
+    assert!(gorilla_len < inputs.len() && xor_len < inputs.len(),
+            "{} f64s: Gorilla {} bytes, XOR {} bytes", inputs.len(), gorilla_len, xor_len);
+
+    c.bench_function("pack f64 gorilla", |b| {
+        b.iter(|| gorilla::pack_f64_gorilla(&inputs, &mut buf).unwrap())
+    });
+    c.bench_function("pack f64 xor", |b| {
+        b.iter(|| nibblepacking::pack_f64_xor(inputs.iter().cloned(), &mut buf).unwrap())
+    });
+}
+
+// Packs a large slice of u64's through an Iterator vs reading the slice directly
+fn pack_u64_iter_vs_slice(c: &mut Criterion) {
+    let inputs: Vec<u64> = (0..4096u64).map(|i| (i * 7919) % 100_003).collect();
+    let mut buf = vec![0u8; nibblepacking::estimate_packed_size(inputs.len())];
+
+    let mut group = c.benchmark_group("pack u64s");
+    group.throughput(Throughput::Elements(inputs.len() as u64));
+    group.bench_function("iterator", |b| {
+        b.iter(|| nibblepacking::pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap())
+    });
+    group.bench_function("slice", |b| {
+        b.iter(|| nibblepacking::pack_u64_slice(&inputs[..], &mut buf, 0).unwrap())
+    });
+    group.finish();
+}
+
+// Packs and unpacks 12 bit values at a fixed width with no block headers, against adaptive NibblePacking
+fn fixed_width_vs_adaptive(c: &mut Criterion) {
+    let inputs: Vec<u64> = (0..4096u64).map(|i| (i * 7919) % 4096).collect();
+    let mut buf = vec![0u8; nibblepacking::estimate_packed_size(inputs.len())];
+    let fixed_len = nibblepacking::pack_u64_fixed_width(&inputs, 12, &mut buf).unwrap();
+    let fixed = buf[..fixed_len].to_vec();
+    let adaptive = nibblepacking::pack_u64_to_vec(inputs.iter().cloned());
+    assert!(fixed.len() < adaptive.len(),
+            "{} 12 bit u64s: fixed width {} bytes, adaptive {} bytes", inputs.len(), fixed.len(), adaptive.len());
+
+    let mut group = c.benchmark_group("fixed width vs adaptive");
+    group.throughput(Throughput::Elements(inputs.len() as u64));
+    group.bench_function("pack fixed width", |b| {
+        b.iter(|| nibblepacking::pack_u64_fixed_width(&inputs, 12, &mut buf).unwrap())
+    });
+    group.bench_function("pack adaptive", |b| {
+        b.iter(|| nibblepacking::pack_u64_slice(&inputs[..], &mut buf, 0).unwrap())
+    });
+    let mut sink = VecSink::<u64>::with_capacity(inputs.len());
+    group.bench_function("unpack fixed width", |b| {
+        b.iter(|| {
+            sink.reset();
+            nibblepacking::unpack_u64_fixed_width(&fixed[..], &mut sink, inputs.len()).unwrap();
+        })
+    });
+    group.bench_function("unpack adaptive", |b| {
+        b.iter(|| {
+            sink.reset();
+            nibblepacking::unpack(&adaptive[..], &mut sink, inputs.len()).unwrap();
+        })
+    });
+    group.finish();
+}
+
 fn unpack_delta_u64s(c: &mut Criterion) {
     c.bench_function("unpack delta u64s", |b| {
         let inputs = increasing_nonzeroes_u64x64(24);
@@ -277,8 +505,17 @@ fn repack_2d_deltas(c: &mut Criterion) {
 }
 
 criterion_group!(benches, //nibblepack8_varlen,
+                          nibblepack8_min_zeros,
+                          nibblepack8_even_widths,
+                          nibblepack8_nonzero_mask,
                           pack_delta_u64s_varlen,
                           unpack_delta_u64s,
+                          pack_u64_iter_vs_slice,
+                          fixed_width_vs_adaptive,
+                          pack_unpack_gauge_u64s,
+                          walk_blocks_vs_unpack,
+                          decode_filter_collect_vs_push,
+                          pack_f64_gorilla_vs_xor,
                           section32_decode_dense_lowcard_varnonzeroes,
                           section32_decode_dense_varnumbits,
                           bench_filter_vect,
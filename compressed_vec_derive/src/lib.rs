@@ -0,0 +1,107 @@
+//! `#[derive(CompressedColumns)]` for compressed_vec.  Implements `compressed_vec::columns::CompressedColumns`
+//! for a struct whose fields are all `Vec<u64>` or `Vec<f64>`, packing each field as one column in field order.
+//! The encoding of each field is given by a `#[compress(...)]` attribute:
+//!
+//! * `#[compress(plain)]`, the default, NibblePacks a `Vec<u64>` as is
+//! * `#[compress(delta)]` packs the deltas of a `Vec<u64>` which never decreases, such as timestamps
+//! * `#[compress(xor)]` XOR encodes a `Vec<f64>`
+//!
+//! ```ignore
+//! use compressed_vec::CompressedColumns;
+//!
+//! #[derive(CompressedColumns)]
+//! struct Readings {
+//!     #[compress(delta)]
+//!     timestamps: Vec<u64>,
+//!     #[compress(xor)]
+//!     temperatures: Vec<f64>,
+//! }
+//!
+//! let bytes = readings.pack_columns()?;
+//! let readings = Readings::unpack_columns(&bytes[..])?;
+//! ```
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Meta, NestedMeta};
+
+#[proc_macro_derive(CompressedColumns, attributes(compress))]
+pub fn derive_compressed_columns(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err)   => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input.ident, "CompressedColumns needs a struct with named fields")),
+        },
+        _ => return Err(Error::new_spanned(&input.ident, "CompressedColumns can only be derived for a struct")),
+    };
+
+    let mut packs = Vec::new();
+    let mut unpacks = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let name = field.ident.as_ref().expect("Named fields have names");
+        let encoding = field_encoding(field)?;
+        packs.push(quote! {
+            writer.column(&self.#name[..], ::compressed_vec::columns::ColumnEncoding::#encoding)?;
+        });
+        unpacks.push(quote! {
+            #name: reader.column(#index, ::compressed_vec::columns::ColumnEncoding::#encoding)?,
+        });
+    }
+
+    let ident = &input.ident;
+    let num_columns = fields.len();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::compressed_vec::columns::CompressedColumns for #ident #ty_generics #where_clause {
+            fn pack_columns(&self) -> ::std::result::Result<::std::vec::Vec<u8>, ::compressed_vec::error::CodingError> {
+                let mut writer = ::compressed_vec::columns::ColumnsWriter::new();
+                #(#packs)*
+                ::std::result::Result::Ok(writer.finish())
+            }
+
+            fn unpack_columns(bytes: &[u8]) -> ::std::result::Result<Self, ::compressed_vec::error::CodingError> {
+                let reader = ::compressed_vec::columns::ColumnsReader::new(bytes)?;
+                if reader.num_columns() != #num_columns {
+                    return ::std::result::Result::Err(::compressed_vec::error::CodingError::InvalidFormat(
+                        ::std::format!("Expected {} columns, found {}", #num_columns, reader.num_columns())));
+                }
+                ::std::result::Result::Ok(Self { #(#unpacks)* })
+            }
+        }
+    })
+}
+
+// The ColumnEncoding variant named by the field's #[compress(...)] attribute, or Plain if it has none
+fn field_encoding(field: &syn::Field) -> Result<syn::Ident, Error> {
+    let mut encoding = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("compress")) {
+        let nested = match attr.parse_meta()? {
+            Meta::List(list) => list.nested,
+            meta => return Err(Error::new_spanned(meta, "Expected #[compress(plain)], (delta) or (xor)")),
+        };
+        for item in nested.iter() {
+            let variant = match item {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("plain") => "Plain",
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("delta") => "Delta",
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("xor")   => "Xor",
+                _ => return Err(Error::new_spanned(item, "Unknown encoding, expected plain, delta or xor")),
+            };
+            if encoding.is_some() {
+                return Err(Error::new_spanned(item, "Only one encoding can be given for a field"));
+            }
+            encoding = Some(syn::Ident::new(variant, item.span()));
+        }
+    }
+    Ok(encoding.unwrap_or_else(|| syn::Ident::new("Plain", proc_macro2::Span::call_site())))
+}
@@ -0,0 +1,38 @@
+use compressed_vec::columns::CompressedColumns;
+use compressed_vec::error::CodingError;
+use compressed_vec_derive::CompressedColumns;
+
+#[derive(CompressedColumns, Debug, PartialEq)]
+struct Readings {
+    #[compress(delta)]
+    timestamps: Vec<u64>,
+    #[compress(xor)]
+    temperatures: Vec<f64>,
+    sensor_ids: Vec<u64>,
+}
+
+#[derive(CompressedColumns, Debug, PartialEq)]
+struct Timestamps {
+    #[compress(delta)]
+    timestamps: Vec<u64>,
+}
+
+#[test]
+fn derived_columns_roundtrip() {
+    let readings = Readings {
+        timestamps: (0..1000).map(|i| 1_600_000_000_000 + i * 10_000).collect(),
+        temperatures: (0..1000).map(|i| 18.5 + (i % 40) as f64 * 0.125).collect(),
+        sensor_ids: (0..1000).map(|i| (i * 13) % 7).collect(),
+    };
+    let bytes = readings.pack_columns().unwrap();
+    assert!(bytes.len() < 1000 * 8);
+    assert_eq!(Readings::unpack_columns(&bytes[..]).unwrap(), readings);
+
+    let empty = Readings { timestamps: vec![], temperatures: vec![], sensor_ids: vec![] };
+    assert_eq!(Readings::unpack_columns(&empty.pack_columns().unwrap()[..]).unwrap(), empty);
+
+    // A buffer of a different struct has the wrong number of columns
+    let other = Timestamps { timestamps: readings.timestamps.clone() }.pack_columns().unwrap();
+    assert!(matches!(Readings::unpack_columns(&other[..]), Err(CodingError::InvalidFormat(_))));
+    assert_eq!(Timestamps::unpack_columns(&other[..]).unwrap().timestamps, readings.timestamps);
+}
@@ -1,8 +1,11 @@
+use std::io::Write;
+
 use crate::error::CodingError;
 
 use scroll::{Pread, Pwrite, LE};
 
-/// Fast write of u64.  numbytes least significant bytes are written.
+/// Fast write of u64.  numbytes least significant bytes are written, always in little endian order
+/// regardless of the host's endianness, so that the packed format is portable.
 /// Writes into out_buffer[offset..offset+numbytes].
 /// Returns offset+numbytes
 #[inline]
@@ -46,3 +49,199 @@ pub fn direct_read_uint_le(inbuf: &[u8], pos: usize) -> Result<u64, CodingError>
             }
         })
 }
+
+/// Big endian counterpart of [`direct_write_uint_le`]: #method.direct_write_uint_le.  The numbytes least
+/// significant bytes of value are written most significant first into out_buffer[offset..offset+numbytes].
+/// Returns offset+numbytes, or NotEnoughSpace.
+#[inline]
+pub fn direct_write_uint_be(out_buffer: &mut [u8],
+                            offset: usize,
+                            value: u64,
+                            numbytes: usize) -> Result<usize, CodingError> {
+    if out_buffer.len() < offset + numbytes { return Err(CodingError::NotEnoughSpace) }
+    out_buffer[offset..offset+numbytes].copy_from_slice(&value.to_be_bytes()[8 - numbytes..]);
+    Ok(offset + numbytes)
+}
+
+/// Big endian counterpart of [`direct_read_uint_le`]: #method.direct_read_uint_le: reads the 8 bytes at pos as
+/// a big endian u64.  If there are less than 8 bytes left, the missing bytes are read as zeroes, which in big
+/// endian order are the least significant ones.  Will never read beyond end of inbuf.
+#[inline(always)]
+pub fn direct_read_uint_be(inbuf: &[u8], pos: usize) -> Result<u64, CodingError> {
+    let numbytes = inbuf.len().saturating_sub(pos).min(8);
+    if numbytes == 0 { return Err(CodingError::NotEnoughSpace) }
+    let mut buf = [0u8; 8];
+    buf[..numbytes].copy_from_slice(&inbuf[pos..pos + numbytes]);
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Returns a mask with the lowest num_bits bits set, for extracting a num_bits wide value from a u64.
+/// Handles the full 64-bit width explicitly, since shifting a u64 left by 64 overflows.
+#[inline(always)]
+pub fn nibble_mask(num_bits: u32) -> u64 {
+    if num_bits >= 64 { u64::MAX } else { (1u64 << num_bits) - 1 }
+}
+
+/// ZigZag encodes a signed value so that numbers close to zero, whether positive or negative, have few bits:
+/// 0 => 0, -1 => 1, 1 => 2, -2 => 3, and so on.
+#[inline(always)]
+pub fn zigzag_encode(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+/// Reverses [`zigzag_encode`]: #method.zigzag_encode
+#[inline(always)]
+pub fn zigzag_decode(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+/// Writes value as an unsigned LEB128 varint: 7 bits per byte, least significant first, with the top bit set on
+/// every byte but the last.  Values under 128 take a single byte.
+pub fn write_varint<B: ByteSink>(out: &mut B, mut value: u64) -> Result<(), CodingError> {
+    while value >= 0x80 {
+        out.push_byte((value as u8) | 0x80)?;
+        value >>= 7;
+    }
+    out.push_byte(value as u8)
+}
+
+/// Reads a varint written by [`write_varint`]: #method.write_varint starting at pos.  Returns the value and the
+/// position after it, InputTooShort if inbuf ends first, or InvalidFormat if it is more than 10 bytes long.
+pub fn read_varint(inbuf: &[u8], pos: usize) -> Result<(u64, usize), CodingError> {
+    let mut value = 0u64;
+    for (i, &byte) in inbuf.get(pos..).unwrap_or(&[]).iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 { return Ok((value, pos + i + 1)) }
+    }
+    if inbuf.len() >= pos + 10 {
+        Err(CodingError::InvalidFormat(format!("Varint at {} is longer than 10 bytes", pos)))
+    } else {
+        Err(CodingError::InputTooShort)
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// Computes the standard (IEEE, as in zlib and Ethernet) CRC-32 of bytes, for detecting corrupted data
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// A destination for NibblePacked bytes.  This is the packing counterpart of the Sink trait used for unpacking,
+/// allowing the packers to write to a fixed slice, a growable Vec, or any std::io::Write.
+pub trait ByteSink {
+    /// Writes a single byte
+    fn push_byte(&mut self, byte: u8) -> Result<(), CodingError>;
+
+    /// Writes the numbytes least significant bytes of value, in little endian order
+    fn write_uint_le(&mut self, value: u64, numbytes: usize) -> Result<(), CodingError>;
+
+    /// Writes a slice of bytes.  Override this if the sink can write a whole slice faster than a byte at a time.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CodingError> {
+        bytes.iter().try_for_each(|&byte| self.push_byte(byte))
+    }
+}
+
+impl ByteSink for Vec<u8> {
+    #[inline]
+    fn push_byte(&mut self, byte: u8) -> Result<(), CodingError> {
+        self.push(byte);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_uint_le(&mut self, value: u64, numbytes: usize) -> Result<(), CodingError> {
+        self.extend_from_slice(&value.to_le_bytes()[..numbytes]);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CodingError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// A ByteSink writing into a fixed size byte slice starting at some offset.  Returns NotEnoughSpace
+/// if the slice fills up.  pos is the offset of the next byte to be written.
+#[derive(Debug)]
+pub struct SliceCursor<'a> {
+    buf: &'a mut [u8],
+    pub pos: usize,
+}
+
+impl<'a> SliceCursor<'a> {
+    pub fn new(buf: &'a mut [u8], offset: usize) -> Self {
+        Self { buf, pos: offset }
+    }
+}
+
+impl<'a> ByteSink for SliceCursor<'a> {
+    #[inline]
+    fn push_byte(&mut self, byte: u8) -> Result<(), CodingError> {
+        if self.pos >= self.buf.len() { return Err(CodingError::NotEnoughSpace) }
+        self.buf[self.pos] = byte;
+        self.pos += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_uint_le(&mut self, value: u64, numbytes: usize) -> Result<(), CodingError> {
+        self.pos = direct_write_uint_le(self.buf, self.pos, value, numbytes)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CodingError> {
+        let end = self.pos + bytes.len();
+        if end > self.buf.len() { return Err(CodingError::NotEnoughSpace) }
+        self.buf[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
+    }
+}
+
+/// A ByteSink adapter for anything implementing std::io::Write, such as a File or a BufWriter
+#[derive(Debug)]
+pub struct WriteSink<W: Write> {
+    pub writer: W,
+}
+
+impl<W: Write> WriteSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> ByteSink for WriteSink<W> {
+    #[inline]
+    fn push_byte(&mut self, byte: u8) -> Result<(), CodingError> {
+        Ok(self.writer.write_all(&[byte])?)
+    }
+
+    #[inline]
+    fn write_uint_le(&mut self, value: u64, numbytes: usize) -> Result<(), CodingError> {
+        Ok(self.writer.write_all(&value.to_le_bytes()[..numbytes])?)
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CodingError> {
+        Ok(self.writer.write_all(bytes)?)
+    }
+}
@@ -0,0 +1,260 @@
+/// Packs a struct of columns, such as rows stored as one `Vec` per field, into one buffer and back.
+/// Each column is packed with its own encoding, and a directory at the start of the buffer records the encoding,
+/// number of values and byte length of every column, so a reader can find any column without decoding the ones
+/// before it:
+///
+/// | Bytes   | Contents                                                              |
+/// | ------- | --------------------------------------------------------------------- |
+/// | varint  | Number of columns                                                     |
+/// | 1       | Per column: encoding, a [`ColumnEncoding`](enum.ColumnEncoding.html)  |
+/// | varint  | Per column: number of values                                          |
+/// | varint  | Per column: length of the packed column in bytes                      |
+/// | ...     | The packed columns, in order                                          |
+///
+/// [`ColumnsWriter`](struct.ColumnsWriter.html) and [`ColumnsReader`](struct.ColumnsReader.html) can be used
+/// directly, but with the `compressed_vec_derive` feature `#[derive(CompressedColumns)]` writes the
+/// [`CompressedColumns`](trait.CompressedColumns.html) impl for a struct of `Vec` fields.  Each field is packed
+/// with the encoding named by its `#[compress(...)]` attribute: `plain` (the default) or `delta` for `Vec<u64>`,
+/// and `xor` for `Vec<f64>`.
+///
+/// ```ignore
+/// use compressed_vec::CompressedColumns;
+///
+/// #[derive(CompressedColumns)]
+/// struct Readings {
+///     #[compress(delta)]
+///     timestamps: Vec<u64>,
+///     #[compress(xor)]
+///     temperatures: Vec<f64>,
+/// }
+/// ```
+use crate::byteutils::*;
+use crate::error::CodingError;
+use crate::nibblepacking::{self, DeltaSink, DoubleXorSink};
+
+/// How one column is packed
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColumnEncoding {
+    Plain = 0x00,    // u64, nibblepacking::pack_u64
+    Delta = 0x01,    // u64, nibblepacking::pack_u64_delta
+    Xor   = 0x02,    // f64, nibblepacking::pack_f64_xor
+}
+
+impl ColumnEncoding {
+    fn from_u8(byte: u8) -> Result<Self, CodingError> {
+        match byte {
+            0x00 => Ok(ColumnEncoding::Plain),
+            0x01 => Ok(ColumnEncoding::Delta),
+            0x02 => Ok(ColumnEncoding::Xor),
+            _    => Err(CodingError::InvalidFormat(format!("Unknown column encoding {:#04x}", byte))),
+        }
+    }
+}
+
+/// A type of value which can be stored in a column, with the encodings it can be packed with
+pub trait ColumnType: Sized {
+    /// Packs values with encoding.  Returns InvalidFormat if encoding is not one for this type, or cannot pack
+    /// values, such as Delta for values which decrease.
+    fn pack_column(values: &[Self], encoding: ColumnEncoding) -> Result<Vec<u8>, CodingError>;
+
+    /// Unpacks num_values values packed by pack_column() with the same encoding
+    fn unpack_column(bytes: &[u8], num_values: usize, encoding: ColumnEncoding) -> Result<Vec<Self>, CodingError>;
+}
+
+fn unsupported<T>(encoding: ColumnEncoding, type_name: &str) -> Result<T, CodingError> {
+    Err(CodingError::InvalidFormat(format!("{:?} encoding is not supported for {} columns", encoding, type_name)))
+}
+
+impl ColumnType for u64 {
+    fn pack_column(values: &[u64], encoding: ColumnEncoding) -> Result<Vec<u8>, CodingError> {
+        match encoding {
+            ColumnEncoding::Plain => Ok(nibblepacking::pack_u64_to_vec(values.iter().cloned())),
+            ColumnEncoding::Delta => {
+                // Delta packing clips a decrease to zero, which would silently decode to the wrong values
+                if let Some(i) = values.windows(2).position(|pair| pair[1] < pair[0]) {
+                    return Err(CodingError::InvalidFormat(format!("Value at {} is less than the one before it",
+                                                                  i + 1)));
+                }
+                Ok(nibblepacking::pack_u64_delta_to_vec(values))
+            },
+            ColumnEncoding::Xor   => unsupported(encoding, "u64"),
+        }
+    }
+
+    fn unpack_column(bytes: &[u8], num_values: usize, encoding: ColumnEncoding) -> Result<Vec<u64>, CodingError> {
+        match encoding {
+            ColumnEncoding::Plain => nibblepacking::unpack_to_vec(bytes, num_values),
+            ColumnEncoding::Delta => {
+                let mut sink = DeltaSink::new();
+                nibblepacking::unpack(bytes, &mut sink, num_values)?;
+                Ok(sink.as_slice().to_vec())
+            },
+            ColumnEncoding::Xor   => unsupported(encoding, "u64"),
+        }
+    }
+}
+
+impl ColumnType for f64 {
+    fn pack_column(values: &[f64], encoding: ColumnEncoding) -> Result<Vec<u8>, CodingError> {
+        match encoding {
+            // pack_f64_xor needs at least one value, so an empty column is no bytes at all
+            ColumnEncoding::Xor if values.is_empty() => Ok(Vec::new()),
+            ColumnEncoding::Xor => {
+                let mut bytes = vec![0u8; 8 + nibblepacking::estimate_packed_size(values.len())];
+                let written = nibblepacking::pack_f64_xor(values.iter().cloned(), &mut bytes)?;
+                bytes.truncate(written);
+                Ok(bytes)
+            },
+            _ => unsupported(encoding, "f64"),
+        }
+    }
+
+    fn unpack_column(bytes: &[u8], num_values: usize, encoding: ColumnEncoding) -> Result<Vec<f64>, CodingError> {
+        match encoding {
+            ColumnEncoding::Xor => {
+                nibblepacking::check_values_fit(bytes, num_values)?;
+                let mut sink = DoubleXorSink::with_capacity(num_values);
+                nibblepacking::unpack_f64_xor(bytes, &mut sink, num_values)?;
+                Ok(sink.output_vec().clone())
+            },
+            _ => unsupported(encoding, "f64"),
+        }
+    }
+}
+
+/// A struct which packs into a single buffer of columns.  Usually derived with `#[derive(CompressedColumns)]`.
+pub trait CompressedColumns: Sized {
+    fn pack_columns(&self) -> Result<Vec<u8>, CodingError>;
+
+    fn unpack_columns(bytes: &[u8]) -> Result<Self, CodingError>;
+}
+
+/// Packs columns one after another, then writes them out after their directory
+#[derive(Debug, Default)]
+pub struct ColumnsWriter {
+    num_columns: usize,
+    directory: Vec<u8>,
+    columns: Vec<u8>,
+}
+
+impl ColumnsWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Packs values as the next column.  Returns InvalidFormat if encoding is not one for T or cannot pack values.
+    pub fn column<T: ColumnType>(&mut self, values: &[T], encoding: ColumnEncoding) -> Result<(), CodingError> {
+        let packed = T::pack_column(values, encoding)?;
+        self.directory.push(encoding as u8);
+        write_varint(&mut self.directory, values.len() as u64)?;
+        write_varint(&mut self.directory, packed.len() as u64)?;
+        self.columns.extend_from_slice(&packed[..]);
+        self.num_columns += 1;
+        Ok(())
+    }
+
+    /// Returns the directory followed by all of the columns
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(10 + self.directory.len() + self.columns.len());
+        write_varint(&mut out, self.num_columns as u64).expect("Writing to a Vec cannot fail");
+        out.extend_from_slice(&self.directory[..]);
+        out.extend_from_slice(&self.columns[..]);
+        out
+    }
+}
+
+/// Reads the directory of a buffer written by a [`ColumnsWriter`](struct.ColumnsWriter.html), then unpacks
+/// any of its columns
+#[derive(Debug)]
+pub struct ColumnsReader<'a> {
+    columns: Vec<(ColumnEncoding, usize, &'a [u8])>,
+}
+
+impl<'a> ColumnsReader<'a> {
+    /// Reads the directory.  Returns InputTooShort if bytes ends before the last column does, or InvalidFormat
+    /// for an unknown encoding or a column with more values than its bytes could hold.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, CodingError> {
+        let (num_columns, mut pos) = read_varint(bytes, 0)?;
+        let mut directory = Vec::new();
+        for _ in 0..num_columns {
+            let encoding = ColumnEncoding::from_u8(*bytes.get(pos).ok_or(CodingError::InputTooShort)?)?;
+            let (num_values, next) = read_varint(bytes, pos + 1)?;
+            let (num_bytes, next) = read_varint(bytes, next)?;
+            // Every block of 8 values takes at least one byte, plus 8 for the first value of an XOR column
+            if num_values > num_bytes.saturating_mul(8).saturating_add(8) {
+                return Err(CodingError::InvalidFormat(
+                    format!("{} values cannot fit in a column of {} bytes", num_values, num_bytes)));
+            }
+            directory.push((encoding, num_values as usize, num_bytes as usize));
+            pos = next;
+        }
+
+        let mut columns = Vec::with_capacity(directory.len());
+        for (encoding, num_values, num_bytes) in directory {
+            let end = pos.checked_add(num_bytes).filter(|&end| end <= bytes.len())
+                         .ok_or(CodingError::InputTooShort)?;
+            columns.push((encoding, num_values, &bytes[pos..end]));
+            pos = end;
+        }
+        Ok(Self { columns })
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Unpacks the column at index, which must have been packed with encoding.  Returns InvalidFormat if there
+    /// is no such column or it was packed differently.
+    pub fn column<T: ColumnType>(&self, index: usize, encoding: ColumnEncoding) -> Result<Vec<T>, CodingError> {
+        let &(stored, num_values, bytes) = self.columns.get(index).ok_or_else(|| CodingError::InvalidFormat(
+            format!("No column {}, there are only {}", index, self.columns.len())))?;
+        if stored != encoding {
+            return Err(CodingError::InvalidFormat(
+                format!("Column {} is packed with {:?}, not {:?}", index, stored, encoding)));
+        }
+        T::unpack_column(bytes, num_values, encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_roundtrip_by_hand() {
+        let timestamps: Vec<u64> = (0..100).map(|i| 1_600_000_000_000 + i * 1000).collect();
+        let codes: Vec<u64> = (0..100).map(|i| (i * 7) % 5).collect();
+        let temps: Vec<f64> = (0..100).map(|i| 20.0 + (i % 10) as f64 * 0.25).collect();
+
+        let mut writer = ColumnsWriter::new();
+        writer.column(&timestamps, ColumnEncoding::Delta).unwrap();
+        writer.column(&codes, ColumnEncoding::Plain).unwrap();
+        writer.column(&temps, ColumnEncoding::Xor).unwrap();
+        writer.column::<f64>(&[], ColumnEncoding::Xor).unwrap();
+        assert!(matches!(writer.column(&codes, ColumnEncoding::Xor), Err(CodingError::InvalidFormat(_))));
+        assert!(matches!(writer.column(&[5u64, 7, 6], ColumnEncoding::Delta), Err(CodingError::InvalidFormat(_))));
+        let bytes = writer.finish();
+
+        let reader = ColumnsReader::new(&bytes).unwrap();
+        assert_eq!(reader.num_columns(), 4);
+        assert_eq!(reader.column::<u64>(0, ColumnEncoding::Delta).unwrap(), timestamps);
+        assert_eq!(reader.column::<u64>(1, ColumnEncoding::Plain).unwrap(), codes);
+        assert_eq!(reader.column::<f64>(2, ColumnEncoding::Xor).unwrap(), temps);
+        assert_eq!(reader.column::<f64>(3, ColumnEncoding::Xor).unwrap(), Vec::<f64>::new());
+
+        assert!(matches!(reader.column::<u64>(0, ColumnEncoding::Plain), Err(CodingError::InvalidFormat(_))));
+        assert!(matches!(reader.column::<u64>(4, ColumnEncoding::Plain), Err(CodingError::InvalidFormat(_))));
+        assert_eq!(ColumnsReader::new(&bytes[..bytes.len() - 1]).unwrap_err(), CodingError::InputTooShort);
+    }
+
+    #[test]
+    fn test_columns_reader_rejects_too_many_values() {
+        // One Plain column claiming 2^62 values in 2 bytes
+        let mut bytes = vec![1u8, ColumnEncoding::Plain as u8];
+        write_varint(&mut bytes, 1u64 << 62).unwrap();
+        write_varint(&mut bytes, 2).unwrap();
+        bytes.extend_from_slice(&[0, 0]);
+        assert!(matches!(ColumnsReader::new(&bytes), Err(CodingError::InvalidFormat(_))));
+    }
+}
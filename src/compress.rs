@@ -0,0 +1,294 @@
+/// A simple compress()/decompress() facade over the NibblePacking primitives, for when you just want a
+/// `Vec<u64>` packed small and back again, without picking the right pack_* function and matching sink.
+///
+/// The encoding is chosen automatically from the data and remembered in the result:
+/// - Delta, if the values never decrease (counters, timestamps, increasing histograms)
+/// - Plain NibblePacking otherwise
+///
+/// For filtering and processing the compressed data directly, use the `vector` module or the low level
+/// functions in `nibblepacking` instead.
+///
+/// When the kind of column is known up front, the typed [`CounterColumn`](struct.CounterColumn.html),
+/// [`GaugeColumn`](struct.GaugeColumn.html) and [`TimestampColumn`](struct.TimestampColumn.html) always pack
+/// and unpack with the matching pair of functions, so bytes packed with one encoding can never be decoded with
+/// the sink for another.
+use packed_simd::u64x8;
+
+use crate::error::CodingError;
+use crate::nibblepacking;
+use crate::sink::{Sink, VecSink};
+
+/// The encoding chosen by [`compress`](fn.compress.html)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum U64Encoding {
+    Plain = 0x00,    // nibblepacking::pack_u64
+    Delta = 0x01,    // nibblepacking::pack_u64_delta
+}
+
+/// A compressed `Vec<u64>`, along with its encoding and number of values
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressedU64 {
+    encoding: U64Encoding,
+    num_values: usize,
+    bytes: Vec<u8>,
+}
+
+/// Compresses values, using delta encoding if they never decrease, and plain NibblePacking otherwise.
+///
+/// ```
+/// # use compressed_vec::compress::{compress, U64Encoding};
+///     let timestamps = vec![1000u64, 1010, 1020, 1030, 1045];
+///     let compressed = compress(&timestamps);
+///     assert_eq!(compressed.encoding(), U64Encoding::Delta);
+///     assert_eq!(compressed.decompress(), timestamps);
+/// ```
+pub fn compress(values: &[u64]) -> CompressedU64 {
+    let is_increasing = values.windows(2).all(|w| w[0] <= w[1]);
+    let mut bytes = vec![0u8; nibblepacking::estimate_packed_size(values.len())];
+    let (encoding, written) = if is_increasing {
+        (U64Encoding::Delta, nibblepacking::pack_u64_delta(values, &mut bytes))
+    } else {
+        (U64Encoding::Plain, nibblepacking::pack_u64(values.iter().cloned(), &mut bytes, 0))
+    };
+    let written = written.expect("Buffer is sized for the worst case");
+    bytes.truncate(written);
+    CompressedU64 { encoding, num_values: values.len(), bytes }
+}
+
+/// Returns the compression ratio, ie raw bytes over packed bytes, that encoding would give for inputs,
+/// without packing them or allocating.  Use this to compare encodings on a sample of a dataset.
+/// Delta encoding clips decreases to 0 just as [`pack_u64_delta`](../nibblepacking/fn.pack_u64_delta.html) does,
+/// so it only gives a meaningful ratio for values which never decrease.  Returns 1.0 for no inputs.
+///
+/// ```
+/// # use compressed_vec::compress::{compression_ratio, U64Encoding};
+///     let timestamps: Vec<u64> = (0..1000).map(|i| 1_600_000_000_000 + i * 1000).collect();
+///     assert!(compression_ratio(&timestamps, U64Encoding::Delta) > compression_ratio(&timestamps, U64Encoding::Plain));
+/// ```
+pub fn compression_ratio(inputs: &[u64], encoding: U64Encoding) -> f64 {
+    if inputs.is_empty() { return 1.0 }
+    let packed_size = match encoding {
+        U64Encoding::Plain => nibblepacking::packed_size(inputs.iter().cloned()),
+        U64Encoding::Delta => nibblepacking::packed_size(nibblepacking::clipped_deltas(inputs.iter().cloned())),
+    };
+    (inputs.len() * 8) as f64 / packed_size as f64
+}
+
+impl CompressedU64 {
+    /// Decompresses back into the original values
+    pub fn decompress(&self) -> Vec<u64> {
+        let mut out = match self.encoding {
+            U64Encoding::Plain => {
+                let mut sink = VecSink::<u64>::new();
+                self.unpack_into(&mut sink);
+                sink.vec
+            },
+            U64Encoding::Delta => {
+                let mut sink = nibblepacking::DeltaSink::new();
+                self.unpack_into(&mut sink);
+                sink.output_vec().clone()
+            },
+        };
+        out.truncate(self.num_values);
+        out
+    }
+
+    fn unpack_into<S: Sink<u64x8>>(&self, sink: &mut S) {
+        nibblepacking::unpack(&self.bytes[..], sink, self.num_values)
+            .expect("Bytes were written by compress()");
+    }
+
+    pub fn encoding(&self) -> U64Encoding { self.encoding }
+
+    packed_accessors!("The compressed bytes, without the encoding or length");
+}
+
+/// Values of a counter, which only goes up, such as requests served.  Packed with
+/// [`pack_u64_delta`](../nibblepacking/fn.pack_u64_delta.html) and decoded with a DeltaSink.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CounterColumn {
+    num_values: usize,
+    bytes: Vec<u8>,
+}
+
+impl CounterColumn {
+    /// Packs values, or returns InvalidFormat if a value is less than the one before it.  pack_u64_delta would
+    /// clip the decrease to zero, so a counter reset would otherwise silently decode to the wrong values.
+    ///
+    /// ```
+    /// # use compressed_vec::compress::CounterColumn;
+    ///     let requests = [1200u64, 1250, 1250, 1310];
+    ///     assert_eq!(CounterColumn::new(&requests).unwrap().decode(), requests);
+    ///     assert!(CounterColumn::new(&[1200, 5]).is_err());
+    /// ```
+    pub fn new(values: &[u64]) -> Result<Self, CodingError> {
+        if let Some(i) = values.windows(2).position(|pair| pair[1] < pair[0]) {
+            return Err(CodingError::InvalidFormat(format!("Counter value at {} is less than the one before it",
+                                                          i + 1)));
+        }
+        Ok(Self { num_values: values.len(), bytes: nibblepacking::pack_u64_delta_to_vec(values) })
+    }
+
+    pub fn decode(&self) -> Vec<u64> {
+        let mut sink = nibblepacking::DeltaSink::with_capacity(self.num_values);
+        nibblepacking::unpack(&self.bytes[..], &mut sink, self.num_values)
+            .expect("Bytes were written by CounterColumn::new()");
+        sink.as_slice().to_vec()
+    }
+
+    packed_accessors!("The packed bytes, without the number of values");
+}
+
+/// Values of a gauge, which goes up and down, such as memory in use.  Packed with
+/// [`pack_gauge`](../nibblepacking/fn.pack_gauge.html) and decoded with a ZigZagDeltaSink.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaugeColumn {
+    num_values: usize,
+    bytes: Vec<u8>,
+}
+
+impl GaugeColumn {
+    pub fn new(values: &[u64]) -> Self {
+        let mut bytes = vec![0u8; nibblepacking::estimate_packed_size(values.len())];
+        let written = nibblepacking::pack_gauge(values, &mut bytes).expect("Buffer is sized for the worst case");
+        bytes.truncate(written);
+        Self { num_values: values.len(), bytes }
+    }
+
+    pub fn decode(&self) -> Vec<u64> {
+        let mut sink = nibblepacking::ZigZagDeltaSink::with_sink(VecSink::with_capacity(self.num_values));
+        nibblepacking::unpack(&self.bytes[..], &mut sink, self.num_values)
+            .expect("Bytes were written by GaugeColumn::new()");
+        sink.as_slice().to_vec()
+    }
+
+    packed_accessors!("The packed bytes, without the number of values");
+}
+
+/// Timestamps, or any values sampled at a roughly fixed rate.  Packed with
+/// [`pack_u64_double_delta`](../nibblepacking/fn.pack_u64_double_delta.html), so a regular series packs to almost
+/// nothing whatever its rate, and out of order timestamps still round trip exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimestampColumn {
+    num_values: usize,
+    bytes: Vec<u8>,
+}
+
+impl TimestampColumn {
+    pub fn new(values: &[u64]) -> Self {
+        let mut bytes = vec![0u8; nibblepacking::estimate_packed_size(values.len())];
+        let written = nibblepacking::pack_u64_double_delta(values, &mut bytes)
+            .expect("Buffer is sized for the worst case");
+        bytes.truncate(written);
+        Self { num_values: values.len(), bytes }
+    }
+
+    pub fn decode(&self) -> Vec<u64> {
+        nibblepacking::unpack_u64_double_delta(&self.bytes[..], self.num_values)
+            .expect("Bytes were written by TimestampColumn::new()")
+    }
+
+    packed_accessors!("The packed bytes, without the number of values");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_picks_delta_for_increasing() {
+        let values: Vec<u64> = (0..100).map(|i| 1_600_000_000_000 + i * 10_000 + (i % 3)).collect();
+        let compressed = compress(&values);
+        assert_eq!(compressed.encoding(), U64Encoding::Delta);
+        assert_eq!(compressed.len(), values.len());
+        assert_eq!(compressed.decompress(), values);
+
+        // Delta should pack the small increments much better than the large absolute values
+        let mut plain_buf = [0u8; 2048];
+        let plain_len = nibblepacking::pack_u64(values.iter().cloned(), &mut plain_buf, 0).unwrap();
+        assert!(compressed.as_bytes().len() < plain_len / 2);
+    }
+
+    #[test]
+    fn test_compress_picks_plain_for_random() {
+        // Simple xorshift so the test is deterministic
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let values: Vec<u64> = (0..77).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state >> (state % 48)
+        }).collect();
+        let compressed = compress(&values);
+        assert_eq!(compressed.encoding(), U64Encoding::Plain);
+        assert_eq!(compressed.decompress(), values);
+    }
+
+    #[test]
+    fn test_compression_ratio_matches_packed_bytes() {
+        let values: Vec<u64> = (0..333).map(|i| 5_000_000 + i * 37 + (i % 4)).collect();
+
+        let mut buf = [0u8; 4096];
+        let plain_len = nibblepacking::pack_u64(values.iter().cloned(), &mut buf, 0).unwrap();
+        let plain_ratio = compression_ratio(&values, U64Encoding::Plain);
+        assert_eq!(plain_ratio, (values.len() * 8) as f64 / plain_len as f64);
+
+        let delta_len = nibblepacking::pack_u64_delta(&values, &mut buf).unwrap();
+        let delta_ratio = compression_ratio(&values, U64Encoding::Delta);
+        assert_eq!(delta_ratio, (values.len() * 8) as f64 / delta_len as f64);
+        assert!(delta_ratio > plain_ratio);
+
+        assert_eq!(compression_ratio(&[], U64Encoding::Plain), 1.0);
+    }
+
+    #[test]
+    fn test_compress_empty_and_single() {
+        let compressed = compress(&[]);
+        assert!(compressed.is_empty());
+        assert_eq!(compressed.decompress(), Vec::<u64>::new());
+
+        let compressed = compress(&[u64::MAX]);
+        assert_eq!(compressed.encoding(), U64Encoding::Delta);
+        assert_eq!(compressed.decompress(), vec![u64::MAX]);
+    }
+
+    #[test]
+    fn test_counter_column_roundtrip() {
+        let values: Vec<u64> = (0..100).map(|i| 1_000_000 + i * i * 3).collect();
+        let column = CounterColumn::new(&values).unwrap();
+        assert_eq!(column.len(), values.len());
+        assert_eq!(column.decode(), values);
+        assert_eq!(CounterColumn::new(&[]).unwrap().decode(), Vec::<u64>::new());
+
+        // A reset would be clipped by delta encoding, so it is rejected instead
+        assert!(matches!(CounterColumn::new(&[5, 10, 3]), Err(CodingError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_gauge_column_roundtrip() {
+        let values: Vec<u64> = (0..100).map(|i| 50_000 + (i * 7919) % 200).chain(vec![0, u64::MAX, 1]).collect();
+        let column = GaugeColumn::new(&values);
+        assert_eq!(column.len(), values.len());
+        assert_eq!(column.decode(), values);
+        assert!(GaugeColumn::new(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_timestamp_column_roundtrip() {
+        // Every 15 seconds in millis, with a little jitter and one out of order sample
+        let mut values: Vec<u64> = (0..200).map(|i| 1_600_000_000_000 + i * 15_000 + (i % 7 == 3) as u64).collect();
+        values.swap(50, 51);
+        let column = TimestampColumn::new(&values);
+        assert_eq!(column.len(), values.len());
+        assert_eq!(column.decode(), values);
+
+        // A perfectly regular series is all zero blocks after the first block
+        let regular: Vec<u64> = (0..800).map(|i| 1_600_000_000_000 + i * 15_000).collect();
+        let column = TimestampColumn::new(&regular);
+        assert_eq!(column.decode(), regular);
+        assert!(column.as_bytes().len() < 120, "{} bytes", column.as_bytes().len());
+
+        assert_eq!(TimestampColumn::new(&[u64::MAX, 0, 7]).decode(), vec![u64::MAX, 0, 7]);
+    }
+}
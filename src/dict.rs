@@ -0,0 +1,132 @@
+/// Dictionary encoding for low cardinality, categorical columns, such as status codes, hostnames or enum tags.
+/// Each distinct value gets an index into a dictionary, and the column is stored as NibblePacked indices.
+///
+/// The dictionary is sorted by how often each value occurs, so the most frequent value gets index 0, the next 1,
+/// and so on.  On a skewed column most indices are then tiny, and whole blocks of the most common value pack
+/// down to a single zero byte, which is where NibblePacking does best.
+///
+/// ```
+/// # use compressed_vec::dict;
+///     let methods = ["GET", "GET", "POST", "GET", "PUT", "GET", "POST", "GET"];
+///     let (dictionary, packed) = dict::dict_encode(&methods);
+///     assert_eq!(dictionary, vec!["GET", "POST", "PUT"]);
+///     assert_eq!(dict::dict_decode(&dictionary, &packed, methods.len()).unwrap(), methods);
+/// ```
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::error::CodingError;
+use crate::nibblepacking;
+
+/// A frequency sorted dictionary built from a column, which encodes columns of the same values into indices
+#[derive(Debug, Clone)]
+pub struct DictEncoder<T: Hash + Eq + Clone> {
+    dict: Vec<T>,
+    indices: HashMap<T, u64>,
+}
+
+impl<T: Hash + Eq + Clone> DictEncoder<T> {
+    /// Builds the dictionary of the distinct values in values, most frequent first.  Values which occur equally
+    /// often are kept in the order they first appear, so the dictionary is the same for the same column.
+    pub fn new(values: &[T]) -> Self {
+        let mut counts: HashMap<&T, (usize, usize)> = HashMap::new();
+        for (pos, value) in values.iter().enumerate() {
+            counts.entry(value).or_insert((0, pos)).0 += 1;
+        }
+        let mut by_count: Vec<(&T, (usize, usize))> = counts.into_iter().collect();
+        by_count.sort_by_key(|&(_, (count, first_pos))| (std::cmp::Reverse(count), first_pos));
+
+        let dict: Vec<T> = by_count.into_iter().map(|(value, _)| value.clone()).collect();
+        let indices = dict.iter().enumerate().map(|(i, value)| (value.clone(), i as u64)).collect();
+        Self { dict, indices }
+    }
+
+    /// The distinct values, most frequent first.  The index of a value here is what it is encoded as.
+    pub fn dictionary(&self) -> &[T] {
+        &self.dict[..]
+    }
+
+    pub fn into_dictionary(self) -> Vec<T> {
+        self.dict
+    }
+
+    /// Returns the index value is encoded as, or None if it is not in the dictionary
+    pub fn index_of(&self, value: &T) -> Option<u64> {
+        self.indices.get(value).cloned()
+    }
+
+    /// Encodes values as their dictionary indices, packed with
+    /// [`pack_u64`](../nibblepacking/fn.pack_u64.html).  Decode using [`dict_decode`](fn.dict_decode.html).
+    /// Returns InvalidFormat if a value is not in the dictionary.
+    pub fn encode(&self, values: &[T]) -> Result<Vec<u8>, CodingError> {
+        let indices = values.iter().enumerate().map(|(pos, value)| {
+            self.index_of(value).ok_or_else(|| CodingError::InvalidFormat(
+                format!("Value at {} is not in the dictionary", pos)))
+        }).collect::<Result<Vec<u64>, CodingError>>()?;
+        Ok(nibblepacking::pack_u64_to_vec(indices.into_iter()))
+    }
+}
+
+/// Builds a frequency sorted dictionary of values and encodes them with it in one go, returning
+/// (dictionary, packed indices).  See [`DictEncoder`](struct.DictEncoder.html).
+pub fn dict_encode<T: Hash + Eq + Clone>(values: &[T]) -> (Vec<T>, Vec<u8>) {
+    let encoder = DictEncoder::new(values);
+    let packed = encoder.encode(values).expect("Every value is in its own dictionary");
+    (encoder.into_dictionary(), packed)
+}
+
+/// Decodes num_values dictionary indices from packed and maps them back to the values in dictionary.
+/// For u64 values, unpacking through a [`MapSink`](../sink/struct.MapSink.html) does the same without the
+/// intermediate Vec of indices.  Returns InvalidFormat if an index is past the end of the dictionary.
+pub fn dict_decode<T: Clone>(dictionary: &[T], packed: &[u8], num_values: usize) -> Result<Vec<T>, CodingError> {
+    nibblepacking::unpack_to_vec(packed, num_values)?.into_iter().map(|index| {
+        dictionary.get(index as usize).cloned().ok_or_else(|| CodingError::InvalidFormat(
+            format!("Index {} is past the end of the {} value dictionary", index, dictionary.len())))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skewed_categories_roundtrip_and_compress() {
+        // Category ids are large numbers, but a handful of them make up most of the column
+        let ids = [0x9f3a_0000_1234u64, 0x9f3a_0000_5678, 0x7777_0000_0001, 0x1234_5678_9abc, 0xdead_beef];
+        let values: Vec<u64> = (0..2000u64).map(|i| {
+            match (i * 7919) % 100 {
+                0..=84  => ids[1],
+                85..=94 => ids[0],
+                95..=97 => ids[3],
+                98      => ids[4],
+                _       => ids[2],
+            }
+        }).collect();
+
+        let (dictionary, packed) = dict_encode(&values[..]);
+        assert_eq!(dictionary, vec![ids[1], ids[0], ids[3], ids[2], ids[4]]);
+        assert_eq!(dict_decode(&dictionary, &packed, values.len()).unwrap(), values);
+
+        let raw = nibblepacking::pack_u64_to_vec(values.iter().cloned());
+        assert!(packed.len() * 4 < raw.len(), "dict {} vs raw {}", packed.len(), raw.len());
+    }
+
+    #[test]
+    fn test_strings_and_ties() {
+        let hosts = ["b", "a", "c", "a", "b", "d"];
+        let encoder = DictEncoder::new(&hosts);
+        // b and a both occur twice; b comes first
+        assert_eq!(encoder.dictionary(), &["b", "a", "c", "d"]);
+        assert_eq!(encoder.index_of(&"c"), Some(2));
+        assert_eq!(encoder.index_of(&"z"), None);
+        assert!(matches!(encoder.encode(&["a", "z"]), Err(CodingError::InvalidFormat(_))));
+
+        let packed = encoder.encode(&["d", "a", "a"]).unwrap();
+        assert_eq!(dict_decode(encoder.dictionary(), &packed, 3).unwrap(), vec!["d", "a", "a"]);
+        assert!(matches!(dict_decode(&["b"], &packed, 3), Err(CodingError::InvalidFormat(_))));
+
+        let (dictionary, packed) = dict_encode::<u64>(&[]);
+        assert!(dictionary.is_empty());
+        assert_eq!(dict_decode(&dictionary, &packed, 0).unwrap(), Vec::<u64>::new());
+    }
+}
@@ -8,6 +8,11 @@ pub enum CodingError {
     InvalidNumRows(usize, usize),    // Number passed into finish(), number of actual rows written so far
     WrongVectorType(u8),             // Eg Used a VectorReader::<u64> on a u32 vector
     ScrollErr(String),
+    IoErr(String),                   // Error writing to a std::io::Write
+    CorruptHeader(u8),               // NibblePack nibbles byte whose nibbles plus trailing nibbles is over 16
+    OutputTooShort(usize, usize),    // Number of values to decode, room in the output slice
+    UnsupportedVersion(u8),          // Format version byte written by a newer version, or not a versioned buffer
+    ValueOutOfRange(f64),            // Float which is NaN, infinite or too large to convert to a fixed point i64
 }
 
 impl From<scroll::Error> for CodingError {
@@ -19,3 +24,9 @@ impl From<scroll::Error> for CodingError {
         }
     }
 }
+
+impl From<std::io::Error> for CodingError {
+    fn from(err: std::io::Error) -> CodingError {
+        CodingError::IoErr(err.to_string())
+    }
+}
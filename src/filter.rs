@@ -6,9 +6,11 @@
 ///
 use core::marker::PhantomData;
 
-use packed_simd::u32x8;
+use packed_simd::{u32x8, u64x8};
 use smallvec::SmallVec;
 
+use crate::error::CodingError;
+use crate::nibblepacking;
 use crate::section::*;
 use crate::sink::{Sink, SinkInput};
 
@@ -320,6 +322,107 @@ where I: Iterator<Item = u32x8> {
     matches
 }
 
+/// A comparison of each value against constants, evaluated 8 values at a time with SIMD compares, for
+/// [`decode_filter_collect`](fn.decode_filter_collect.html)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Comparison {
+    Equals(u64),
+    NotEquals(u64),
+    LessThan(u64),
+    LessOrEqual(u64),
+    GreaterThan(u64),
+    GreaterOrEqual(u64),
+    /// Between the two values, inclusive at both ends
+    Between(u64, u64),
+}
+
+impl Comparison {
+    /// Returns a bitmask with bit i set if value i of data matches
+    #[inline]
+    pub fn matches8(&self, data: u64x8) -> u8 {
+        match *self {
+            Comparison::Equals(n)         => data.eq(u64x8::splat(n)).bitmask(),
+            Comparison::NotEquals(n)      => data.ne(u64x8::splat(n)).bitmask(),
+            Comparison::LessThan(n)       => data.lt(u64x8::splat(n)).bitmask(),
+            Comparison::LessOrEqual(n)    => data.le(u64x8::splat(n)).bitmask(),
+            Comparison::GreaterThan(n)    => data.gt(u64x8::splat(n)).bitmask(),
+            Comparison::GreaterOrEqual(n) => data.ge(u64x8::splat(n)).bitmask(),
+            Comparison::Between(lo, hi)   => (data.ge(u64x8::splat(lo)) & data.le(u64x8::splat(hi))).bitmask(),
+        }
+    }
+
+    /// Whether a single value matches, the same as one lane of matches8()
+    pub fn matches(&self, value: u64) -> bool {
+        match *self {
+            Comparison::Equals(n)         => value == n,
+            Comparison::NotEquals(n)      => value != n,
+            Comparison::LessThan(n)       => value < n,
+            Comparison::LessOrEqual(n)    => value <= n,
+            Comparison::GreaterThan(n)    => value > n,
+            Comparison::GreaterOrEqual(n) => value >= n,
+            Comparison::Between(lo, hi)   => value >= lo && value <= hi,
+        }
+    }
+}
+
+/// A Sink which compares each block of 8 values and appends only the matching ones to a Vec.  Values past
+/// num_values, ie the padding of the last block, are masked out rather than compared.
+#[derive(Debug)]
+struct CollectSink {
+    comparison: Comparison,
+    values_left: usize,
+    out: Vec<u64>,
+}
+
+impl Sink<u64x8> for CollectSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let mut mask = self.comparison.matches8(data);
+        if self.values_left < 8 { mask &= ((1u16 << self.values_left) - 1) as u8; }
+        self.values_left = self.values_left.saturating_sub(8);
+        if mask == 0 { return }
+
+        // Compress the matching lanes to the front of a stack array: every lane is written there, but the
+        // write position only moves past the ones which match, so there are no branches on the mask
+        let mut compressed = [0u64; 8];
+        let mut pos = 0;
+        for i in 0..8 {
+            compressed[pos] = data.extract(i);
+            pos += ((mask >> i) & 1) as usize;
+        }
+        self.out.extend_from_slice(&compressed[..pos]);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.out.clear();
+    }
+}
+
+/// Decodes num_values values packed with [`pack_u64`](../nibblepacking/fn.pack_u64.html) and returns only the
+/// ones matching comparison, in order, as one fused scan: each block of 8 is compared with SIMD and the matching
+/// values are compressed together before being appended, so the values which do not match are never stored in
+/// the output.  The compress step is a branch free scalar loop; AVX-512 VCOMPRESS is not used.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::filter::{decode_filter_collect, Comparison};
+///     let packed = nibblepacking::pack_u64_to_vec([5u64, 50, 7, 500, 9].iter().cloned());
+///     let big = decode_filter_collect(&packed[..], 5, Comparison::GreaterThan(10)).unwrap();
+///     assert_eq!(big, vec![50, 500]);
+/// ```
+pub fn decode_filter_collect(encoded: &[u8],
+                             num_values: usize,
+                             comparison: Comparison) -> Result<Vec<u64>, CodingError> {
+    let mut sink = CollectSink { comparison, values_left: num_values, out: Vec::new() };
+    nibblepacking::unpack(encoded, &mut sink, num_values)?;
+    Ok(sink.out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -369,4 +472,24 @@ mod tests {
         expected_pos.resize(67, 0);
         assert_eq!(matches, expected_pos);
     }
+
+    #[test]
+    fn test_decode_filter_collect_matches_decode_then_filter() {
+        let inputs: Vec<u64> = (0..1003u64).map(|i| if i % 11 == 0 { 0 } else { (i * 7919) % 5000 }).collect();
+        let packed = nibblepacking::pack_u64_to_vec(inputs.iter().cloned());
+        let comparisons = [Comparison::Equals(0), Comparison::NotEquals(0), Comparison::LessThan(100),
+                           Comparison::LessOrEqual(4999), Comparison::GreaterThan(4000),
+                           Comparison::GreaterOrEqual(5000), Comparison::Between(1000, 1999)];
+        for comparison in comparisons.iter() {
+            let expected: Vec<u64> = inputs.iter().cloned().filter(|&n| comparison.matches(n)).collect();
+            let collected = decode_filter_collect(&packed[..], inputs.len(), *comparison).unwrap();
+            assert_eq!(collected, expected, "{:?}", comparison);
+        }
+
+        // The 5 padding zeroes of the last block never match
+        let zeroes = decode_filter_collect(&packed[..], inputs.len(), Comparison::Equals(0)).unwrap();
+        assert_eq!(zeroes.len(), (inputs.len() + 10) / 11);
+        assert_eq!(decode_filter_collect(&packed[..packed.len() - 1], inputs.len(), Comparison::Equals(0)),
+                   Err(CodingError::InputTooShort));
+    }
 }
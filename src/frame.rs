@@ -0,0 +1,172 @@
+/// A framing format for persisting packed u64 values in append-only logs.  Every flush is written as one frame:
+///
+/// | Bytes   | Contents                                                        |
+/// | ------- | --------------------------------------------------------------- |
+/// | varint  | Length of the payload in bytes                                  |
+/// | 4       | CRC-32 of the payload, little endian                            |
+/// | payload | Number of values as a varint, then the values NibblePacked      |
+///
+/// Frames are self delimiting, so they can simply be concatenated in a file and read back one after another.
+/// A frame whose payload does not match its CRC is reported as an error, and can be stepped over using
+/// [`FrameReader::skip_frame`](struct.FrameReader.html#method.skip_frame) to carry on with the frames after it.
+use crate::byteutils::*;
+use crate::error::CodingError;
+use crate::nibblepacking::{nibble_pack8_to, unpack};
+use crate::sink::{Sink, VecSink};
+
+/// Writes frames, reusing one payload buffer across frames
+#[derive(Debug, Default)]
+pub struct FrameWriter {
+    payload: Vec<u8>,
+}
+
+impl FrameWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes one frame holding values to out, which could be a `Vec<u8>` or a `WriteSink` for a file.
+    /// Returns the number of bytes in the frame.
+    pub fn write_frame<B: ByteSink>(&mut self, values: &[u64], out: &mut B) -> Result<usize, CodingError> {
+        self.payload.clear();
+        write_varint(&mut self.payload, values.len() as u64)?;
+        for chunk in values.chunks(8) {
+            let mut block = [0u64; 8];
+            block[..chunk.len()].copy_from_slice(chunk);
+            nibble_pack8_to(&block, &mut self.payload)?;
+        }
+
+        let mut header = Vec::with_capacity(14);
+        write_varint(&mut header, self.payload.len() as u64)?;
+        header.write_uint_le(crc32(&self.payload[..]) as u64, 4)?;
+        out.write_bytes(&header[..])?;
+        out.write_bytes(&self.payload[..])?;
+        Ok(header.len() + self.payload.len())
+    }
+}
+
+/// Reads frames back one at a time, reusing one output Vec across frames
+#[derive(Debug)]
+pub struct FrameReader {
+    sink: VecSink<u64>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self { sink: VecSink::new() }
+    }
+
+    /// Reads the frame at the start of buf, returning its values and the rest of buf after the frame.
+    /// Returns InputTooShort if buf ends partway through the frame, or InvalidFormat if the CRC does not match
+    /// or the payload cannot be decoded.
+    pub fn read_frame<'a>(&mut self, buf: &'a [u8]) -> Result<(&[u64], &'a [u8]), CodingError> {
+        let (crc, payload, rest) = split_frame(buf)?;
+        let actual_crc = crc32(payload);
+        if actual_crc != crc {
+            return Err(CodingError::InvalidFormat(
+                format!("Frame CRC {:#010x} does not match payload CRC {:#010x}", crc, actual_crc)));
+        }
+
+        let (num_values, pos) = read_varint(payload, 0)?;
+        self.sink.reset();
+        let leftover = unpack(&payload[pos..], &mut self.sink, num_values as usize)
+            .map_err(|e| CodingError::InvalidFormat(format!("Could not decode frame payload: {:?}", e)))?;
+        if !leftover.is_empty() {
+            return Err(CodingError::InvalidFormat(format!("{} extra bytes after frame values", leftover.len())));
+        }
+        Ok((self.sink.as_slice(), rest))
+    }
+
+    /// Returns the rest of buf after the frame at its start, without checking or decoding the frame.
+    /// Use this to step over a corrupt frame.
+    pub fn skip_frame(buf: &[u8]) -> Result<&[u8], CodingError> {
+        split_frame(buf).map(|(_, _, rest)| rest)
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Splits a frame into (CRC, payload, rest of buf)
+fn split_frame(buf: &[u8]) -> Result<(u32, &[u8], &[u8]), CodingError> {
+    let (payload_len, pos) = read_varint(buf, 0)?;
+    // Compare against what is left rather than adding to payload_len, which can be close to u64::MAX
+    let remaining = buf.len() - pos;
+    if remaining < 4 || ((remaining - 4) as u64) < payload_len { return Err(CodingError::InputTooShort) }
+    let crc = u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
+    let (payload, rest) = buf[pos + 4..].split_at(payload_len as usize);
+    Ok((crc, payload, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_write_read_frames_in_sequence() {
+        let frames: Vec<Vec<u64>> = vec![(0..13).map(|i| i * 1001).collect(),
+                                         vec![],
+                                         (0..300).map(|i| 1 << (i % 60)).collect()];
+        let mut log = Vec::new();
+        let mut writer = FrameWriter::new();
+        let lengths: Vec<usize> = frames.iter().map(|values| writer.write_frame(values, &mut log).unwrap()).collect();
+        assert_eq!(lengths.iter().sum::<usize>(), log.len());
+
+        let mut reader = FrameReader::new();
+        let mut rest = &log[..];
+        for values in frames.iter() {
+            let (read_values, next) = reader.read_frame(rest).unwrap();
+            assert_eq!(read_values, &values[..]);
+            rest = next;
+        }
+        assert!(rest.is_empty());
+
+        // A frame cut short
+        assert_eq!(reader.read_frame(&log[..lengths[0] - 1]).err(), Some(CodingError::InputTooShort));
+    }
+
+    #[test]
+    fn test_skip_corrupt_frame() {
+        let mut log = Vec::new();
+        let mut writer = FrameWriter::new();
+        writer.write_frame(&[1, 2, 3], &mut log).unwrap();
+        let second_start = log.len();
+        writer.write_frame(&[400, 500, 600], &mut log).unwrap();
+        writer.write_frame(&[7, 8, 9], &mut log).unwrap();
+
+        // Flip a bit in the packed values of the second frame
+        log[second_start + 8] ^= 0x10;
+
+        let mut reader = FrameReader::new();
+        let (values, rest) = reader.read_frame(&log[..]).unwrap();
+        assert_eq!(values, [1, 2, 3]);
+        match reader.read_frame(rest) {
+            Err(CodingError::InvalidFormat(msg)) => assert!(msg.contains("CRC")),
+            other => panic!("Expected a CRC error, got {:?}", other),
+        }
+        let rest = FrameReader::skip_frame(rest).unwrap();
+        let (values, rest) = reader.read_frame(rest).unwrap();
+        assert_eq!(values, [7, 8, 9]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_skip_frame_with_huge_length() {
+        // A 10 byte varint length with the top bit set, followed by a CRC and a few bytes
+        let mut buf = vec![0xffu8; 9];
+        buf.push(0x01);
+        buf.extend_from_slice(&[0u8; 8]);
+        assert_eq!(FrameReader::skip_frame(&buf[..]).err(), Some(CodingError::InputTooShort));
+        assert_eq!(FrameReader::new().read_frame(&buf[..]).err(), Some(CodingError::InputTooShort));
+        assert_eq!(FrameReader::skip_frame(&buf[..12]).err(), Some(CodingError::InputTooShort));
+    }
+}
@@ -0,0 +1,251 @@
+/// Gorilla-style compression of f64 streams, as described in the Facebook Gorilla paper
+/// (http://www.vldb.org/pvldb/vol8/p1816-teller.pdf).
+///
+/// Like [`pack_f64_xor`](../nibblepacking/fn.pack_f64_xor.html), each value is XORed with the previous one,
+/// but instead of NibblePacking the XORs 8 at a time, each XOR is written at bit granularity:
+/// - `0`: the value is the same as the previous one
+/// - `10`: the meaningful bits of the XOR fit in the previous window of leading/trailing zeroes, and are
+///   written using the same window
+/// - `11`: a new window: 5 bits of leading zeroes, 6 bits of meaningful bit length, then the meaningful bits
+///
+/// This often beats NibblePacking for slowly changing floats, at the cost of slower, bit by bit decoding.
+use crate::byteutils::nibble_mask;
+use crate::error::CodingError;
+
+/// Writes bits MSB first into a byte slice
+#[derive(Debug)]
+struct BitWriter<'a> {
+    buf: &'a mut [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, bit_pos: 0 }
+    }
+
+    /// Writes the num_bits lowest bits of value, most significant first
+    #[inline]
+    fn write_bits(&mut self, value: u64, num_bits: u32) -> Result<(), CodingError> {
+        if self.bit_pos + num_bits as usize > self.buf.len() * 8 {
+            return Err(CodingError::NotEnoughSpace);
+        }
+        let mut remaining = num_bits;
+        while remaining > 0 {
+            let byte_idx = self.bit_pos / 8;
+            let free_bits = 8 - (self.bit_pos % 8) as u32;
+            let num_taken = free_bits.min(remaining);
+            let chunk = (value >> (remaining - num_taken)) & nibble_mask(num_taken);
+            // Clear out each new byte first, since the buffer might not be zeroed
+            if free_bits == 8 { self.buf[byte_idx] = 0; }
+            self.buf[byte_idx] |= (chunk << (free_bits - num_taken)) as u8;
+            self.bit_pos += num_taken as usize;
+            remaining -= num_taken;
+        }
+        Ok(())
+    }
+
+    /// Number of bytes written so far, including any final partial byte
+    fn num_bytes(&self) -> usize {
+        (self.bit_pos + 7) / 8
+    }
+}
+
+/// Reads bits MSB first from a byte slice
+#[derive(Debug)]
+struct BitReader<'a> {
+    buf: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, bit_pos: 0 }
+    }
+
+    #[inline]
+    fn read_bits(&mut self, num_bits: u32) -> Result<u64, CodingError> {
+        if self.bit_pos + num_bits as usize > self.buf.len() * 8 {
+            return Err(CodingError::InputTooShort);
+        }
+        let mut value = 0u64;
+        let mut remaining = num_bits;
+        while remaining > 0 {
+            let byte = self.buf[self.bit_pos / 8] as u64;
+            let avail_bits = 8 - (self.bit_pos % 8) as u32;
+            let num_taken = avail_bits.min(remaining);
+            let chunk = (byte >> (avail_bits - num_taken)) & nibble_mask(num_taken);
+            value = (value << num_taken) | chunk;
+            self.bit_pos += num_taken as usize;
+            remaining -= num_taken;
+        }
+        Ok(value)
+    }
+
+    #[inline]
+    fn read_bit(&mut self) -> Result<bool, CodingError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+}
+
+// Leading zeroes are written in 5 bits, so are capped at 31
+const MAX_LEADING_ZEROS: u32 = 31;
+
+/// Packs a slice of f64 values using Gorilla XOR compression.  The first value is written in full.
+/// Returns the number of bytes written, InputTooShort if there are no inputs, or NotEnoughSpace.
+///
+/// ```
+/// # use compressed_vec::gorilla;
+///     let inputs = [12.0f64, 12.0, 24.0, 15.0, 12.5];
+///     let mut buf = [0u8; 256];
+///     let written = gorilla::pack_f64_gorilla(&inputs, &mut buf).unwrap();
+///     assert_eq!(gorilla::unpack_f64_gorilla(&buf[..written], inputs.len()).unwrap(), inputs);
+/// ```
+pub fn pack_f64_gorilla(inputs: &[f64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let (first, rest) = inputs.split_first().ok_or(CodingError::InputTooShort)?;
+    let mut writer = BitWriter::new(out_buffer);
+    let mut last = first.to_bits();
+    writer.write_bits(last, 64)?;
+
+    // The current window of leading and trailing zeroes, once there is one
+    let mut window: Option<(u32, u32)> = None;
+    for f in rest {
+        let bits = f.to_bits();
+        let xor = bits ^ last;
+        last = bits;
+        if xor == 0 {
+            writer.write_bits(0, 1)?;
+            continue;
+        }
+
+        let leading = xor.leading_zeros().min(MAX_LEADING_ZEROS);
+        let trailing = xor.trailing_zeros();
+        match window {
+            Some((win_leading, win_trailing)) if leading >= win_leading && trailing >= win_trailing => {
+                writer.write_bits(0b10, 2)?;
+                writer.write_bits(xor >> win_trailing, 64 - win_leading - win_trailing)?;
+            },
+            _ => {
+                let meaningful_bits = 64 - leading - trailing;
+                writer.write_bits(0b11, 2)?;
+                writer.write_bits(leading as u64, 5)?;
+                // 64 meaningful bits does not fit in 6 bits, but 0 is impossible, so it stands in for 64
+                writer.write_bits((meaningful_bits % 64) as u64, 6)?;
+                writer.write_bits(xor >> trailing, meaningful_bits)?;
+                window = Some((leading, trailing));
+            },
+        }
+    }
+    Ok(writer.num_bytes())
+}
+
+/// Unpacks num_values f64 values encoded using [`pack_f64_gorilla`](fn.pack_f64_gorilla.html).
+/// Returns InputTooShort up front if encoded is too short to hold num_values values.
+pub fn unpack_f64_gorilla(encoded: &[u8], num_values: usize) -> Result<Vec<f64>, CodingError> {
+    if num_values == 0 { return Ok(Vec::new()) }
+    // The first value takes 64 bits and every one after it at least 1, so check before allocating for them
+    if num_values - 1 > (encoded.len() * 8).saturating_sub(64) { return Err(CodingError::InputTooShort) }
+    let mut out = Vec::with_capacity(num_values);
+
+    let mut reader = BitReader::new(encoded);
+    let mut last = reader.read_bits(64)?;
+    out.push(f64::from_bits(last));
+
+    let mut window = (0u32, 0u32);
+    for _ in 1..num_values {
+        if reader.read_bit()? {
+            if reader.read_bit()? {
+                let leading = reader.read_bits(5)? as u32;
+                let meaningful_bits = match reader.read_bits(6)? as u32 {
+                    0 => 64,
+                    n => n,
+                };
+                if leading + meaningful_bits > 64 {
+                    return Err(CodingError::InvalidFormat(
+                        format!("{} leading zeroes plus {} meaningful bits is over 64", leading, meaningful_bits)));
+                }
+                window = (leading, 64 - leading - meaningful_bits);
+            }
+            let (leading, trailing) = window;
+            let xor = reader.read_bits(64 - leading - trailing)? << trailing;
+            last ^= xor;
+        }
+        out.push(f64::from_bits(last));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nibblepacking::pack_f64_xor;
+
+    #[test]
+    fn test_gorilla_roundtrip_paper_example() {
+        // Repeats, a new window, window reuse, full 64-bit XOR, and special values
+        let inputs = [12.0f64, 12.0, 24.0, 15.0, 12.5, 12.0, 12.0, -12.0, f64::MAX, f64::MIN_POSITIVE,
+                      0.0, -0.0, f64::INFINITY, f64::NAN, 1.0];
+        let mut buf = [0xffu8; 512];
+        let written = pack_f64_gorilla(&inputs, &mut buf).unwrap();
+
+        let out = unpack_f64_gorilla(&buf[..written], inputs.len()).unwrap();
+        let out_bits: Vec<u64> = out.iter().map(|f| f.to_bits()).collect();
+        let in_bits: Vec<u64> = inputs.iter().map(|f| f.to_bits()).collect();
+        assert_eq!(out_bits, in_bits);
+
+        assert_eq!(pack_f64_gorilla(&[], &mut buf), Err(CodingError::InputTooShort));
+        assert_eq!(pack_f64_gorilla(&inputs, &mut buf[..10]), Err(CodingError::NotEnoughSpace));
+        assert_eq!(unpack_f64_gorilla(&buf[..9], inputs.len()), Err(CodingError::InputTooShort));
+        assert_eq!(unpack_f64_gorilla(&buf[..written], usize::MAX), Err(CodingError::InputTooShort));
+    }
+
+    #[test]
+    fn test_gorilla_smaller_than_xor_for_slow_series() {
+        let inputs: Vec<f64> = (0..256).map(|i| 100.0 + (i / 8) as f64 * 0.25).collect();
+        let mut buf = [0u8; 4096];
+        let written = pack_f64_gorilla(&inputs, &mut buf).unwrap();
+        let mut xor_buf = [0u8; 4096];
+        let xor_written = pack_f64_xor(inputs.iter().cloned(), &mut xor_buf).unwrap();
+        assert!(written < xor_written, "gorilla {} vs xor {}", written, xor_written);
+        assert_eq!(unpack_f64_gorilla(&buf[..written], inputs.len()).unwrap(), inputs);
+    }
+}
+
+// NOTE: cfg(test) is needed so that proptest can just be a "dev-dependency" and not linked for final library
+#[cfg(test)]
+mod props {
+    extern crate proptest;
+
+    use self::proptest::prelude::*;
+    use super::*;
+
+    // Slowly changing series, which mostly reuse the previous window
+    prop_compose! {
+        fn arb_slow_f64s()
+                        (start in -1e6f64..1e6, steps in proptest::collection::vec(-4i32..4, 1..200))
+                        -> Vec<f64> {
+            steps.iter().scan(start, |acc, &step| { *acc += step as f64 * 0.5; Some(*acc) }).collect()
+        }
+    }
+
+    fn roundtrip_bits(inputs: &[f64]) {
+        let mut buf = vec![0u8; inputs.len() * 10 + 8];
+        let written = pack_f64_gorilla(inputs, &mut buf).unwrap();
+        let out = unpack_f64_gorilla(&buf[..written], inputs.len()).unwrap();
+        let out_bits: Vec<u64> = out.iter().map(|f| f.to_bits()).collect();
+        let in_bits: Vec<u64> = inputs.iter().map(|f| f.to_bits()).collect();
+        assert_eq!(out_bits, in_bits);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_gorilla_any_f64s(inputs in proptest::collection::vec(any::<f64>(), 1..200)) {
+            roundtrip_bits(&inputs[..]);
+        }
+
+        #[test]
+        fn prop_gorilla_slow_f64s(inputs in arb_slow_f64s()) {
+            roundtrip_bits(&inputs[..]);
+        }
+    }
+}
@@ -84,8 +84,9 @@ pub fn compress_geom_nonincreasing(num_buckets: u16,
 ///
 /// Meant to be used again and again to parse next histogram, thus the last_hist_deltas
 /// state is reused to compute the next set of deltas.
-/// If the new set of values is less than last_hist_deltas then the new set of values is
-/// encoded instead of the diffs.
+/// If any of the new values is less than last_hist_deltas (ie a counter reset), then the whole new set of values
+/// is encoded instead of the diffs, and finish() returns true.  Mixing raw values and diffs in one histogram
+/// could not be decoded.
 /// For more details, see the "2D Delta" section in [compression.md](doc/compression.md)
 #[derive(Default)]
 #[derive(Debug)]
@@ -94,6 +95,7 @@ pub struct DeltaDiffPackSink<'a> {
     i: usize,
     last_hist_deltas: Vec<u64>,
     pack_array: [u64; 8],
+    hist_offset: usize,     // out_offset at the start of the current histogram
     out_offset: usize,
     out_buf: &'a mut [u8],
 }
@@ -110,9 +112,10 @@ impl<'a> DeltaDiffPackSink<'a> {
         self.out_offset = 0;
     }
 
-    /// Call this to finish packing the remainder of the deltas and reset for next go
+    /// Call this to finish packing the remainder of the deltas and reset for next go.
+    /// Returns true if a value dropped, in which case the raw values were packed instead of the diffs.
     #[inline]
-    pub fn finish(&mut self) {
+    pub fn finish(&mut self) -> bool {
         // TODO: move this to a pack_remainder function?
         if self.i != 0 {
             for j in self.i..8 {
@@ -120,14 +123,22 @@ impl<'a> DeltaDiffPackSink<'a> {
             }
             self.out_offset = nibble_pack8(&self.pack_array, self.out_buf, self.out_offset).unwrap();
         }
+        let value_dropped = self.value_dropped;
+        if value_dropped {
+            // last_hist_deltas now holds the new values, so overwrite the diffs packed so far with them
+            let new_values = self.last_hist_deltas.iter().cloned();
+            self.out_offset = pack_u64(new_values, self.out_buf, self.hist_offset).unwrap();
+        }
         self.i = 0;
         self.value_dropped = false;
+        value_dropped
     }
 }
 
 impl<'a> Sink<u64x8> for DeltaDiffPackSink<'a> {
     #[inline]
     fn process(&mut self, data: u64x8) {
+        if self.i == 0 { self.hist_offset = self.out_offset; }
         let maxlen = self.last_hist_deltas.len();
         let looplen = if self.i + 8 <= maxlen { 8 } else { maxlen - self.i };
         for n in 0..looplen {
@@ -166,6 +177,7 @@ impl<'a> Sink<u64x8> for DeltaDiffPackSink<'a> {
         for elem in self.last_hist_deltas.iter_mut() {
             *elem = 0;
         }
+        self.hist_offset = 0;
         self.out_offset = 0;
     }
 }
@@ -236,3 +248,35 @@ fn delta_diffpack_sink_test() {
         assert_eq!(dsink.output_vec()[..inputs[0].len()], diffs[i - 1][..]);
     }
 }
+
+#[test]
+fn delta_diffpack_sink_value_dropped() {
+    // The third histogram is a counter reset: every bucket is below the one before
+    let inputs = [ [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078],
+                   [3u64, 1004, 1006, 1008, 1009, 2012, 2020, 3056, 4070, 5090, 6101, 7150],
+                   [1u64, 2, 4, 8, 9, 12, 20, 56, 70, 90, 101, 150],
+                   [2u64, 4, 8, 16, 18, 24, 40, 112, 140, 180, 202, 300] ];
+    let num_buckets = inputs[0].len();
+    let compressed_inputs: Vec<[u8; 256]> = inputs.iter().map(|input| {
+        let mut buf = [0u8; 256];
+        pack_u64_delta(&input[..], &mut buf).unwrap();
+        buf
+    }).collect();
+
+    let mut out_buf = [0u8; 1024];
+    let mut sink = DeltaDiffPackSink::new(num_buckets, &mut out_buf);
+    for i in 0..inputs.len() {
+        sink.reset_out_buf();
+        unpack(&compressed_inputs[i], &mut sink, num_buckets).unwrap();
+        assert_eq!(sink.value_dropped, i == 2);
+        assert_eq!(sink.finish(), i == 2);
+
+        let mut dsink = DeltaSink::new();
+        unpack(&sink.out_buf[..sink.out_offset], &mut dsink, num_buckets).unwrap();
+        let expected: Vec<u64> = match i {
+            0 | 2 => inputs[i].to_vec(),   // Raw values: the first histogram, and after the drop
+            _     => inputs[i].iter().zip(inputs[i - 1].iter()).map(|(nb, na)| nb - na).collect(),
+        };
+        assert_eq!(dsink.as_slice(), &expected[..]);
+    }
+}
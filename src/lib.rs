@@ -86,6 +86,19 @@
 #[macro_use]
 extern crate memoffset;
 
+// len(), is_empty() and as_bytes() for the packed column types, which all keep num_values and bytes fields
+macro_rules! packed_accessors {
+    ($bytes_doc:literal) => {
+        /// The number of values packed
+        pub fn len(&self) -> usize { self.num_values }
+
+        pub fn is_empty(&self) -> bool { self.num_values == 0 }
+
+        #[doc = $bytes_doc]
+        pub fn as_bytes(&self) -> &[u8] { &self.bytes[..] }
+    };
+}
+
 pub mod nibblepacking;
 pub mod nibblepack_simd;
 pub mod byteutils;
@@ -95,8 +108,22 @@ pub mod section;
 pub mod error;
 pub mod filter;
 pub mod sink;
+pub mod gorilla;
+pub mod compress;
+pub mod sketch;
+pub mod dict;
+pub mod frame;
+pub mod columns;
+pub mod packed_vec;
 
 // Public crate-level exports for convenience
 pub use vector::{VectorU64Appender, VectorU32Appender, VectorF32XorAppender,
                  VectorReader};
-pub use sink::{VecSink, Section256Sink, AddConstSink};
+pub use sink::{VecSink, Section256Sink, AddConstSink, MapSink, SubtractSink, ChannelSink, RingBufferSink,
+               HashSink, FoldSink, ReverseBlockSink, LimitSink, VerifySink, VerifyMismatch,
+               ReservoirSink, PerValueSink, ValueConsumer, Reusable};
+pub use compress::{compress, CompressedU64, CounterColumn, GaugeColumn, TimestampColumn};
+pub use columns::CompressedColumns;
+pub use packed_vec::CompressedVec;
+#[cfg(feature = "compressed_vec_derive")]
+pub use compressed_vec_derive::CompressedColumns;
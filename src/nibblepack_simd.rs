@@ -46,16 +46,92 @@ pub fn pack8_u64_simd(inputs: u64x8, out_buffer: &mut [u8], offset: usize) -> Re
 
         let mut input_buf = [0u64; 8];
         inputs.write_to_slice_unaligned(&mut input_buf);
+        let mut cursor = SliceCursor::new(out_buffer, off);
         if (num_nibbles % 2) == 0 {
-            pack_to_even_nibbles(&input_buf, out_buffer, off, num_nibbles, trailing_nibbles)
+            pack_to_even_nibbles(&input_buf, &mut cursor, num_nibbles, trailing_nibbles)?;
         } else {
-            pack_universal(&input_buf, out_buffer, off, num_nibbles, trailing_nibbles)
+            pack_universal(&input_buf, &mut cursor, num_nibbles, trailing_nibbles)?;
         }
+        Ok(cursor.pos)
     } else {
         Ok(off)
     }
 }
 
+/// Computes the NibblePack nonzero bitmask for 8 u64 inputs: bit i is set if inputs[i] is nonzero.
+// Optimized using AVX2: compare each 64-bit lane to zero, then movemask the sign bits of the result
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"),
+          target_feature = "avx2",
+          not(feature = "scalar_nonzero_mask")))]
+#[inline(always)]
+pub(crate) fn compute_nonzero_mask(inputs: &[u64; 8]) -> u8 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    unsafe {
+        let zeroes = _mm256_setzero_si256();
+        let lower = _mm256_loadu_si256(inputs.as_ptr() as *const __m256i);
+        let upper = _mm256_loadu_si256(inputs.as_ptr().add(4) as *const __m256i);
+        let lower_zero = _mm256_movemask_pd(_mm256_castsi256_pd(_mm256_cmpeq_epi64(lower, zeroes))) as u8;
+        let upper_zero = _mm256_movemask_pd(_mm256_castsi256_pd(_mm256_cmpeq_epi64(upper, zeroes))) as u8;
+        !(lower_zero | (upper_zero << 4))
+    }
+}
+
+/// Computes the NibblePack nonzero bitmask for 8 u64 inputs: bit i is set if inputs[i] is nonzero.
+/// On x86 built without AVX2, the SSE2 version is used if the CPU has SSE2.  That is checked at runtime, but the
+/// standard library caches the answer, and it is known at compile time on x86_64, where SSE2 is always present.
+/// The `scalar_nonzero_mask` feature forces the scalar version everywhere.
+#[cfg(not(all(any(target_arch = "x86", target_arch = "x86_64"),
+          target_feature = "avx2",
+          not(feature = "scalar_nonzero_mask"))))]
+#[inline(always)]
+pub(crate) fn compute_nonzero_mask(inputs: &[u64; 8]) -> u8 {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "scalar_nonzero_mask")))]
+    {
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { compute_nonzero_mask_sse2(inputs) };
+        }
+    }
+    compute_nonzero_mask_scalar(inputs)
+}
+
+// SSE2 version of compute_nonzero_mask, for x86 CPUs without AVX2.  SSE2 has no 64-bit compare, so the 32-bit
+// halves are compared to zero and each half ANDed with the other half of its lane; then movemask_pd takes one
+// sign bit per 64-bit lane, two lanes at a time.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn compute_nonzero_mask_sse2(inputs: &[u64; 8]) -> u8 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    let zeroes = _mm_setzero_si128();
+    let mut zero_mask = 0u8;
+    for pair in 0..4 {
+        let values = _mm_loadu_si128(inputs.as_ptr().add(pair * 2) as *const __m128i);
+        let halves_zero = _mm_cmpeq_epi32(values, zeroes);
+        let lanes_zero = _mm_and_si128(halves_zero, _mm_shuffle_epi32(halves_zero, 0b10_11_00_01));
+        zero_mask |= (_mm_movemask_pd(_mm_castsi128_pd(lanes_zero)) as u8) << (pair * 2);
+    }
+    !zero_mask
+}
+
+// Scalar version of compute_nonzero_mask, for CPUs without SSE2 or AVX2
+#[inline(always)]
+pub(crate) fn compute_nonzero_mask_scalar(inputs: &[u64; 8]) -> u8 {
+    let mut nonzero_mask = 0u8;
+    for i in 0..8 {
+        if inputs[i] != 0 {
+            nonzero_mask |= 1 << i;
+        }
+    }
+    nonzero_mask
+}
+
 // Variable shifts for each SIMD lane to decode NibblePacked data
 const U32_SIMD_SHIFTS: [u32x8; 9] = [
     // 0 nibbles: this should never be used
@@ -436,13 +512,13 @@ fn preload_u32x8_nibbles(buf: &[u8],
     let total_bytes = (num_nibbles * nonzeroes as usize + 1) / 2;
     let mut i = 0;
     let mut off = 2;
-    let simd_word = u32x8::splat(0);
+    let mut simd_word = u32x8::splat(0);
     while i < 8 && off < (total_bytes + 2) {
         let inword = direct_read_uint_le(buf, off)?;
         // Safe because we are checking boundaries in while loop conditions
-        unsafe { simd_word.replace_unchecked(i, inword as u32) };
+        simd_word = unsafe { simd_word.replace_unchecked(i, inword as u32) };
         let shift2 = (num_nibbles * 4) / 8 * 8;  // round off shift to lower byte boundary
-        unsafe { simd_word.replace_unchecked(i + 1, (inword >> shift2) as u32) };
+        simd_word = unsafe { simd_word.replace_unchecked(i + 1, (inword >> shift2) as u32) };
         i += 2;
         off += num_nibbles;
     }
@@ -557,6 +633,8 @@ pub fn unpack8_u32_simd<'a, Output: Sink<u32x8>>(
                             format!("{:?} nibbles is too many for u32 decoder", num_nibbles))),
         };
 
+        if inbuf.len() < 2 + num_bytes as usize { return Err(CodingError::NotEnoughSpace) }
+
         let shuffled = simd_unpack_inner(simd_inputs, num_nibbles, trailing_zeros,
                                          nonzero_count, nonzero_mask);
 
@@ -664,6 +742,44 @@ fn test_unpack_u32simd_3_4nibbles() {
     assert_eq!(sink.values[..inputs.len()], inputs);
 }
 
+#[test]
+fn test_unpack_u32simd_5_8nibbles_short_buffer() {
+    // A final block in a buffer shorter than MAX_U32_NIBBLEPACKED_LEN goes through preload_u32x8_nibbles
+    for &inputs in [[0x1_2345u64, 0x2_3456, 0x3_4567, 0x4_5678, 0x5_6789, 0x6_789a, 0x7_89ab, 0x8_9abc],
+                    [0xffff_ffffu64, 1, 0, 3, 0x8000_0000, 5, 6, 7]].iter() {
+        let mut buf = [0u8; 512];
+        let written = nibble_pack8(&inputs, &mut buf, 0).unwrap();
+        assert!(written < MAX_U32_NIBBLEPACKED_LEN);
+
+        let mut sink = U32_256Sink::new();
+        let rest = unpack8_u32_simd(&buf[..written], &mut sink).unwrap();
+        assert_eq!(rest.len(), 0);
+        let expected: Vec<u32> = inputs.iter().map(|&x| x as u32).collect();
+        assert_eq!(sink.values[..8], expected[..]);
+    }
+}
+
+#[test]
+fn test_compute_nonzero_mask_all_patterns() {
+    for pattern in 0..=255u8 {
+        let mut inputs = [0u64; 8];
+        for i in 0..8 {
+            // Use values with only the top or bottom bit set to catch any sign or truncation issues
+            if pattern & (1 << i) != 0 {
+                inputs[i] = if i % 2 == 0 { 1 } else { 1 << 63 };
+            }
+        }
+        assert_eq!(compute_nonzero_mask_scalar(&inputs), pattern);
+        assert_eq!(compute_nonzero_mask(&inputs), pattern);
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                assert_eq!(unsafe { compute_nonzero_mask_sse2(&inputs) }, pattern);
+            }
+        }
+    }
+}
+
 // NOTE: cfg(test) is needed so that proptest can just be a "dev-dependency" and not linked for final library
 // NOTE2: somehow cargo is happier when we put props tests in its own module
 #[cfg(test)]
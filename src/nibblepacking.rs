@@ -1,9 +1,15 @@
-use packed_simd::{u32x8, u64x8, FromCast};
+use std::borrow::BorrowMut;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use arrayref::array_ref;
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use packed_simd::{shuffle, f64x8, u32x8, u64x8, FromBits, FromCast};
 
 use crate::error::CodingError;
 use crate::byteutils::*;
 use crate::sink::*;
-use crate::nibblepack_simd::unpack8_u32_simd;
+use crate::nibblepack_simd::{compute_nonzero_mask, unpack8_u32_simd};
 
 /// Packs a slice of u64 numbers that are increasing, using delta encoding.  That is, the delta between successive
 /// elements is encoded, rather than the absolute numbers.  The first number is encoded as is.
@@ -13,710 +19,6405 @@ use crate::nibblepack_simd::unpack8_u32_simd;
 /// (such as Prometheus-style increasing histograms).  If a successive input is less than the previous input,
 /// currently this method WILL CLIP and record the difference as 0.
 pub fn pack_u64_delta(inputs: &[u64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    pack_u64(clipped_deltas(inputs.iter().cloned()), out_buffer, 0)
+}
+
+/// The deltas packed by pack_u64_delta: each input minus the one before, clipped to 0 if it decreased
+pub(crate) fn clipped_deltas<I: Iterator<Item = u64>>(inputs: I) -> impl Iterator<Item = u64> {
     let mut last = 0u64;
-    let deltas = inputs.into_iter().map(|&n| {
+    inputs.map(move |n| {
         let delta = n.saturating_sub(last);
         last = n;
         delta
-    });
-    pack_u64(deltas, out_buffer, 0)
+    })
 }
 
-/// Packs a stream of double-precision IEEE-754 / f64 numbers using XOR encoding.
-/// The first f64 is written as is; after that, each successive f64 is XORed with the previous one and the xor
-/// value is written, based on the premise that when changes are small so is the XORed value.
-/// Stream must have at least one value, otherwise InputTooShort is returned
-pub fn pack_f64_xor<I: Iterator<Item = f64>>(mut stream: I,
-                                             out_buffer: &mut [u8]) -> Result<usize, CodingError> {
-    let mut last: u64 = match stream.next() {
-        Some(num) => {
-            let num_bits = num.to_bits();
-            direct_write_uint_le(out_buffer, 0, num_bits, 8)?;
-            num_bits
-        },
-        None      => return Err(CodingError::InputTooShort)
+/// Same as [`pack_u64_delta`]: #method.pack_u64_delta, but returns the first value separately from the packed
+/// deltas of all the values after it, for schemas which store a chunk's base in its own field so that every
+/// chunk body holds only deltas.  An empty input gives a base of 0 and an empty body.
+/// Decode using [`unpack_u64_delta_split`]: #method.unpack_u64_delta_split.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let (base, body) = nibblepacking::pack_u64_delta_split(&[1_000_000u64, 1_000_010, 1_000_025]);
+///     assert_eq!(base, 1_000_000);
+///     let values = nibblepacking::unpack_u64_delta_split(base, &body[..], 3).unwrap();
+///     assert_eq!(values, vec![1_000_000, 1_000_010, 1_000_025]);
+/// ```
+pub fn pack_u64_delta_split(inputs: &[u64]) -> (u64, Vec<u8>) {
+    let base = match inputs.first() {
+        Some(&base) => base,
+        None        => return (0, Vec::new()),
     };
-    pack_u64(stream.map(|f| {
-        let f_bits = f.to_bits();
-        let delta = last ^ f_bits;
-        last = f_bits;
-        delta
-    }), out_buffer, 8)
+    // The first delta is the base itself, which is returned instead of packed
+    (base, pack_u64_to_vec(clipped_deltas(inputs.iter().cloned()).skip(1)))
 }
 
+/// Same as [`pack_u64_delta`]: #method.pack_u64_delta, but lossless for counters which reset.  Where a value is
+/// less than the one before, instead of clipping the delta to 0 the value itself is stored, and its position is
+/// set in a reset bitmap, just as the histogram DeltaDiffPackSink falls back to raw values when one drops.
+/// The bitmap has one bit per value, bit n of byte i being position i * 8 + n, and its bytes are NibblePacked
+/// first, so that with rare resets it costs about a byte per 64 values.  Then the deltas and reset values follow.
+/// Decode using [`unpack_u64_delta_with_resets`]: #method.unpack_u64_delta_with_resets.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let requests = [100u64, 150, 210, 12, 40, 95, 3, 20];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64_delta_with_resets(&requests, &mut buf).unwrap();
+///     let decoded = nibblepacking::unpack_u64_delta_with_resets(&buf[..written], requests.len()).unwrap();
+///     assert_eq!(decoded, requests);
+/// ```
+pub fn pack_u64_delta_with_resets(inputs: &[u64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let mut resets = vec![0u8; (inputs.len() + 7) / 8];
+    let mut last = 0u64;
+    let values = inputs.iter().enumerate().map(|(pos, &n)| {
+        let value = if n < last {
+            resets[pos / 8] |= 1 << (pos % 8);
+            n
+        } else {
+            n - last
+        };
+        last = n;
+        value
+    }).collect::<Vec<u64>>();
+    let off = pack_u64(resets.iter().map(|&mask| mask as u64), out_buffer, 0)?;
+    pack_u64(values.into_iter(), out_buffer, off)
+}
 
+/// Packs a slice of u64 numbers which all share some trailing zero bits, such as multiples of 1000 or of a
+/// power of two.  The number of trailing zero bits common to every nonzero value is written once as the first
+/// byte, then every value is shifted right by it and NibblePacked.  Per-block trailing nibbles only drop whole
+/// nibbles, so this saves up to 3 more bits per value, and stacks with them.
+/// Decode using [`unpack_u64_scaled`]: #method.unpack_u64_scaled.
 ///
-/// Packs a stream of plain u64 numbers using NibblePacking.
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::VecSink;
+///     let inputs = [8000u64, 16000, 3000, 125000];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64_scaled(&inputs, &mut buf).unwrap();
+///     assert_eq!(buf[0], 3);    // 1000 = 2^3 * 125
+///     let mut sink = VecSink::<u64>::new();
+///     nibblepacking::unpack_u64_scaled(&buf[..written], &mut sink, inputs.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &inputs[..]);
+/// ```
+pub fn pack_u64_scaled(inputs: &[u64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let ored_bits = inputs.iter().fold(0u64, |acc, &n| acc | n);
+    // All zeroes has 64 trailing zeroes, but shifting by 64 is not possible and there is nothing to save
+    let shift = if ored_bits == 0 { 0 } else { ored_bits.trailing_zeros() };
+    let off = direct_write_uint_le(out_buffer, 0, shift as u64, 1)?;
+    pack_u64(inputs.iter().map(|&n| n >> shift), out_buffer, off)
+}
+
+/// Packs a slice of u64 gauge values, ie values which hover around some level with small ups and downs.
+/// The delta between successive elements is ZigZag encoded, so that small decreases take as few bits as small
+/// increases, instead of being clipped as in [`pack_u64_delta`]: #method.pack_u64_delta.  Decode using
+/// [`ZigZagDeltaSink`]: struct.ZigZagDeltaSink.html.  The first number is encoded as a delta from zero.
 ///
-/// This is especially powerful when combined with
-/// other packers which can do for example delta or floating point XOR or other kinds of encoding which reduces
-/// the # of bits needed and produces many zeroes.  This is why an Iterator is used for the API, as sources will
-/// typically transform the incoming data by reducing the bits needed.
-/// This method does no transformations to the input data.  You might want one of the other pack_* methods.
+/// ## Choosing an encoding
+/// * Gauges, which go up and down: use this method
+/// * Counters and increasing histograms, which only go up: use [`pack_u64_delta`]: #method.pack_u64_delta
+/// * Floating point values: use [`pack_f64_xor`]: #method.pack_f64_xor
+/// * Values sampled at a fixed rate, such as timestamps: use [`pack_u64_stepped`]: #method.pack_u64_stepped,
+///   or [`pack_u64_double_delta`]: #method.pack_u64_double_delta if the rate is not known up front
 ///
 /// ```
 /// # use compressed_vec::nibblepacking;
-///     let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
-///     let mut buf = [0u8; 1024];
-///     nibblepacking::pack_u64(inputs.into_iter().cloned(), &mut buf, 0);
+///     let inputs = [5000u64, 5002, 4999, 5001, 4998, 5000];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_gauge(&inputs, &mut buf).unwrap();
+///     let mut sink = nibblepacking::ZigZagDeltaSink::new();
+///     nibblepacking::unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &inputs[..]);
 /// ```
-/// NOTE: The NibblePack algorithm always packs 8 u64's at a time.  If the length of the input stream is not
-/// divisible by 8, extra 0 values pad the input.
-// TODO: should this really be a function, or maybe a struct with more methods?
-// TODO: also benchmark this vs just reading from a slice of u64's
-#[inline]
-pub fn pack_u64<I: Iterator<Item = u64>>(stream: I,
-                                         out_buffer: &mut [u8],
-                                         offset: usize) -> Result<usize, CodingError> {
-    let mut in_buffer = [0u64; 8];
-    let mut bufindex = 0;
-    let mut off = offset;
-    // NOTE: using pointer math is actually NOT any faster!
-    for num in stream {
-        in_buffer[bufindex] = num;
-        bufindex += 1;
-        if bufindex >= 8 {
-            // input buffer is full, encode!
-            off = nibble_pack8(&in_buffer, out_buffer, off)?;
-            bufindex = 0;
-        }
-    }
-    // If buffer is partially filled, then encode the remainer
-    if bufindex > 0 {
-        while bufindex < 8 {
-            in_buffer[bufindex] = 0;
-            bufindex += 1;
-        }
-        off = nibble_pack8(&in_buffer, out_buffer, off)?;
-    }
-    Ok(off)
+pub fn pack_gauge(inputs: &[u64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    pack_zigzag_deltas(inputs.iter().cloned(), out_buffer, 0)
 }
 
+/// Packs values which go up by a known step each time, such as the timestamps of a series sampled at a fixed
+/// interval.  Each value is stored as how far its delta from the value before differs from expected_step,
+/// ZigZag encoded so that jitter either way is cheap.  Perfectly regular data packs to all zero blocks of 1 byte
+/// each, which makes this the best encoding for fixed rate sampling.  The first value is stored as its difference
+/// from expected_step, so a series starting at a large value costs one wide value at the start.
+/// Decode using [`ZigZagDeltaSink::with_step`]: struct.ZigZagDeltaSink.html#method.with_step with the same step.
 ///
-/// NibblePacking is an encoding technique for packing 8 u64's tightly into the same number of nibbles.
-/// It can be combined with a prediction algorithm to efficiency encode floats and long values.
-/// This is really an inner function; the intention is for the user to use one of the higher level pack* methods.
-/// Please see http://github.com/filodb/FiloDB/doc/compression.md for more answers.
-///
-/// # Arguments
-/// * `inputs` - ref to 8 u64 values to pack, could be the output of a predictor
-/// * `out_buffer` - a &mut [u8] to write the encoded output to.
-/// * `offset` - offset within the out_buffer to write to
-/// Outputs the ending offset, or an error.
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let timestamps = [60_000u64, 120_000, 180_002, 240_000, 300_000];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64_stepped(&timestamps, 60_000, &mut buf).unwrap();
+///     let mut sink = nibblepacking::ZigZagDeltaSink::with_step(60_000);
+///     nibblepacking::unpack(&buf[..written], &mut sink, timestamps.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &timestamps[..]);
+/// ```
+pub fn pack_u64_stepped(inputs: &[u64], expected_step: u64, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let mut last = 0u64;
+    let residuals = inputs.iter().map(|&n| {
+        let residual = n.wrapping_sub(last).wrapping_sub(expected_step) as i64;
+        last = n;
+        zigzag_encode(residual)
+    });
+    pack_u64(residuals, out_buffer, 0)
+}
+
+/// Packs values whose deltas change slowly, such as timestamps sampled at a fixed but unknown rate, using
+/// double-delta encoding: each value's delta from the one before is taken, and then the ZigZag encoded change in
+/// that delta is NibblePacked.  A perfectly regular series packs to all zero blocks after the first two values,
+/// whatever its rate, and jitter costs a few bits either way.  The first two values cost one wide value each, as
+/// the first is stored as its delta from zero.  Wraps around, so any input round trips exactly.
+/// Decode using [`unpack_u64_double_delta`]: #method.unpack_u64_double_delta.
 ///
-#[inline(always)]
-pub fn nibble_pack8(inputs: &[u64; 8],
-                    out_buffer: &mut [u8],
-                    offset: usize) -> Result<usize, CodingError> {
-    // Compute the nonzero bitmask.  TODO: use SIMD here
-    let mut nonzero_mask = 0u8;
-    let mut off = offset;
-    for i in 0..8 {
-        if inputs[i] != 0 {
-            nonzero_mask |= 1 << i;
-        }
-    }
-    // Check for both nonzero byte and at least one more byte after that for nibbles
-    if (off + 1) >= out_buffer.len() {
-        return Err(CodingError::NotEnoughSpace);
-    }
-    out_buffer[off] = nonzero_mask;
-    off += 1;
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let timestamps = [1_600_000_015_000u64, 1_600_000_030_000, 1_600_000_045_000, 1_600_000_060_001];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64_double_delta(&timestamps, &mut buf).unwrap();
+///     assert_eq!(nibblepacking::unpack_u64_double_delta(&buf[..written], 4).unwrap(), timestamps);
+/// ```
+pub fn pack_u64_double_delta(inputs: &[u64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    pack_double_deltas(inputs.iter().cloned(), out_buffer, 0)
+}
 
-    // if no nonzero values, we're done!
-    if nonzero_mask != 0 {
-        // TODO: use SIMD here
-        // otherwise, get min of leading and trailing zeros, encode it
-        let min_leading_zeros = inputs.into_iter().map(|x| x.leading_zeros()).min().unwrap();
-        let min_trailing_zeros = inputs.into_iter().map(|x| x.trailing_zeros()).min().unwrap();
-        // Below impl seems to be equally fast, though it generates much more efficient code and SHOULD be much faster
-        // let mut ored_bits = 0u64;
-        // inputs.into_iter().for_each(|&x| ored_bits |= x);
-        // let min_leading_zeros = ored_bits.leading_zeros();
-        // let min_trailing_zeros = ored_bits.trailing_zeros();
+#[inline]
+fn pack_double_deltas<I: Iterator<Item = u64>>(stream: I,
+                                               out_buffer: &mut [u8],
+                                               offset: usize) -> Result<usize, CodingError> {
+    let mut last = 0u64;
+    let deltas = stream.map(|n| {
+        let delta = n.wrapping_sub(last);
+        last = n;
+        delta
+    });
+    pack_zigzag_deltas(deltas, out_buffer, offset)
+}
 
-        // Convert min leading/trailing to # nibbles.  Start packing!
-        // NOTE: num_nibbles cannot be 0; that would imply every input was zero
-        let trailing_nibbles = min_trailing_zeros / 4;
-        let num_nibbles = 16 - (min_leading_zeros / 4) - trailing_nibbles;
-        let nibble_word = (((num_nibbles - 1) << 4) | trailing_nibbles) as u8;
-        out_buffer[off] = nibble_word;
-        off += 1;
+/// Packs timestamps in whatever unit they are stored in, but with a coarser real resolution, such as
+/// millisecond timestamps stored as nanoseconds.  The greatest common divisor of all the values is found,
+/// for example 1_000_000 for whole milliseconds in nanoseconds, and every value is divided by it before being
+/// packed with [`pack_u64_double_delta`]: #method.pack_u64_double_delta, so the trailing zeroes of the unit
+/// cost nothing.  The divisor is written first, as 8 raw bytes; if the values share no divisor it is 1.
+/// Decode using [`unpack_timestamps_auto_unit`]: #method.unpack_timestamps_auto_unit, which multiplies back.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let nanos = [1_600_000_000_015_000_000u64, 1_600_000_000_030_000_000, 1_600_000_000_045_001_000];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_timestamps_auto_unit(&nanos, &mut buf).unwrap();
+///     assert_eq!(buf[..8], 1000u64.to_le_bytes());
+///     assert_eq!(nibblepacking::unpack_timestamps_auto_unit(&buf[..written], 3).unwrap(), nanos);
+/// ```
+pub fn pack_timestamps_auto_unit(inputs: &[u64], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let divisor = match inputs.iter().fold(0, |acc, &n| gcd(acc, n)) {
+        0 => 1,     // No values, or all zero
+        divisor => divisor,
+    };
+    let off = direct_write_uint_le(out_buffer, 0, divisor, 8)?;
+    pack_double_deltas(inputs.iter().map(|&n| n / divisor), out_buffer, off)
+}
 
-        if (num_nibbles % 2) == 0 {
-            off = pack_to_even_nibbles(inputs, out_buffer, off, num_nibbles, trailing_nibbles)?;
-        } else {
-            off = pack_universal(inputs, out_buffer, off, num_nibbles, trailing_nibbles)?;
-        }
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let rem = a % b;
+        a = b;
+        b = rem;
     }
-    Ok(off)
+    a
 }
 
+/// Packs a boolean column with long runs of the same value, such as a feature flag sampled over time, as its runs
+/// rather than its bits.  The first value is written as a 0 or 1 byte, then the number of transitions as a varint,
+/// then the positions where the value flips, which are increasing and so packed with
+/// [`pack_u64_delta`]: #method.pack_u64_delta as the lengths of the runs.  A column of a few runs takes a few bytes
+/// whatever its length, where bit packing takes a bit per value; a column which flips often is better bit packed.
+/// Decode using [`unpack_bool_rle`]: #method.unpack_bool_rle.
 ///
-/// Inner function to pack the raw inputs to nibbles when # nibbles is even (always # bytes)
-/// It's somehow really fast, perhaps because it is really simple.
-///
-/// # Arguments
-/// * `trailing_zero_nibbles` - the min # of trailing zero nibbles across all inputs
-/// * `num_nibbles` - the max # of nibbles having nonzero bits in all inputs
-#[inline]
-pub(crate) fn pack_to_even_nibbles(
-    inputs: &[u64; 8],
-    out_buffer: &mut [u8],
-    offset: usize,
-    num_nibbles: u32,
-    trailing_zero_nibbles: u32
-) -> Result<usize, CodingError> {
-    // In the future, explore these optimizations: functions just for specific nibble widths
-    let shift = trailing_zero_nibbles * 4;
-    assert!(num_nibbles % 2 == 0);
-    let num_bytes_each = (num_nibbles / 2) as usize;
-    let mut off = offset;
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let flags: Vec<bool> = (0..10_000).map(|i| i >= 2500 && i < 7000).collect();
+///     let mut buf = [0u8; 64];
+///     let written = nibblepacking::pack_bool_rle(&flags, &mut buf).unwrap();
+///     assert!(written < 10);
+///     assert_eq!(nibblepacking::unpack_bool_rle(&buf[..written], flags.len()).unwrap(), flags);
+/// ```
+pub fn pack_bool_rle(inputs: &[bool], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let transitions: Vec<u64> = (1..inputs.len()).filter(|&i| inputs[i] != inputs[i - 1]).map(|i| i as u64).collect();
+    let mut header = vec![inputs.first().cloned().unwrap_or(false) as u8];
+    write_varint(&mut header, transitions.len() as u64)?;
+    if out_buffer.len() < header.len() { return Err(CodingError::NotEnoughSpace) }
+    out_buffer[..header.len()].copy_from_slice(&header[..]);
+    let written = pack_u64_delta(&transitions, &mut out_buffer[header.len()..])?;
+    Ok(header.len() + written)
+}
 
-    // for each nonzero input, shift and write out exact # of bytes
-    for &x in inputs {
-        if x != 0 {
-            off = direct_write_uint_le(out_buffer, off, x >> shift, num_bytes_each)?;
-        }
-    };
-    Ok(off)
+/// Diagnostics from [`pack_timestamps`]: #method.pack_timestamps about how far out of order the timestamps were
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ReorderStats {
+    pub bytes_written: usize,
+    /// The largest amount any timestamp was less than the one before it, 0 if they were all in order
+    pub max_backward_jump: u64,
+    /// Number of timestamps which went backwards by more than the reorder window
+    pub num_outside_window: usize,
 }
 
-/// Universal, generic nibble packing algorithm, packing 8 64-bit values to a byte buffer.
-/// This code is inspired by bitpacking crate: https://github.com/tantivy-search/bitpacking/
-/// but modified for the NibblePacking algorithm.  No macros, so slightly less efficient.
-/// TODO: consider using macros like in bitpacking to achieve even more speed :D
+/// Packs timestamps which are mostly increasing, but may arrive slightly out of order.  Unlike
+/// [`pack_u64_delta`]: #method.pack_u64_delta, which clips a backward step to zero, the deltas are ZigZag encoded
+/// as in [`pack_gauge`]: #method.pack_gauge, so out of order timestamps round trip exactly and a small step back
+/// is as cheap as a small step forward.  Decode using [`ZigZagDeltaSink`]: struct.ZigZagDeltaSink.html.
+///
+/// Backward steps are expected to be within reorder_window.  Larger ones are still packed losslessly but are
+/// counted in the returned stats, as they more likely point to corrupt data or clock problems than reordering.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let timestamps = [1000u64, 1010, 1005, 1020, 1030, 1025];
+///     let mut buf = [0u8; 256];
+///     let stats = nibblepacking::pack_timestamps(&timestamps, 10, &mut buf).unwrap();
+///     assert_eq!(stats.max_backward_jump, 5);
+///     assert_eq!(stats.num_outside_window, 0);
+/// ```
+pub fn pack_timestamps(inputs: &[u64],
+                       reorder_window: u64,
+                       out_buffer: &mut [u8]) -> Result<ReorderStats, CodingError> {
+    let mut stats = ReorderStats::default();
+    for pair in inputs.windows(2) {
+        let backward_jump = pair[0].saturating_sub(pair[1]);
+        stats.max_backward_jump = stats.max_backward_jump.max(backward_jump);
+        if backward_jump > reorder_window { stats.num_outside_window += 1; }
+    }
+    stats.bytes_written = pack_zigzag_deltas(inputs.iter().cloned(), out_buffer, 0)?;
+    Ok(stats)
+}
+
+// ZigZag delta encodes a stream starting from zero, wrapping around so any u64 (or i64 cast to u64) is lossless
 #[inline]
-pub(crate) fn pack_universal(
-    inputs: &[u64; 8],
-    out_buffer: &mut [u8],
-    offset: usize,
-    num_nibbles: u32,
-    trailing_zero_nibbles: u32
-) -> Result<usize, CodingError> {
-    let trailing_shift = trailing_zero_nibbles * 4;
-    let num_bits = num_nibbles * 4;
-    let mut out_word = 0u64;
-    let mut bit_cursor = 0;
-    let mut off = offset;
+fn pack_zigzag_deltas<I: Iterator<Item = u64>>(stream: I,
+                                               out_buffer: &mut [u8],
+                                               offset: usize) -> Result<usize, CodingError> {
+    let mut last = 0u64;
+    let deltas = stream.map(|n| {
+        let delta = n.wrapping_sub(last) as i64;
+        last = n;
+        zigzag_encode(delta)
+    });
+    pack_u64(deltas, out_buffer, offset)
+}
 
-    for &x in inputs {
-        if x != 0 {
-            let remaining = 64 - bit_cursor;
-            let shifted_input = x >> trailing_shift;
+/// Packs f64 values with bounded precision, such as temperatures to 0.01 degrees, as fixed point integers.
+/// Each value is multiplied by scale and rounded to the nearest i64, then the integers are ZigZag delta encoded
+/// as in [`pack_gauge`]: #method.pack_gauge.  The scale is written first, as 8 raw bytes.
+/// For smooth series this compresses far better than [`pack_f64_xor`]: #method.pack_f64_xor, at the cost of
+/// precision: every value decoded by [`unpack_f64_fixed`]: #method.unpack_f64_fixed is within 0.5 / scale of
+/// the original (plus f64 rounding error when dividing back by scale).
+///
+/// Returns InvalidFormat if scale is not a positive finite number, or ValueOutOfRange if a value is NaN or
+/// infinite or its scaled value does not fit in an i64 (see [`f64_to_fixed`]: #method.f64_to_fixed).
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let temps = [21.37f64, 21.38, 21.40, 21.39, 21.35];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_f64_fixed(&temps, 100.0, &mut buf).unwrap();
+///     let out = nibblepacking::unpack_f64_fixed(&buf[..written], temps.len()).unwrap();
+///     assert!(out.iter().zip(temps.iter()).all(|(a, b)| (a - b).abs() <= 0.005 + 1e-9));
+/// ```
+pub fn pack_f64_fixed(inputs: &[f64], scale: f64, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let fixed = to_fixed_point(inputs, scale)?;
+    let off = direct_write_uint_le(out_buffer, 0, scale.to_bits(), 8)?;
+    pack_zigzag_deltas(fixed.into_iter().map(|n| n as u64), out_buffer, off)
+}
 
-            // This is least significant portion of input
-            out_word |= shifted_input << bit_cursor;
+/// Converts f to fixed point: multiplies it by scale and rounds to the nearest i64.  This is the conversion used
+/// by [`pack_f64_fixed`]: #method.pack_f64_fixed and [`pack_f64_monotonic`]: #method.pack_f64_monotonic.
+/// A plain `as i64` cast saturates out of range values and turns NaN into 0, silently corrupting the column, so
+/// instead this returns ValueOutOfRange(f) if f times scale is NaN, infinite, or outside the range of an i64.
+///
+/// ```
+/// # use compressed_vec::{error::CodingError, nibblepacking::f64_to_fixed};
+///     assert_eq!(f64_to_fixed(21.375, 100.0), Ok(2138));
+///     assert_eq!(f64_to_fixed(-0.004, 100.0), Ok(0));
+///     assert_eq!(f64_to_fixed(1e17, 1000.0), Err(CodingError::ValueOutOfRange(1e17)));
+///     assert!(f64_to_fixed(f64::NAN, 100.0).is_err());
+/// ```
+#[inline]
+pub fn f64_to_fixed(f: f64, scale: f64) -> Result<i64, CodingError> {
+    let scaled = (f * scale).round();
+    // i64::MAX as f64 rounds up to 2^63, which is out of range.  NaN fails both comparisons.
+    if scaled >= i64::MIN as f64 && scaled < i64::MAX as f64 {
+        Ok(scaled as i64)
+    } else {
+        Err(CodingError::ValueOutOfRange(f))
+    }
+}
 
-            // Write out current word if we've used up all 64 bits
-            if remaining <= num_bits {
-                off = direct_write_uint_le(out_buffer, off, out_word, 8)?;
+// Converts each value with f64_to_fixed, after checking that scale is valid
+fn to_fixed_point(inputs: &[f64], scale: f64) -> Result<Vec<i64>, CodingError> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(CodingError::InvalidFormat(format!("Scale {} must be positive and finite", scale)));
+    }
+    inputs.iter().map(|&f| f64_to_fixed(f, scale)).collect()
+}
 
-                if remaining < num_bits {
-                    // Most significant portion left over from previous word
-                    out_word = shifted_input >> (remaining as i32);
-                } else {
-                    out_word = 0; // reset for 64-bit input case
-                }
-            }
+/// Experimental: packs monotonically increasing f64 values, such as cumulative energy or byte counters, as fixed
+/// point integers in the same way as [`pack_f64_fixed`]: #method.pack_f64_fixed, but delta encoded as in
+/// [`pack_u64_delta`]: #method.pack_u64_delta since the deltas are never negative, saving the ZigZag sign bit.
+/// The scale and then the first fixed point value are written first, as 8 raw bytes each, followed by the
+/// packed deltas of the values after it.  Decode using [`unpack_f64_monotonic`]: #method.unpack_f64_monotonic.
+///
+/// Precision is the same as pack_f64_fixed: every decoded value is within 0.5 / scale of the original.
+/// XOR encoding gains little on a counter, as every increment changes many mantissa bits, so prefer this when the
+/// values only go up and a fixed resolution is acceptable; use [`pack_f64_xor`]: #method.pack_f64_xor when every
+/// bit must be kept.
+///
+/// Returns InvalidFormat for an invalid scale or ValueOutOfRange for an out of range value as in pack_f64_fixed,
+/// or InvalidFormat if a value rounds to less than the one before it.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let kwh = [1520.25f64, 1520.75, 1521.5, 1521.5, 1523.0];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_f64_monotonic(&kwh, 4.0, &mut buf).unwrap();
+///     assert_eq!(nibblepacking::unpack_f64_monotonic(&buf[..written], kwh.len()).unwrap(), kwh);
+/// ```
+pub fn pack_f64_monotonic(inputs: &[f64], scale: f64, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let fixed = to_fixed_point(inputs, scale)?;
+    if let Some(i) = fixed.windows(2).position(|pair| pair[1] < pair[0]) {
+        return Err(CodingError::InvalidFormat(format!("Value {} at {} is less than the one before it",
+                                                      inputs[i + 1], i + 1)));
+    }
+    let first = fixed.first().cloned().unwrap_or(0);
+    let off = direct_write_uint_le(out_buffer, 0, scale.to_bits(), 8)?;
+    let off = direct_write_uint_le(out_buffer, off, first as u64, 8)?;
+    // Each value is at least the one before, so the difference as a u64 is exact even across the whole i64 range
+    let deltas = fixed.windows(2).map(|pair| (pair[1] as u64).wrapping_sub(pair[0] as u64));
+    pack_u64(deltas, out_buffer, off)
+}
 
-            bit_cursor = (bit_cursor + num_bits) % 64;
-        }
-    };
+/// Packs each block of 8 values for a [`BlockPacker`]: struct.BlockPacker.html.  The default,
+/// [`NibblePack8`]: struct.NibblePack8.html, writes the plain NibblePack layout of pack_u64; other layouts such as
+/// big endian blocks or collapsed zero runs plug in their own, so that they all share the same buffering.
+pub trait BlockEncoder<B: ByteSink> {
+    /// Packs one block of 8 values onto out.  The last block of a stream is padded with zeroes.
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut B) -> Result<(), CodingError>;
 
-    // Write remainder word if there are any bits remaining, and only advance buffer right # of bytes
-    if bit_cursor > 0 {
-        off = direct_write_uint_le(out_buffer, off, out_word, ((bit_cursor + 7) / 8) as usize)?;
+    /// Called once after the last block, for layouts which hold output back between blocks
+    fn finish(&mut self, _out: &mut B) -> Result<(), CodingError> {
+        Ok(())
     }
-    Ok(off)
 }
 
+/// The BlockEncoder for the plain NibblePack layout, which packs every block with
+/// [`nibble_pack8_to`]: #method.nibble_pack8_to
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NibblePack8;
 
-const ZERO_U64OCTET: u64x8 = u64x8::splat(0);
+impl<B: ByteSink> BlockEncoder<B> for NibblePack8 {
+    #[inline]
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+        nibble_pack8_to(block, out)
+    }
+}
 
-/// A Sink which accumulates delta-encoded NibblePacked data back into increasing u64 numbers
-#[derive(Debug)]
-pub struct DeltaSink {
-    acc: u64,
-    sink: VecSink<u64>,
+/// Buffers values into blocks of 8, packing each block onto a ByteSink as soon as it fills up.  This is the
+/// incremental core shared by the packers which take values a few at a time, such as
+/// [`DeltaPacker`]: struct.DeltaPacker.html and [`PackedAppender`]: struct.PackedAppender.html, which transform
+/// or pre-load the values before they get here.  Call finish() at the end to pack any final partial block.
+/// Blocks are NibblePacked unless another [`BlockEncoder`]: trait.BlockEncoder.html is given to with_encoder().
+///
+/// ```
+/// # use compressed_vec::nibblepacking::BlockPacker;
+///     let mut packer = BlockPacker::new(Vec::new());
+///     packer.extend(&[3u64, 1, 4, 1, 5]).unwrap();
+///     let packed_bytes: Vec<u8> = packer.finish().unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct BlockPacker<B: ByteSink, E: BlockEncoder<B> = NibblePack8> {
+    out: B,
+    encoder: E,
+    in_buffer: [u64; 8],
+    bufindex: usize,
 }
 
-impl DeltaSink {
-    pub fn with_sink(inner_sink: VecSink<u64>) -> DeltaSink {
-        DeltaSink { acc: 0, sink: inner_sink }
+impl<B: ByteSink> BlockPacker<B> {
+    pub fn new(out: B) -> Self {
+        Self::with_encoder(out, NibblePack8)
     }
 
-    pub fn new() -> DeltaSink {
-        DeltaSink::with_sink(VecSink::<u64>::new())
+    /// Packs the pending partial block, padded with zeroes, onto the output but keeps it pending, so later values
+    /// still go into the same block.  Used for snapshots; the caller must drop the bytes written before pushing.
+    fn pack_pending(&mut self) -> Result<(), CodingError> {
+        if self.bufindex > 0 {
+            let mut block = [0u64; 8];
+            block[..self.bufindex].copy_from_slice(self.pending());
+            nibble_pack8_to(&block, &mut self.out)?;
+        }
+        Ok(())
     }
+}
 
-    pub fn output_vec(&self) -> &Vec<u64> {
-        &self.sink.vec
+impl<B: ByteSink, E: BlockEncoder<B>> BlockPacker<B, E> {
+    /// Creates a BlockPacker which packs each block with encoder
+    pub fn with_encoder(out: B, encoder: E) -> Self {
+        Self { out, encoder, in_buffer: [0u64; 8], bufindex: 0 }
     }
-}
 
-impl Sink<u64x8> for DeltaSink {
+    /// Adds one value
     #[inline]
-    fn process(&mut self, data: u64x8) {
-        let mut buf = u64x8::splat(0);
-        let mut acc = self.acc;
-        for i in 0..8 {
-            acc += data.extract(i);
-            buf = buf.replace(i, acc);
+    pub fn push(&mut self, value: u64) -> Result<(), CodingError> {
+        self.in_buffer[self.bufindex] = value;
+        self.bufindex += 1;
+        if self.bufindex >= 8 {
+            self.encoder.encode_block(&self.in_buffer, &mut self.out)?;
+            self.bufindex = 0;
         }
-        self.acc = acc;
-        self.sink.process(buf);
+        Ok(())
     }
 
-    fn process_zeroes(&mut self) {
-        todo!();
+    /// Adds a chunk of values
+    pub fn extend(&mut self, values: &[u64]) -> Result<(), CodingError> {
+        values.iter().try_for_each(|&v| self.push(v))
     }
 
-    fn reset(&mut self) {
-        self.acc = 0;
-        self.sink.reset()
+    /// The values waiting for their block of 8 to fill up
+    pub fn pending(&self) -> &[u64] {
+        &self.in_buffer[..self.bufindex]
+    }
+
+    /// Packs any remaining partial block, padded with zeroes, and returns the output ByteSink
+    pub fn finish(mut self) -> Result<B, CodingError> {
+        if self.bufindex > 0 {
+            for n in self.in_buffer[self.bufindex..].iter_mut() {
+                *n = 0;
+            }
+            self.encoder.encode_block(&self.in_buffer, &mut self.out)?;
+        }
+        self.encoder.finish(&mut self.out)?;
+        Ok(self.out)
     }
 }
 
-/// A sink which uses simple successive XOR encoding to decode a NibblePacked floating point stream
-/// encoded using [`pack_f64_xor`]: #method.pack_f64_xor
+/// Incremental version of [`pack_u64_delta`]: #method.pack_u64_delta, for packing an increasing stream which
+/// arrives in chunks of any size.  The delta base and the current partial block of 8 are carried across calls to
+/// push(), so the output is identical to packing the whole sequence at once.  Call finish() at the end to pack
+/// any final partial block.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::DeltaPacker;
+///     let mut packer = DeltaPacker::new(Vec::new());
+///     packer.extend(&[100u64, 105, 110]).unwrap();
+///     packer.extend(&[111u64, 120]).unwrap();
+///     let packed_bytes: Vec<u8> = packer.finish().unwrap();
+/// ```
 #[derive(Debug)]
-pub struct DoubleXorSink {
+pub struct DeltaPacker<B: ByteSink> {
+    packer: BlockPacker<B>,
     last: u64,
-    vec: Vec<f64>,
 }
 
-impl DoubleXorSink {
-    /// Creates a new DoubleXorSink with a vec which is owned by this struct.
-    pub fn new(the_vec: Vec<f64>) -> DoubleXorSink {
-        DoubleXorSink { last: 0, vec: the_vec }
+impl<B: ByteSink> DeltaPacker<B> {
+    pub fn new(out: B) -> Self {
+        Self { packer: BlockPacker::new(out), last: 0 }
     }
 
-    fn reset(&mut self, init_value: u64) {
-        self.vec.clear();
-        self.vec.push(f64::from_bits(init_value));
-        self.last = init_value;
+    /// Adds one value.  As with pack_u64_delta, a value less than the previous one is recorded as a delta of 0.
+    #[inline]
+    pub fn push(&mut self, value: u64) -> Result<(), CodingError> {
+        let delta = value.saturating_sub(self.last);
+        self.last = value;
+        self.packer.push(delta)
+    }
+
+    /// Adds a chunk of values
+    pub fn extend(&mut self, values: &[u64]) -> Result<(), CodingError> {
+        values.iter().try_for_each(|&v| self.push(v))
+    }
+
+    /// Packs any remaining partial block, padded with zeroes, and returns the output ByteSink
+    pub fn finish(self) -> Result<B, CodingError> {
+        self.packer.finish()
     }
 }
 
-impl Sink<u64x8> for DoubleXorSink {
-    #[inline]
-    fn process(&mut self, data: u64x8) {
-        let mut buf = [0f64; 8];
-        let mut last = self.last;
-        for i in 0..8 {
-        // XOR new piece of data with last, which yields original value
-            let numbits = last ^ data.extract(i);
-            buf[i] = f64::from_bits(numbits);
-            last = numbits
+/// Appends more values to a buffer already packed with [`pack_u64`]: #method.pack_u64, without re-packing it.
+/// The last block of the buffer may be partly padding, so it is decoded back into the pending block of 8 and
+/// its bytes are dropped; packing then continues where the original values left off.  Call finish() to pack
+/// the final partial block and get the extended buffer back.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let packed = nibblepacking::pack_u64_to_vec([1u64, 2, 3].iter().cloned());
+///     let mut appender = nibblepacking::PackedAppender::try_new(packed, 3).unwrap();
+///     appender.extend(&[4u64, 5]).unwrap();
+///     assert_eq!(appender.num_values(), 5);
+///     let packed: Vec<u8> = appender.finish().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PackedAppender {
+    packer: BlockPacker<Vec<u8>>,
+    num_values: usize,
+}
+
+impl PackedAppender {
+    /// Re-opens encoded, which holds num_values values, for appending.  Returns InputTooShort if encoded has
+    /// fewer than num_values values, or the error from decoding the last block if that is corrupt.
+    /// Any bytes after the last block are dropped.
+    pub fn try_new(mut encoded: Vec<u8>, num_values: usize) -> Result<Self, CodingError> {
+        let full_len = walk_blocks(&encoded[..], num_values / 8, |_, _| {})?;
+        let mut last_block = [0u64; 8];
+        if num_values % 8 > 0 {
+            let mut sink = OctetSink { values: ZERO_U64OCTET };
+            nibble_unpack8(&encoded[full_len..], &mut sink).map_err(truncated_input)?;
+            sink.values.write_to_slice_unaligned(&mut last_block);
         }
-        self.last = last;
+        encoded.truncate(full_len);
+        let mut packer = BlockPacker::new(encoded);
+        packer.extend(&last_block[..num_values % 8])?;
+        Ok(Self { packer, num_values })
+    }
 
-        self.vec.extend(&buf);
+    /// Adds one value
+    #[inline]
+    pub fn push(&mut self, value: u64) -> Result<(), CodingError> {
+        self.num_values += 1;
+        self.packer.push(value)
     }
 
-    fn process_zeroes(&mut self) {
-        todo!();
+    /// Adds a chunk of values
+    pub fn extend(&mut self, values: &[u64]) -> Result<(), CodingError> {
+        values.iter().try_for_each(|&v| self.push(v))
     }
 
-    fn reset(&mut self) {
-        self.vec.clear();
+    /// The total number of values, including the ones originally packed
+    pub fn num_values(&self) -> usize {
+        self.num_values
+    }
+
+    /// Packs any remaining partial block, padded with zeroes, and returns the extended buffer
+    pub fn finish(self) -> Result<Vec<u8>, CodingError> {
+        self.packer.finish()
     }
 }
 
-/// A sink that converts u32x8 output from SIMD 32-bit unpacker to 64-bit
-// TODO: figure out right place for this?
-#[derive(Debug)]
-struct U32ToU64Sink<'a, S: Sink<u64x8>> {
-    u64sink: &'a mut S
+/// Packs values appended one at a time, such as an ingestion write buffer, while allowing the values packed so
+/// far to be read at any point.  Full blocks are packed once as they fill up, so taking a snapshot costs at most
+/// packing one partial block rather than re-packing everything.
+///
+/// [`snapshot`]: #method.snapshot packs the pending partial block, padded with zeroes, onto the end of the output
+/// and returns the whole buffer, which decodes with [`unpack`]: #method.unpack like any buffer from
+/// [`pack_u64`]: #method.pack_u64.  The next push() drops those bytes again before carrying on, and since the
+/// snapshot borrows the writer it cannot be held across a push; copy it with to_vec() to keep it.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::{self, IncrementalWriter};
+///     let mut writer = IncrementalWriter::new();
+///     writer.extend(&[10u64, 20, 30]).unwrap();
+///     let snapshot = writer.snapshot().unwrap().to_vec();
+///     writer.push(40).unwrap();
+///     assert_eq!(nibblepacking::unpack_to_vec(&snapshot, 3).unwrap(), vec![10, 20, 30]);
+///     assert_eq!(nibblepacking::unpack_to_vec(writer.snapshot().unwrap(), 4).unwrap(), vec![10, 20, 30, 40]);
+/// ```
+#[derive(Debug, Default)]
+pub struct IncrementalWriter {
+    packer: BlockPacker<Vec<u8>>,
+    full_len: usize,        // Length of the output holding only full blocks; anything after is a snapshot's block
+    num_values: usize,
 }
 
-impl<'a, S: Sink<u64x8>> U32ToU64Sink<'a, S> {
-    #[inline]
-    pub fn new(u64sink: &'a mut S) -> Self {
-        Self { u64sink }
+impl IncrementalWriter {
+    pub fn new() -> Self {
+        Self::default()
     }
-}
 
-impl<'a, S: Sink<u64x8>> Sink<u32x8> for U32ToU64Sink<'a, S> {
+    /// Adds one value
     #[inline]
-    fn process(&mut self, data: u32x8) {
-        self.u64sink.process(u64x8::from_cast(data));
+    pub fn push(&mut self, value: u64) -> Result<(), CodingError> {
+        self.packer.out.truncate(self.full_len);
+        self.num_values += 1;
+        self.packer.push(value)?;
+        self.full_len = self.packer.out.len();
+        Ok(())
     }
 
-    #[inline]
-    fn process_zeroes(&mut self) {
-        self.u64sink.process_zeroes();
+    /// Adds a chunk of values
+    pub fn extend(&mut self, values: &[u64]) -> Result<(), CodingError> {
+        values.iter().try_for_each(|&v| self.push(v))
     }
 
-    fn reset(&mut self) {}
+    /// The number of values pushed so far
+    pub fn current_len(&self) -> usize {
+        self.num_values
+    }
+
+    /// Returns the bytes of every value pushed so far, including the partial last block padded with zeroes
+    pub fn snapshot(&mut self) -> Result<&[u8], CodingError> {
+        self.packer.out.truncate(self.full_len);
+        self.packer.pack_pending()?;
+        Ok(&self.packer.out[..])
+    }
+
+    /// Packs any remaining partial block and returns the packed buffer
+    pub fn finish(mut self) -> Result<Vec<u8>, CodingError> {
+        self.packer.out.truncate(self.full_len);
+        self.packer.finish()
+    }
 }
 
-/// Unpacks num_values values from an encoded buffer, by calling nibble_unpack8 enough times.
-/// The output.process() method is called numValues times rounded up to the next multiple of 8.
-/// Returns "remainder" byteslice or unpacking error (say if one ran out of space)
+/// Packs a stream of double-precision IEEE-754 / f64 numbers using XOR encoding.
+/// The first f64 is written as is; after that, each successive f64 is XORed with the previous one and the xor
+/// value is written, based on the premise that when changes are small so is the XORed value.
+/// Only the bits of each value are used, never float arithmetic, so every value including NaN payloads,
+/// infinities and -0.0 is restored bit for bit.
+/// Stream must have at least one value, otherwise InputTooShort is returned.  A single value is written as just
+/// the 8 byte initial value, with no blocks after it.
+pub fn pack_f64_xor<I: Iterator<Item = f64>>(mut stream: I,
+                                             out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let mut last: u64 = match stream.next() {
+        Some(num) => {
+            let num_bits = num.to_bits();
+            direct_write_uint_le(out_buffer, 0, num_bits, 8)?;
+            num_bits
+        },
+        None      => return Err(CodingError::InputTooShort)
+    };
+    pack_u64(stream.map(|f| {
+        let f_bits = f.to_bits();
+        let delta = last ^ f_bits;
+        last = f_bits;
+        delta
+    }), out_buffer, 8)
+}
+
+/// Same as [`pack_f64_xor`]: #method.pack_f64_xor, but a block of XORs which fits in the nibble window (number of
+/// nibbles and trailing zero nibbles) of the last block to write one is packed using that window and without its
+/// own nibbles byte.  A "same window" flag bit is kept for every block, so on smooth series, where successive
+/// XORs have much the same width, this saves most of the nibbles bytes at the cost of one bit per 8 values.
+/// A block only reuses a window if that is no larger than packing it with its own window.
 ///
-/// # Arguments
-/// * `inbuf` - NibblePacked compressed byte slice containing "remaining" bytes, starting with bitmask byte
-/// * `output` - a Trait which processes each resulting u64
-/// * `num_values` - the number of u64 values to decode
-#[inline]
-pub fn unpack<'a, Output>(
-    encoded: &'a [u8],
-    output: &mut Output,
-    num_values: usize,
-) -> Result<&'a [u8], CodingError>
-where Output: Sink<u64x8> {
-    let mut values_left = num_values as isize;
-    let mut inbuf = encoded;
-    while values_left > 0 {
-        inbuf = nibble_unpack8(inbuf, output)?;
-        values_left -= 8;
+/// The output is the 8 byte initial value, then the flags for all the blocks, 1 bit each, least significant bit
+/// first and rounded up to a whole byte, then the blocks.
+/// Decode using [`unpack_f64_xor_windowed`]: #method.unpack_f64_xor_windowed.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::nibblepacking::DoubleXorSink;
+///     let inputs: Vec<f64> = (0..64).map(|i| 20.0 + (i % 4) as f64 * 0.5).collect();
+///     let mut buf = [0u8; 1024];
+///     let written = nibblepacking::pack_f64_xor_windowed(inputs.iter().cloned(), &mut buf).unwrap();
+///     let mut sink = DoubleXorSink::with_capacity(inputs.len());
+///     nibblepacking::unpack_f64_xor_windowed(&buf[..written], &mut sink, inputs.len()).unwrap();
+///     assert_eq!(sink.output_vec()[..], inputs[..]);
+/// ```
+pub fn pack_f64_xor_windowed<I: Iterator<Item = f64>>(mut stream: I,
+                                                      out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let mut last: u64 = match stream.next() {
+        Some(num) => num.to_bits(),
+        None      => return Err(CodingError::InputTooShort)
+    };
+    let initial = last;
+    let mut flags = Vec::new();
+    let mut packer = BlockPacker::with_encoder(Vec::new(),
+                                               SharedWindows { flags: &mut flags, num_blocks: 0, window: None });
+    for f in stream {
+        let f_bits = f.to_bits();
+        packer.push(last ^ f_bits)?;
+        last = f_bits;
     }
-    Ok(inbuf)
+    let blocks = packer.finish()?;
+
+    let off = direct_write_uint_le(out_buffer, 0, initial, 8)?;
+    let mut cursor = SliceCursor::new(out_buffer, off);
+    cursor.write_bytes(&flags[..])?;
+    cursor.write_bytes(&blocks[..])?;
+    Ok(cursor.pos)
 }
 
-/// Unpacks a buffer encoded with [`pack_f64_xor`]: #method.pack_f64_xor
+/// Lossy version of [`pack_f64_xor`]: #method.pack_f64_xor for when full precision is not needed, such as for
+/// dashboards.  Low order mantissa bits of each value are zeroed out, so successive XORs have fewer bits, then
+/// the values are XOR packed as usual; decode using [`unpack_f64_xor`]: #method.unpack_f64_xor.
 ///
-/// This wraps unpack() method with a read of the initial f64 value. InputTooShort error is returned
-/// if the input does not have enough bytes given the number of values read.
-/// NOTE: the sink is automatically cleared at the beginning.
+/// The number of bits dropped from each value depends on its magnitude: a value with exponent e can lose
+/// mantissa bits worth less than 2^m, where 2^m is the largest power of two not above max_abs_error.
+/// Since the bits are truncated, every decoded value is strictly within max_abs_error of the original, and has
+/// the same sign.  Zero, subnormal, infinite and NaN values are kept exactly.
+///
+/// Returns InvalidFormat if max_abs_error is not a positive, finite, normal number.
+pub fn pack_f64_lossy(inputs: &[f64], max_abs_error: f64, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    if !(max_abs_error.is_normal() && max_abs_error > 0.0) {
+        return Err(CodingError::InvalidFormat(format!("Max error {} must be positive and finite", max_abs_error)));
+    }
+    let error_exp = ((max_abs_error.to_bits() >> 52) & 0x7ff) as i64;
+    pack_f64_xor(inputs.iter().map(|&f| {
+        let bits = f.to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        if exp == 0 || exp == 0x7ff {
+            f
+        } else {
+            // One mantissa bit is worth 2^(exp - 1023 - 52); dropping k bits loses less than 2^(exp - 1023 - 52 + k)
+            let drop_bits = (error_exp - exp + 52).max(0).min(52) as u32;
+            f64::from_bits(bits & !nibble_mask(drop_bits))
+        }
+    }), out_buffer)
+}
+
+/// Packs a slice of (u64, f64) pairs, such as (timestamp, value) records, into two aligned buffers.
+/// The u64 column is delta encoded using [`pack_u64_delta`]: #method.pack_u64_delta and the f64 column is
+/// XOR encoded using [`pack_f64_xor`]: #method.pack_f64_xor.  Both columns always hold the same number of values.
+/// Returns the number of bytes written to (u64_buffer, f64_buffer).
+/// Input must have at least one pair, otherwise InputTooShort is returned.
 ///
 /// ```
 /// # use compressed_vec::nibblepacking;
-/// # let encoded = [0xffu8; 16];
-///     let mut out = Vec::<f64>::with_capacity(64);
-///     let mut sink = nibblepacking::DoubleXorSink::new(out);
-///     let res = nibblepacking::unpack_f64_xor(&encoded[..], &mut sink, 16);
+///     let pairs = [(1000u64, 10.5f64), (2000, 10.75), (3000, 11.0)];
+///     let mut ts_buf = [0u8; 256];
+///     let mut val_buf = [0u8; 256];
+///     let (ts_len, val_len) = nibblepacking::pack_u64_f64_pairs(&pairs, &mut ts_buf, &mut val_buf).unwrap();
+///     let out = nibblepacking::unpack_u64_f64_pairs(&ts_buf[..ts_len], &val_buf[..val_len], pairs.len()).unwrap();
+///     assert_eq!(out, pairs);
 /// ```
-pub fn unpack_f64_xor<'a>(encoded: &'a [u8],
-                          sink: &mut DoubleXorSink,
-                          num_values: usize) -> Result<&'a [u8], CodingError> {
-    assert!(num_values >= 1);
-    let init_value = direct_read_uint_le(encoded, 0)?;
-    sink.reset(init_value);
+pub fn pack_u64_f64_pairs(pairs: &[(u64, f64)],
+                          u64_buffer: &mut [u8],
+                          f64_buffer: &mut [u8]) -> Result<(usize, usize), CodingError> {
+    if pairs.is_empty() { return Err(CodingError::InputTooShort) }
+    let u64_len = pack_u64(clipped_deltas(pairs.iter().map(|&(n, _)| n)), u64_buffer, 0)?;
+    let f64_len = pack_f64_xor(pairs.iter().map(|&(_, f)| f), f64_buffer)?;
+    Ok((u64_len, f64_len))
+}
 
-    unpack(&encoded[8..], sink, num_values - 1)
+
+/// Packs a slice of IPv4 addresses by converting each to its u32 value and NibblePacking it.
+/// Addresses in the same subnet share their upper bits, so clustered address ranges pack well.
+pub fn pack_ipv4(addrs: &[Ipv4Addr], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    pack_u64(addrs.iter().map(|&addr| u32::from(addr) as u64), out_buffer, 0)
 }
 
-/// Unpacks 8 u64's packed using nibble_pack8 by calling the output.process() method 8 times, once for each encoded
-/// value.  Always calls 8 times regardless of what is in the input, unless the input is too short.
-/// Returns "remainder" byteslice or unpacking error (say if one ran out of space).
-/// Uses the SIMD U32 unpack func if possible to speed things up
+/// Packs a slice of IPv6 addresses as their u128 values using [`pack_u128`]: #method.pack_u128
+pub fn pack_ipv6(addrs: &[Ipv6Addr], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    pack_u128(addrs.iter().map(|&addr| u128::from(addr)), out_buffer)
+}
+
+/// Packs a stream of u128 values, such as IDs and hashes, by splitting each into a high and a low u64 half.
+/// The layout is the high halves of all the values NibblePacked as with [`pack_u64`]: #method.pack_u64,
+/// immediately followed by the low halves packed the same way.  No length is needed in between, since the
+/// decoder knows the number of values and so where the high halves end.
+/// Values below 2^64 have a zero high half, so if most values are small the high halves take only one byte per
+/// block of 8.  Decode using [`unpack_u128`]: #method.unpack_u128.
+pub fn pack_u128<I: Iterator<Item = u128>>(stream: I, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let mut lows = Vec::with_capacity(stream.size_hint().0);
+    let off = pack_u64(stream.map(|n| {
+        lows.push(n as u64);
+        (n >> 64) as u64
+    }), out_buffer, 0)?;
+    pack_u64_slice(&lows[..], out_buffer, off)
+}
+
+/// Packs a mostly null column of optional u64's as null run lengths plus the dense stream of present values.
+/// Layout: the number of present values as 4 little endian bytes, then for each present value the number of
+/// nulls before it, packed with [`pack_u64`]: #method.pack_u64, then the present values, also with pack_u64.
+/// Trailing nulls are not stored; [`unpack_sparse_u64`]: #method.unpack_sparse_u64 fills them in from the total
+/// number of values.
 ///
-/// # Arguments
-/// * `inbuf` - NibblePacked compressed byte slice containing "remaining" bytes, starting with bitmask byte
-/// * `output` - a Trait which processes each resulting u64
-// NOTE: The 'a is a lifetime annotation.  When you use two references in Rust, and return one, Rust needs
-//       annotations to help it determine to which input the output lifetime is related to, so Rust knows
-//       that the output of the slice will live as long as the reference to the input slice is valid.
+/// ## Compared to a null bitmap
+/// A bitmap always costs 1 bit per value, present or not.  Here each present value costs a run length instead,
+/// typically 1-2 nibbles plus a share of the block headers, and nulls cost nothing.  This wins when roughly
+/// fewer than 1 in 10 values are present, as in wide schemas where most columns of a row are empty, and loses
+/// for denser columns, where most run lengths are zero but still cost a few bits each.
+pub fn pack_sparse_u64(inputs: &[Option<u64>], out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let num_present = inputs.iter().filter(|v| v.is_some()).count();
+    if num_present > u32::max_value() as usize {
+        return Err(CodingError::InvalidFormat(format!("{} present values does not fit in a u32", num_present)));
+    }
+    let off = direct_write_uint_le(out_buffer, 0, num_present as u64, 4)?;
+
+    let mut null_run = 0u64;
+    let null_runs = inputs.iter().filter_map(|v| match v {
+        Some(_) => { let run = null_run; null_run = 0; Some(run) },
+        None    => { null_run += 1; None },
+    });
+    let off = pack_u64(null_runs, out_buffer, off)?;
+    pack_u64(inputs.iter().filter_map(|&v| v), out_buffer, off)
+}
+
+
+///
+/// Packs a stream of plain u64 numbers using NibblePacking.
+///
+/// This is especially powerful when combined with
+/// other packers which can do for example delta or floating point XOR or other kinds of encoding which reduces
+/// the # of bits needed and produces many zeroes.  This is why an Iterator is used for the API, as sources will
+/// typically transform the incoming data by reducing the bits needed.
+/// This method does no transformations to the input data.  You might want one of the other pack_* methods.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+///     let mut buf = [0u8; 1024];
+///     nibblepacking::pack_u64(inputs.into_iter().cloned(), &mut buf, 0);
+/// ```
+/// NOTE: The NibblePack algorithm always packs 8 u64's at a time.  If the length of the input stream is not
+/// divisible by 8, extra 0 values pad the input.  So an empty stream writes nothing, and a single value is
+/// written as a whole block of 8, of which 7 are padding.
+// TODO: should this really be a function, or maybe a struct with more methods?
+// NOTE: see pack_u64_slice() for a version reading straight from a slice of u64's
 #[inline]
-pub fn nibble_unpack8<'a, Output: Sink<u64x8>>(
-    inbuf: &'a [u8],
-    output: &mut Output,
-) -> Result<&'a [u8], CodingError> {
-    if inbuf.is_empty() { return Err(CodingError::NotEnoughSpace) }
-    let nonzero_mask = inbuf[0];
-    if nonzero_mask == 0 {
-        // All 8 words are 0; skip further processing
-        output.process(ZERO_U64OCTET);
-        Ok(&inbuf[1..])
-    } else {
-        if inbuf.len() < 2 { return Err(CodingError::NotEnoughSpace) }
-        let num_bits = ((inbuf[1] >> 4) + 1) * 4;
-        let trailing_zeros = (inbuf[1] & 0x0f) * 4;
+pub fn pack_u64<I: Iterator<Item = u64>>(stream: I,
+                                         out_buffer: &mut [u8],
+                                         offset: usize) -> Result<usize, CodingError> {
+    let cursor = pack_u64_to(stream, SliceCursor::new(out_buffer, offset))?;
+    Ok(cursor.pos)
+}
 
-        // Use SIMD u32 unpacker if total resulting bits is <= 32
-        // Improves filtering throughput about 2x
-        if (num_bits + trailing_zeros) <= 32 {
-            let mut wrapper_sink = U32ToU64Sink::new(output);
-            return unpack8_u32_simd(inbuf, &mut wrapper_sink);
-        }
+/// Same as [`pack_u64`]: #method.pack_u64, but writes the packed bytes to any ByteSink, such as a `Vec<u8>` or a
+/// `WriteSink` wrapping a std::io::Write, and returns the ByteSink once the stream is packed.
+#[inline]
+pub fn pack_u64_to<I: Iterator<Item = u64>, B: ByteSink>(stream: I, out: B) -> Result<B, CodingError> {
+    let mut packer = BlockPacker::new(out);
+    for num in stream {
+        packer.push(num)?;
+    }
+    packer.finish()
+}
 
-        let total_bytes = 2 + (num_bits as u32 * nonzero_mask.count_ones() + 7) / 8;
-        let mask: u64 = if num_bits >= 64 { std::u64::MAX } else { (1u64 << num_bits) - 1u64 };
-        let mut bit_cursor = 0;
-        let mut out_array = [0u64; 8];
+/// Same as [`pack_u64`]: #method.pack_u64, but lays out the packed bits of each block big endian, for interop
+/// with readers which expect big endian data natively.  The block headers and so the block lengths are the same
+/// as pack_u64, but each value's nibbles are written most significant first, and values follow each other from
+/// the most significant bit of each byte down.  So when every value of a block takes a whole number of bytes,
+/// each value's bytes appear in plain big endian order.
+///
+/// NOTE: big and little endian buffers are NOT interchangeable.  Decode only using
+/// [`unpack_u64_be`]: #method.unpack_u64_be; the other unpack functions would silently give wrong values, as
+/// nothing in the buffer records which layout it uses.  Header-only helpers such as walk_blocks() and
+/// validate() work on both.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let mut buf = [0u8; 64];
+///     let written = nibblepacking::pack_u64_be([0x1122u64, 0x3344].iter().cloned(), &mut buf, 0).unwrap();
+///     assert_eq!(&buf[..written], &[0b11, 0x30, 0x11, 0x22, 0x33, 0x44]);
+/// ```
+pub fn pack_u64_be<I: Iterator<Item = u64>>(stream: I,
+                                            out_buffer: &mut [u8],
+                                            offset: usize) -> Result<usize, CodingError> {
+    let mut packer = BlockPacker::with_encoder(SliceCursor::new(out_buffer, offset), NibblePack8Be);
+    for num in stream {
+        packer.push(num)?;
+    }
+    Ok(packer.finish()?.pos)
+}
 
-        // Read in first word
-        let mut in_word = direct_read_uint_le(inbuf, 2)?;
-        let mut pos = 10;
+// The BlockEncoder for pack_u64_be
+#[derive(Debug)]
+struct NibblePack8Be;
 
-        for bit in 0..8 {
-            if (nonzero_mask & (1 << bit)) != 0 {
-                let remaining = 64 - bit_cursor;
+impl<B: ByteSink> BlockEncoder<B> for NibblePack8Be {
+    #[inline]
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+        nibble_pack8_be(block, out)
+    }
+}
 
-                // Shift and read in LSB (or entire nibbles if they fit)
-                let shifted_in = in_word >> bit_cursor;
-                let mut out_word = shifted_in & mask;
+// Writes the numbytes least significant bytes of value in big endian order
+#[inline]
+fn write_uint_be<B: ByteSink>(out: &mut B, value: u64, numbytes: usize) -> Result<(), CodingError> {
+    out.write_bytes(&value.to_be_bytes()[8 - numbytes..])
+}
 
-                // If remaining bits are in next word, read next word -- if there's space
-                // We don't want to read the next word though if we're already at the end
-                if remaining <= num_bits && pos < (total_bytes as usize) {
-                    // Read in MSB bits from next word
-                    in_word = direct_read_uint_le(inbuf, pos)?;
-                    pos += 8;
-                    if remaining < num_bits {
-                        let shifted = in_word << remaining;
-                        out_word |= shifted & mask;
-                    }
-                }
+// Packs a block for pack_u64_be: the same headers as nibble_pack8, then the values as a big endian bit stream
+fn nibble_pack8_be<B: ByteSink>(inputs: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+    let nonzero_mask = compute_nonzero_mask(inputs);
+    out.push_byte(nonzero_mask)?;
+    if nonzero_mask == 0 { return Ok(()) }
 
-                out_array[bit] = out_word << trailing_zeros;
+    let (min_leading_zeros, min_trailing_zeros) = min_leading_trailing_zeros(inputs);
+    let trailing_nibbles = min_trailing_zeros / 4;
+    let num_nibbles = 16 - (min_leading_zeros / 4) - trailing_nibbles;
+    out.push_byte((((num_nibbles - 1) << 4) | trailing_nibbles) as u8)?;
 
-                // Update other indices
-                bit_cursor = (bit_cursor + num_bits) % 64;
+    // Bits not written out yet are the low acc_bits bits of acc, which never holds more than 64 + 64 bits
+    let num_bits = num_nibbles * 4;
+    let mut acc = 0u128;
+    let mut acc_bits = 0;
+    for &n in inputs.iter().filter(|&&n| n != 0) {
+        acc = (acc << num_bits) | (n >> (trailing_nibbles * 4)) as u128;
+        acc_bits += num_bits;
+        if acc_bits >= 64 {
+            acc_bits -= 64;
+            write_uint_be(out, (acc >> acc_bits) as u64, 8)?;
+            acc &= (1u128 << acc_bits) - 1;
+        }
+    }
+    // Left align what is left in whole bytes, padding the last byte with zero bits
+    let numbytes = (acc_bits as usize + 7) / 8;
+    write_uint_be(out, (acc << (numbytes as u32 * 8 - acc_bits)) as u64, numbytes)
+}
+
+/// Packs inputs which are all known to fit in bits bits, for example because a schema says so, at exactly that
+/// width.  Unlike [`pack_u64`]: #method.pack_u64 there are no per block headers and no leading or trailing zeroes
+/// to find: bits is written once as the first byte, then every value follows as a little endian bit stream of
+/// bits bits each, so every block of 8 takes exactly bits bytes.  The last block is padded with zeroes.
+/// This trades adaptivity for speed: a column whose values are mostly much narrower than bits packs larger than
+/// with pack_u64.  Returns InvalidFormat if bits is over 64 or any value does not fit in bits bits.
+/// Decode using [`unpack_u64_fixed_width`]: #method.unpack_u64_fixed_width.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::VecSink;
+///     let inputs: Vec<u64> = (0..100).map(|i| (i * 37) % 4096).collect();
+///     let mut buf = [0u8; 1024];
+///     let written = nibblepacking::pack_u64_fixed_width(&inputs, 12, &mut buf).unwrap();
+///     assert_eq!(written, 1 + 13 * 12);
+///     let mut sink = VecSink::<u64>::new();
+///     nibblepacking::unpack_u64_fixed_width(&buf[..written], &mut sink, inputs.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &inputs[..]);
+/// ```
+pub fn pack_u64_fixed_width(inputs: &[u64], bits: u32, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    if bits > 64 { return Err(CodingError::InvalidFormat(format!("Width of {} bits is over 64", bits))) }
+    let mask = nibble_mask(bits);
+    let total_bytes = 1 + (inputs.len() + 7) / 8 * bits as usize;
+    if out_buffer.len() < total_bytes { return Err(CodingError::NotEnoughSpace) }
+    // Checked before writing anything, so that out_buffer is untouched on an error
+    if let Some(pos) = inputs.iter().position(|&n| n & !mask != 0) {
+        return Err(CodingError::InvalidFormat(
+            format!("Value {} at {} does not fit in {} bits", inputs[pos], pos, bits)));
+    }
+    out_buffer[0] = bits as u8;
+
+    let mut off = 1;
+    for chunk in inputs.chunks(8) {
+        let block_end = off + bits as usize;
+        // Bits not written out yet are the low acc_bits bits of acc, which never holds more than 64 + 64 bits
+        let mut acc = 0u128;
+        let mut acc_bits = 0;
+        for &n in chunk {
+            acc |= (n as u128) << acc_bits;
+            acc_bits += bits;
+            if acc_bits >= 64 {
+                out_buffer[off..off + 8].copy_from_slice(&(acc as u64).to_le_bytes());
+                off += 8;
+                acc >>= 64;
+                acc_bits -= 64;
             }
         }
-        output.process(u64x8::from_slice_unaligned(&out_array));
-        // Return the "remaining slice" - the rest of input buffer after we've parsed our bytes.
-        // This allows for easy and clean chaining of nibble_unpack8 calls with no mutable state
-        Ok(&inbuf[(total_bytes as usize)..])
+        // Then the rest of the block's bits bytes: under 8 for a full block, more for a short last block
+        while off < block_end {
+            let numbytes = (block_end - off).min(8);
+            out_buffer[off..off + numbytes].copy_from_slice(&(acc as u64).to_le_bytes()[..numbytes]);
+            off += numbytes;
+            acc >>= 64;
+        }
     }
+    Ok(off)
 }
 
-#[test]
-fn nibblepack8_all_zeroes() {
-    let mut buf = [0u8; 512];
-    let inputs = [0u64; 8];
-    let res = nibble_pack8(&inputs, &mut buf, 0);
-    dbg!(is_x86_feature_detected!("avx2"));
-    assert_eq!(res, Ok(1));
-    assert_eq!(buf[..1], [0u8]);
+/// The format version written by [`pack_versioned`]: #method.pack_versioned.  Version 1 is a stream of blocks
+/// as written by [`pack_u64`]: #method.pack_u64.  Bump this for any change which old decoders would misdecode.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Same as [`pack_u64`]: #method.pack_u64, but first writes a one byte [`FORMAT_VERSION`]: constant.FORMAT_VERSION
+/// at offset, so that buffers stored long term can be checked by [`unpack_versioned`]: #method.unpack_versioned
+/// instead of being silently misdecoded after a future format change.
+pub fn pack_versioned<I: Iterator<Item = u64>>(stream: I,
+                                               out_buffer: &mut [u8],
+                                               offset: usize) -> Result<usize, CodingError> {
+    let off = direct_write_uint_le(out_buffer, offset, FORMAT_VERSION as u64, 1)?;
+    pack_u64(stream, out_buffer, off)
 }
 
-#[rustfmt::skip]
-#[test]
-fn nibblepack8_all_evennibbles() {
-    // All 8 are nonzero, even # nibbles
-    let mut buf = [0u8; 512];
-    let inputs = [ 0x0000_00fe_dcba_0000u64, 0x0000_0033_2211_0000u64,
-                   0x0000_0044_3322_0000u64, 0x0000_0055_4433_0000u64,
+/// Returns an upper bound on the number of bytes [`pack_u64`]: #method.pack_u64 writes for num_values values:
+/// every block of 8 taking a bitmask byte, a nibbles byte, and 8 full u64's.  Most data packs much smaller.
+#[inline]
+pub fn estimate_packed_size(num_values: usize) -> usize {
+    max_packed_size_for_bits(num_values, 64)
+}
+
+/// Returns an upper bound on the number of bytes [`pack_u64`]: #method.pack_u64 writes for num_values values
+/// which all fit in max_bits bits, ie are below 2^max_bits.  Each block then takes at most a bitmask byte, a
+/// nibbles byte and 8 values of max_bits rounded up to whole nibbles, so this is a much tighter bound than
+/// [`estimate_packed_size`]: #method.estimate_packed_size for bounded columns.  max_bits above 64 is treated as 64.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     // Values below 2^20 take at most 5 nibbles each, so 22 bytes per block instead of 66
+///     assert_eq!(nibblepacking::max_packed_size_for_bits(1000, 20), 125 * 22);
+/// ```
+#[inline]
+pub const fn max_packed_size_for_bits(num_values: usize, max_bits: u32) -> usize {
+    let num_blocks = (num_values + 7) / 8;
+    if max_bits == 0 { return num_blocks }    // Every block is a zero bitmask byte
+    let max_nibbles = if max_bits >= 64 { 16 } else { (max_bits as usize + 3) / 4 };
+    num_blocks * (2 + 8 * max_nibbles / 2)
+}
+
+/// Returns the largest difference between successive inputs, in one pass, as pack_u64_delta computes them: a
+/// value less than the one before gives a difference of 0.  The first value, which pack_u64_delta packs as is, is
+/// not counted, so this is the largest value in the body written by
+/// [`pack_u64_delta_split`]: #method.pack_u64_delta_split.  Fewer than two inputs give 0.
+/// Its bit width bounds the packed size of the deltas, or flags an unexpected jump:
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let timestamps: Vec<u64> = (0..1000).map(|i| 1_600_000_000_000 + i * 10_000 + i % 7).collect();
+///     let max = nibblepacking::max_delta(&timestamps);
+///     assert_eq!(max, 10_001);
+///     let bound = nibblepacking::max_packed_size_for_bits(999, 64 - max.leading_zeros());
+///     assert!(nibblepacking::pack_u64_delta_split(&timestamps).1.len() <= bound);
+/// ```
+pub fn max_delta(inputs: &[u64]) -> u64 {
+    inputs.windows(2).map(|pair| pair[1].saturating_sub(pair[0])).max().unwrap_or(0)
+}
+
+/// Returns the exact number of bytes [`pack_u64`]: #method.pack_u64 would write for stream, without writing
+/// anything.  Only the bitmask and nibble width of each block of 8 are computed, so this is much cheaper than
+/// packing, and can be used to compare encodings or size buffers exactly.
+pub fn packed_size<I: Iterator<Item = u64>>(stream: I) -> usize {
+    let mut packer = BlockPacker::with_encoder(ByteCount(0), PackedSize);
+    for num in stream {
+        // Counting can't fail
+        let _ = packer.push(num);
+    }
+    packer.finish().map_or(0, |count| count.0)
+}
+
+// A ByteSink which only counts the bytes written to it
+#[derive(Debug)]
+struct ByteCount(usize);
+
+impl ByteSink for ByteCount {
+    #[inline]
+    fn push_byte(&mut self, _byte: u8) -> Result<(), CodingError> {
+        self.0 += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_uint_le(&mut self, _value: u64, numbytes: usize) -> Result<(), CodingError> {
+        self.0 += numbytes;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CodingError> {
+        self.0 += bytes.len();
+        Ok(())
+    }
+}
+
+// The BlockEncoder for packed_size, which counts the size of each block instead of packing it
+#[derive(Debug)]
+struct PackedSize;
+
+impl BlockEncoder<ByteCount> for PackedSize {
+    #[inline]
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut ByteCount) -> Result<(), CodingError> {
+        out.0 += block_packed_size(block);
+        Ok(())
+    }
+}
+
+#[inline]
+fn block_packed_size(inputs: &[u64; 8]) -> usize {
+    let nonzero_mask = compute_nonzero_mask(inputs);
+    if nonzero_mask == 0 { return 1 }
+    let (min_leading_zeros, min_trailing_zeros) = min_zeros_ored(inputs);
+    let num_nibbles = 16 - (min_leading_zeros / 4) - (min_trailing_zeros / 4);
+    2 + (num_nibbles * nonzero_mask.count_ones() + 1) as usize / 2
+}
+
+/// Same as [`pack_u64`]: #method.pack_u64, but allocates and returns the output buffer, so the caller does not
+/// need to size one up front.  The Vec is allocated using [`estimate_packed_size`]: #method.estimate_packed_size
+/// for the number of values the stream says it has, and grows if needed.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let packed = nibblepacking::pack_u64_to_vec((0..100u64).map(|n| n * 3));
+/// ```
+pub fn pack_u64_to_vec<I: Iterator<Item = u64>>(stream: I) -> Vec<u8> {
+    let out = Vec::with_capacity(estimate_packed_size(stream.size_hint().0));
+    pack_u64_to(stream, out).expect("Writing to a Vec cannot fail")
+}
+
+/// Same as [`pack_u64_delta`]: #method.pack_u64_delta, but allocates and returns the output buffer, as
+/// [`pack_u64_to_vec`]: #method.pack_u64_to_vec does for pack_u64.  Decreases are clipped to 0 in the same way.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let packed = nibblepacking::pack_u64_delta_to_vec(&[1000u64, 1010, 1025]);
+///     let mut sink = nibblepacking::DeltaSink::new();
+///     nibblepacking::unpack(&packed[..], &mut sink, 3).unwrap();
+///     assert_eq!(sink.as_slice(), &[1000u64, 1010, 1025]);
+/// ```
+pub fn pack_u64_delta_to_vec(inputs: &[u64]) -> Vec<u8> {
+    pack_u64_to_vec(clipped_deltas(inputs.iter().cloned()))
+}
+
+/// Same as [`pack_u64`]: #method.pack_u64, producing identical output, but reads a slice directly 8 values at a
+/// time instead of going through an Iterator one value at a time.  Use this for large precomputed arrays, such
+/// as ones memory mapped from a file.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let inputs: Vec<u64> = (0..1000).map(|n| n * n).collect();
+///     let mut buf = vec![0u8; nibblepacking::estimate_packed_size(inputs.len())];
+///     let written = nibblepacking::pack_u64_slice(&inputs[..], &mut buf, 0).unwrap();
+/// ```
+#[inline]
+pub fn pack_u64_slice(inputs: &[u64], out_buffer: &mut [u8], offset: usize) -> Result<usize, CodingError> {
+    let mut off = offset;
+    let mut chunks = inputs.chunks_exact(8);
+    for chunk in &mut chunks {
+        off = nibble_pack8(array_ref![chunk, 0, 8], out_buffer, off)?;
+    }
+    let remainder = chunks.remainder();
+    if !remainder.is_empty() {
+        let mut in_buffer = [0u64; 8];
+        in_buffer[..remainder.len()].copy_from_slice(remainder);
+        off = nibble_pack8(&in_buffer, out_buffer, off)?;
+    }
+    Ok(off)
+}
+
+/// The nibbles header byte marking a run of all-zero blocks written by
+/// [`pack_u64_zero_runs`]: #method.pack_u64_zero_runs.  It reads as 16 nibbles plus 2 trailing nibbles, which is
+/// over 64 bits, so it is never written for a NibblePacked block.  A run is a 0xff bitmask byte, this
+/// byte, then the number of zero blocks in the run as a varint.
+pub const ZERO_RUN_HEADER: u8 = 0xf2;
+
+// A run marker costs at least 3 bytes, so only runs of more zero blocks than that are collapsed
+const MIN_ZERO_RUN: u64 = 4;
+
+/// Same as [`pack_u64`]: #method.pack_u64, but collapses each run of at least 4 consecutive all-zero blocks,
+/// which would take a byte each, into a [`ZERO_RUN_HEADER`]: constant.ZERO_RUN_HEADER marker plus a varint count
+/// of blocks.  For very sparse columns, such as error counters which are zero for hours at a time, thousands of
+/// zero blocks then take 3 or 4 bytes.  Other blocks are packed exactly as by pack_u64.
+///
+/// NOTE: only [`unpack_zero_runs`]: #method.unpack_zero_runs decodes run markers.  The other unpack functions,
+/// and helpers such as validate() and walk_blocks(), read a block at a time and do not understand them.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let inputs: Vec<u64> = (0..10_000u64).map(|i| if i % 5000 == 17 { i } else { 0 }).collect();
+///     let mut buf = [0u8; 64];
+///     let written = nibblepacking::pack_u64_zero_runs(inputs.iter().cloned(), &mut buf, 0).unwrap();
+///     assert!(written < 20);
+///     let mut sink = compressed_vec::VecSink::<u64>::new();
+///     nibblepacking::unpack_zero_runs(&buf[..written], &mut sink, inputs.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &inputs[..]);
+/// ```
+pub fn pack_u64_zero_runs<I: Iterator<Item = u64>>(stream: I,
+                                                   out_buffer: &mut [u8],
+                                                   offset: usize) -> Result<usize, CodingError> {
+    let mut packer = BlockPacker::with_encoder(SliceCursor::new(out_buffer, offset), ZeroRuns { zero_run: 0 });
+    for num in stream {
+        packer.push(num)?;
+    }
+    Ok(packer.finish()?.pos)
+}
+
+// The BlockEncoder for pack_u64_zero_runs, which holds back each run of all-zero blocks until it ends
+#[derive(Debug)]
+struct ZeroRuns {
+    zero_run: u64,
+}
+
+impl<B: ByteSink> BlockEncoder<B> for ZeroRuns {
+    // Counts an all-zero block into the current run, or writes out the run and then packs the block
+    #[inline]
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+        if block.iter().all(|&n| n == 0) {
+            self.zero_run += 1;
+            Ok(())
+        } else {
+            write_zero_run(self.zero_run, out)?;
+            self.zero_run = 0;
+            nibble_pack8_to(block, out)
+        }
+    }
+
+    fn finish(&mut self, out: &mut B) -> Result<(), CodingError> {
+        write_zero_run(self.zero_run, out)
+    }
+}
+
+fn write_zero_run<B: ByteSink>(zero_run: u64, out: &mut B) -> Result<(), CodingError> {
+    if zero_run >= MIN_ZERO_RUN {
+        out.push_byte(0xff)?;
+        out.push_byte(ZERO_RUN_HEADER)?;
+        write_varint(out, zero_run)
+    } else {
+        (0..zero_run).try_for_each(|_| out.push_byte(0))
+    }
+}
+
+// A raw block is 8 little endian u64's
+const RAW_BLOCK_BYTES: usize = 64;
+
+/// Same as [`pack_u64`]: #method.pack_u64, but a block whose NibblePacked form would be larger than storing its
+/// 8 values raw, such as a block of 8 random full width values, is stored raw instead.  Such a block takes
+/// 66 bytes NibblePacked, as the bitmask and nibbles bytes come on top of its 64 bytes of values, so high entropy
+/// blocks save 2 bytes each while every other block is packed exactly as by pack_u64.  A "raw" flag bit is kept
+/// for every block, which costs one bit per 8 values.
+///
+/// The output is the flags for all the blocks, 1 bit each, least significant bit first and rounded up to a whole
+/// byte, then the blocks.  Decode using [`unpack_u64_adaptive`]: #method.unpack_u64_adaptive.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::VecSink;
+///     let inputs: Vec<u64> = (0..16u64).map(|i| if i < 8 { u64::MAX - i } else { i }).collect();
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64_adaptive(inputs.iter().cloned(), &mut buf).unwrap();
+///     assert_eq!(written, 1 + 64 + 6);
+///     let mut sink = VecSink::<u64>::new();
+///     nibblepacking::unpack_u64_adaptive(&buf[..written], &mut sink, inputs.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &inputs[..]);
+/// ```
+pub fn pack_u64_adaptive<I: Iterator<Item = u64>>(stream: I, out_buffer: &mut [u8]) -> Result<usize, CodingError> {
+    let mut flags = Vec::new();
+    let mut packer = BlockPacker::with_encoder(Vec::new(), RawOrNibblePacked { flags: &mut flags, num_blocks: 0 });
+    for num in stream {
+        packer.push(num)?;
+    }
+    let blocks = packer.finish()?;
+
+    let mut cursor = SliceCursor::new(out_buffer, 0);
+    cursor.write_bytes(&flags[..])?;
+    cursor.write_bytes(&blocks[..])?;
+    Ok(cursor.pos)
+}
+
+// The BlockEncoder for pack_u64_adaptive, which sets the flag of each block stored raw
+#[derive(Debug)]
+struct RawOrNibblePacked<'a> {
+    flags: &'a mut Vec<u8>,
+    num_blocks: usize,
+}
+
+impl<'a, B: ByteSink> BlockEncoder<B> for RawOrNibblePacked<'a> {
+    #[inline]
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+        if self.num_blocks % 8 == 0 { self.flags.push(0u8); }
+        if block_packed_size(block) > RAW_BLOCK_BYTES {
+            self.flags[self.num_blocks / 8] |= 1 << (self.num_blocks % 8);
+            block.iter().try_for_each(|&n| out.write_uint_le(n, 8))?;
+        } else {
+            nibble_pack8_to(block, out)?;
+        }
+        self.num_blocks += 1;
+        Ok(())
+    }
+}
+
+///
+/// NibblePacking is an encoding technique for packing 8 u64's tightly into the same number of nibbles.
+/// It can be combined with a prediction algorithm to efficiency encode floats and long values.
+/// This is really an inner function; the intention is for the user to use one of the higher level pack* methods.
+/// Please see http://github.com/filodb/FiloDB/doc/compression.md for more answers.
+///
+/// # Arguments
+/// * `inputs` - ref to 8 u64 values to pack, could be the output of a predictor
+/// * `out_buffer` - a &mut [u8] to write the encoded output to.
+/// * `offset` - offset within the out_buffer to write to
+/// Outputs the ending offset, or an error.
+///
+#[inline(always)]
+pub fn nibble_pack8(inputs: &[u64; 8],
+                    out_buffer: &mut [u8],
+                    offset: usize) -> Result<usize, CodingError> {
+    // Check for both nonzero byte and at least one more byte after that for nibbles
+    if (offset + 1) >= out_buffer.len() {
+        return Err(CodingError::NotEnoughSpace);
+    }
+    let mut cursor = SliceCursor::new(out_buffer, offset);
+    nibble_pack8_to(inputs, &mut cursor)?;
+    Ok(cursor.pos)
+}
+
+/// Same as [`nibble_pack8`]: #method.nibble_pack8, but writes the packed bytes to any ByteSink,
+/// such as a `Vec<u8>` or a `WriteSink` wrapping a std::io::Write.
+#[inline(always)]
+pub fn nibble_pack8_to<B: ByteSink>(inputs: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+    let nonzero_mask = compute_nonzero_mask(inputs);
+    out.push_byte(nonzero_mask)?;
+
+    // if no nonzero values, we're done!
+    if nonzero_mask != 0 {
+        // otherwise, get min of leading and trailing zeros, encode it
+        let (min_leading_zeros, min_trailing_zeros) = min_leading_trailing_zeros(inputs);
+
+        // Convert min leading/trailing to # nibbles.  Start packing!
+        // NOTE: num_nibbles cannot be 0; that would imply every input was zero
+        let trailing_nibbles = min_trailing_zeros / 4;
+        let num_nibbles = 16 - (min_leading_zeros / 4) - trailing_nibbles;
+        let nibble_word = (((num_nibbles - 1) << 4) | trailing_nibbles) as u8;
+        out.push_byte(nibble_word)?;
+
+        if (num_nibbles % 2) == 0 {
+            pack_to_even_nibbles(inputs, out, num_nibbles, trailing_nibbles)?;
+        } else {
+            pack_universal(inputs, out, num_nibbles, trailing_nibbles)?;
+        }
+    }
+    Ok(())
+}
+
+// The BlockEncoder for pack_f64_xor_windowed, which sets the flag of each block packed with the last window
+#[derive(Debug)]
+struct SharedWindows<'a> {
+    flags: &'a mut Vec<u8>,
+    num_blocks: usize,
+    window: Option<(u32, u32)>,
+}
+
+impl<'a, B: ByteSink> BlockEncoder<B> for SharedWindows<'a> {
+    #[inline]
+    fn encode_block(&mut self, block: &[u64; 8], out: &mut B) -> Result<(), CodingError> {
+        if self.num_blocks % 8 == 0 { self.flags.push(0u8); }
+        if nibble_pack8_windowed(block, out, &mut self.window)? {
+            self.flags[self.num_blocks / 8] |= 1 << (self.num_blocks % 8);
+        }
+        self.num_blocks += 1;
+        Ok(())
+    }
+}
+
+// Packs a block for pack_f64_xor_windowed.  window is the (num nibbles, trailing nibbles) of the last block written
+// with a nibbles byte.  Returns true if the block reused it and so was written without one.
+fn nibble_pack8_windowed<B: ByteSink>(inputs: &[u64; 8],
+                                      out: &mut B,
+                                      window: &mut Option<(u32, u32)>) -> Result<bool, CodingError> {
+    let nonzero_mask = compute_nonzero_mask(inputs);
+    out.push_byte(nonzero_mask)?;
+    if nonzero_mask == 0 { return Ok(false) }
+
+    let (min_leading_zeros, min_trailing_zeros) = min_leading_trailing_zeros(inputs);
+    let trailing_nibbles = min_trailing_zeros / 4;
+    let num_nibbles = 16 - (min_leading_zeros / 4) - trailing_nibbles;
+    let count = nonzero_mask.count_ones();
+    if let Some((window_nibbles, window_trailing)) = *window {
+        let fits = window_trailing <= trailing_nibbles &&
+                   window_nibbles + window_trailing >= num_nibbles + trailing_nibbles;
+        if fits && (window_nibbles * count + 1) / 2 <= 1 + (num_nibbles * count + 1) / 2 {
+            pack_universal(inputs, out, window_nibbles, window_trailing)?;
+            return Ok(true)
+        }
+    }
+    out.push_byte((((num_nibbles - 1) << 4) | trailing_nibbles) as u8)?;
+    pack_universal(inputs, out, num_nibbles, trailing_nibbles)?;
+    *window = Some((num_nibbles, trailing_nibbles));
+    Ok(false)
+}
+
+/// Returns the (min leading zeros, min trailing zeros) across the nonzero inputs, by ORing all the inputs
+/// together first.  The ORed word has a bit set wherever any input does, so its leading and trailing zeros
+/// are the minimums across the inputs.  Generates much tighter code than [`min_zeros_per_lane`]: #method.min_zeros_per_lane
+/// and is the default; see the "nibblepack8 min zeros" benchmark.
+#[inline(always)]
+pub(crate) fn min_zeros_ored(inputs: &[u64; 8]) -> (u32, u32) {
+    let ored_bits = inputs.iter().fold(0u64, |acc, &x| acc | x);
+    (ored_bits.leading_zeros(), ored_bits.trailing_zeros())
+}
+
+/// Returns the (min leading zeros, min trailing zeros) across the nonzero inputs, by computing the leading
+/// and trailing zeros of each input separately.  Used instead of [`min_zeros_ored`]: #method.min_zeros_ored
+/// when the `lane_min_zeros` feature is enabled.
+#[cfg(any(test, feature = "lane_min_zeros"))]
+#[inline(always)]
+pub(crate) fn min_zeros_per_lane(inputs: &[u64; 8]) -> (u32, u32) {
+    let min_leading_zeros = inputs.iter().map(|x| x.leading_zeros()).min().unwrap();
+    let min_trailing_zeros = inputs.iter().map(|x| x.trailing_zeros()).min().unwrap();
+    (min_leading_zeros, min_trailing_zeros)
+}
+
+#[cfg(not(feature = "lane_min_zeros"))]
+use self::min_zeros_ored as min_leading_trailing_zeros;
+#[cfg(feature = "lane_min_zeros")]
+use self::min_zeros_per_lane as min_leading_trailing_zeros;
+
+///
+/// Inner function to pack the raw inputs to nibbles when # nibbles is even (always # bytes)
+/// It's somehow really fast, perhaps because it is really simple.
+///
+/// # Arguments
+/// * `trailing_zero_nibbles` - the min # of trailing zero nibbles across all inputs
+/// * `num_nibbles` - the max # of nibbles having nonzero bits in all inputs
+#[inline]
+pub(crate) fn pack_to_even_nibbles<B: ByteSink>(
+    inputs: &[u64; 8],
+    out: &mut B,
+    num_nibbles: u32,
+    trailing_zero_nibbles: u32
+) -> Result<(), CodingError> {
+    let shift = trailing_zero_nibbles * 4;
+    assert!(num_nibbles % 2 == 0);
+    let num_bytes_each = (num_nibbles / 2) as usize;
+
+    // The most common widths of 1 to 4 bytes each get a loop with the width fixed at compile time; see the
+    // "nibblepack8 even nibble widths" benchmark, which can be run with `--features generic_even_nibbles` to compare
+    if cfg!(not(feature = "generic_even_nibbles")) {
+        match num_bytes_each {
+            1 => return pack_fixed_bytes::<B, 1>(inputs, out, shift),
+            2 => return pack_fixed_bytes::<B, 2>(inputs, out, shift),
+            3 => return pack_fixed_bytes::<B, 3>(inputs, out, shift),
+            4 => return pack_fixed_bytes::<B, 4>(inputs, out, shift),
+            _ => {},
+        }
+    }
+    pack_even_nibbles_generic(inputs, out, num_bytes_each, shift)
+}
+
+// Writes each nonzero input shifted right by shift as N bytes, gathered into one write of the whole block
+#[inline(always)]
+fn pack_fixed_bytes<B: ByteSink, const N: usize>(inputs: &[u64; 8],
+                                                 out: &mut B,
+                                                 shift: u32) -> Result<(), CodingError> {
+    let mut buf = [0u8; 32];
+    let mut len = 0;
+    for &x in inputs {
+        if x != 0 {
+            buf[len..len + N].copy_from_slice(&(x >> shift).to_le_bytes()[..N]);
+            len += N;
+        }
+    }
+    out.write_bytes(&buf[..len])
+}
+
+// For each nonzero input, shift and write out exact # of bytes
+#[inline]
+fn pack_even_nibbles_generic<B: ByteSink>(inputs: &[u64; 8],
+                                          out: &mut B,
+                                          num_bytes_each: usize,
+                                          shift: u32) -> Result<(), CodingError> {
+    for &x in inputs {
+        if x != 0 {
+            out.write_uint_le(x >> shift, num_bytes_each)?;
+        }
+    };
+    Ok(())
+}
+
+/// Universal, generic nibble packing algorithm, packing 8 64-bit values to a byte buffer.
+/// This code is inspired by bitpacking crate: https://github.com/tantivy-search/bitpacking/
+/// but modified for the NibblePacking algorithm.  No macros, so slightly less efficient.
+/// TODO: consider using macros like in bitpacking to achieve even more speed :D
+#[inline]
+pub(crate) fn pack_universal<B: ByteSink>(
+    inputs: &[u64; 8],
+    out: &mut B,
+    num_nibbles: u32,
+    trailing_zero_nibbles: u32
+) -> Result<(), CodingError> {
+    let trailing_shift = trailing_zero_nibbles * 4;
+    let num_bits = num_nibbles * 4;
+    let mask = nibble_mask(num_bits);
+    let mut out_word = 0u64;
+    let mut bit_cursor = 0;
+
+    for &x in inputs {
+        if x != 0 {
+            let remaining = 64 - bit_cursor;
+            let shifted_input = (x >> trailing_shift) & mask;
+
+            // This is least significant portion of input
+            out_word |= shifted_input << bit_cursor;
+
+            // Write out current word if we've used up all 64 bits
+            if remaining <= num_bits {
+                out.write_uint_le(out_word, 8)?;
+
+                if remaining < num_bits {
+                    // Most significant portion left over from previous word
+                    out_word = shifted_input >> (remaining as i32);
+                } else {
+                    out_word = 0; // reset for 64-bit input case
+                }
+            }
+
+            bit_cursor = (bit_cursor + num_bits) % 64;
+        }
+    };
+
+    // Write remainder word if there are any bits remaining, and only advance buffer right # of bytes
+    if bit_cursor > 0 {
+        out.write_uint_le(out_word, ((bit_cursor + 7) / 8) as usize)?;
+    }
+    Ok(())
+}
+
+
+pub(crate) const ZERO_U64OCTET: u64x8 = u64x8::splat(0);
+
+/// A Sink which accumulates delta-encoded NibblePacked data back into increasing u64 numbers
+#[derive(Debug)]
+pub struct DeltaSink {
+    acc: u64,
+    base: u64,
+    sink: VecSink<u64>,
+}
+
+impl DeltaSink {
+    pub fn with_sink(inner_sink: VecSink<u64>) -> DeltaSink {
+        DeltaSink { acc: 0, base: 0, sink: inner_sink }
+    }
+
+    pub fn new() -> DeltaSink {
+        DeltaSink::with_sink(VecSink::<u64>::new())
+    }
+
+    /// Creates a DeltaSink with room to decode num_values values without growing, see [`VecSink::with_capacity`]
+    pub fn with_capacity(num_values: usize) -> DeltaSink {
+        DeltaSink::with_sink(VecSink::<u64>::with_capacity(num_values))
+    }
+
+    /// Creates a DeltaSink whose deltas are added to base instead of 0.  To decode a column packed in chunks,
+    /// start each chunk's sink with the last_value() of the chunk before.  The base is kept across reset().
+    pub fn with_base(base: u64) -> DeltaSink {
+        DeltaSink { acc: base, base, sink: VecSink::<u64>::new() }
+    }
+
+    /// The accumulated total so far, which after a decode is the last value decoded (padding deltas are zero),
+    /// or the base if nothing has been decoded yet
+    #[inline]
+    pub fn last_value(&self) -> u64 {
+        self.acc
+    }
+
+    pub fn output_vec(&self) -> &Vec<u64> {
+        &self.sink.vec
+    }
+
+    /// Returns the decoded values without the trailing padding, see [`VecSink::as_slice`]
+    pub fn as_slice(&self) -> &[u64] {
+        self.sink.as_slice()
+    }
+
+    /// Adds each delta in data onto acc in turn, returning the running totals.  Shared with the sink used by
+    /// [`range_decode_delta`]: #method.range_decode_delta
+    #[inline]
+    fn accumulate(acc: &mut u64, data: u64x8) -> u64x8 {
+        let mut buf = u64x8::splat(0);
+        let mut sum = *acc;
+        for i in 0..8 {
+            sum += data.extract(i);
+            buf = buf.replace(i, sum);
+        }
+        *acc = sum;
+        buf
+    }
+}
+
+impl Sink<u64x8> for DeltaSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let buf = DeltaSink::accumulate(&mut self.acc, data);
+        self.sink.process(buf);
+    }
+
+    fn process_zeroes(&mut self) {
+        todo!();
+    }
+
+    fn reset(&mut self) {
+        self.acc = self.base;
+        self.sink.reset()
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.sink.mark_padding(num_padding);
+    }
+}
+
+impl Reusable<u64x8> for DeltaSink {
+    fn capacity(&self) -> usize {
+        self.sink.capacity()
+    }
+}
+
+/// A Sink which accumulates ZigZag delta encoded NibblePacked data from [`pack_gauge`]: #method.pack_gauge
+/// back into the original u64 numbers
+#[derive(Debug)]
+pub struct ZigZagDeltaSink {
+    acc: u64,
+    step: u64,
+    sink: VecSink<u64>,
+}
+
+impl ZigZagDeltaSink {
+    pub fn with_sink(inner_sink: VecSink<u64>) -> ZigZagDeltaSink {
+        ZigZagDeltaSink { acc: 0, step: 0, sink: inner_sink }
+    }
+
+    pub fn new() -> ZigZagDeltaSink {
+        ZigZagDeltaSink::with_sink(VecSink::<u64>::new())
+    }
+
+    /// Creates a sink which adds step back to every delta, for decoding [`pack_u64_stepped`]: #method.pack_u64_stepped
+    pub fn with_step(step: u64) -> ZigZagDeltaSink {
+        ZigZagDeltaSink { step, ..ZigZagDeltaSink::new() }
+    }
+
+    pub fn output_vec(&self) -> &Vec<u64> {
+        &self.sink.vec
+    }
+
+    /// Returns the decoded values without the trailing padding, see [`VecSink::as_slice`]
+    pub fn as_slice(&self) -> &[u64] {
+        self.sink.as_slice()
+    }
+}
+
+impl Default for ZigZagDeltaSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sink<u64x8> for ZigZagDeltaSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let mut buf = u64x8::splat(0);
+        let mut acc = self.acc;
+        for i in 0..8 {
+            acc = acc.wrapping_add(self.step).wrapping_add(zigzag_decode(data.extract(i)) as u64);
+            buf = buf.replace(i, acc);
+        }
+        self.acc = acc;
+        self.sink.process(buf);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        if self.step != 0 { return self.process(ZERO_U64OCTET) }
+        // All deltas are zero, so the values stay the same
+        self.sink.process(u64x8::splat(self.acc));
+    }
+
+    fn reset(&mut self) {
+        self.acc = 0;
+        self.sink.reset()
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.sink.mark_padding(num_padding);
+    }
+}
+
+impl Reusable<u64x8> for ZigZagDeltaSink {
+    fn capacity(&self) -> usize {
+        self.sink.capacity()
+    }
+}
+
+/// A sink which uses simple successive XOR encoding to decode a NibblePacked floating point stream
+/// encoded using [`pack_f64_xor`]: #method.pack_f64_xor
+/// The padding decoded with the last block is dropped from the Vec once decoding is done, so the Vec holds
+/// exactly the values asked for.
+#[derive(Debug)]
+pub struct DoubleXorSink<V: BorrowMut<Vec<f64>> = Vec<f64>> {
+    last: u64,
+    vec: V,
+    append: bool,
+}
+
+impl<V: BorrowMut<Vec<f64>>> DoubleXorSink<V> {
+    /// Creates a new DoubleXorSink writing to the_vec, which is either a Vec owned by this struct or a
+    /// borrowed &mut Vec<f64>, for example one the caller has grown ahead of time.
+    pub fn new(the_vec: V) -> Self {
+        DoubleXorSink { last: 0, vec: the_vec, append: false }
+    }
+
+    /// Switches to append mode, where decoding and reset() no longer clear the Vec, so that multiple columns
+    /// can be decoded one after another into the same Vec.  Record vec.len() before each decode to find where
+    /// each column starts.
+    pub fn with_append_mode(mut self) -> Self {
+        self.append = true;
+        self
+    }
+
+    pub fn output_vec(&self) -> &Vec<f64> {
+        self.vec.borrow()
+    }
+
+    /// Clears the output (unless appending), keeping its capacity, and starts a new stream with the given
+    /// initial f64 bits.
+    fn reset_with_initial(&mut self, init_value: u64) {
+        self.reset();
+        self.vec.borrow_mut().push(f64::from_bits(init_value));
+        self.last = init_value;
+    }
+}
+
+impl DoubleXorSink {
+    /// Creates a DoubleXorSink owning a new Vec with room to decode num_values values using
+    /// [`unpack_f64_xor`]: #method.unpack_f64_xor without growing: the initial value plus the rest rounded up to
+    /// a multiple of 8.
+    pub fn with_capacity(num_values: usize) -> Self {
+        Self::new(Vec::with_capacity(1 + (num_values.saturating_sub(1) + 7) / 8 * 8))
+    }
+}
+
+/// A Sink which rebuilds u128 values from the two u64 streams written by [`pack_u128`]: #method.pack_u128.
+/// The high halves are decoded first, each pushing a new value, then the low halves are ORed into them.
+/// Use [`unpack_u128`]: #method.unpack_u128, which switches between the two halves.
+#[derive(Debug)]
+pub struct U128Sink {
+    vec: Vec<u128>,
+    low_pos: Option<usize>,    // Where the next low halves go, once the high halves are done
+}
+
+impl U128Sink {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates a U128Sink with room to decode num_values values without growing, see [`VecSink::with_capacity`]
+    pub fn with_capacity(num_values: usize) -> Self {
+        U128Sink { vec: Vec::with_capacity((num_values + 7) / 8 * 8), low_pos: None }
+    }
+
+    fn start_low_halves(&mut self) {
+        self.low_pos = Some(0);
+    }
+
+    /// Returns the decoded values, without padding
+    pub fn as_slice(&self) -> &[u128] {
+        &self.vec[..]
+    }
+}
+
+impl Default for U128Sink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sink<u64x8> for U128Sink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        match self.low_pos {
+            None => {
+                for i in 0..8 {
+                    self.vec.push((data.extract(i) as u128) << 64);
+                }
+            },
+            Some(pos) => {
+                // The padding values of the last block have nowhere to go, and are zero anyway
+                let num_values = (self.vec.len() - pos).min(8);
+                for i in 0..num_values {
+                    self.vec[pos + i] |= data.extract(i) as u128;
+                }
+                self.low_pos = Some(pos + num_values);
+            },
+        }
+    }
+
+    fn process_zeroes(&mut self) {
+        self.process(ZERO_U64OCTET);
+    }
+
+    fn reset(&mut self) {
+        self.vec.clear();
+        self.low_pos = None;
+    }
+
+    // Drops the padding after the high halves, so that the low halves line up with just the real values
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        if self.low_pos.is_none() {
+            self.vec.truncate(self.vec.len().saturating_sub(num_padding));
+        }
+    }
+}
+
+impl Reusable<u64x8> for U128Sink {
+    fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+}
+
+/// Reconstructs 8 values from 8 XOR deltas and the value before them: lane i becomes
+/// start ^ data[0] ^ ... ^ data[i].  This is a serial chain where each lane depends on the one before.
+#[inline(always)]
+pub fn prefix_xor_serial(start: u64, data: u64x8) -> u64x8 {
+    let mut out = data;
+    let mut last = start;
+    for i in 0..8 {
+        // XOR new piece of data with last, which yields original value
+        last ^= data.extract(i);
+        out = out.replace(i, last);
+    }
+    out
+}
+
+/// Same as [`prefix_xor_serial`]: #method.prefix_xor_serial, but computed as a parallel prefix XOR: three rounds
+/// of XORing in the lanes shifted up by 1, 2 and then 4, so there is no dependency chain across the lanes.
+/// Used instead of prefix_xor_serial when the `simd` feature is enabled.
+#[inline(always)]
+pub fn prefix_xor_simd(start: u64, data: u64x8) -> u64x8 {
+    let x = data ^ shuffle!(ZERO_U64OCTET, data, [0, 8, 9, 10, 11, 12, 13, 14]);
+    let x = x ^ shuffle!(ZERO_U64OCTET, x, [0, 1, 8, 9, 10, 11, 12, 13]);
+    let x = x ^ shuffle!(ZERO_U64OCTET, x, [0, 1, 2, 3, 8, 9, 10, 11]);
+    x ^ u64x8::splat(start)
+}
+
+#[cfg(not(feature = "simd"))]
+use self::prefix_xor_serial as prefix_xor;
+#[cfg(feature = "simd")]
+use self::prefix_xor_simd as prefix_xor;
+
+impl<V: BorrowMut<Vec<f64>>> Sink<u64x8> for DoubleXorSink<V> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let values = prefix_xor(self.last, data);
+        self.last = values.extract(7);
+
+        let vec = self.vec.borrow_mut();
+        let new_len = vec.len() + 8;
+        vec.resize(new_len, 0.0);
+        f64x8::from_bits(values).write_to_slice_unaligned(&mut vec[new_len - 8..]);
+    }
+
+    fn process_zeroes(&mut self) {
+        todo!();
+    }
+
+    fn reset(&mut self) {
+        if !self.append {
+            self.vec.borrow_mut().clear();
+        }
+        self.last = 0;
+    }
+
+    // Drop the padding right away, so that in append mode the next column starts right after this one
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        let vec = self.vec.borrow_mut();
+        vec.truncate(vec.len().saturating_sub(num_padding));
+    }
+}
+
+impl<V: BorrowMut<Vec<f64>>> Reusable<u64x8> for DoubleXorSink<V> {
+    fn capacity(&self) -> usize {
+        self.vec.borrow().capacity()
+    }
+}
+
+/// A sink that converts u32x8 output from SIMD 32-bit unpacker to 64-bit
+// TODO: figure out right place for this?
+#[derive(Debug)]
+struct U32ToU64Sink<'a, S: Sink<u64x8>> {
+    u64sink: &'a mut S
+}
+
+impl<'a, S: Sink<u64x8>> U32ToU64Sink<'a, S> {
+    #[inline]
+    pub fn new(u64sink: &'a mut S) -> Self {
+        Self { u64sink }
+    }
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u32x8> for U32ToU64Sink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u32x8) {
+        self.u64sink.process(u64x8::from_cast(data));
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.u64sink.process_zeroes();
+    }
+
+    fn reset(&mut self) {}
+}
+
+// Running out of bytes partway through a block can only mean a stream was cut short, so the stream decoders all
+// report nibble_unpack8's NotEnoughSpace as InputTooShort
+#[inline]
+pub(crate) fn truncated_input(e: CodingError) -> CodingError {
+    match e {
+        CodingError::NotEnoughSpace => CodingError::InputTooShort,
+        e => e,
+    }
+}
+
+// Every block of 8 values takes at least one byte, so more than 8 values per byte of encoded can only be a corrupt
+// count.  Checked before sizing an output from num_values, so that a bad count fails instead of allocating hugely.
+#[inline]
+pub(crate) fn check_values_fit(encoded: &[u8], num_values: usize) -> Result<(), CodingError> {
+    if num_values > encoded.len().saturating_mul(8) { return Err(CodingError::InputTooShort) }
+    Ok(())
+}
+
+/// Unpacks num_values values from an encoded buffer, by calling nibble_unpack8 enough times.
+/// The output.process() method is called numValues times rounded up to the next multiple of 8, after which
+/// output.mark_padding() is told how many of the final values are just padding.
+/// Returns "remainder" byteslice or unpacking error (say if one ran out of space)
+///
+/// Returns InputTooShort if encoded ends before num_values values, however far short it is.  Every block takes
+/// at least one byte, so if num_values is more than 8 times the length of encoded, that is found straight away,
+/// before anything is decoded or output is called at all.
+///
+/// # Arguments
+/// * `inbuf` - NibblePacked compressed byte slice containing "remaining" bytes, starting with bitmask byte
+/// * `output` - a Trait which processes each resulting u64
+/// * `num_values` - the number of u64 values to decode
+#[inline]
+pub fn unpack<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    if num_values > encoded.len().saturating_mul(8) { return Err(CodingError::InputTooShort) }
+    let mut values_left = num_values as isize;
+    let mut inbuf = encoded;
+    while values_left > 0 {
+        inbuf = nibble_unpack8(inbuf, output).map_err(truncated_input)?;
+        values_left -= 8;
+    }
+    output.mark_padding(-values_left as usize);
+    Ok(inbuf)
+}
+
+/// Unpacks num_values values of one column whose blocks are stored in several separate buffers, such as when the
+/// blocks of different columns are striped together.  The slices are decoded in order as if they were one buffer,
+/// so every slice must hold whole blocks; a split made between two blocks written by pack_u64 always does.
+/// As with [`unpack`]: #method.unpack, output.mark_padding() is called once at the end.
+/// Returns InputTooShort if the slices run out before num_values values or a block runs past the end of its slice,
+/// just as [`unpack`]: #method.unpack does for a truncated buffer.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::sink::VecSink;
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64(0..16u64, &mut buf, 0).unwrap();
+///     let first_block = nibblepacking::block_len(&buf[..written]).unwrap();
+///     let (first, second) = buf[..written].split_at(first_block);
+///     let mut sink = VecSink::<u64>::new();
+///     nibblepacking::unpack_scattered(&[first, second], &mut sink, 16).unwrap();
+///     assert_eq!(sink.as_slice(), &(0..16).collect::<Vec<u64>>()[..]);
+/// ```
+pub fn unpack_scattered<Output>(
+    block_slices: &[&[u8]],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<(), CodingError>
+where Output: Sink<u64x8> {
+    let mut values_left = num_values as isize;
+    let mut slices = block_slices.iter();
+    let mut inbuf: &[u8] = &[];
+    while values_left > 0 {
+        while inbuf.is_empty() {
+            inbuf = slices.next().ok_or(CodingError::InputTooShort)?;
+        }
+        if block_len(inbuf).map_err(truncated_input)? > inbuf.len() { return Err(CodingError::InputTooShort) }
+        inbuf = nibble_unpack8(inbuf, output).map_err(truncated_input)?;
+        values_left -= 8;
+    }
+    output.mark_padding(-values_left as usize);
+    Ok(())
+}
+
+/// Counters from [`unpack_metered`]: #method.unpack_metered, for checking whether a column hits the fast paths
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct DecodeMetrics {
+    /// Number of blocks of 8 decoded
+    pub blocks: usize,
+    /// Blocks which were all zero, decoded from their 1 byte header alone
+    pub zero_blocks: usize,
+    /// Blocks narrow enough for the SIMD u32 unpacker, ie at most 32 bits including trailing zeroes
+    pub simd_u32_blocks: usize,
+    /// Total bytes consumed from the input
+    pub bytes_read: usize,
+}
+
+/// Same as [`unpack`]: #method.unpack, but also counts what kinds of blocks were decoded and how many bytes
+/// were read.  unpack itself does no counting, so use this when tuning and unpack in production.
+///
+/// ```
+/// # use compressed_vec::{nibblepacking, VecSink};
+///     let packed = nibblepacking::pack_u64_to_vec((0..24u64).map(|n| if n < 16 { 0 } else { n }));
+///     let mut sink = VecSink::<u64>::new();
+///     let (_rest, metrics) = nibblepacking::unpack_metered(&packed, &mut sink, 24).unwrap();
+///     assert_eq!((metrics.blocks, metrics.zero_blocks, metrics.bytes_read), (3, 2, packed.len()));
+/// ```
+pub fn unpack_metered<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<(&'a [u8], DecodeMetrics), CodingError>
+where Output: Sink<u64x8> {
+    let mut metrics = DecodeMetrics::default();
+    let mut values_left = num_values as isize;
+    let mut inbuf = encoded;
+    while values_left > 0 {
+        match inbuf {
+            [0, ..]                  => metrics.zero_blocks += 1,
+            [_, nibbles_byte, ..] if ((nibbles_byte >> 4) + 1 + (nibbles_byte & 0x0f)) * 4 <= 32 => {
+                metrics.simd_u32_blocks += 1
+            },
+            _ => {},
+        }
+        inbuf = nibble_unpack8(inbuf, output).map_err(truncated_input)?;
+        metrics.blocks += 1;
+        values_left -= 8;
+    }
+    output.mark_padding(-values_left as usize);
+    metrics.bytes_read = encoded.len() - inbuf.len();
+    Ok((inbuf, metrics))
+}
+
+/// Same as [`unpack`]: #method.unpack, but takes ownership of a Bytes buffer and returns the rest of it after the
+/// decoded blocks as another Bytes.  The remainder shares the same reference counted memory, so no bytes are
+/// copied, and it is not tied to the lifetime of a borrow, which is easier to hand between async tasks.
+/// Needs the `bytes` feature.
+#[cfg(feature = "bytes")]
+pub fn unpack_bytes<Output: Sink<u64x8>>(buf: Bytes,
+                                         output: &mut Output,
+                                         num_values: usize) -> Result<Bytes, CodingError> {
+    let rest_len = unpack(&buf[..], output, num_values)?.len();
+    Ok(buf.slice(buf.len() - rest_len..))
+}
+
+/// Unpacks num_values values written by [`pack_u64_zero_runs`]: #method.pack_u64_zero_runs.  Each run marker
+/// of N blocks is expanded by calling output.process() N times with 8 zeroes, so the sink sees exactly the same
+/// calls as when unpacking the same values written by pack_u64.  Returns InvalidFormat if a run is empty or
+/// extends past num_values.
+pub fn unpack_zero_runs<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    let mut blocks_left = (num_values + 7) / 8;
+    let mut inbuf = encoded;
+    while blocks_left > 0 {
+        if inbuf.len() >= 2 && inbuf[0] != 0 && inbuf[1] == ZERO_RUN_HEADER {
+            let (run, pos) = read_varint(inbuf, 2)?;
+            if run == 0 || run > blocks_left as u64 {
+                return Err(CodingError::InvalidFormat(
+                    format!("Zero run of {} blocks with {} blocks left", run, blocks_left)));
+            }
+            for _ in 0..run {
+                output.process(ZERO_U64OCTET);
+            }
+            blocks_left -= run as usize;
+            inbuf = &inbuf[pos..];
+        } else {
+            inbuf = nibble_unpack8(inbuf, output).map_err(truncated_input)?;
+            blocks_left -= 1;
+        }
+    }
+    output.mark_padding((8 - num_values % 8) % 8);
+    Ok(inbuf)
+}
+
+/// Unpacks num_values values written by [`pack_u64_adaptive`]: #method.pack_u64_adaptive, calling output in the
+/// same way as [`unpack`]: #method.unpack whether each block was stored raw or NibblePacked.  Returns the rest of
+/// encoded after the last block, or InputTooShort if encoded ends before num_values values.
+pub fn unpack_u64_adaptive<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    let num_blocks = (num_values / 8) + (num_values % 8 != 0) as usize;
+    let num_flag_bytes = (num_blocks + 7) / 8;
+    if encoded.len() < num_flag_bytes { return Err(CodingError::InputTooShort) }
+    let (flags, mut inbuf) = encoded.split_at(num_flag_bytes);
+    for block in 0..num_blocks {
+        if flags[block / 8] & (1 << (block % 8)) != 0 {
+            if inbuf.len() < RAW_BLOCK_BYTES { return Err(CodingError::InputTooShort) }
+            let mut values = [0u64; 8];
+            for (i, n) in values.iter_mut().enumerate() {
+                *n = u64::from_le_bytes(*array_ref![inbuf, i * 8, 8]);
+            }
+            output.process(u64x8::from_slice_unaligned(&values));
+            inbuf = &inbuf[RAW_BLOCK_BYTES..];
+        } else {
+            inbuf = nibble_unpack8(inbuf, output).map_err(truncated_input)?;
+        }
+    }
+    output.mark_padding((8 - num_values % 8) % 8);
+    Ok(inbuf)
+}
+
+/// Unpacks num_values values written by [`pack_u64_be`]: #method.pack_u64_be, calling output in the same way as
+/// [`unpack`]: #method.unpack.
+pub fn unpack_u64_be<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    let mut values_left = num_values as isize;
+    let mut inbuf = encoded;
+    while values_left > 0 {
+        inbuf = nibble_unpack8_be(inbuf, output).map_err(truncated_input)?;
+        values_left -= 8;
+    }
+    output.mark_padding(-values_left as usize);
+    Ok(inbuf)
+}
+
+// Unpacks one block written by nibble_pack8_be
+fn nibble_unpack8_be<'a, Output: Sink<u64x8>>(inbuf: &'a [u8], output: &mut Output) -> Result<&'a [u8], CodingError> {
+    if inbuf.is_empty() { return Err(CodingError::NotEnoughSpace) }
+    let nonzero_mask = inbuf[0];
+    if nonzero_mask == 0 {
+        output.process(ZERO_U64OCTET);
+        return Ok(&inbuf[1..])
+    }
+    if inbuf.len() < 2 { return Err(CodingError::NotEnoughSpace) }
+    let num_bits = ((inbuf[1] >> 4) as u32 + 1) * 4;
+    let trailing_zeros = (inbuf[1] & 0x0f) as u32 * 4;
+    if num_bits + trailing_zeros > 64 { return Err(CodingError::CorruptHeader(inbuf[1])) }
+    let total_bytes = 2 + (num_bits as usize * nonzero_mask.count_ones() as usize + 7) / 8;
+    if inbuf.len() < total_bytes { return Err(CodingError::NotEnoughSpace) }
+
+    // Values start on a nibble boundary, so a value of up to 64 bits always lies within 9 bytes of its first byte
+    let block = &inbuf[..total_bytes];
+    let mask = nibble_mask(num_bits) as u128;
+    let mut bit_pos = 0;
+    let mut out_array = [0u64; 8];
+    for (bit, out) in out_array.iter_mut().enumerate() {
+        if nonzero_mask & (1 << bit) != 0 {
+            let byte_pos = 2 + bit_pos / 8;
+            let window = ((direct_read_uint_be(block, byte_pos)? as u128) << 8) |
+                         *block.get(byte_pos + 8).unwrap_or(&0) as u128;
+            let value = (window >> (72 - (bit_pos % 8) as u32 - num_bits)) & mask;
+            *out = (value as u64) << trailing_zeros;
+            bit_pos += num_bits as usize;
+        }
+    }
+    output.process(u64x8::from_slice_unaligned(&out_array));
+    Ok(&inbuf[total_bytes..])
+}
+
+/// The most values [`unpack_u64_fixed_width`]: #method.unpack_u64_fixed_width decodes from a width 0 buffer, whose
+/// length says nothing about how many values it holds
+pub const MAX_ZERO_WIDTH_VALUES: usize = u32::MAX as usize;
+
+/// Unpacks num_values values written by [`pack_u64_fixed_width`]: #method.pack_u64_fixed_width, calling output in
+/// the same way as [`unpack`]: #method.unpack.  As every block is the same size, a short buffer is found up front
+/// and is InputTooShort; a width over 64 is InvalidFormat.  A width of 0 takes no bytes at all, so num_values
+/// cannot be checked against the buffer; instead it is InvalidFormat if over [`MAX_ZERO_WIDTH_VALUES`]:
+/// constant.MAX_ZERO_WIDTH_VALUES.
+pub fn unpack_u64_fixed_width<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    let bits = *encoded.first().ok_or(CodingError::InputTooShort)? as u32;
+    if bits > 64 { return Err(CodingError::InvalidFormat(format!("Width of {} bits is over 64", bits))) }
+    let num_blocks = num_values / 8 + (num_values % 8 != 0) as usize;
+    let num_padding = (8 - num_values % 8) % 8;
+    let total_bytes = num_blocks.checked_mul(bits as usize).and_then(|n| n.checked_add(1))
+                                .filter(|&n| n <= encoded.len()).ok_or(CodingError::InputTooShort)?;
+
+    if bits == 0 {
+        if num_values > MAX_ZERO_WIDTH_VALUES {
+            return Err(CodingError::InvalidFormat(
+                format!("{} values of width 0 is over the limit of {}", num_values, MAX_ZERO_WIDTH_VALUES)));
+        }
+        (0..num_blocks).for_each(|_| output.process(ZERO_U64OCTET));
+        output.mark_padding(num_padding);
+        return Ok(&encoded[1..])
+    }
+
+    // Blocks with at least 8 bytes after them are read in place; the last ones are copied out to a zero padded
+    // buffer first, so that every value can be read from the 9 bytes starting at its first byte
+    let data = &encoded[1..total_bytes];
+    let mask = nibble_mask(bits);
+    let mut padded = [0u8; 72];
+    let mut pos = 0;
+    for _ in 0..num_blocks {
+        let block = if pos + bits as usize + 8 <= data.len() {
+            &data[pos..]
+        } else {
+            padded[..bits as usize].copy_from_slice(&data[pos..pos + bits as usize]);
+            &padded[..]
+        };
+        output.process(u64x8::from_slice_unaligned(&unpack8_fixed_width(block, bits, mask)));
+        pos += bits as usize;
+    }
+    output.mark_padding(num_padding);
+    Ok(&encoded[total_bytes..])
+}
+
+// Reads a block of 8 values of bits bits from the start of block, which must have 8 bytes after the block
+#[inline]
+fn unpack8_fixed_width(block: &[u8], bits: u32, mask: u64) -> [u64; 8] {
+    let mut out_array = [0u64; 8];
+    for (i, out) in out_array.iter_mut().enumerate() {
+        let bit_pos = i * bits as usize;
+        let byte_pos = bit_pos / 8;
+        let shift = (bit_pos % 8) as u32;
+        let word = u64::from_le_bytes(*array_ref![block, byte_pos, 8]) >> shift;
+        // A value only spills into a 9th byte if it is wider than 56 bits
+        *out = if shift + bits <= 64 { word } else { word | (block[byte_pos + 8] as u64) << (64 - shift) } & mask;
+    }
+    out_array
+}
+
+/// Unpacks num_values values written by [`pack_versioned`]: #method.pack_versioned, after checking the version
+/// byte.  Returns UnsupportedVersion with the byte found if it is not a version this decoder understands.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::VecSink;
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_versioned([1u64, 2, 3].iter().cloned(), &mut buf, 0).unwrap();
+///     let mut sink = VecSink::<u64>::new();
+///     nibblepacking::unpack_versioned(&buf[..written], &mut sink, 3).unwrap();
+///     assert_eq!(sink.as_slice(), &[1, 2, 3]);
+/// ```
+pub fn unpack_versioned<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    match encoded.first() {
+        None                  => Err(CodingError::InputTooShort),
+        Some(&FORMAT_VERSION) => unpack(&encoded[1..], output, num_values),
+        Some(&version)        => Err(CodingError::UnsupportedVersion(version)),
+    }
+}
+
+/// Unpacks num_values values encoded using [`pack_u64_scaled`]: #method.pack_u64_scaled, shifting each value
+/// back left before passing it to output.
+pub fn unpack_u64_scaled<'a, Output>(
+    encoded: &'a [u8],
+    output: &mut Output,
+    num_values: usize,
+) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    let shift = *encoded.first().ok_or(CodingError::InputTooShort)?;
+    if shift >= 64 {
+        return Err(CodingError::InvalidFormat(format!("Shift {} is too large for u64 values", shift)));
+    }
+    let mut shift_sink = ShiftLeftSink { inner: output, shift: u64x8::splat(shift as u64) };
+    unpack(&encoded[1..], &mut shift_sink, num_values)
+}
+
+// Shifts every value left by a fixed number of bits before forwarding to the inner sink
+#[derive(Debug)]
+struct ShiftLeftSink<'a, S: Sink<u64x8>> {
+    inner: &'a mut S,
+    shift: u64x8,
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u64x8> for ShiftLeftSink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.inner.process(data << self.shift);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.inner.process_zeroes();
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.inner.mark_padding(num_padding);
+    }
+}
+
+/// A sink writing decoded values into a caller provided slice, dropping any padding past its end
+#[derive(Debug)]
+struct SliceSink<'a> {
+    out: &'a mut [u64],
+    pos: usize,
+}
+
+impl<'a> Sink<u64x8> for SliceSink<'a> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let num_values = (self.out.len() - self.pos).min(8);
+        if num_values == 8 {
+            data.write_to_slice_unaligned(&mut self.out[self.pos..self.pos + 8]);
+        } else {
+            for i in 0..num_values {
+                self.out[self.pos + i] = data.extract(i);
+            }
+        }
+        self.pos += num_values;
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(ZERO_U64OCTET);
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+    }
+}
+
+/// Decodes num_values values encoded with [`pack_u64`]: #method.pack_u64 into the start of out, without
+/// allocating.  Returns the rest of encoded after the decoded blocks.
+/// The two ways this can fail are reported distinctly:
+/// * OutputTooShort(num_values, out.len()) if out has room for fewer than num_values values; nothing is decoded
+/// * InputTooShort if encoded ends before num_values values, ie the input was truncated
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let packed = nibblepacking::pack_u64_to_vec([5u64, 6, 7].iter().cloned());
+///     let mut out = [0u64; 3];
+///     nibblepacking::unpack_into(&packed[..], &mut out, 3).unwrap();
+///     assert_eq!(out, [5, 6, 7]);
+/// ```
+pub fn unpack_into<'a>(encoded: &'a [u8], out: &mut [u64], num_values: usize) -> Result<&'a [u8], CodingError> {
+    if out.len() < num_values {
+        return Err(CodingError::OutputTooShort(num_values, out.len()));
+    }
+    let mut sink = SliceSink { out: &mut out[..num_values], pos: 0 };
+    unpack(encoded, &mut sink, num_values)
+}
+
+/// The allocating counterpart of [`pack_u64_to_vec`]: #method.pack_u64_to_vec.  Decodes num_values values
+/// encoded with [`pack_u64`]: #method.pack_u64 into a new Vec of exactly num_values values, with the padding of
+/// the last block already dropped.  Returns InputTooShort if encoded ends before num_values values.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let packed = nibblepacking::pack_u64_to_vec([5u64, 6, 7].iter().cloned());
+///     assert_eq!(nibblepacking::unpack_to_vec(&packed[..], 3).unwrap(), vec![5, 6, 7]);
+/// ```
+pub fn unpack_to_vec(encoded: &[u8], num_values: usize) -> Result<Vec<u64>, CodingError> {
+    check_values_fit(encoded, num_values)?;
+    let mut sink = VecSink::<u64>::with_capacity(num_values);
+    unpack(encoded, &mut sink, num_values)?;
+    let mut values = sink.vec;
+    values.truncate(num_values);
+    Ok(values)
+}
+
+/// Re-packs the first num_values values of encoded into the canonical form written by
+/// [`pack_u64`]: #method.pack_u64, so that equal value sequences always give equal bytes, eg for content
+/// addressed deduplication.  Buffers holding the same values can otherwise differ in several ways, all of which
+/// this irons out:
+/// * nonzero values in the padding of the last block, such as after cutting a buffer short of a block boundary
+/// * blocks packed with more nibbles than their values need, which decode fine but are not what pack_u64 writes
+/// * bytes after the last block, which are dropped
+///
+/// The result always holds exactly num_values / 8 blocks, rounded up.  A canonical buffer normalizes to the same
+/// bytes.  Returns InputTooShort if encoded ends before num_values values.
+pub fn normalize(encoded: &[u8], num_values: usize) -> Result<Vec<u8>, CodingError> {
+    let values = unpack_to_vec(encoded, num_values)?;
+    Ok(pack_u64_to_vec(values.into_iter()))
+}
+
+/// Reconstructs the num_values values of a column from the base and body written by
+/// [`pack_u64_delta_split`]: #method.pack_u64_delta_split.  The body holds the deltas of the num_values - 1
+/// values after the base, which are summed by a DeltaSink seeded with the base.
+pub fn unpack_u64_delta_split(base: u64, body: &[u8], num_values: usize) -> Result<Vec<u64>, CodingError> {
+    if num_values == 0 { return Ok(Vec::new()) }
+    let mut sink = DeltaSink::with_base(base);
+    unpack(body, &mut sink, num_values - 1)?;
+    let mut values = Vec::with_capacity(num_values);
+    values.push(base);
+    values.extend_from_slice(sink.as_slice());
+    Ok(values)
+}
+
+/// Unpacks num_values values packed by [`pack_u64_delta_with_resets`]: #method.pack_u64_delta_with_resets.  Values
+/// are summed as by a DeltaSink, except that at a position set in the reset bitmap the sum restarts from the value
+/// stored there.
+pub fn unpack_u64_delta_with_resets(encoded: &[u8], num_values: usize) -> Result<Vec<u64>, CodingError> {
+    let mut resets = VecSink::<u64>::new();
+    let rest = unpack(encoded, &mut resets, (num_values + 7) / 8)?;
+    check_values_fit(rest, num_values)?;
+    let mut sink = VecSink::<u64>::with_capacity(num_values);
+    unpack(rest, &mut sink, num_values)?;
+
+    let resets = resets.as_slice();
+    let mut last = 0u64;
+    Ok(sink.as_slice().iter().enumerate().map(|(pos, &value)| {
+        last = if resets[pos / 8] & (1 << (pos % 8)) != 0 { value } else { last.wrapping_add(value) };
+        last
+    }).collect())
+}
+
+/// Unpacks num_values values packed by [`pack_u64_double_delta`]: #method.pack_u64_double_delta.  A
+/// ZigZagDeltaSink sums the changes back into deltas, which are then summed into the values.
+pub fn unpack_u64_double_delta(encoded: &[u8], num_values: usize) -> Result<Vec<u64>, CodingError> {
+    check_values_fit(encoded, num_values)?;
+    let mut sink = ZigZagDeltaSink::with_sink(VecSink::<u64>::with_capacity(num_values));
+    unpack(encoded, &mut sink, num_values)?;
+    let mut last = 0u64;
+    Ok(sink.as_slice().iter().map(|&delta| {
+        last = last.wrapping_add(delta);
+        last
+    }).collect())
+}
+
+/// Unpacks num_values timestamps packed by [`pack_timestamps_auto_unit`]: #method.pack_timestamps_auto_unit
+pub fn unpack_timestamps_auto_unit(encoded: &[u8], num_values: usize) -> Result<Vec<u64>, CodingError> {
+    if encoded.len() < 8 { return Err(CodingError::InputTooShort) }
+    let divisor = direct_read_uint_le(encoded, 0)?;
+    let mut values = unpack_u64_double_delta(&encoded[8..], num_values)?;
+    for n in values.iter_mut() {
+        *n = n.wrapping_mul(divisor);
+    }
+    Ok(values)
+}
+
+/// Unpacks num_values booleans packed by [`pack_bool_rle`]: #method.pack_bool_rle.  Returns InvalidFormat if the
+/// first byte is not 0 or 1, or the transitions are not increasing positions within num_values.
+pub fn unpack_bool_rle(encoded: &[u8], num_values: usize) -> Result<Vec<bool>, CodingError> {
+    let mut value = match encoded.first() {
+        Some(&first) if first <= 1 => first == 1,
+        Some(&first) => return Err(CodingError::InvalidFormat(format!("First value byte {} is not 0 or 1", first))),
+        None => return Err(CodingError::InputTooShort),
+    };
+    let (num_transitions, pos) = read_varint(encoded, 1)?;
+    let mut sink = DeltaSink::new();
+    unpack(&encoded[pos..], &mut sink, num_transitions as usize)?;
+
+    let mut values = Vec::with_capacity(num_values);
+    for &transition in &sink.as_slice()[..num_transitions as usize] {
+        if transition <= values.len() as u64 || transition >= num_values as u64 {
+            return Err(CodingError::InvalidFormat(format!("Transition at {} is out of order or past {} values",
+                                                          transition, num_values)));
+        }
+        values.resize(transition as usize, value);
+        value = !value;
+    }
+    values.resize(num_values, value);
+    Ok(values)
+}
+
+/// Lazily merges two columns packed by [`pack_u64_delta`]: #method.pack_u64_delta, such as the timestamps of two
+/// shards, into one sorted stream.  Both columns must be increasing, as pack_u64_delta requires anyway, so this is
+/// a standard two way merge; equal values are all kept, the ones from a first.  Each column is decoded and summed
+/// one block of 8 at a time as the merge reaches it, the same as a DeltaSink would, so neither column is ever
+/// decoded in full and memory use stays constant however long they are.
+///
+/// A decoding error in either column ends the merged stream early; check
+/// [`MergeDeltaIter::error`]: struct.MergeDeltaIter.html#method.error afterwards.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let mut a_buf = [0u8; 256];
+///     let mut b_buf = [0u8; 256];
+///     let a_len = nibblepacking::pack_u64_delta(&[100u64, 200, 300], &mut a_buf).unwrap();
+///     let b_len = nibblepacking::pack_u64_delta(&[150u64, 250], &mut b_buf).unwrap();
+///     let merged: Vec<u64> = nibblepacking::merge_delta(&a_buf[..a_len], 3, &b_buf[..b_len], 2).collect();
+///     assert_eq!(merged, vec![100, 150, 200, 250, 300]);
+/// ```
+pub fn merge_delta<'a>(a: &'a [u8], a_len: usize, b: &'a [u8], b_len: usize) -> MergeDeltaIter<'a> {
+    let mut a = DeltaIter::new(a, a_len);
+    let mut b = DeltaIter::new(b, b_len);
+    let (next_a, next_b) = (a.next(), b.next());
+    MergeDeltaIter { a, b, next_a, next_b }
+}
+
+/// The iterator returned by [`merge_delta`]: fn.merge_delta.html
+#[derive(Debug)]
+pub struct MergeDeltaIter<'a> {
+    a: DeltaIter<'a>,
+    b: DeltaIter<'a>,
+    next_a: Option<u64>,
+    next_b: Option<u64>,
+}
+
+impl<'a> MergeDeltaIter<'a> {
+    /// The error which ended the merge early, if either column could not be decoded
+    pub fn error(&self) -> Option<&CodingError> {
+        self.a.error.as_ref().or(self.b.error.as_ref())
+    }
+}
+
+impl<'a> Iterator for MergeDeltaIter<'a> {
+    type Item = u64;
+
+    #[inline]
+    fn next(&mut self) -> Option<u64> {
+        // Stop at the first error rather than carry on with only the other column, which would not be the merge
+        if self.error().is_some() { return None }
+        match (self.next_a, self.next_b) {
+            (Some(a), Some(b)) if b < a => { self.next_b = self.b.next(); Some(b) },
+            (Some(a), _)                => { self.next_a = self.a.next(); Some(a) },
+            (None, Some(b))             => { self.next_b = self.b.next(); Some(b) },
+            (None, None)                => None,
+        }
+    }
+}
+
+/// A column packed with [`pack_u64_delta`]: #method.pack_u64_delta which can be read at random.  Delta encoded
+/// values normally have to be decoded from the start, since each value is the sum of all the deltas before it.
+/// Here pack() makes one more pass over the packed column to record a checkpoint for each block: its byte
+/// offset and the value just before it.  Then get(i) decodes only the block holding i, adding its deltas to the
+/// checkpoint, so random access costs one block decode whatever the position.  As nothing is built lazily, a
+/// PackedDelta can be shared between threads.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::PackedDelta;
+///     let values: Vec<u64> = (0..1000).map(|i| 1_600_000_000 + i * 15).collect();
+///     let column = PackedDelta::pack(&values).unwrap();
+///     assert_eq!(column.get(777), Some(values[777]));
+///     assert_eq!(column.get(1000), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PackedDelta {
+    bytes: Vec<u8>,
+    num_values: usize,
+    checkpoints: Vec<(usize, u64)>,     // (byte offset, value before) for each block
+}
+
+impl PackedDelta {
+    /// Packs values, or returns InvalidFormat if a value is less than the one before it, since pack_u64_delta
+    /// would clip the decrease to zero and get() would then return the wrong values.
+    pub fn pack(values: &[u64]) -> Result<Self, CodingError> {
+        if let Some(pos) = values.windows(2).position(|pair| pair[1] < pair[0]) {
+            return Err(CodingError::InvalidFormat(format!("Value at {} is less than the one before", pos + 1)));
+        }
+        let bytes = pack_u64_delta_to_vec(values);
+        let checkpoints = delta_checkpoints(&bytes, values.len())?.into_iter()
+                                                                  .map(|(offset, before, _)| (offset, before))
+                                                                  .collect();
+        Ok(Self { bytes, num_values: values.len(), checkpoints })
+    }
+
+    /// Returns the value at index, or None if index is past the end
+    pub fn get(&self, index: usize) -> Option<u64> {
+        if index >= self.num_values { return None }
+        let block = index / 8;
+        let (offset, before) = self.checkpoints[block];
+        DeltaIter::starting_at(&self.bytes[offset..], self.num_values - block * 8, before).nth(index % 8)
+    }
+
+    packed_accessors!("The packed bytes, which decode with a DeltaSink");
+}
+
+/// A sorted set of u64s, such as a posting list, packed with [`pack_u64_delta`]: #method.pack_u64_delta and
+/// queryable without decoding it.  Alongside the bytes is an index with the byte offset, the value before and the
+/// first value of each block.  contains() binary searches the first values for the one block which could hold
+/// the value, then decodes only that block.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::PackedSet;
+///     let doc_ids: Vec<u64> = (0..1000).map(|i| i * 7 + 3).collect();
+///     let set = PackedSet::new(&doc_ids).unwrap();
+///     assert!(set.contains(7 * 500 + 3));
+///     assert!(!set.contains(7 * 500 + 4));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PackedSet {
+    bytes: Vec<u8>,
+    num_values: usize,
+    blocks: Vec<(usize, u64, u64)>,    // (byte offset, value before, first value) for each block
+}
+
+impl PackedSet {
+    /// Packs sorted values and builds the block index.  Repeated values are kept but do no harm.
+    /// Returns InvalidFormat if a value is less than the one before it.
+    pub fn new(sorted: &[u64]) -> Result<Self, CodingError> {
+        if let Some(pos) = sorted.windows(2).position(|pair| pair[1] < pair[0]) {
+            return Err(CodingError::InvalidFormat(format!("Value at {} is less than the one before", pos + 1)));
+        }
+        let bytes = pack_u64_delta_to_vec(sorted);
+        let blocks = delta_checkpoints(&bytes, sorted.len())?;
+        Ok(Self { bytes, num_values: sorted.len(), blocks })
+    }
+
+    /// Whether value is in the set.  Decodes at most one block.
+    pub fn contains(&self, value: u64) -> bool {
+        // The candidate is the last block starting at or below value
+        let block = match self.blocks.partition_point(|&(_, _, first)| first <= value) {
+            0 => return false,
+            n => n - 1,
+        };
+        let (offset, before, _) = self.blocks[block];
+        let num_valid = (self.num_values - block * 8).min(8);
+        DeltaIter::starting_at(&self.bytes[offset..], num_valid, before).any(|n| n == value)
+    }
+
+    // len() counts repeated values
+    packed_accessors!("The packed bytes, which decode with a DeltaSink");
+}
+
+// Returns (byte offset, value before, first value) for each block of num_values delta packed values
+fn delta_checkpoints(encoded: &[u8], num_values: usize) -> Result<Vec<(usize, u64, u64)>, CodingError> {
+    let mut checkpoints = Vec::with_capacity((num_values + 7) / 8);
+    let mut values = DeltaIter::new(encoded, num_values);
+    let mut before = 0;
+    loop {
+        // Every 8th value starts a block, so here the iterator is always at a block boundary
+        let offset = encoded.len() - values.inbuf.len();
+        let first = match values.next() {
+            Some(first) => first,
+            None        => break,
+        };
+        checkpoints.push((offset, before, first));
+        before = values.by_ref().take(7).last().unwrap_or(first);
+    }
+    match values.error {
+        Some(err) => Err(err),
+        None      => Ok(checkpoints),
+    }
+}
+
+// Decodes a delta packed column one block at a time, yielding the running sums
+#[derive(Debug)]
+struct DeltaIter<'a> {
+    inbuf: &'a [u8],
+    values_left: usize,
+    acc: u64,
+    block: [u64; 8],
+    pos: usize,
+    error: Option<CodingError>,
+}
+
+impl<'a> DeltaIter<'a> {
+    fn new(encoded: &'a [u8], num_values: usize) -> Self {
+        Self::starting_at(encoded, num_values, 0)
+    }
+
+    // Starts partway through a column, at a block boundary, with before the value just before that block
+    fn starting_at(encoded: &'a [u8], num_values: usize, before: u64) -> Self {
+        Self { inbuf: encoded, values_left: num_values, acc: before, block: [0; 8], pos: 8, error: None }
+    }
+}
+
+impl<'a> Iterator for DeltaIter<'a> {
+    type Item = u64;
+
+    #[inline]
+    fn next(&mut self) -> Option<u64> {
+        if self.values_left == 0 || self.error.is_some() { return None }
+        if self.pos == 8 {
+            let mut sink = OctetSink { values: ZERO_U64OCTET };
+            match nibble_unpack8(self.inbuf, &mut sink) {
+                Ok(rest) => self.inbuf = rest,
+                Err(CodingError::NotEnoughSpace) => { self.error = Some(CodingError::InputTooShort); return None },
+                Err(err) => { self.error = Some(err); return None },
+            }
+            sink.values.write_to_slice_unaligned(&mut self.block);
+            self.pos = 0;
+        }
+        self.acc = self.acc.wrapping_add(self.block[self.pos]);
+        self.pos += 1;
+        self.values_left -= 1;
+        Some(self.acc)
+    }
+}
+
+/// Whether a [`Decoder`](struct.Decoder.html) has decoded every block or has more to go
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DecodeProgress {
+    Done,
+    More,
+}
+
+/// A resumable version of [`unpack`]: #method.unpack, which decodes a bounded number of blocks per call so
+/// that a long decode can be spread over several turns of an event loop, yielding in between.  All of the state
+/// needed to carry on is kept here: the byte offset of the next block, the number of values left, and the sink,
+/// which keeps its own running state such as the DeltaSink accumulator.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::{self, Decoder, DecodeProgress, DeltaSink};
+///     let values: Vec<u64> = (0..100).map(|i| 1000 + i * 3).collect();
+///     let mut buf = [0u8; 1024];
+///     let written = nibblepacking::pack_u64_delta(&values[..], &mut buf).unwrap();
+///
+///     let mut decoder = Decoder::new(&buf[..written], DeltaSink::new(), values.len());
+///     while decoder.decode_blocks(4).unwrap() == DecodeProgress::More {
+///         // yield to other tasks here
+///     }
+///     assert_eq!(decoder.into_sink().as_slice(), &values[..]);
+/// ```
+#[derive(Debug)]
+pub struct Decoder<'a, S: Sink<u64x8>> {
+    encoded: &'a [u8],
+    offset: usize,
+    values_left: usize,
+    sink: S,
+}
+
+impl<'a, S: Sink<u64x8>> Decoder<'a, S> {
+    /// Creates a Decoder for num_values values at the start of encoded, decoding into sink
+    pub fn new(encoded: &'a [u8], sink: S, num_values: usize) -> Self {
+        Self { encoded, offset: 0, values_left: num_values, sink }
+    }
+
+    /// Decodes up to max_blocks more blocks of 8 values into the sink, returning Done once all num_values
+    /// values have been decoded, after which the padding is marked and further calls do nothing.
+    /// On an error the offset is left at the start of the block which failed; as with [`unpack`]: #method.unpack,
+    /// input which ends partway through a block is InputTooShort.
+    pub fn decode_blocks(&mut self, max_blocks: usize) -> Result<DecodeProgress, CodingError> {
+        for _ in 0..max_blocks {
+            if self.values_left == 0 { break }
+            let rest = nibble_unpack8(&self.encoded[self.offset..], &mut self.sink).map_err(truncated_input)?;
+            self.offset = self.encoded.len() - rest.len();
+            if self.values_left <= 8 {
+                self.sink.mark_padding(8 - self.values_left);
+                self.values_left = 0;
+            } else {
+                self.values_left -= 8;
+            }
+        }
+        Ok(if self.values_left == 0 { DecodeProgress::Done } else { DecodeProgress::More })
+    }
+
+    /// The byte offset within encoded of the next block to decode, or of the end of the blocks once done
+    pub fn offset(&self) -> usize { self.offset }
+
+    /// The number of values not decoded yet
+    pub fn values_left(&self) -> usize { self.values_left }
+
+    /// The rest of encoded after the blocks decoded so far
+    pub fn remaining(&self) -> &'a [u8] { &self.encoded[self.offset..] }
+
+    pub fn sink(&self) -> &S { &self.sink }
+
+    pub fn sink_mut(&mut self) -> &mut S { &mut self.sink }
+
+    pub fn into_sink(self) -> S { self.sink }
+}
+
+/// A sink which forwards only the values whose position falls within [start, end) to an inner sink,
+/// regrouping them into octets so that the inner sink sees the range as if it started at position 0.
+#[derive(Debug)]
+struct RangeSink<'a, S: Sink<u64x8>> {
+    inner: &'a mut S,
+    pos: usize,
+    start: usize,
+    end: usize,
+    buf: [u64; 8],
+    buf_len: usize,
+}
+
+impl<'a, S: Sink<u64x8>> RangeSink<'a, S> {
+    fn new(inner: &'a mut S, first_pos: usize, start: usize, end: usize) -> Self {
+        Self { inner, pos: first_pos, start, end, buf: [0u64; 8], buf_len: 0 }
+    }
+
+    /// Forwards any remaining values as a final padded octet
+    fn finish(&mut self) {
+        if self.buf_len > 0 {
+            let num_padding = 8 - self.buf_len;
+            self.buf[self.buf_len..].iter_mut().for_each(|x| *x = 0);
+            self.inner.process(u64x8::from_slice_unaligned(&self.buf));
+            self.buf_len = 0;
+            self.inner.mark_padding(num_padding);
+        } else {
+            self.inner.mark_padding(0);
+        }
+    }
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u64x8> for RangeSink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        // Fast path: whole octet in range and aligned with the output octets
+        if self.buf_len == 0 && self.pos >= self.start && self.pos + 8 <= self.end {
+            self.inner.process(data);
+        } else {
+            for i in 0..8 {
+                let idx = self.pos + i;
+                if idx >= self.start && idx < self.end {
+                    self.buf[self.buf_len] = data.extract(i);
+                    self.buf_len += 1;
+                    if self.buf_len == 8 {
+                        self.inner.process(u64x8::from_slice_unaligned(&self.buf));
+                        self.buf_len = 0;
+                    }
+                }
+            }
+        }
+        self.pos += 8;
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(ZERO_U64OCTET);
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// A sink which turns deltas into running totals before passing them on, for range decoding delta encoded data
+#[derive(Debug)]
+struct PrefixSumSink<'a, S: Sink<u64x8>> {
+    acc: u64,
+    inner: &'a mut S,
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u64x8> for PrefixSumSink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let buf = DeltaSink::accumulate(&mut self.acc, data);
+        self.inner.process(buf);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.inner.process(u64x8::splat(self.acc));
+    }
+
+    fn reset(&mut self) {
+        self.acc = 0;
+    }
+}
+
+fn check_range(start: usize, end: usize) -> Result<(), CodingError> {
+    if start > end {
+        Err(CodingError::InvalidFormat(format!("Range start {} is past end {}", start, end)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Decodes only the values at positions [start, end) of a buffer encoded with [`pack_u64`]: #method.pack_u64.
+/// Blocks before start are skipped without being decoded, and only the blocks overlapping the range are
+/// unpacked.  The output sink receives just the values in the range, grouped into octets starting from start,
+/// followed by a mark_padding() call for the last octet.
+/// Returns the remainder of the buffer after the last decoded block.
+pub fn range_decode<'a, Output>(encoded: &'a [u8],
+                                start: usize,
+                                end: usize,
+                                output: &mut Output) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    check_range(start, end)?;
+    let skip_blocks = start / 8;
+    let skipped_bytes = walk_blocks(encoded, skip_blocks, |_, _| {})?;
+    let inbuf = &encoded[skipped_bytes..];
+
+    let first_pos = skip_blocks * 8;
+    let mut range_sink = RangeSink::new(output, first_pos, start, end);
+    let rest = unpack(inbuf, &mut range_sink, end.saturating_sub(first_pos))?;
+    range_sink.finish();
+    Ok(rest)
+}
+
+/// Decodes only the values at positions [start, end) of a buffer encoded with
+/// [`pack_u64_delta`]: #method.pack_u64_delta, passing the reconstructed (not delta) values to the output.
+/// Since every value depends on all the deltas before it, the blocks before start are still decoded to
+/// accumulate the true base, but only values in the range are forwarded, as in [`range_decode`]: #method.range_decode
+pub fn range_decode_delta<'a, Output>(encoded: &'a [u8],
+                                      start: usize,
+                                      end: usize,
+                                      output: &mut Output) -> Result<&'a [u8], CodingError>
+where Output: Sink<u64x8> {
+    check_range(start, end)?;
+    let mut range_sink = RangeSink::new(output, 0, start, end);
+    let rest = {
+        let mut sum_sink = PrefixSumSink { acc: 0, inner: &mut range_sink };
+        unpack(encoded, &mut sum_sink, end)?
+    };
+    range_sink.finish();
+    Ok(rest)
+}
+
+/// A sink which adds up delta encoded octets lane by lane, remembering the very first delta
+#[derive(Debug)]
+struct DeltaSumSink {
+    first: Option<u64>,
+    lane_sums: u64x8,
+}
+
+impl Sink<u64x8> for DeltaSumSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        if self.first.is_none() { self.first = Some(data.extract(0)) }
+        self.lane_sums += data;
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        if self.first.is_none() { self.first = Some(0) }
+    }
+
+    fn reset(&mut self) {
+        self.first = None;
+        self.lane_sums = ZERO_U64OCTET;
+    }
+}
+
+fn sum_deltas(encoded: &[u8], num_values: usize) -> Result<DeltaSumSink, CodingError> {
+    let mut sink = DeltaSumSink { first: None, lane_sums: ZERO_U64OCTET };
+    unpack(encoded, &mut sink, num_values)?;
+    Ok(sink)
+}
+
+/// Returns the last of num_values values encoded with [`pack_u64_delta`]: #method.pack_u64_delta, which is the
+/// sum of all the deltas, without materializing the column.  The deltas are summed 8 lanes at a time and the
+/// padding deltas are zero, so this is just an unpack plus a SIMD add per block.  Returns 0 if num_values is 0.
+pub fn decode_delta_sum(encoded: &[u8], num_values: usize) -> Result<u64, CodingError> {
+    Ok(sum_deltas(encoded, num_values)?.lane_sums.wrapping_sum())
+}
+
+/// A sink which keeps the first, running total and max of delta encoded octets.  Each block's running totals are
+/// a parallel prefix sum, as in [`prefix_xor_simd`]: #method.prefix_xor_simd, so the max is one SIMD reduction.
+#[derive(Debug)]
+struct DeltaStatsSink {
+    first: Option<u64>,
+    acc: u64,
+    max: u64,
+}
+
+impl Sink<u64x8> for DeltaStatsSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        if self.first.is_none() { self.first = Some(data.extract(0)) }
+        let x = data + shuffle!(ZERO_U64OCTET, data, [0, 8, 9, 10, 11, 12, 13, 14]);
+        let x = x + shuffle!(ZERO_U64OCTET, x, [0, 1, 8, 9, 10, 11, 12, 13]);
+        let x = x + shuffle!(ZERO_U64OCTET, x, [0, 1, 2, 3, 8, 9, 10, 11]);
+        let values = x + u64x8::splat(self.acc);
+        self.max = self.max.max(values.max_element());
+        self.acc = values.extract(7);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(ZERO_U64OCTET);
+    }
+
+    fn reset(&mut self) {
+        self.first = None;
+        self.acc = 0;
+        self.max = 0;
+    }
+}
+
+/// Returns (first, last, max, count) of num_values values encoded with [`pack_u64_delta`]: #method.pack_u64_delta
+/// in one pass, without materializing the column.  Useful for counter aggregates such as rate = (last - first).
+/// The values are summed with wrapping adds, and max is the largest of them.  As pack_u64_delta clips decreases,
+/// a column it packed never decreases and max is the same as last; they differ only if the deltas wrap past
+/// u64::MAX.  For num_values of 0, returns all zeroes.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let counters = [100u64, 105, 105, 130, 170];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64_delta(&counters, &mut buf).unwrap();
+///     let stats = nibblepacking::decode_delta_stats(&buf[..written], counters.len()).unwrap();
+///     assert_eq!(stats, (100, 170, 170, 5));
+/// ```
+pub fn decode_delta_stats(encoded: &[u8], num_values: usize) -> Result<(u64, u64, u64, usize), CodingError> {
+    let mut sink = DeltaStatsSink { first: None, acc: 0, max: 0 };
+    unpack(encoded, &mut sink, num_values)?;
+    Ok((sink.first.unwrap_or(0), sink.acc, sink.max, num_values))
+}
+
+/// Unpacks num_values f64 values encoded using [`pack_f64_fixed`]: #method.pack_f64_fixed, dividing by the
+/// scale stored at the start of the buffer.
+pub fn unpack_f64_fixed(encoded: &[u8], num_values: usize) -> Result<Vec<f64>, CodingError> {
+    if encoded.len() < 8 { return Err(CodingError::InputTooShort) }
+    let scale = f64::from_bits(direct_read_uint_le(encoded, 0)?);
+    let mut sink = ZigZagDeltaSink::new();
+    unpack(&encoded[8..], &mut sink, num_values)?;
+    Ok(sink.as_slice().iter().map(|&n| n as i64 as f64 / scale).collect())
+}
+
+/// Unpacks num_values f64 values encoded using [`pack_f64_monotonic`]: #method.pack_f64_monotonic.
+pub fn unpack_f64_monotonic(encoded: &[u8], num_values: usize) -> Result<Vec<f64>, CodingError> {
+    if encoded.len() < 16 { return Err(CodingError::InputTooShort) }
+    let scale = f64::from_bits(direct_read_uint_le(encoded, 0)?);
+    let first = direct_read_uint_le(encoded, 8)?;
+    if num_values == 0 { return Ok(Vec::new()) }
+    check_values_fit(&encoded[16..], num_values - 1)?;
+    let mut sink = DeltaSink::with_capacity(num_values - 1);
+    unpack(&encoded[16..], &mut sink, num_values - 1)?;
+    Ok(std::iter::once(0).chain(sink.as_slice().iter().cloned())
+        .map(|n| first.wrapping_add(n) as i64 as f64 / scale)
+        .collect())
+}
+
+/// A sink which just holds on to the last 8 values it was given
+#[derive(Debug)]
+pub(crate) struct OctetSink {
+    pub(crate) values: u64x8,
+}
+
+impl Sink<u64x8> for OctetSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.values = data;
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.values = ZERO_U64OCTET;
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Returns true if two NibblePacked buffers, holding a_len and b_len values, encode the same values.
+/// Identical bytes are only validated, not decoded and compared.  Otherwise the buffers are decoded one block at a time
+/// and compared, stopping at the first difference; padding values past the end of either buffer are ignored.
+/// A buffer which is too short or cannot be decoded never compares equal.
+pub fn packed_values_eq(a: &[u8], a_len: usize, b: &[u8], b_len: usize) -> bool {
+    if a_len != b_len { return false }
+    if a == b { return validate(a, a_len).is_ok() }
+
+    let mut a_buf = a;
+    let mut b_buf = b;
+    let mut a_sink = OctetSink { values: ZERO_U64OCTET };
+    let mut b_sink = OctetSink { values: ZERO_U64OCTET };
+    let mut values_left = a_len;
+    while values_left > 0 {
+        a_buf = match nibble_unpack8(a_buf, &mut a_sink) {
+            Ok(rest) => rest,
+            Err(_)   => return false,
+        };
+        b_buf = match nibble_unpack8(b_buf, &mut b_sink) {
+            Ok(rest) => rest,
+            Err(_)   => return false,
+        };
+        let num_valid = values_left.min(8);
+        let diff_mask = !a_sink.values.eq(b_sink.values).bitmask();
+        if diff_mask & (0xffu16 >> (8 - num_valid)) as u8 != 0 { return false }
+        values_left -= num_valid;
+    }
+    true
+}
+
+/// Decodes num_values values one block at a time into an [u64; 8] on the stack, calling f(block, valid) for
+/// each block, where valid is the number of real values at the start of the block: 8, except for the last
+/// block where the rest are padding.  Nothing is allocated and no sink is involved, which makes this the
+/// lightest way to run a closure over a column in a hot loop.
+/// Returns the rest of encoded after the last block, or an error as for [`unpack`]: #method.unpack.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let packed = nibblepacking::pack_u64_to_vec((1..=10u64).map(|n| n * n));
+///     let mut sum = 0;
+///     nibblepacking::for_each_block(&packed, 10, |block, valid| sum += block[..valid].iter().sum::<u64>()).unwrap();
+///     assert_eq!(sum, 385);
+/// ```
+#[inline]
+pub fn for_each_block<F>(encoded: &[u8], num_values: usize, mut f: F) -> Result<&[u8], CodingError>
+where F: FnMut(&[u64; 8], usize) {
+    let mut sink = OctetSink { values: ZERO_U64OCTET };
+    let mut block = [0u64; 8];
+    let mut values_left = num_values;
+    let mut inbuf = encoded;
+    while values_left > 0 {
+        inbuf = nibble_unpack8(inbuf, &mut sink).map_err(truncated_input)?;
+        sink.values.write_to_slice_unaligned(&mut block);
+        let valid = values_left.min(8);
+        f(&block, valid);
+        values_left -= valid;
+    }
+    Ok(inbuf)
+}
+
+/// Unpacks exactly N values into an array on the stack, avoiding any allocation for small fixed size decodes.
+/// N does not need to be a multiple of 8; extra values decoded from the last block are dropped.
+/// Returns the array and the remainder of the buffer after the last decoded block.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let mut buf = [0u8; 64];
+///     let written = nibblepacking::pack_u64([1u64, 2, 3].iter().cloned(), &mut buf, 0).unwrap();
+///     let (values, _rest) = nibblepacking::unpack_array::<3>(&buf[..written]).unwrap();
+///     assert_eq!(values, [1u64, 2, 3]);
+/// ```
+pub fn unpack_array<const N: usize>(encoded: &[u8]) -> Result<([u64; N], &[u8]), CodingError> {
+    let mut out = [0u64; N];
+    let mut sink = OctetSink { values: ZERO_U64OCTET };
+    let mut octet = [0u64; 8];
+    let mut inbuf = encoded;
+    for chunk in out.chunks_mut(8) {
+        inbuf = nibble_unpack8(inbuf, &mut sink).map_err(truncated_input)?;
+        sink.values.write_to_slice_unaligned(&mut octet);
+        chunk.copy_from_slice(&octet[..chunk.len()]);
+    }
+    Ok((out, inbuf))
+}
+
+/// An iterator over just the nonzero values of a NibblePacked buffer holding num_values values, yielding
+/// (index, value) pairs in order.  All-zero blocks are stepped over by their 1 byte header without decoding, and
+/// within a block only the set bits of the nonzero bitmask are visited, so the work done is proportional to the
+/// number of nonzero values plus the number of blocks.  This is the fastest way to process a sparse column.
+///
+/// A decoding error, such as the buffer being too short, ends the iteration early; check
+/// [`error`]: #method.error afterwards to tell that apart from the end of the values.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let inputs = [0u64, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 42];
+///     let packed = nibblepacking::pack_u64_to_vec(inputs.iter().cloned());
+///     let pairs: Vec<(usize, u64)> = nibblepacking::SparseUnpackIter::new(&packed, inputs.len()).collect();
+///     assert_eq!(pairs, vec![(2, 7), (11, 42)]);
+/// ```
+#[derive(Debug)]
+pub struct SparseUnpackIter<'a> {
+    inbuf: &'a [u8],
+    num_values: usize,
+    next_start: usize,      // Index of the first value of the next block to decode
+    values: [u64; 8],
+    mask: u8,               // Bits of the current block's nonzero values not yet yielded
+    error: Option<CodingError>,
+}
+
+impl<'a> SparseUnpackIter<'a> {
+    pub fn new(encoded: &'a [u8], num_values: usize) -> Self {
+        Self { inbuf: encoded, num_values, next_start: 0, values: [0; 8], mask: 0, error: None }
+    }
+
+    /// The error which ended the iteration early, if any
+    pub fn error(&self) -> Option<&CodingError> {
+        self.error.as_ref()
+    }
+
+    // Moves to the next block with any nonzero values, decoding it.  Returns false at the end or on error.
+    fn next_nonzero_block(&mut self) -> bool {
+        while self.next_start < self.num_values {
+            self.next_start += 8;
+            match self.inbuf.first() {
+                None     => return self.fail(CodingError::InputTooShort),
+                Some(&0) => self.inbuf = &self.inbuf[1..],
+                Some(&mask) => {
+                    let mut sink = OctetSink { values: ZERO_U64OCTET };
+                    match nibble_unpack8(self.inbuf, &mut sink) {
+                        Ok(rest) => self.inbuf = rest,
+                        Err(CodingError::NotEnoughSpace) => return self.fail(CodingError::InputTooShort),
+                        Err(err) => return self.fail(err),
+                    }
+                    sink.values.write_to_slice_unaligned(&mut self.values);
+                    self.mask = mask;
+                    return true;
+                },
+            }
+        }
+        false
+    }
+
+    fn fail(&mut self, err: CodingError) -> bool {
+        self.error = Some(err);
+        false
+    }
+}
+
+impl<'a> Iterator for SparseUnpackIter<'a> {
+    type Item = (usize, u64);
+
+    #[inline]
+    fn next(&mut self) -> Option<(usize, u64)> {
+        while self.mask == 0 {
+            if !self.next_nonzero_block() { return None }
+        }
+        let bit = self.mask.trailing_zeros() as usize;
+        self.mask &= self.mask - 1;
+        let index = self.next_start - 8 + bit;
+        // Padding in the last block is normally zero, but do not yield it if it is not
+        if index >= self.num_values {
+            self.mask = 0;
+            return None
+        }
+        Some((index, self.values[bit]))
+    }
+}
+
+/// Unpacks a buffer encoded with [`pack_f64_xor`]: #method.pack_f64_xor
+///
+/// This wraps unpack() method with a read of the initial f64 value. InputTooShort error is returned
+/// if the input does not have enough bytes given the number of values read.
+/// NOTE: the sink is automatically cleared at the beginning, unless it is in append mode, and ends up holding
+/// exactly num_values values, without padding.
+/// A single value is just the initial f64, with no blocks after it.  Unpacking zero values reads nothing
+/// and leaves the sink empty, just like unpack().
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # let encoded = [0xffu8; 16];
+///     let mut out = Vec::<f64>::with_capacity(64);
+///     let mut sink = nibblepacking::DoubleXorSink::new(out);
+///     let res = nibblepacking::unpack_f64_xor(&encoded[..], &mut sink, 16);
+/// ```
+pub fn unpack_f64_xor<'a, V: BorrowMut<Vec<f64>>>(encoded: &'a [u8],
+                          sink: &mut DoubleXorSink<V>,
+                          num_values: usize) -> Result<&'a [u8], CodingError> {
+    if num_values == 0 {
+        sink.reset();
+        return Ok(encoded);
+    }
+    if encoded.len() < 8 { return Err(CodingError::InputTooShort) }
+    let init_value = direct_read_uint_le(encoded, 0)?;
+    sink.reset_with_initial(init_value);
+
+    unpack(&encoded[8..], sink, num_values - 1)
+}
+
+/// Unpacks num_values f64's written by [`pack_f64_xor_windowed`]: #method.pack_f64_xor_windowed, reading the
+/// flag of each block to know whether it has its own nibbles byte or reuses the last one seen.
+/// Returns InvalidFormat if a block is flagged before any block has a nibbles byte.
+pub fn unpack_f64_xor_windowed<'a, V: BorrowMut<Vec<f64>>>(encoded: &'a [u8],
+                                                           sink: &mut DoubleXorSink<V>,
+                                                           num_values: usize) -> Result<&'a [u8], CodingError> {
+    if num_values == 0 {
+        sink.reset();
+        return Ok(encoded);
+    }
+    let num_blocks = (num_values - 1 + 7) / 8;
+    let flags_end = 8 + (num_blocks + 7) / 8;
+    if encoded.len() < flags_end { return Err(CodingError::InputTooShort) }
+    sink.reset_with_initial(direct_read_uint_le(encoded, 0)?);
+
+    let flags = &encoded[8..flags_end];
+    let mut inbuf = &encoded[flags_end..];
+    let mut window_byte = None;
+    for block in 0..num_blocks {
+        inbuf = if flags[block / 8] & (1 << (block % 8)) != 0 {
+            let nibbles_byte = window_byte.ok_or_else(|| CodingError::InvalidFormat(
+                format!("Block {} reuses a window before any was written", block)))?;
+            nibble_unpack8_with_window(inbuf, nibbles_byte, sink).map_err(truncated_input)?
+        } else {
+            if inbuf.len() >= 2 && inbuf[0] != 0 { window_byte = Some(inbuf[1]); }
+            nibble_unpack8(inbuf, sink).map_err(truncated_input)?
+        };
+    }
+    sink.mark_padding(num_blocks * 8 - (num_values - 1));
+    Ok(inbuf)
+}
+
+// Unpacks a block written without its nibbles byte, by copying it after the given one so nibble_unpack8 can decode it
+fn nibble_unpack8_with_window<'a, Output: Sink<u64x8>>(inbuf: &'a [u8],
+                                                       nibbles_byte: u8,
+                                                       output: &mut Output) -> Result<&'a [u8], CodingError> {
+    if inbuf.len() < 2 || inbuf[0] == 0 { return nibble_unpack8(inbuf, output) }
+    // The longest block is the bitmask and nibbles bytes, then 8 values of 16 nibbles
+    let mut block = [0u8; 2 + 64];
+    let num_bytes = (inbuf.len() - 1).min(64);
+    block[0] = inbuf[0];
+    block[1] = nibbles_byte;
+    block[2..2 + num_bytes].copy_from_slice(&inbuf[1..1 + num_bytes]);
+    let rest = nibble_unpack8(&block[..2 + num_bytes], output)?;
+    // One less byte was read from inbuf than from block, as it has no nibbles byte
+    Ok(&inbuf[1 + num_bytes - rest.len()..])
+}
+
+/// Unpacks the two buffers written by [`pack_u64_f64_pairs`]: #method.pack_u64_f64_pairs back into
+/// num_values (u64, f64) pairs.
+pub fn unpack_u64_f64_pairs(u64_encoded: &[u8],
+                            f64_encoded: &[u8],
+                            num_values: usize) -> Result<Vec<(u64, f64)>, CodingError> {
+    if num_values == 0 { return Ok(Vec::new()) }
+    let mut delta_sink = DeltaSink::new();
+    unpack(u64_encoded, &mut delta_sink, num_values)?;
+    let mut xor_sink = DoubleXorSink::new(Vec::with_capacity(num_values + 8));
+    unpack_f64_xor(f64_encoded, &mut xor_sink, num_values)?;
+
+    Ok(delta_sink.output_vec().iter().cloned()
+                 .zip(xor_sink.vec.iter().cloned())
+                 .take(num_values)
+                 .collect())
+}
+
+/// Unpacks num_values IPv4 addresses encoded using [`pack_ipv4`]: #method.pack_ipv4
+pub fn unpack_ipv4(encoded: &[u8], num_values: usize) -> Result<Vec<Ipv4Addr>, CodingError> {
+    let mut sink = VecSink::<u64>::new();
+    unpack(encoded, &mut sink, num_values)?;
+    Ok(sink.vec.iter().take(num_values).map(|&n| Ipv4Addr::from(n as u32)).collect())
+}
+
+/// Unpacks num_values IPv6 addresses encoded using [`pack_ipv6`]: #method.pack_ipv6
+pub fn unpack_ipv6(encoded: &[u8], num_values: usize) -> Result<Vec<Ipv6Addr>, CodingError> {
+    check_values_fit(encoded, num_values)?;
+    let mut sink = U128Sink::with_capacity(num_values);
+    unpack_u128(encoded, &mut sink, num_values)?;
+    Ok(sink.as_slice().iter().map(|&n| Ipv6Addr::from(n)).collect())
+}
+
+/// Unpacks num_values u128 values encoded using [`pack_u128`]: #method.pack_u128 into sink, which is reset
+/// first.  Returns the rest of encoded after both halves.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::{self, U128Sink};
+///     let ids = [7u128, 1 << 100, u128::MAX, 12345];
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u128(ids.iter().cloned(), &mut buf).unwrap();
+///     let mut sink = U128Sink::new();
+///     nibblepacking::unpack_u128(&buf[..written], &mut sink, ids.len()).unwrap();
+///     assert_eq!(sink.as_slice(), &ids[..]);
+/// ```
+pub fn unpack_u128<'a>(encoded: &'a [u8],
+                       sink: &mut U128Sink,
+                       num_values: usize) -> Result<&'a [u8], CodingError> {
+    sink.reset();
+    let rest = unpack(encoded, sink, num_values)?;
+    sink.start_low_halves();
+    unpack(rest, sink, num_values)
+}
+
+/// Unpacks num_values optional u64's encoded using [`pack_sparse_u64`]: #method.pack_sparse_u64.
+/// Returns InvalidFormat if the null runs and present values add up to more than num_values.
+/// The count of present values is checked before anything is allocated for them, so a corrupt count fails
+/// with InvalidFormat or InputTooShort rather than a huge allocation.
+pub fn unpack_sparse_u64(encoded: &[u8], num_values: usize) -> Result<Vec<Option<u64>>, CodingError> {
+    if encoded.len() < 4 { return Err(CodingError::InputTooShort) }
+    let num_present = (direct_read_uint_le(encoded, 0)? & 0xffff_ffff) as usize;
+    if num_present > num_values {
+        return Err(CodingError::InvalidFormat(format!("{} values present out of {}", num_present, num_values)));
+    }
+    // Every block of 8 takes at least one byte
+    if num_present > encoded.len() * 8 { return Err(CodingError::InputTooShort) }
+    let mut runs_sink = VecSink::<u64>::with_capacity(num_present);
+    let rest = unpack(&encoded[4..], &mut runs_sink, num_present)?;
+    let mut values_sink = VecSink::<u64>::with_capacity(num_present);
+    unpack(rest, &mut values_sink, num_present)?;
+
+    let mut out = Vec::with_capacity(num_values);
+    for (&run, &value) in runs_sink.as_slice().iter().zip(values_sink.as_slice()) {
+        // A corrupt run could be anywhere up to u64::MAX
+        match run.checked_add(out.len() as u64) {
+            Some(end) if end < num_values as u64 => {},
+            _ => return Err(CodingError::InvalidFormat(format!("More than {} values encoded", num_values))),
+        }
+        out.resize(out.len() + run as usize, None);
+        out.push(Some(value));
+    }
+    out.resize(num_values, None);
+    Ok(out)
+}
+
+/// Unpacks one block of 8 u64's packed using [`nibble_pack8`]: #method.nibble_pack8, by calling output.process()
+/// exactly once with all 8 values, zeroes included.  This is the building block of [`unpack`]: #method.unpack,
+/// and is public so that blocks can be decoded one at a time, eg to stop early or switch sinks between blocks.
+/// Uses the SIMD U32 unpack func if possible to speed things up
+///
+/// # Contract
+/// * inbuf must start at a block boundary: the start of the packed data, or a remainder returned by this function
+/// * On success, returns the rest of inbuf after this block, ie the start of the next block
+/// * On error nothing is passed to output.  NotEnoughSpace means inbuf ends partway through the block, and
+///   CorruptHeader means the nibbles byte describes values wider than 64 bits
+/// * Blocks carry no count of values, so the caller must know how many blocks there are.  The last block of a
+///   stream is padded out to 8 values with zeroes.
+///
+/// # Block format
+/// * Byte 0 is a bitmask of the values which are nonzero, bit 0 for the first value.  If it is 0, all 8 values
+///   are zero and the block is this 1 byte long.
+/// * Byte 1 holds the number of nibbles stored for each nonzero value minus 1 in the upper 4 bits, and the
+///   number of trailing zero nibbles dropped from every value in the lower 4 bits.
+/// * Then each nonzero value, shifted right by the trailing nibbles, is stored in the given number of nibbles,
+///   least significant bits first, packed back to back and rounded up to a whole byte at the end of the block.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::VecSink;
+///     let packed = nibblepacking::pack_u64_to_vec((0..20u64).map(|n| n * 256));
+///     let mut sink = VecSink::<u64>::new();
+///     let mut rest = &packed[..];
+///     for _ in 0..3 {
+///         rest = nibblepacking::nibble_unpack8(rest, &mut sink).unwrap();
+///     }
+///     assert!(rest.is_empty());
+///     assert_eq!(sink.vec[..20], (0..20u64).map(|n| n * 256).collect::<Vec<_>>()[..]);
+/// ```
+// NOTE: The 'a is a lifetime annotation.  When you use two references in Rust, and return one, Rust needs
+//       annotations to help it determine to which input the output lifetime is related to, so Rust knows
+//       that the output of the slice will live as long as the reference to the input slice is valid.
+#[inline]
+pub fn nibble_unpack8<'a, Output: Sink<u64x8>>(
+    inbuf: &'a [u8],
+    output: &mut Output,
+) -> Result<&'a [u8], CodingError> {
+    if inbuf.is_empty() { return Err(CodingError::NotEnoughSpace) }
+    let nonzero_mask = inbuf[0];
+    if nonzero_mask == 0 {
+        // All 8 words are 0; skip further processing
+        output.process(ZERO_U64OCTET);
+        Ok(&inbuf[1..])
+    } else {
+        if inbuf.len() < 2 { return Err(CodingError::NotEnoughSpace) }
+        let num_bits = ((inbuf[1] >> 4) + 1) * 4;
+        let trailing_zeros = (inbuf[1] & 0x0f) * 4;
+        // A corrupt nibbles byte could describe values wider than 64 bits
+        if num_bits + trailing_zeros > 64 { return Err(CodingError::CorruptHeader(inbuf[1])) }
+
+        // Use SIMD u32 unpacker if total resulting bits is <= 32
+        // Improves filtering throughput about 2x
+        if (num_bits + trailing_zeros) <= 32 {
+            let mut wrapper_sink = U32ToU64Sink::new(output);
+            return unpack8_u32_simd(inbuf, &mut wrapper_sink);
+        }
+
+        let total_bytes = 2 + (num_bits as u32 * nonzero_mask.count_ones() + 7) / 8;
+        if inbuf.len() < total_bytes as usize { return Err(CodingError::NotEnoughSpace) }
+        let mask = nibble_mask(num_bits as u32);
+        let mut bit_cursor = 0;
+        let mut out_array = [0u64; 8];
+
+        // Read in first word
+        let mut in_word = direct_read_uint_le(inbuf, 2)?;
+        let mut pos = 10;
+
+        for bit in 0..8 {
+            if (nonzero_mask & (1 << bit)) != 0 {
+                let remaining = 64 - bit_cursor;
+
+                // Shift and read in LSB (or entire nibbles if they fit)
+                let shifted_in = in_word >> bit_cursor;
+                let mut out_word = shifted_in & mask;
+
+                // If remaining bits are in next word, read next word -- if there's space
+                // We don't want to read the next word though if we're already at the end
+                if remaining <= num_bits && pos < (total_bytes as usize) {
+                    // Read in MSB bits from next word
+                    in_word = direct_read_uint_le(inbuf, pos)?;
+                    pos += 8;
+                    if remaining < num_bits {
+                        let shifted = in_word << remaining;
+                        out_word |= shifted & mask;
+                    }
+                }
+
+                out_array[bit] = out_word << trailing_zeros;
+
+                // Update other indices
+                bit_cursor = (bit_cursor + num_bits) % 64;
+            }
+        }
+        output.process(u64x8::from_slice_unaligned(&out_array));
+        // Return the "remaining slice" - the rest of input buffer after we've parsed our bytes.
+        // This allows for easy and clean chaining of nibble_unpack8 calls with no mutable state
+        Ok(&inbuf[(total_bytes as usize)..])
+    }
+}
+
+/// Returns the number of bytes taken up by the NibblePacked block of 8 values at the start of inbuf.
+/// Only the bitmask and nibble header bytes are read; the packed nibbles themselves are not checked.
+#[inline]
+pub fn block_len(inbuf: &[u8]) -> Result<usize, CodingError> {
+    if inbuf.is_empty() { return Err(CodingError::NotEnoughSpace) }
+    let nonzero_mask = inbuf[0];
+    if nonzero_mask == 0 {
+        Ok(1)
+    } else {
+        if inbuf.len() < 2 { return Err(CodingError::NotEnoughSpace) }
+        let num_bits = ((inbuf[1] >> 4) as usize + 1) * 4;
+        Ok(2 + (num_bits * nonzero_mask.count_ones() as usize + 7) / 8)
+    }
+}
+
+/// Walks over the headers of num_blocks NibblePacked blocks at the start of encoded, calling f(offset, len)
+/// for each block, without decoding any values.  Only the bitmask and nibble header bytes of each block are read,
+/// and the length is computed with a popcount, so this is fast enough to scan millions of blocks, for example
+/// to skip ahead or build an index of block offsets.
+/// Returns the total number of bytes walked, or InputTooShort if a block runs past the end of encoded.
+#[inline]
+pub fn walk_blocks<F>(encoded: &[u8], num_blocks: usize, mut f: F) -> Result<usize, CodingError>
+where F: FnMut(usize, usize) {
+    let mut off = 0;
+    for _ in 0..num_blocks {
+        let len = block_len(&encoded[off..]).map_err(|_| CodingError::InputTooShort)?;
+        if off + len > encoded.len() { return Err(CodingError::InputTooShort) }
+        f(off, len);
+        off += len;
+    }
+    Ok(off)
+}
+
+/// Returns the byte offset within encoded of the block holding the value at index, for building seek tables
+/// which map value positions to byte positions.  Values within a block are packed together and cannot be
+/// addressed on their own, so this is block granular: every index from 8b to 8b + 7 gives the offset of block b,
+/// from which decoding with [`nibble_unpack8`]: #method.nibble_unpack8 gives all 8 values.
+/// Built on [`walk_blocks`]: #method.walk_blocks, so only block headers are read.
+///
+/// encoded holds num_values values.  Returns InvalidFormat if index is not below num_values, including an index
+/// into the padding of the last block, and InputTooShort if encoded ends before the block holding index.
+pub fn value_byte_offset(encoded: &[u8], num_values: usize, index: usize) -> Result<usize, CodingError> {
+    if index >= num_values {
+        return Err(CodingError::InvalidFormat(format!("Index {} is past the end of {} values", index, num_values)))
+    }
+    let block = index / 8;
+    let mut block_offset = 0;
+    walk_blocks(encoded, block + 1, |off, _| block_offset = off)?;
+    Ok(block_offset)
+}
+
+/// Checks that encoded is a well formed buffer of exactly num_values values written by
+/// [`pack_u64`]: #method.pack_u64, without decoding any values or calling a sink, for scrubbing stored data faster
+/// than a full decode.  Like [`walk_blocks`]: #method.walk_blocks only the block headers are read.  Returns the
+/// first problem found:
+/// * CorruptHeader if a nibbles byte describes values wider than 64 bits
+/// * InputTooShort if a block runs past the end of encoded
+/// * InvalidFormat if the last block has nonzero values in its padding, or if bytes are left after the last block
+pub fn validate(encoded: &[u8], num_values: usize) -> Result<(), CodingError> {
+    let num_blocks = (num_values + 7) / 8;
+    let mut off = 0;
+    for block in 0..num_blocks {
+        if off >= encoded.len() { return Err(CodingError::InputTooShort) }
+        let nonzero_mask = encoded[off];
+        if nonzero_mask != 0 {
+            let nibbles_byte = *encoded.get(off + 1).ok_or(CodingError::InputTooShort)?;
+            let num_nibbles = (nibbles_byte >> 4) + 1;
+            if num_nibbles + (nibbles_byte & 0x0f) > 16 {
+                return Err(CodingError::CorruptHeader(nibbles_byte))
+            }
+        }
+        let num_padding = if block == num_blocks - 1 { num_blocks * 8 - num_values } else { 0 };
+        if (nonzero_mask as u16) >> (8 - num_padding) != 0 {
+            return Err(CodingError::InvalidFormat(format!("Nonzero padding in the last block, bitmask {:#04x}",
+                                                          nonzero_mask)))
+        }
+        off += block_len(&encoded[off..])?;
+        if off > encoded.len() { return Err(CodingError::InputTooShort) }
+    }
+    if off < encoded.len() {
+        return Err(CodingError::InvalidFormat(format!("{} bytes after the last block", encoded.len() - off)))
+    }
+    Ok(())
+}
+
+/// Returns a bitmap with one bit per block of 8 in a buffer holding num_values values, set when the block is
+/// entirely zero.  Bit b of word b / 64 is block b, ie values 8b to 8b + 7.  Only the block headers are read,
+/// using [`walk_blocks`]: #method.walk_blocks, so this is a fast way to find the non-empty regions of a very
+/// sparse column and skip the rest during scans.
+pub fn all_zero_block_bitmap(encoded: &[u8], num_values: usize) -> Result<Vec<u64>, CodingError> {
+    let num_blocks = (num_values + 7) / 8;
+    let mut bitmap = vec![0u64; (num_blocks + 63) / 64];
+    let mut block_num = 0;
+    walk_blocks(encoded, num_blocks, |off, _| {
+        if encoded[off] == 0 { bitmap[block_num / 64] |= 1 << (block_num % 64); }
+        block_num += 1;
+    })?;
+    Ok(bitmap)
+}
+
+/// A re-encoding suggested by [`TieringHint`]: struct.TieringHint.html, see its suggested_reencoding() method
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Reencoding {
+    /// The current encoding is about as good as a header only look can tell
+    Keep,
+    /// Mostly headers, ie mostly all zero blocks: a constant or run length encoding would be smaller
+    RunLength,
+    /// Wide values of much the same width, such as timestamps or ids packed as is: delta or frame of reference
+    /// encoding would store only the small differences
+    Delta,
+}
+
+/// Cheap statistics about a packed buffer from [`tiering_hint`]: #method.tiering_hint, for deciding whether to
+/// re-encode a column, for example during compaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TieringHint {
+    pub num_blocks: usize,
+    /// Bytes spent on bitmask and nibbles bytes
+    pub header_bytes: usize,
+    /// Bytes spent on the packed values themselves
+    pub payload_bytes: usize,
+    /// The most common number of nibbles per value among the blocks which are not all zero, or None if they all are
+    pub most_common_nibbles: Option<u8>,
+    /// How many of the blocks which are not all zero have the most common width
+    pub most_common_nibbles_blocks: usize,
+    /// The number of blocks which are not all zero
+    pub nonzero_blocks: usize,
+}
+
+impl TieringHint {
+    /// The fraction of all bytes which are headers rather than values, 0.0 for an empty buffer
+    pub fn header_fraction(&self) -> f64 {
+        let total = self.header_bytes + self.payload_bytes;
+        if total == 0 { 0.0 } else { self.header_bytes as f64 / total as f64 }
+    }
+
+    /// A heuristic suggestion of a better encoding:
+    /// * If over half the bytes are headers, the column is mostly all zero blocks, so suggest RunLength
+    /// * If at least 90% of the nonzero blocks share one width of 8 to 15 nibbles, the values are wide but similar,
+    ///   so suggest Delta.  A width of 16 nibbles means every bit is used, as in hashes or random ids, which no
+    ///   integer re-encoding helps, so this is not suggested for those.
+    /// * Otherwise Keep
+    pub fn suggested_reencoding(&self) -> Reencoding {
+        if self.header_fraction() > 0.5 { return Reencoding::RunLength }
+        match self.most_common_nibbles {
+            Some(nibbles) if (8..16).contains(&nibbles) &&
+                             self.most_common_nibbles_blocks * 10 >= self.nonzero_blocks * 9 => Reencoding::Delta,
+            _ => Reencoding::Keep,
+        }
+    }
+}
+
+/// Returns a [`TieringHint`]: struct.TieringHint.html for a buffer of num_values values, from a single pass over
+/// the block headers using [`walk_blocks`]: #method.walk_blocks, without decoding any values.
+pub fn tiering_hint(encoded: &[u8], num_values: usize) -> Result<TieringHint, CodingError> {
+    let num_blocks = (num_values + 7) / 8;
+    let mut width_counts = [0usize; 17];
+    let mut header_bytes = 0;
+    let total = walk_blocks(encoded, num_blocks, |off, _| {
+        if encoded[off] == 0 {
+            header_bytes += 1;
+        } else {
+            header_bytes += 2;
+            width_counts[((encoded[off + 1] >> 4) + 1) as usize] += 1;
+        }
+    })?;
+    let (most_common, &count) = width_counts.iter().enumerate().max_by_key(|&(_, count)| count)
+                                            .expect("There are 17 widths");
+    Ok(TieringHint {
+        num_blocks,
+        header_bytes,
+        payload_bytes: total - header_bytes,
+        most_common_nibbles: if count > 0 { Some(most_common as u8) } else { None },
+        most_common_nibbles_blocks: count,
+        nonzero_blocks: width_counts.iter().sum(),
+    })
+}
+
+/// Returns a relative cost of decoding num_values values from encoded, for a scheduler to order decodes cheapest
+/// first.  This is not a time: it is the number of blocks, each of which costs a sink call however small, plus
+/// the number of payload bytes past the block headers, which is what the unpacking loop spends its time on.
+/// All-zero blocks therefore cost 1, and a dense block of 64 bit values costs 65.  The same buffer always gives
+/// the same cost, and it is computed from a header-only walk using [`walk_blocks`]: #method.walk_blocks.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let zeroes = nibblepacking::pack_u64_to_vec((0..1000).map(|_| 0u64));
+///     let dense = nibblepacking::pack_u64_to_vec((0..1000u64).map(|i| i * 7919));
+///     assert_eq!(nibblepacking::decode_cost(&zeroes, 1000).unwrap(), 125);
+///     assert!(nibblepacking::decode_cost(&dense, 1000).unwrap() > 125);
+/// ```
+pub fn decode_cost(encoded: &[u8], num_values: usize) -> Result<usize, CodingError> {
+    let num_blocks = (num_values + 7) / 8;
+    let mut payload_bytes = 0;
+    walk_blocks(encoded, num_blocks, |off, len| {
+        if encoded[off] != 0 { payload_bytes += len - 2 }
+    })?;
+    Ok(num_blocks + payload_bytes)
+}
+
+/// Returns the prefix of a buffer encoded with [`pack_u64`]: #method.pack_u64 which covers the first keep_values
+/// values, along with the number of values it decodes to.  Blocks of 8 are atomic, so if keep_values is not a
+/// multiple of 8 the whole enclosing block is kept and the returned count is rounded up to the next multiple of 8.
+/// Only block headers are read, using [`walk_blocks`]: #method.walk_blocks.
+/// Returns InputTooShort if encoded has fewer than keep_values values.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let inputs: Vec<u64> = (0..20).collect();
+///     let mut buf = [0u8; 256];
+///     let written = nibblepacking::pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+///     let (prefix, num_values) = nibblepacking::truncate_packed(&buf[..written], 10).unwrap();
+///     assert_eq!(num_values, 16);
+/// ```
+pub fn truncate_packed(encoded: &[u8], keep_values: usize) -> Result<(&[u8], usize), CodingError> {
+    let num_blocks = (keep_values + 7) / 8;
+    let end = walk_blocks(encoded, num_blocks, |_, _| {})?;
+    Ok((&encoded[..end], num_blocks * 8))
+}
+
+/// One piece of a packed column split by [`split_packed`]: fn.split_packed.html.  The bytes are whole blocks,
+/// so they decode on their own with [`unpack`]: #method.unpack as value_count values.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PackedChunk<'a> {
+    pub bytes: &'a [u8],
+    /// Index within the whole column of the first value in this chunk
+    pub start_value_index: usize,
+    pub value_count: usize,
+    /// For a column packed with pack_u64_delta, the value before this chunk, to start its DeltaSink from with
+    /// [`DeltaSink::with_base`]: struct.DeltaSink.html#method.with_base.  Only set by
+    /// [`split_packed_delta`]: fn.split_packed_delta.html.
+    pub delta_base: Option<u64>,
+}
+
+/// Splits num_values values packed with [`pack_u64`]: #method.pack_u64 into at most n chunks of roughly equal
+/// numbers of bytes, cut at block boundaries, for decoding in parallel or storing separately.  Each chunk starts
+/// where the one before ends, and none is empty, so there can be fewer than n chunks when there are few blocks or
+/// their sizes are very uneven.  Only the block headers are read, using [`walk_blocks`]: #method.walk_blocks.
+/// Returns InvalidFormat if n is 0, or InputTooShort if encoded has fewer than num_values values.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let values: Vec<u64> = (0..1000).collect();
+///     let packed = nibblepacking::pack_u64_to_vec(values.iter().cloned());
+///     let chunks = nibblepacking::split_packed(&packed, values.len(), 4).unwrap();
+///     assert_eq!(chunks.len(), 4);
+///     let chunk = &chunks[2];
+///     let decoded = nibblepacking::unpack_to_vec(chunk.bytes, chunk.value_count).unwrap();
+///     assert_eq!(decoded[..], values[chunk.start_value_index..chunk.start_value_index + chunk.value_count]);
+/// ```
+pub fn split_packed(encoded: &[u8], num_values: usize, n: usize) -> Result<Vec<PackedChunk<'_>>, CodingError> {
+    if n == 0 { return Err(CodingError::InvalidFormat("Cannot split into 0 chunks".to_string())) }
+    let num_blocks = (num_values + 7) / 8;
+    let mut block_offsets = Vec::with_capacity(num_blocks + 1);
+    let total_bytes = walk_blocks(encoded, num_blocks, |off, _| block_offsets.push(off))?;
+    block_offsets.push(total_bytes);
+
+    let mut chunks = Vec::with_capacity(n.min(num_blocks));
+    let mut start_block = 0;
+    for k in 1..=n {
+        if start_block == num_blocks { break }
+        // End at the first block boundary at or past k/n of the bytes, taking at least one block
+        let target = total_bytes * k / n;
+        let mut end_block = start_block + 1;
+        while end_block < num_blocks && block_offsets[end_block] < target {
+            end_block += 1;
+        }
+        if k == n { end_block = num_blocks }
+        let start_value_index = start_block * 8;
+        chunks.push(PackedChunk {
+            bytes: &encoded[block_offsets[start_block]..block_offsets[end_block]],
+            start_value_index,
+            value_count: (end_block * 8).min(num_values) - start_value_index,
+            delta_base: None,
+        });
+        start_block = end_block;
+    }
+    Ok(chunks)
+}
+
+/// Same as [`split_packed`]: fn.split_packed.html, for a column packed with
+/// [`pack_u64_delta`]: #method.pack_u64_delta, also filling in the delta_base of each chunk.  Finding the bases
+/// means summing the deltas of every chunk, which is a full decode pass but with no output.
+///
+/// ```
+/// # use compressed_vec::nibblepacking::{self, DeltaSink};
+///     let values: Vec<u64> = (0..1000).map(|i| 5000 + i * 3).collect();
+///     let mut buf = [0u8; 4096];
+///     let written = nibblepacking::pack_u64_delta(&values, &mut buf).unwrap();
+///     let chunks = nibblepacking::split_packed_delta(&buf[..written], values.len(), 3).unwrap();
+///     let chunk = &chunks[1];
+///     let mut sink = DeltaSink::with_base(chunk.delta_base.unwrap());
+///     nibblepacking::unpack(chunk.bytes, &mut sink, chunk.value_count).unwrap();
+///     assert_eq!(sink.as_slice()[0], values[chunk.start_value_index]);
+/// ```
+pub fn split_packed_delta(encoded: &[u8], num_values: usize, n: usize) -> Result<Vec<PackedChunk<'_>>, CodingError> {
+    let mut chunks = split_packed(encoded, num_values, n)?;
+    let mut base = 0u64;
+    for chunk in chunks.iter_mut() {
+        chunk.delta_base = Some(base);
+        base = base.wrapping_add(decode_delta_sum(chunk.bytes, chunk.value_count)?);
+    }
+    Ok(chunks)
+}
+
+/// Renders a human readable description of every NibblePacked block in encoded, one line per block,
+/// without decoding any values.  Useful for debugging buffers which fail to decode.
+/// A block which runs past the end of the buffer is annotated as truncated and ends the dump.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+///     let mut buf = [0u8; 64];
+///     let written = nibblepacking::pack_u64([0u64, 1000, 1001, 1002].iter().cloned(), &mut buf, 0).unwrap();
+///     assert_eq!(nibblepacking::dump(&buf[..written]),
+///                "block 0 bitmask=0b00001110 3 nibbles, 0 trailing nibbles, bytes 0..7\n");
+/// ```
+pub fn dump(encoded: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let mut off = 0;
+    let mut block_num = 0;
+    while off < encoded.len() {
+        let inbuf = &encoded[off..];
+        let nonzero_mask = inbuf[0];
+        let _ = write!(out, "block {} bitmask={:#010b} ", block_num, nonzero_mask);
+        match block_len(inbuf) {
+            Ok(len) if len <= inbuf.len() => {
+                if nonzero_mask == 0 {
+                    let _ = writeln!(out, "empty, bytes {}..{}", off, off + len);
+                } else {
+                    let num_nibbles = (inbuf[1] >> 4) + 1;
+                    let trailing_nibbles = inbuf[1] & 0x0f;
+                    let _ = writeln!(out, "{} nibbles, {} trailing nibbles, bytes {}..{}",
+                                     num_nibbles, trailing_nibbles, off, off + len);
+                }
+                off += len;
+            },
+            Ok(len) => {
+                let _ = writeln!(out, "truncated: needs bytes {}..{} but buffer ends at {}",
+                                 off, off + len, encoded.len());
+                break;
+            },
+            Err(_) => {
+                let _ = writeln!(out, "truncated: missing nibble header byte at {}", off + 1);
+                break;
+            },
+        }
+        block_num += 1;
+    }
+    out
+}
+
+#[test]
+fn nibblepack8_all_zeroes() {
+    let mut buf = [0u8; 512];
+    let inputs = [0u64; 8];
+    let res = nibble_pack8(&inputs, &mut buf, 0);
+    dbg!(is_x86_feature_detected!("avx2"));
+    assert_eq!(res, Ok(1));
+    assert_eq!(buf[..1], [0u8]);
+}
+
+#[rustfmt::skip]
+#[test]
+fn nibblepack8_all_evennibbles() {
+    // All 8 are nonzero, even # nibbles
+    let mut buf = [0u8; 512];
+    let inputs = [ 0x0000_00fe_dcba_0000u64, 0x0000_0033_2211_0000u64,
+                   0x0000_0044_3322_0000u64, 0x0000_0055_4433_0000u64,
                    0x0000_0066_5544_0000u64, 0x0000_0076_5432_0000u64,
                    0x0000_0087_6543_0000u64, 0x0000_0098_7654_0000u64, ];
     let res = nibble_pack8(&inputs, &mut buf, 0);
 
-    // Expected result:
-    let expected_buf = [
-        0xffu8, // Every input is nonzero, all bits on
-        0x54u8, // six nibbles wide, four zero nibbles trailing
-        0xbau8, 0xdcu8, 0xfeu8, 0x11u8, 0x22u8, 0x33u8, 0x22u8, 0x33u8, 0x44u8,
-        0x33u8, 0x44u8, 0x55u8, 0x44u8, 0x55u8, 0x66u8, 0x32u8, 0x54u8, 0x76u8,
-        0x43u8, 0x65u8, 0x87u8, 0x54u8, 0x76u8, 0x98u8, ];
-    assert_eq!(res, Ok(2 + 3 * 8));
-    assert_eq!(buf[..expected_buf.len()], expected_buf);
+    // Expected result:
+    let expected_buf = [
+        0xffu8, // Every input is nonzero, all bits on
+        0x54u8, // six nibbles wide, four zero nibbles trailing
+        0xbau8, 0xdcu8, 0xfeu8, 0x11u8, 0x22u8, 0x33u8, 0x22u8, 0x33u8, 0x44u8,
+        0x33u8, 0x44u8, 0x55u8, 0x44u8, 0x55u8, 0x66u8, 0x32u8, 0x54u8, 0x76u8,
+        0x43u8, 0x65u8, 0x87u8, 0x54u8, 0x76u8, 0x98u8, ];
+    assert_eq!(res, Ok(2 + 3 * 8));
+    assert_eq!(buf[..expected_buf.len()], expected_buf);
+}
+
+// Even nibbles with different combos of partial
+#[rustfmt::skip]   // We format the arrays specially to help visually see input vs output.  Don't reformat.
+#[test]
+fn nibblepack8_partial_evennibbles() {
+    // All 8 are nonzero, even # nibbles
+    let mut buf = [0u8; 512];
+    let inputs = [
+        0u64,
+        0x0000_0033_2211_0000u64, 0x0000_0044_3322_0000u64,
+        0x0000_0055_4433_0000u64, 0x0000_0066_5544_0000u64,
+        0u64,
+        0u64,
+        0u64,
+    ];
+    let res = nibble_pack8(&inputs, &mut buf, 0);
+
+    // Expected result:
+    let expected_buf = [
+        0b0001_1110u8, // only some bits on
+        0x54u8,        // six nibbles wide, four zero nibbles trailing
+        0x11u8, 0x22u8, 0x33u8, 0x22u8, 0x33u8, 0x44u8,
+        0x33u8, 0x44u8, 0x55u8, 0x44u8, 0x55u8, 0x66u8,
+    ];
+    assert_eq!(res, Ok(2 + 3 * 4));
+    assert_eq!(buf[..expected_buf.len()], expected_buf);
+}
+
+#[test]
+fn nibblepack8_fixed_byte_widths_match_generic() {
+    // Every even width with some zero lanes, shifted by a couple of trailing zero nibbles
+    for num_bytes_each in 1..=8usize {
+        let mask = nibble_mask(num_bytes_each as u32 * 8);
+        let mut inputs = [0u64; 8];
+        for (i, n) in inputs.iter_mut().enumerate() {
+            if i % 3 != 1 { *n = (0x9e37_79b9_7f4a_7c15u64.wrapping_mul(i as u64 + 1) & mask) << 8 | 0x100; }
+        }
+        let mut generic = Vec::new();
+        pack_even_nibbles_generic(&inputs, &mut generic, num_bytes_each, 8).unwrap();
+        let mut specialized = Vec::new();
+        pack_to_even_nibbles(&inputs, &mut specialized, num_bytes_each as u32 * 2, 2).unwrap();
+        assert_eq!(specialized, generic, "{} bytes each", num_bytes_each);
+
+        // A slice too short for the block is still NotEnoughSpace
+        let mut buf = [0u8; 8];
+        let mut cursor = SliceCursor::new(&mut buf, 8 - num_bytes_each);
+        assert_eq!(pack_to_even_nibbles(&inputs, &mut cursor, num_bytes_each as u32 * 2, 2),
+                   Err(CodingError::NotEnoughSpace));
+    }
+}
+
+// Odd nibbles with different combos of partial
+#[rustfmt::skip]
+#[test]
+fn nibblepack8_partial_oddnibbles() {
+    // All 8 are nonzero, even # nibbles
+    let mut buf = [0u8; 512];
+    let inputs = [
+        0u64,
+        0x0000_0033_2210_0000u64, 0x0000_0044_3320_0000u64,
+        0x0000_0055_4430_0000u64, 0x0000_0066_5540_0000u64,
+        0x0000_0076_5430_0000u64, 0u64, 0u64,
+    ];
+    let res = nibble_pack8(&inputs, &mut buf, 0);
+
+    // Expected result:
+    let expected_buf = [
+        0b0011_1110u8, // only some bits on
+        0x45u8,        // five nibbles wide, five zero nibbles trailing
+        0x21u8, 0x32u8, 0x23u8, 0x33u8, 0x44u8, // First two values
+        0x43u8, 0x54u8, 0x45u8, 0x55u8, 0x66u8,
+        0x43u8, 0x65u8, 0x07u8,
+    ];
+    assert_eq!(res, Ok(expected_buf.len()));
+    assert_eq!(buf[..expected_buf.len()], expected_buf);
+}
+
+// Odd nibbles > 8 nibbles
+#[rustfmt::skip]
+#[test]
+fn nibblepack8_partial_oddnibbles_large() {
+    // All 8 are nonzero, even # nibbles
+    let mut buf = [0u8; 512];
+    let inputs = [
+        0u64,
+        0x0005_4433_2211_0000u64, 0x0000_0044_3320_0000u64,
+        0x0007_6655_4433_0000u64, 0x0000_0066_5540_0000u64,
+        0x0001_9876_5430_0000u64, 0u64, 0u64,
+    ];
+    let res = nibble_pack8(&inputs, &mut buf, 0);
+
+    // Expected result:
+    let expected_buf = [
+        0b0011_1110u8, // only some bits on
+        0x84u8,        // nine nibbles wide, four zero nibbles trailing
+        0x11u8, 0x22u8, 0x33u8, 0x44u8, 0x05u8, 0x32u8, 0x43u8, 0x04u8, 0,
+        0x33u8, 0x44u8, 0x55u8, 0x66u8, 0x07u8, 0x54u8, 0x65u8, 0x06u8, 0,
+        0x30u8, 0x54u8, 0x76u8, 0x98u8, 0x01u8,
+    ];
+    assert_eq!(res, Ok(expected_buf.len()));
+    assert_eq!(buf[..expected_buf.len()], expected_buf);
+}
+
+#[rustfmt::skip]
+#[test]
+fn nibblepack8_64bit_numbers() {
+    let mut buf = [0u8; 512];
+    let inputs = [0, 0, -1i32 as u64, -2i32 as u64, 0, -100234i32 as u64, 0, 0];
+    let res = nibble_pack8(&inputs, &mut buf, 0);
+
+    let expected_buf = [
+        0b0010_1100u8,
+        0xf0u8, // all 16 nibbles wide, zero nibbles trailing
+        0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8,
+        0xfeu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8,
+        0x76u8, 0x78u8, 0xfeu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8,
+    ];
+    assert_eq!(res, Ok(expected_buf.len()));
+    assert_eq!(buf[..expected_buf.len()], expected_buf);
+}
+
+#[test]
+fn nibble_mask_boundaries() {
+    assert_eq!(nibble_mask(4), 0x0f);
+    assert_eq!(nibble_mask(60), 0x0fff_ffff_ffff_ffffu64);
+    assert_eq!(nibble_mask(64), u64::MAX);
+}
+
+// 15 nibbles goes through pack_universal, 16 nibbles through pack_to_even_nibbles; both must round trip
+#[rustfmt::skip]
+#[test]
+fn pack_unpack_15_16_nibbles() {
+    let inputs15 = [0x0fff_ffff_ffff_ffffu64, 0x0123_4567_89ab_cdefu64, 0, 1,
+                    0x0800_0000_0000_0000u64, 0, 0x0fed_cba9_8765_4321u64, 2];
+    let inputs16 = [u64::MAX, 0xf123_4567_89ab_cdefu64, 0, 1,
+                    0x8000_0000_0000_0000u64, 0, 0xfed_cba9_8765_4321u64, 2];
+    for inputs in [inputs15, inputs16].iter() {
+        let mut buf = [0u8; 512];
+        let written = nibble_pack8(inputs, &mut buf, 0).unwrap();
+
+        let mut sink = VecSink::<u64>::new();
+        let res = nibble_unpack8(&buf[..written], &mut sink);
+        assert_eq!(res.unwrap().len(), 0);
+        assert_eq!(sink.vec[..], inputs[..]);
+    }
+
+    // The universal packer must produce the same bytes as the even nibble packer at full 64-bit width
+    let mut even_buf = Vec::new();
+    let mut univ_buf = Vec::new();
+    pack_to_even_nibbles(&inputs16, &mut even_buf, 16, 0).unwrap();
+    pack_universal(&inputs16, &mut univ_buf, 16, 0).unwrap();
+    assert_eq!(univ_buf, even_buf);
+}
+
+#[test]
+fn nibble_pack8_byte_sinks_identical() {
+    let blocks = [[0u64; 8],
+                  [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034],
+                  [0x0123_4567_89ab_cdefu64, 0, 3, 0, 0x0fed_cba9_8765_4321u64, 7, 0, 1],
+                  [u64::MAX; 8]];
+    for inputs in blocks.iter() {
+        let mut buf = [0u8; 512];
+        let written = nibble_pack8(inputs, &mut buf, 0).unwrap();
+
+        let mut vec = Vec::new();
+        nibble_pack8_to(inputs, &mut vec).unwrap();
+        assert_eq!(vec[..], buf[..written]);
+
+        let mut write_sink = WriteSink::new(Vec::new());
+        nibble_pack8_to(inputs, &mut write_sink).unwrap();
+        assert_eq!(write_sink.writer[..], buf[..written]);
+    }
+
+    let mut small = [0u8; 4];
+    let mut cursor = SliceCursor::new(&mut small, 0);
+    assert_eq!(nibble_pack8_to(&blocks[3], &mut cursor), Err(CodingError::NotEnoughSpace));
+}
+
+#[test]
+fn min_zeros_implementations_agree() {
+    let blocks = [[0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034],
+                  [0x0123_4567_89ab_cdefu64, 0, 3, 0, 0x0fed_cba9_8765_4321u64, 7, 0, 1],
+                  [0x100u64, 0, 0, 0x2000, 0, 0, 0, 0],
+                  [u64::MAX; 8],
+                  [0u64; 8]];
+    for inputs in blocks.iter() {
+        assert_eq!(min_zeros_ored(inputs), min_zeros_per_lane(inputs));
+    }
+}
+
+// The packed format is always little endian, whatever the host.  The expected bytes below were written out by
+// hand from the format, so this test fails if any packer writes native endian words.  Run it on a big endian
+// target to verify, eg: cross test --target s390x-unknown-linux-gnu
+#[rustfmt::skip]
+#[test]
+fn packed_bytes_endian_independent() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034,
+                  0, 0, 0, 0, 0, 0, 0, 0,
+                  0x0123_4567_89ab_cdef, 0, 7, 0x0fed_cba9_8765_4321, 0x8000_0000_0000_0000, 1, 0, 0xff];
+    let mut buf = [0u8; 512];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let expected = [
+        0xfeu8, 0x20,                        // 7 nonzero values, 3 nibbles, no trailing zero nibbles
+        0xe8, 0x93, 0x3e, 0xea, 0xb3, 0x3e,  // 1000 = 0x3e8 and 1001 = 0x3e9 are 0xe8, 0x93, 0x3e
+        0xd5, 0xa7, 0x7d, 0xda, 0x0b,
+        0x00,                                // all zeroes
+        0xbd, 0xf0,                          // 6 nonzero values, 16 nibbles
+        0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01,
+        0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x21, 0x43, 0x65, 0x87, 0xa9, 0xcb, 0xed, 0x0f,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(buf[..written], expected[..]);
+
+    let floats = [1.5f64, 1.5, 2.25, -3.0];
+    let written = pack_f64_xor(floats.iter().cloned(), &mut buf).unwrap();
+    let expected = [
+        0x00u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x3f,   // 1.5 = 0x3ff8_0000_0000_0000
+        0x06, 0x3c, 0xfa, 0x7f, 0x0a, 0x80,
+    ];
+    assert_eq!(buf[..written], expected[..]);
+}
+
+#[test]
+fn unpack8_all_zeroes() {
+    let compressed_array = [0x00u8];
+    let mut sink = VecSink::<u64>::new();
+    let res = nibble_unpack8(&compressed_array, &mut sink);
+    assert_eq!(res.unwrap().is_empty(), true);
+    assert_eq!(sink.vec.len(), 8);
+    assert_eq!(sink.vec[..], [0u64; 8]);
+}
+
+#[rustfmt::skip]
+#[test]
+fn unpack8_input_too_short() {
+    let compressed = [
+        0b0011_1110u8, // only some bits on
+        0x84u8,        // nine nibbles wide, four zero nibbles trailing
+        0x11u8, 0x22u8, 0x33u8, 0x44u8, 0x05u8, 0x32u8, 0x43u8, 0x04u8, 0,
+        0x33u8, 0x44u8, 0x55u8, 0x66u8, 0x07u8,
+    ]; // too short!!
+    let mut sink = VecSink::<u64>::new();
+    let res = nibble_unpack8(&compressed, &mut sink);
+    assert_eq!(res, Err(CodingError::NotEnoughSpace));
+}
+
+// Tests the case where nibbles lines up with 64-bit boundaries - edge case
+#[test]
+fn unpack8_4nibbles_allfull() {
+    // 4 nibbles = 2^16, so values < 65536
+    let inputs = [65535u64; 8];
+    let mut buf = [0u8; 512];
+    let written = nibble_pack8(&inputs, &mut buf, 0).unwrap();
+
+    let mut sink = VecSink::<u64>::new();
+    let res = nibble_unpack8(&buf[..written], &mut sink);
+    assert_eq!(res.unwrap().len(), 0);
+    assert_eq!(sink.vec[..], inputs);
+}
+
+#[test]
+fn unpack8_partial_oddnibbles() {
+    let compressed = [
+        0b0011_1110u8, // only some bits on
+        0x84u8,        // nine nibbles wide, four zero nibbles trailing
+        0x11u8, 0x22u8, 0x33u8, 0x44u8, 0x05u8, 0x32u8, 0x43u8, 0x04u8, 0,
+        0x33u8, 0x44u8, 0x55u8, 0x66u8, 0x07u8, 0x54u8, 0x65u8, 0x06u8, 0,
+        0x30u8, 0x54u8, 0x76u8, 0x98u8, 0x01u8,
+        0x00u8, ]; // extra padding... just to test the return value
+    let mut sink = VecSink::<u64>::new();
+    let res = nibble_unpack8(&compressed, &mut sink);
+    assert_eq!(res.unwrap().len(), 1);
+    assert_eq!(sink.vec.len(), 8);
+
+    let orig = [
+        0u64,
+        0x0005_4433_2211_0000u64, 0x0000_0044_3320_0000u64,
+        0x0007_6655_4433_0000u64, 0x0000_0066_5540_0000u64,
+        0x0001_9876_5430_0000u64, 0u64, 0u64,
+    ];
+
+    assert_eq!(sink.vec[..], orig);
+}
+
+#[test]
+fn pack_unpack_u64_plain() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+    let mut buf = [0u8; 512];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    println!("Packed {} u64 inputs (plain) into {} bytes", inputs.len(), written);
+
+    let mut sink = VecSink::<u64>::new();
+    let res = unpack(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(res.unwrap().len(), 0);
+    assert_eq!(sink.vec[..inputs.len()], inputs);
+}
+
+#[test]
+fn test_unpack_u64_plain_iter() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+    let mut buf = [0u8; 512];
+    // NOTE: into_iter() of an array returns an Iterator<Item = &u64>, cloned() is needed to convert back to u64
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut sink = U64_256Sink::new();
+    unpack(&buf[0..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.values[0..inputs.len()], inputs);
+}
+
+#[test]
+fn pack_unpack_u64_deltas() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+    let mut buf = [0u8; 512];
+    // NOTE: into_iter() of an array returns an Iterator<Item = &u64>, cloned() is needed to convert back to u64
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    println!("Packed {} u64 inputs (delta) into {} bytes", inputs.len(), written);
+
+    let mut sink = DeltaSink::new();
+    let res = unpack(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(res.unwrap().len(), 0);
+    assert_eq!(sink.sink.vec[..inputs.len()], inputs);
+}
+
+#[test]
+fn pack_unpack_f64_xor() {
+    let inputs = [0f64, 0.5, 2.5, 10., 25., 100.];
+    let mut buf = [0u8; 512];
+    let written = pack_f64_xor(inputs.iter().cloned(), &mut buf).unwrap();
+    println!("Packed {} f64 inputs (XOR) into {} bytes", inputs.len(), written);
+
+    let out = Vec::<f64>::with_capacity(64);
+    let mut sink = DoubleXorSink::new(out);
+    let res = unpack_f64_xor(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(res.unwrap().len(), 0);
+    assert_eq!(sink.vec[..inputs.len()], inputs);
+}
+
+#[test]
+fn f64_xor_windowed_smooth_and_spiky() {
+    let mut buf = [0u8; 4096];
+    let mut xor_buf = [0u8; 4096];
+    // A sensor reading wandering around a level, so successive XORs have much the same width
+    let smooth: Vec<f64> = (0..400).map(|i| 72.0 + ((i * 37) % 11) as f64 * 0.125).collect();
+    let written = pack_f64_xor_windowed(smooth.iter().cloned(), &mut buf).unwrap();
+    let xor_written = pack_f64_xor(smooth.iter().cloned(), &mut xor_buf).unwrap();
+    assert!(written < xor_written, "{} not less than {}", written, xor_written);
+    let mut sink = DoubleXorSink::new(Vec::new());
+    let rest = unpack_f64_xor_windowed(&buf[..written], &mut sink, smooth.len()).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(sink.output_vec()[..], smooth[..]);
+
+    // Big jumps now and then widen a block, which then gets its own nibbles byte
+    let spiky: Vec<f64> = smooth.iter().enumerate()
+                                .map(|(i, &f)| if i % 29 == 0 { f * 1.0e9 + 0.3 } else { f })
+                                .collect();
+    let written = pack_f64_xor_windowed(spiky.iter().cloned(), &mut buf).unwrap();
+    let xor_written = pack_f64_xor(spiky.iter().cloned(), &mut xor_buf).unwrap();
+    assert!(written <= xor_written + (spiky.len() / 8 + 7) / 8);
+    unpack_f64_xor_windowed(&buf[..written], &mut sink, spiky.len()).unwrap();
+    assert_eq!(sink.output_vec()[..], spiky[..]);
+
+    // Truncated input, and a first block flagged before any window was written
+    assert_eq!(unpack_f64_xor_windowed(&buf[..written - 1], &mut sink, spiky.len()).map(|_| ()),
+               Err(CodingError::InputTooShort));
+    let written = pack_f64_xor_windowed([1.0f64, 2.0].iter().cloned(), &mut buf).unwrap();
+    buf[8] = 1;
+    assert!(matches!(unpack_f64_xor_windowed(&buf[..written], &mut sink, 2), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn f64_special_values_bit_exact() {
+    // NaN compares unequal to itself, so all comparisons are on the bits
+    let bits = [0x7ff8_0000_0000_0000u64,   // quiet NaN, first so it goes through the raw initial value
+                0x7ff0_0000_0000_0001,      // signaling NaN
+                0xfff8_0000_0000_0000,      // negative quiet NaN
+                0x7ff8_dead_beef_1234,      // quiet NaN with a payload
+                0xfff4_0000_0bad_f00d,      // negative signaling NaN with a payload
+                f64::INFINITY.to_bits(),
+                f64::NEG_INFINITY.to_bits(),
+                (-0.0f64).to_bits(),
+                0.0f64.to_bits(),
+                0x0000_0000_0000_0001,      // smallest subnormal
+                (-1.5f64).to_bits(),
+                0xffff_ffff_ffff_ffff];     // all ones is also a NaN
+    let inputs: Vec<f64> = bits.iter().map(|&b| f64::from_bits(b)).collect();
+    let to_bits = |floats: &[f64]| floats.iter().map(|f| f.to_bits()).collect::<Vec<u64>>();
+
+    let mut buf = [0u8; 512];
+    let written = pack_f64_xor(inputs.iter().cloned(), &mut buf).unwrap();
+    let mut sink = DoubleXorSink::new(Vec::new());
+    unpack_f64_xor(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(to_bits(&sink.vec[..]), bits);
+
+    // The lossy packer keeps non-finite values, zeroes and subnormals exactly
+    let written = pack_f64_lossy(&inputs[..], 0.1, &mut buf).unwrap();
+    let mut sink = DoubleXorSink::new(Vec::new());
+    unpack_f64_xor(&buf[..written], &mut sink, inputs.len()).unwrap();
+    let lossy_bits = to_bits(&sink.vec[..]);
+    for i in 0..bits.len() {
+        if i != 10 { assert_eq!(lossy_bits[i], bits[i], "value {}", i) }
+    }
+
+    let pairs: Vec<(u64, f64)> = inputs.iter().enumerate().map(|(i, &f)| (i as u64, f)).collect();
+    let mut ts_buf = [0u8; 256];
+    let (ts_len, val_len) = pack_u64_f64_pairs(&pairs[..], &mut ts_buf, &mut buf).unwrap();
+    let out = unpack_u64_f64_pairs(&ts_buf[..ts_len], &buf[..val_len], pairs.len()).unwrap();
+    let out_floats: Vec<f64> = out.iter().map(|&(_, f)| f).collect();
+    assert_eq!(to_bits(&out_floats[..]), bits);
+}
+
+#[test]
+fn unpack_as_slice_hides_padding() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056];
+    let mut buf = [0u8; 512];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut sink = VecSink::<u64>::new();
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.vec.len(), 16);
+    assert_eq!(sink.as_slice().len(), 10);
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // Unpacking again without a reset appends right after the values of the first unpack
+    unpack(&buf[..written], &mut sink, 3).unwrap();
+    assert_eq!(sink.as_slice().len(), 13);
+    assert_eq!(sink.as_slice()[..10], inputs[..]);
+    assert_eq!(sink.as_slice()[10..], inputs[..3]);
+
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sink = DeltaSink::new();
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+}
+
+#[test]
+fn pack_unpack_gauge() {
+    let inputs = [u64::MAX - 2, u64::MAX, 3, 0, 1_000_000, 999_998, 1_000_001, 1_000_001, 1_000_000, 999_999];
+    let mut buf = [0u8; 512];
+    let written = pack_gauge(&inputs[..], &mut buf).unwrap();
+
+    let mut sink = ZigZagDeltaSink::new();
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    assert_eq!(zigzag_encode(0), 0);
+    assert_eq!(zigzag_encode(-1), 1);
+    assert_eq!(zigzag_encode(1), 2);
+    assert_eq!(zigzag_encode(i64::MIN), u64::MAX);
+    assert_eq!(zigzag_decode(u64::MAX), i64::MIN);
+}
+
+#[test]
+fn range_decode_middle() {
+    let inputs: Vec<u64> = (0..100).map(|i| 10_000 + i * 37 + (i % 5)).collect();
+    let mut buf = [0u8; 1024];
+
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let mut sink = VecSink::<u64>::new();
+    range_decode(&buf[..written], 21, 67, &mut sink).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[21..67]);
+
+    // Block aligned range, and an empty range
+    let mut sink = VecSink::<u64>::new();
+    range_decode(&buf[..written], 16, 32, &mut sink).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[16..32]);
+    let mut sink = VecSink::<u64>::new();
+    range_decode(&buf[..written], 40, 40, &mut sink).unwrap();
+    assert_eq!(sink.as_slice().len(), 0);
+
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sink = VecSink::<u64>::new();
+    range_decode_delta(&buf[..written], 21, 67, &mut sink).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[21..67]);
+
+    let mut sink = VecSink::<u64>::new();
+    assert!(range_decode(&buf[..written], 10, 5, &mut sink).is_err());
+    assert_eq!(range_decode(&buf[..4], 90, 95, &mut sink), Err(CodingError::InputTooShort));
+
+    // Truncated delta input is reported the same way, whether it runs out inside the range or before it
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(range_decode_delta(&buf[..written - 1], 21, 67, &mut sink), Err(CodingError::InputTooShort));
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(range_decode_delta(&buf[..4], 90, 95, &mut sink), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn unpack_through_dictionary() {
+    let dict = [1_000_000u64, 42, 7_777_777, 0, 12345];
+    let indices = [0u64, 1, 2, 3, 4, 4, 2, 0, 1, 1, 3];
+    let mut buf = [0u8; 256];
+    let written = pack_u64(indices.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut vec_sink = VecSink::<u64>::new();
+    let mut sink = MapSink::new(&dict[..], &mut vec_sink);
+    unpack(&buf[..written], &mut sink, indices.len()).unwrap();
+    assert_eq!(sink.check(), Ok(()));
+    let expected: Vec<u64> = indices.iter().map(|&i| dict[i as usize]).collect();
+    assert_eq!(vec_sink.as_slice(), &expected[..]);
+
+    // Out of range index
+    let indices = [0u64, 1, 5, 2];
+    let written = pack_u64(indices.iter().cloned(), &mut buf, 0).unwrap();
+    let mut vec_sink = VecSink::<u64>::new();
+    let mut sink = MapSink::new(&dict[..], &mut vec_sink);
+    unpack(&buf[..written], &mut sink, indices.len()).unwrap();
+    assert!(sink.check().is_err());
+}
+
+#[test]
+fn zero_runs_roundtrip_long_stretches() {
+    // Long zero stretches between bursts, a short run of 2 zero blocks which stays as plain zero bytes,
+    // and trailing zeroes running into the padding
+    let mut inputs = vec![0u64; 20_000];
+    for i in (3000..3010).chain(3024..3030).chain(15_000..15_001) {
+        inputs[i] = i as u64 * 31;
+    }
+    let mut buf = [0u8; 256];
+    for &len in [0usize, 5, 8, 40, 3005, 3013, 20_000, 19_999].iter() {
+        let written = pack_u64_zero_runs(inputs[..len].iter().cloned(), &mut buf, 0).unwrap();
+        let plain = pack_u64_to_vec(inputs[..len].iter().cloned());
+        assert!(written <= plain.len());
+
+        let mut sink = VecSink::<u64>::new();
+        let rest = unpack_zero_runs(&buf[..written], &mut sink, len).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(sink.as_slice(), &inputs[..len]);
+
+        // Runs shorter than 4 blocks are packed just like pack_u64, and 5 zero blocks collapse into one run
+        if len < 32 { assert_eq!(&buf[..written], &plain[..]) }
+        if len == 40 { assert_eq!(&buf[..written], &[0xff, ZERO_RUN_HEADER, 5]) }
+    }
+
+    let written = pack_u64_zero_runs(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let plain_len = pack_u64_to_vec(inputs.iter().cloned()).len();
+    assert!(written * 20 < plain_len, "Zero runs: {} bytes, plain: {} bytes", written, plain_len);
+
+    // A run claiming more blocks than are left, or none at all, is rejected
+    let mut sink = VecSink::<u64>::new();
+    assert!(matches!(unpack_zero_runs(&[0xff, ZERO_RUN_HEADER, 5], &mut sink, 32),
+                     Err(CodingError::InvalidFormat(_))));
+    assert!(matches!(unpack_zero_runs(&[0xff, ZERO_RUN_HEADER, 0], &mut sink, 32),
+                     Err(CodingError::InvalidFormat(_))));
+    assert_eq!(unpack_zero_runs(&[0xff, ZERO_RUN_HEADER], &mut sink, 32), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn adaptive_roundtrip_mixed_entropy() {
+    // Random full width blocks, which are stored raw, between small values, with a partial last block
+    let mut state = 0x1234_5678_9abc_def0u64;
+    let inputs: Vec<u64> = (0..203u64).map(|i| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        if (i / 8) % 3 == 1 { state | (1 << 63) | 1 } else { i % 50 }
+    }).collect();
+    let mut buf = [0u8; 4096];
+    let written = pack_u64_adaptive(inputs.iter().cloned(), &mut buf).unwrap();
+    let mut sink = VecSink::<u64>::new();
+    assert!(unpack_u64_adaptive(&buf[..written], &mut sink, inputs.len()).unwrap().is_empty());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // Each of the 8 raw blocks saves 2 bytes, less the 4 bytes of flags for the 26 blocks
+    let nibble_packed = pack_u64_to_vec(inputs.iter().cloned());
+    assert_eq!(written, nibble_packed.len() - 8 * 2 + 4);
+    assert_eq!(buf[0], 0b1001_0010);
+
+    // Without any high entropy blocks the output is the flags, then just what pack_u64 writes
+    let small: Vec<u64> = (0..20).collect();
+    let written = pack_u64_adaptive(small.iter().cloned(), &mut buf).unwrap();
+    assert_eq!(&buf[..written], &[&[0u8][..], &pack_u64_to_vec(small.iter().cloned())[..]].concat()[..]);
+
+    let written = pack_u64_adaptive(inputs.iter().cloned(), &mut buf).unwrap();
+    assert_eq!(unpack_u64_adaptive(&buf[..written - 1], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
+    assert_eq!(unpack_u64_adaptive(&buf[..3], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
+    // A raw block cut short
+    assert_eq!(unpack_u64_adaptive(&buf[..4 + 6 + 30], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn sparse_unpack_iter_yields_nonzero_pairs() {
+    // Mostly zero, with nonzero values scattered across blocks, whole zero blocks in between, a full width block,
+    // and a partial last block
+    let mut inputs = vec![0u64; 1003];
+    let nonzero = [(0usize, 5u64), (7, 1), (8, 300), (13, 2), (200, u64::MAX), (201, 0x8000_0000_0000_0001),
+                   (640, 99), (999, 12345), (1002, 7)];
+    for &(i, v) in nonzero.iter() {
+        inputs[i] = v;
+    }
+    let mut buf = [0u8; 256];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut iter = SparseUnpackIter::new(&buf[..written], inputs.len());
+    let pairs: Vec<(usize, u64)> = iter.by_ref().collect();
+    assert_eq!(pairs, nonzero.to_vec());
+    assert!(iter.error().is_none());
+
+    // Fewer values than were packed stops at num_values, even partway through a block
+    let pairs: Vec<(usize, u64)> = SparseUnpackIter::new(&buf[..written], 201).collect();
+    assert_eq!(pairs, nonzero[..5].to_vec());
+    assert_eq!(SparseUnpackIter::new(&buf[..written], 0).count(), 0);
+
+    // Running out of input ends the iteration with an error
+    let mut iter = SparseUnpackIter::new(&buf[..written - 1], inputs.len());
+    assert_eq!(iter.by_ref().count(), nonzero.len() - 1);
+    assert_eq!(iter.error(), Some(&CodingError::InputTooShort));
+}
+
+#[test]
+fn merge_delta_two_shards() {
+    // Two shards of timestamps, interleaved unevenly, with duplicates across the shards and a run where
+    // one shard has many values in a row
+    let a: Vec<u64> = (0..300u64).map(|i| 1_000_000 + i * 10 + if i > 200 { 5_000 } else { 0 }).collect();
+    let b: Vec<u64> = (0..123u64).map(|i| 1_000_000 + i * 25).collect();
+    let mut a_buf = [0u8; 2048];
+    let mut b_buf = [0u8; 2048];
+    let a_written = pack_u64_delta(&a[..], &mut a_buf).unwrap();
+    let b_written = pack_u64_delta(&b[..], &mut b_buf).unwrap();
+
+    let mut iter = merge_delta(&a_buf[..a_written], a.len(), &b_buf[..b_written], b.len());
+    let merged: Vec<u64> = iter.by_ref().collect();
+    assert!(iter.error().is_none());
+    assert!(merged.windows(2).all(|pair| pair[0] <= pair[1]));
+    let mut expected: Vec<u64> = a.iter().chain(b.iter()).cloned().collect();
+    expected.sort();
+    assert_eq!(merged, expected);
+
+    // Either side empty is just the other column
+    let merged: Vec<u64> = merge_delta(&[], 0, &b_buf[..b_written], b.len()).collect();
+    assert_eq!(merged, b);
+    let merged: Vec<u64> = merge_delta(&a_buf[..a_written], a.len(), &[], 0).collect();
+    assert_eq!(merged, a);
+
+    // A truncated column stops the merge with an error
+    let mut iter = merge_delta(&a_buf[..a_written], a.len(), &b_buf[..b_written - 3], b.len());
+    assert!(iter.by_ref().count() < a.len() + b.len());
+    assert_eq!(iter.error(), Some(&CodingError::InputTooShort));
+}
+
+#[test]
+fn decode_cost_grows_with_density() {
+    let num_values = 1000;
+    let zeroes = pack_u64_to_vec((0..num_values).map(|_| 0u64));
+    let sparse = pack_u64_to_vec((0..num_values as u64).map(|i| if i % 50 == 0 { i } else { 0 }));
+    let small = pack_u64_to_vec((0..num_values as u64).map(|i| i % 16));
+    let dense = pack_u64_to_vec((0..num_values as u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+
+    let costs: Vec<usize> = [&zeroes, &sparse, &small, &dense].iter()
+                                .map(|buf| decode_cost(&buf[..], num_values).unwrap()).collect();
+    assert_eq!(costs[0], 125);
+    assert!(costs.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", costs);
+    // The cost is blocks plus bytes after the headers, which is below the total size for dense data
+    assert!(costs[3] <= dense.len());
+
+    assert_eq!(decode_cost(&dense[..dense.len() - 1], num_values), Err(CodingError::InputTooShort));
+    assert_eq!(decode_cost(&[], 0), Ok(0));
+}
+
+#[test]
+fn pack_unpack_u64_be() {
+    // Odd and even nibble widths, trailing zero nibbles, full 64 bit values, zero blocks and a partial last block
+    let mut inputs: Vec<u64> = (0..64u64).map(|i| i * 0x123).collect();
+    inputs.extend((0..8u64).map(|i| (i + 1) * 0x1_0000));
+    inputs.extend((0..8u64).map(|i| u64::MAX - i * 0x0102_0304_0506_0708));
+    inputs.extend((0..16u64).map(|i| if i % 5 == 0 { 0xabc } else { 0 }));
+    inputs.extend((0..8u64).map(|_| 0));
+    inputs.extend((0..5u64).map(|i| 0xfedc_ba98_7654_3210 >> (i * 9)));
+
+    let mut be_buf = [0u8; 2048];
+    let mut le_buf = [0u8; 2048];
+    let be_written = pack_u64_be(inputs.iter().cloned(), &mut be_buf, 0).unwrap();
+    let le_written = pack_u64(inputs.iter().cloned(), &mut le_buf, 0).unwrap();
+    assert_eq!(be_written, le_written);
+    assert_ne!(&be_buf[..be_written], &le_buf[..le_written]);
+    validate(&be_buf[..be_written], inputs.len()).unwrap();
+
+    let mut sink = VecSink::<u64>::new();
+    let rest = unpack_u64_be(&be_buf[..be_written + 3], &mut sink, inputs.len()).unwrap();
+    assert_eq!(rest.len(), 3);
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // Decoding with the other byte order gives different values
+    let mut sink = VecSink::<u64>::new();
+    unpack(&be_buf[..be_written], &mut sink, inputs.len()).unwrap();
+    assert_ne!(sink.as_slice(), &inputs[..]);
+
+    // Whole byte values are plain big endian; 3 nibble values run on from the high nibble down
+    let written = pack_u64_be([0x1a0b_0c0du64].iter().cloned(), &mut be_buf, 0).unwrap();
+    assert_eq!(&be_buf[..written], &[1, 0x70, 0x1a, 0x0b, 0x0c, 0x0d]);
+    let written = pack_u64_be([0xabcu64, 0x123].iter().cloned(), &mut be_buf, 0).unwrap();
+    assert_eq!(&be_buf[..written], &[0b11, 0x20, 0xab, 0xc1, 0x23]);
+
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(unpack_u64_be(&[1, 0xf1, 0, 0], &mut sink, 8), Err(CodingError::CorruptHeader(0xf1)));
+    assert_eq!(unpack_u64_be(&be_buf[..written - 1], &mut sink, 2), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn incremental_writer_snapshots_decode_to_prefix() {
+    let inputs: Vec<u64> = (0..100u64).map(|i| if i % 9 == 0 { 0 } else { 1000 + i * i }).collect();
+    let mut writer = IncrementalWriter::new();
+    assert_eq!(writer.snapshot().unwrap(), &[] as &[u8]);
+
+    let mut snapshots = Vec::new();
+    for (i, &n) in inputs.iter().enumerate() {
+        writer.push(n).unwrap();
+        assert_eq!(writer.current_len(), i + 1);
+        if i % 7 == 0 || i % 8 == 7 {
+            let snapshot = writer.snapshot().unwrap().to_vec();
+            // Taking a snapshot twice in a row gives the same bytes, which are what pack_u64 writes
+            assert_eq!(writer.snapshot().unwrap(), &snapshot[..]);
+            assert_eq!(snapshot, pack_u64_to_vec(inputs[..=i].iter().cloned()));
+            snapshots.push((i + 1, snapshot));
+        }
+    }
+    // Pushes after a snapshot do not change the copy taken
+    for (len, snapshot) in snapshots {
+        assert_eq!(unpack_to_vec(&snapshot, len).unwrap(), &inputs[..len]);
+    }
+    assert_eq!(unpack_to_vec(&pack_u64_to_vec(inputs.iter().cloned()), usize::MAX), Err(CodingError::InputTooShort));
+    assert_eq!(writer.finish().unwrap(), pack_u64_to_vec(inputs.iter().cloned()));
+}
+
+#[test]
+fn normalize_gives_one_canonical_form() {
+    let inputs: Vec<u64> = (0..100u64).map(|i| if i % 3 == 0 { 0 } else { i * 0x0123_4567 }).collect();
+    let canonical = pack_u64_to_vec(inputs.iter().cloned());
+    assert_eq!(normalize(&canonical, inputs.len()).unwrap(), canonical);
+
+    // The same first 97 values, with the padding of the last block holding the 3 values after them
+    let long = pack_u64_to_vec(inputs.iter().chain(&[1, 2, 3]).cloned());
+    let short = pack_u64_to_vec(inputs[..97].iter().cloned());
+    assert_ne!(long[..], short[..]);
+    assert_eq!(normalize(&long, 97).unwrap(), short);
+    assert_eq!(normalize(&short, 97).unwrap(), short);
+
+    // Bytes after the last block
+    let random: Vec<u64> = (1..=20u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)).collect();
+    let mut buf = [0u8; 512];
+    let written = pack_u64(random.iter().cloned(), &mut buf, 0).unwrap();
+    assert_eq!(normalize(&buf[..written + 5], random.len()).unwrap(), pack_u64_to_vec(random.iter().cloned()));
+
+    assert_eq!(normalize(&canonical[..canonical.len() - 1], inputs.len()), Err(CodingError::InputTooShort));
+    assert_eq!(normalize(&[], 0).unwrap(), Vec::<u8>::new());
+}
+
+#[test]
+fn for_each_block_sum_matches_unpack() {
+    let inputs: Vec<u64> = (0..1003u64).map(|i| if i % 10 == 0 { 0 } else { i * 7919 % 100_000 }).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned().chain(vec![1, 1, 1].into_iter()));
+
+    let mut sum = 0u64;
+    let mut valids = Vec::new();
+    let rest = for_each_block(&packed, inputs.len(), |block, valid| {
+        sum += block[..valid].iter().sum::<u64>();
+        valids.push(valid);
+    }).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(sum, unpack_to_vec(&packed, inputs.len()).unwrap().iter().sum::<u64>());
+    assert_eq!(sum, inputs.iter().sum::<u64>());
+    // Every block is whole but the last, whose padding holds the extra values
+    assert_eq!(valids.len(), 126);
+    assert!(valids[..125].iter().all(|&valid| valid == 8));
+    assert_eq!(valids[125], 3);
+
+    assert_eq!(for_each_block(&packed, 0, |_, _| panic!("No blocks to visit")).unwrap().len(), packed.len());
+    assert!(for_each_block(&packed[..packed.len() - 1], inputs.len(), |_, _| {}).is_err());
+}
+
+#[test]
+fn packed_delta_get_matches_full_decode() {
+    // Increasing values with repeats, big jumps, and a partial last block
+    let values: Vec<u64> = (0..1003u64).map(|i| 5_000_000 + i * 17 + if i > 500 { 1 << 40 } else { 0 } - (i % 3))
+                                       .scan(0, |max, n| { *max = n.max(*max); Some(*max) }).collect();
+    let column = PackedDelta::pack(&values).unwrap();
+    assert_eq!(column.len(), values.len());
+
+    let mut sink = DeltaSink::new();
+    unpack(column.as_bytes(), &mut sink, column.len()).unwrap();
+    assert_eq!(sink.as_slice(), &values[..]);
+
+    for i in (0..values.len()).rev() {
+        assert_eq!(column.get(i), Some(sink.as_slice()[i]), "index {}", i);
+    }
+    assert_eq!(column.get(values.len()), None);
+
+    let empty = PackedDelta::pack(&[]).unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(empty.get(0), None);
+
+    // A decrease would be clipped to zero by the delta packing, so it is rejected
+    assert!(matches!(PackedDelta::pack(&[10, 20, 15, 30]), Err(CodingError::InvalidFormat(_))));
+
+    // No interior mutability, so a column can be shared between threads
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<PackedDelta>();
+}
+
+#[test]
+fn unpack_metered_counts_block_kinds() {
+    // 3 zero blocks, 2 narrow blocks, 2 wide blocks, then a partial last block
+    let mut inputs: Vec<u64> = vec![0; 24];
+    inputs.extend((1..=16u64).map(|i| i * 100));
+    inputs.extend((1..=8u64).map(|i| i << 40));
+    inputs.extend((1..=8u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+    inputs.extend(&[0, 1, 0]);
+    let mut buf = [0u8; 1024];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut sink = VecSink::<u64>::new();
+    let (rest, metrics) = unpack_metered(&buf[..written + 2], &mut sink, inputs.len()).unwrap();
+    assert_eq!(rest.len(), 2);
+    assert_eq!(sink.as_slice(), &inputs[..]);
+    assert_eq!(metrics, DecodeMetrics { blocks: 8, zero_blocks: 3, simd_u32_blocks: 3, bytes_read: written });
+
+    // Same output and remainder as unpack
+    let mut plain_sink = VecSink::<u64>::new();
+    assert_eq!(unpack(&buf[..written + 2], &mut plain_sink, inputs.len()).unwrap().len(), 2);
+    assert_eq!(plain_sink.as_slice(), sink.as_slice());
+}
+
+#[test]
+fn timestamps_auto_unit_ms_stored_as_ns() {
+    // Whole milliseconds in nanoseconds, roughly every 10 seconds with some jitter
+    let nanos: Vec<u64> = (0..500u64).map(|i| (1_600_000_000_000 + i * 10_000 + (i * 7919) % 13) * 1_000_000)
+                                     .collect();
+    let mut buf = [0u8; 4096];
+    let written = pack_timestamps_auto_unit(&nanos, &mut buf).unwrap();
+    assert_eq!(direct_read_uint_le(&buf, 0).unwrap(), 1_000_000);
+    assert_eq!(unpack_timestamps_auto_unit(&buf[..written], nanos.len()).unwrap(), nanos);
+
+    let double_delta_written = pack_u64_double_delta(&nanos, &mut buf).unwrap();
+    assert!(written * 2 < double_delta_written,
+            "Auto unit: {} bytes, double delta: {} bytes", written, double_delta_written);
+
+    // No common divisor, all zeroes, and no values at all fall back to a divisor of 1
+    for values in [vec![1_000_000u64, 2_000_001], vec![0u64; 10], vec![]].iter() {
+        let written = pack_timestamps_auto_unit(values, &mut buf).unwrap();
+        assert_eq!(direct_read_uint_le(&buf, 0).unwrap(), 1);
+        assert_eq!(&unpack_timestamps_auto_unit(&buf[..written], values.len()).unwrap(), values);
+    }
+    assert_eq!(unpack_timestamps_auto_unit(&buf[..7], 0), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn split_packed_chunks_concatenate_to_column() {
+    // Uneven block sizes, so equal byte chunks hold different numbers of values
+    let values: Vec<u64> = (0..2003u64).map(|i| if i < 1000 { i % 16 } else { i * 0x1_0000_0001 }).collect();
+    let packed = pack_u64_to_vec(values.iter().cloned());
+    for &n in [1usize, 2, 3, 7, 250, 251, 252, 10_000].iter() {
+        let chunks = split_packed(&packed, values.len(), n).unwrap();
+        assert!(chunks.len() <= n.min(251), "n {}", n);
+        assert!(chunks.iter().all(|chunk| chunk.value_count > 0));
+        let mut decoded = Vec::new();
+        for chunk in chunks.iter() {
+            assert_eq!(chunk.start_value_index, decoded.len());
+            assert!(chunk.delta_base.is_none());
+            decoded.extend(unpack_to_vec(chunk.bytes, chunk.value_count).unwrap());
+        }
+        assert_eq!(decoded, values);
+        assert_eq!(chunks.iter().map(|chunk| chunk.bytes.len()).sum::<usize>(), packed.len());
+    }
+    // The byte sizes are roughly equal even though the values are not
+    let chunks = split_packed(&packed, values.len(), 4).unwrap();
+    assert!(chunks.iter().all(|chunk| chunk.bytes.len() * 4 < packed.len() * 3 / 2));
+    assert!(chunks[0].value_count > chunks[3].value_count * 2);
+
+    let increasing: Vec<u64> = values.iter().scan(0u64, |acc, &n| { *acc += n; Some(*acc) }).collect();
+    let mut buf = vec![0u8; estimate_packed_size(increasing.len())];
+    let written = pack_u64_delta(&increasing, &mut buf).unwrap();
+    let mut decoded = Vec::new();
+    for chunk in split_packed_delta(&buf[..written], increasing.len(), 5).unwrap() {
+        let mut sink = DeltaSink::with_base(chunk.delta_base.unwrap());
+        unpack(chunk.bytes, &mut sink, chunk.value_count).unwrap();
+        decoded.extend_from_slice(sink.as_slice());
+    }
+    assert_eq!(decoded, increasing);
+
+    assert!(split_packed(&packed, values.len(), 0).is_err());
+    assert_eq!(split_packed(&packed[..10], values.len(), 2), Err(CodingError::InputTooShort));
+    assert!(split_packed(&[], 0, 3).unwrap().is_empty());
+}
+
+#[test]
+fn packed_set_contains_matches_hash_set() {
+    use std::collections::HashSet;
+
+    // Clusters of dense ids with big gaps between, plus a repeat and the extremes
+    let mut values: Vec<u64> = (0..3000u64).map(|i| (i / 100) * 1_000_000 + (i % 100) * 3).collect();
+    values.push(values[2999]);
+    values.push(u64::MAX);
+    values.sort_unstable();
+    let set = PackedSet::new(&values).unwrap();
+    assert_eq!(set.len(), values.len());
+    let reference: HashSet<u64> = values.iter().cloned().collect();
+
+    for probe in (0..31_000_000u64).step_by(997).chain(values.iter().flat_map(|&n| vec![n, n.wrapping_add(1)])) {
+        assert_eq!(set.contains(probe), reference.contains(&probe), "probe {}", probe);
+    }
+    assert!(set.contains(0) && set.contains(u64::MAX) && !set.contains(u64::MAX - 1));
+
+    let mut sink = DeltaSink::new();
+    unpack(set.as_bytes(), &mut sink, set.len()).unwrap();
+    assert_eq!(sink.as_slice(), &values[..]);
+
+    let empty = PackedSet::new(&[]).unwrap();
+    assert!(empty.is_empty() && !empty.contains(0));
+    assert!(matches!(PackedSet::new(&[1, 5, 4]), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn max_delta_matches_manual_max() {
+    let inputs: Vec<u64> = (0..500u64).map(|i| 1_000_000 + i * 60 + (i * i * 37) % 1000).collect();
+    let mut manual = 0;
+    for i in 1..inputs.len() {
+        if inputs[i] >= inputs[i - 1] {
+            manual = manual.max(inputs[i] - inputs[i - 1]);
+        }
+    }
+    assert!(inputs.windows(2).any(|pair| pair[1] < pair[0]));
+    assert_eq!(max_delta(&inputs), manual);
+
+    // The same as the largest delta actually packed, other than the first value
+    let (_, body) = pack_u64_delta_split(&inputs);
+    let deltas = unpack_to_vec(&body[..], inputs.len() - 1).unwrap();
+    assert_eq!(max_delta(&inputs), deltas.into_iter().max().unwrap());
+
+    assert_eq!(max_delta(&[]), 0);
+    assert_eq!(max_delta(&[u64::MAX]), 0);
+    assert_eq!(max_delta(&[5, 3, 1]), 0);
+    assert_eq!(max_delta(&[0, u64::MAX]), u64::MAX);
+}
+
+#[test]
+fn bool_rle_roundtrip_long_runs() {
+    // A flag which flips a handful of times over a long column
+    let flips = [0usize, 1000, 1003, 4096, 9999];
+    let flags: Vec<bool> = (0..10_000).map(|i| flips.iter().filter(|&&flip| flip <= i).count() % 2 == 0).collect();
+    let mut buf = [0u8; 1024];
+    let written = pack_bool_rle(&flags, &mut buf).unwrap();
+    assert_eq!(buf[..2], [0, 4]);
+    assert_eq!(unpack_bool_rle(&buf[..written], flags.len()).unwrap(), flags);
+    // Bit packing takes one bit per value
+    assert!(written * 50 < flags.len() / 8, "written {}", written);
+
+    for flags in [vec![], vec![false], vec![true; 17], vec![true, false, true, false]].iter() {
+        let written = pack_bool_rle(flags, &mut buf).unwrap();
+        assert_eq!(&unpack_bool_rle(&buf[..written], flags.len()).unwrap(), flags);
+    }
+
+    // Transitions past the number of values asked for
+    let written = pack_bool_rle(&flags, &mut buf).unwrap();
+    assert!(matches!(unpack_bool_rle(&buf[..written], 5000), Err(CodingError::InvalidFormat(_))));
+    buf[0] = 2;
+    assert!(matches!(unpack_bool_rle(&buf[..written], flags.len()), Err(CodingError::InvalidFormat(_))));
+    assert_eq!(pack_bool_rle(&flags, &mut buf[..3]), Err(CodingError::NotEnoughSpace));
+}
+
+#[test]
+fn unpack_rejects_impossible_num_values_up_front() {
+    // All zero blocks are the smallest possible, one byte for 8 values
+    let zeroes = [0u8; 3];
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(unpack(&zeroes[..], &mut sink, 24).unwrap().len(), 0);
+    assert_eq!(sink.as_slice(), &[0u64; 24][..]);
+
+    for &num_values in [25usize, 1 << 40, usize::MAX].iter() {
+        let mut sink = VecSink::<u64>::new();
+        assert_eq!(unpack(&zeroes[..], &mut sink, num_values), Err(CodingError::InputTooShort));
+        assert!(sink.vec.is_empty(), "num_values {}", num_values);
+    }
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(unpack(&[], &mut sink, 1), Err(CodingError::InputTooShort));
+
+    // A buffer only slightly short gives the same error, once the decode reaches the block cut off
+    let packed = pack_u64_to_vec((0..20u64).map(|n| n * 1000));
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(unpack(&packed[..packed.len() - 1], &mut sink, 20), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn pack_unpack_u64_fixed_width() {
+    for &bits in [0u32, 1, 5, 12, 31, 32, 57, 63, 64].iter() {
+        let mask = nibble_mask(bits);
+        for &num_values in [0usize, 1, 8, 13, 1003].iter() {
+            let inputs: Vec<u64> = (0..num_values as u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15) & mask)
+                                                          .collect();
+            let mut buf = vec![0u8; 2 + (num_values + 7) / 8 * 64];
+            let written = pack_u64_fixed_width(&inputs, bits, &mut buf).unwrap();
+            assert_eq!(written, 1 + (num_values + 7) / 8 * bits as usize, "bits {} n {}", bits, num_values);
+
+            buf[written] = 0xaa;
+            let mut sink = VecSink::<u64>::new();
+            let rest = unpack_u64_fixed_width(&buf[..written + 1], &mut sink, num_values).unwrap();
+            assert_eq!(rest, &[0xaa]);
+            assert_eq!(sink.as_slice(), &inputs[..], "bits {} n {}", bits, num_values);
+        }
+    }
+
+    // Values using all 12 bits pack smaller without the 2 header bytes per block
+    let inputs: Vec<u64> = (0..800u64).map(|i| (i * 7919) % 4096).collect();
+    let mut buf = [0u8; 2048];
+    assert_eq!(pack_u64_fixed_width(&inputs, 12, &mut buf).unwrap(), 1 + 100 * 12);
+    assert!(pack_u64_to_vec(inputs.iter().cloned()).len() > 100 * 13);
+
+    // A value too wide for bits is caught before anything is written
+    let mut small_buf = [0x55u8; 16];
+    assert!(matches!(pack_u64_fixed_width(&[1, 2, 3, 4, 5, 6, 7, 8, 16], 4, &mut small_buf),
+                     Err(CodingError::InvalidFormat(_))));
+    assert_eq!(small_buf, [0x55u8; 16]);
+    assert!(matches!(pack_u64_fixed_width(&[1], 65, &mut buf), Err(CodingError::InvalidFormat(_))));
+    let written = pack_u64_fixed_width(&inputs, 12, &mut buf).unwrap();
+    let mut sink = VecSink::<u64>::new();
+    assert_eq!(unpack_u64_fixed_width(&buf[..written - 1], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
+    assert_eq!(unpack_u64_fixed_width(&buf[..written], &mut sink, usize::MAX), Err(CodingError::InputTooShort));
+    assert!(sink.vec.is_empty());
+
+    // Width 0 buffers are one byte however many values they hold, so the count is capped instead
+    assert!(matches!(unpack_u64_fixed_width(&[0], &mut sink, usize::MAX), Err(CodingError::InvalidFormat(_))));
+    assert!(matches!(unpack_u64_fixed_width(&[0], &mut sink, MAX_ZERO_WIDTH_VALUES + 1),
+                     Err(CodingError::InvalidFormat(_))));
+    assert!(sink.vec.is_empty());
+}
+
+#[test]
+fn unpack_u64_fixed_width_zero_width_into_delta_and_xor_sinks() {
+    let zeroes = [0u64; 13];
+    let mut buf = [0u8; 16];
+    let written = pack_u64_fixed_width(&zeroes, 0, &mut buf).unwrap();
+    assert_eq!(written, 1);
+
+    // Zero deltas on from the base repeat it
+    let mut sink = DeltaSink::with_base(500);
+    unpack_u64_fixed_width(&buf[..written], &mut sink, zeroes.len()).unwrap();
+    assert_eq!(sink.as_slice(), &[500u64; 13]);
+
+    // Zero XORs on from 0 bits are all 0.0
+    let mut sink = DoubleXorSink::new(Vec::new());
+    unpack_u64_fixed_width(&buf[..written], &mut sink, zeroes.len()).unwrap();
+    assert_eq!(sink.output_vec()[..], [0.0f64; 13]);
+}
+
+#[test]
+fn delta_with_resets_roundtrip_counter() {
+    // A request counter which restarts from 0 three times, once partway up and once straight after a restart
+    let mut counter = Vec::new();
+    let mut total = 5_000u64;
+    for i in 0..1000u64 {
+        if i == 300 || i == 301 || i == 777 { total = 0; }
+        if i == 500 { total = 10; }
+        total += i % 13;
+        counter.push(total);
+    }
+    let num_resets = counter.windows(2).filter(|pair| pair[1] < pair[0]).count();
+    assert_eq!(num_resets, 3);
+
+    let mut buf = vec![0u8; estimate_packed_size(counter.len()) * 2];
+    let written = pack_u64_delta_with_resets(&counter, &mut buf).unwrap();
+    assert_eq!(unpack_u64_delta_with_resets(&buf[..written], counter.len()).unwrap(), counter);
+
+    // pack_u64_delta clips the resets so decodes wrongly, but the bitmap costs little more
+    let mut delta_buf = vec![0u8; estimate_packed_size(counter.len())];
+    let delta_written = pack_u64_delta(&counter, &mut delta_buf).unwrap();
+    let mut sink = DeltaSink::new();
+    unpack(&delta_buf[..delta_written], &mut sink, counter.len()).unwrap();
+    assert_ne!(sink.as_slice(), &counter[..]);
+    assert!(written < delta_written + 40, "with resets {} vs delta {}", written, delta_written);
+
+    for inputs in [vec![], vec![7], vec![9, 3], vec![0; 20]].iter() {
+        let written = pack_u64_delta_with_resets(inputs, &mut buf).unwrap();
+        assert_eq!(&unpack_u64_delta_with_resets(&buf[..written], inputs.len()).unwrap(), inputs);
+    }
+    let written = pack_u64_delta_with_resets(&counter, &mut buf).unwrap();
+    assert!(unpack_u64_delta_with_resets(&buf[..written / 2], counter.len()).is_err());
+}
+
+#[test]
+fn pack_unpack_f64_fixed() {
+    // Smooth temperature-like series, to 0.01 degrees, including negative values
+    let inputs: Vec<f64> = (0..200).map(|i| ((i as f64 / 20.0).sin() * 1500.0).round() / 100.0).collect();
+    let scale = 100.0;
+    let mut buf = [0u8; 2048];
+    let written = pack_f64_fixed(&inputs[..], scale, &mut buf).unwrap();
+
+    let out = unpack_f64_fixed(&buf[..written], inputs.len()).unwrap();
+    assert_eq!(out.len(), inputs.len());
+    for (a, b) in out.iter().zip(inputs.iter()) {
+        assert!((a - b).abs() <= 1.0 / scale, "{} vs {}", a, b);
+    }
+
+    let mut xor_buf = [0u8; 4096];
+    let xor_written = pack_f64_xor(inputs.iter().cloned(), &mut xor_buf).unwrap();
+    assert!(written < xor_written, "Fixed point: {} bytes, XOR: {} bytes", written, xor_written);
+
+    assert!(pack_f64_fixed(&inputs[..], 0.0, &mut buf).is_err());
+    assert!(pack_f64_fixed(&[1e300], scale, &mut buf).is_err());
+}
+
+#[test]
+fn f64_to_fixed_rejects_out_of_range() {
+    assert_eq!(f64_to_fixed(1.5, 2.0), Ok(3));
+    assert_eq!(f64_to_fixed(-2.5, 1.0), Ok(-3));   // rounds half away from zero
+
+    // 2^62 is fine, 2^63 is one past i64::MAX, and -2^63 is exactly i64::MIN
+    assert_eq!(f64_to_fixed(2f64.powi(61), 2.0), Ok(1i64 << 62));
+    assert_eq!(f64_to_fixed(2f64.powi(62), 2.0), Err(CodingError::ValueOutOfRange(2f64.powi(62))));
+    assert_eq!(f64_to_fixed(-(2f64.powi(62)), 2.0), Ok(i64::MIN));
+    assert_eq!(f64_to_fixed(-(2f64.powi(63)), 2.0), Err(CodingError::ValueOutOfRange(-(2f64.powi(63)))));
+
+    // Small values overflow with a large scale
+    assert_eq!(f64_to_fixed(1e10, 1e10), Err(CodingError::ValueOutOfRange(1e10)));
+    assert_eq!(f64_to_fixed(f64::INFINITY, 1.0), Err(CodingError::ValueOutOfRange(f64::INFINITY)));
+    assert_eq!(f64_to_fixed(f64::NEG_INFINITY, 1.0), Err(CodingError::ValueOutOfRange(f64::NEG_INFINITY)));
+    assert!(matches!(f64_to_fixed(f64::NAN, 1.0), Err(CodingError::ValueOutOfRange(f)) if f.is_nan()));
+
+    // The packers report the first bad value, and still reject a bad scale with InvalidFormat
+    let mut buf = [0u8; 256];
+    assert_eq!(pack_f64_fixed(&[1.0, 1e300, f64::NAN], 100.0, &mut buf), Err(CodingError::ValueOutOfRange(1e300)));
+    assert!(matches!(pack_f64_fixed(&[1.0, f64::NAN], 100.0, &mut buf),
+                     Err(CodingError::ValueOutOfRange(f)) if f.is_nan()));
+    assert_eq!(pack_f64_monotonic(&[1.0, 2.0, 1e18], 1000.0, &mut buf),
+               Err(CodingError::ValueOutOfRange(1e18)));
+    assert!(matches!(pack_f64_fixed(&[1.0], f64::NAN, &mut buf), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn pack_unpack_f64_monotonic() {
+    // A cumulative counter starting below zero, going up by irregular amounts, with some flat spots
+    let mut total = -12.5f64;
+    let inputs: Vec<f64> = (0..300).map(|i| { total += ((i * 37) % 11) as f64 * 0.0137; total }).collect();
+    let scale = 1000.0;
+    let mut buf = [0u8; 4096];
+    let written = pack_f64_monotonic(&inputs[..], scale, &mut buf).unwrap();
+
+    let out = unpack_f64_monotonic(&buf[..written], inputs.len()).unwrap();
+    assert_eq!(out.len(), inputs.len());
+    for (a, b) in out.iter().zip(inputs.iter()) {
+        assert!((a - b).abs() <= 0.5 / scale + 1e-9, "{} vs {}", a, b);
+    }
+
+    let mut xor_buf = [0u8; 4096];
+    let xor_written = pack_f64_xor(inputs.iter().cloned(), &mut xor_buf).unwrap();
+    assert!(written < xor_written, "Monotonic: {} bytes, XOR: {} bytes", written, xor_written);
+
+    assert_eq!(unpack_f64_monotonic(&buf[..written], 0).unwrap(), Vec::<f64>::new());
+    let written = pack_f64_monotonic(&[], scale, &mut buf).unwrap();
+    assert_eq!(unpack_f64_monotonic(&buf[..written], 0).unwrap(), Vec::<f64>::new());
+    let extremes = [i64::MIN as f64, 0.0, 9.0e18];
+    let written = pack_f64_monotonic(&extremes, 1.0, &mut buf).unwrap();
+    assert_eq!(unpack_f64_monotonic(&buf[..written], 3).unwrap(), extremes);
+    assert!(matches!(pack_f64_monotonic(&[1.0, 2.0, 1.5], scale, &mut buf), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn sink_builder_matches_manual_chain() {
+    let dict = [1_000_000u64, 42, 7_777_777, 0, 12345];
+    let indices = [0u64, 1, 2, 3, 4, 4, 2, 0, 1, 1, 3, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+    let mut buf = [0u8; 256];
+    let written = pack_u64(indices.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut vec_sink = VecSink::<u64>::new();
+    let mut add_sink = AddConstSink::new(100u64, &mut vec_sink);
+    let mut map_sink = MapSink::new(&dict[..], &mut add_sink);
+    unpack(&buf[..written], &mut map_sink, indices.len()).unwrap();
+    assert_eq!(map_sink.check(), Ok(()));
+
+    let mut pipeline = SinkBuilder::new(VecSink::<u64>::new()).map(&dict[..]).add_const(100).build();
+    unpack(&buf[..written], &mut pipeline, indices.len()).unwrap();
+    assert_eq!(pipeline.check(), Ok(()));
+    assert_eq!(pipeline.sink.as_slice(), &vec_sink.vec[..indices.len()]);
+
+    // Stage errors surface through the pipeline, and reset clears them
+    let written = pack_u64([7u64, 1].iter().cloned(), &mut buf, 0).unwrap();
+    pipeline.reset();
+    unpack(&buf[..written], &mut pipeline, 2).unwrap();
+    assert!(pipeline.check().is_err());
+    pipeline.reset();
+    assert_eq!(pipeline.check(), Ok(()));
+}
+
+#[test]
+fn sink_builder_downsample_and_tee() {
+    let dict: Vec<u64> = (0..50u64).map(|i| i * 1000 + 7).collect();
+    let indices: Vec<u64> = (0..40u64).map(|i| (i * 17) % 50).collect();
+    let mut buf = [0u8; 512];
+
+    // Every length and downsampling factor, so that the final partial block lands everywhere
+    for num_values in 0..=indices.len() {
+        let written = pack_u64(indices[..num_values].iter().cloned(), &mut buf, 0).unwrap();
+        for every in 1..=9 {
+            let kept: Vec<u64> = indices[..num_values].iter().step_by(every).map(|&i| dict[i as usize]).collect();
+            let mut side_sink = VecSink::<u64>::new();
+            let mut pipeline = SinkBuilder::new(VecSink::<u64>::new())
+                                   .map(&dict[..]).downsample(every).tee(&mut side_sink).add_const(1).build();
+            unpack(&buf[..written], &mut pipeline, num_values).unwrap();
+            let expected: Vec<u64> = kept.iter().map(|&v| v + 1).collect();
+            assert_eq!(pipeline.sink.as_slice(), &expected[..], "{} values, every {}", num_values, every);
+            drop(pipeline);
+            assert_eq!(side_sink.as_slice(), &kept[..], "{} values, every {}", num_values, every);
+        }
+    }
+
+    // Without a reset, a second decode carries on downsampling where the first left off
+    let written = pack_u64(indices[..13].iter().cloned(), &mut buf, 0).unwrap();
+    let mut pipeline = SinkBuilder::new(VecSink::<u64>::new()).downsample(3).build();
+    unpack(&buf[..written], &mut pipeline, 13).unwrap();
+    unpack(&buf[..written], &mut pipeline, 13).unwrap();
+    let twice: Vec<u64> = indices[..13].iter().chain(&indices[..13]).cloned().step_by(3).collect();
+    assert_eq!(pipeline.sink.as_slice(), &twice[..]);
+
+    // An unpack of 0 values in between marks no new padding, so it does not move where downsampling resumes
+    let mut pipeline = SinkBuilder::new(VecSink::<u64>::new()).downsample(3).build();
+    unpack(&buf[..written], &mut pipeline, 13).unwrap();
+    unpack(&buf[..0], &mut pipeline, 0).unwrap();
+    unpack(&buf[..written], &mut pipeline, 13).unwrap();
+    assert_eq!(pipeline.sink.as_slice(), &twice[..]);
+}
+
+#[test]
+fn compare_packed_values() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+    let mut buf_a = [0u8; 256];
+    let mut buf_b = [0u8; 256];
+    let len_a = pack_u64(inputs.iter().cloned(), &mut buf_a, 0).unwrap();
+    let len_b = pack_u64(inputs.iter().cloned(), &mut buf_b, 0).unwrap();
+    assert!(packed_values_eq(&buf_a[..len_a], inputs.len(), &buf_b[..len_b], inputs.len()));
+
+    // Same values, but the padding in the final block differs
+    let len_b = pack_u64(inputs.iter().cloned().chain([9u64, 9, 9, 9].iter().cloned()), &mut buf_b, 0).unwrap();
+    assert!(buf_a[..len_a] != buf_b[..len_b]);
+    assert!(packed_values_eq(&buf_a[..len_a], inputs.len(), &buf_b[..len_b], inputs.len()));
+
+    // Differing values, and differing lengths
+    let mut other = inputs;
+    other[10] += 1;
+    let len_b = pack_u64(other.iter().cloned(), &mut buf_b, 0).unwrap();
+    assert!(!packed_values_eq(&buf_a[..len_a], inputs.len(), &buf_b[..len_b], inputs.len()));
+    assert!(packed_values_eq(&buf_a[..len_a], 10, &buf_b[..len_b], 10));
+    assert!(!packed_values_eq(&buf_a[..len_a], inputs.len(), &buf_a[..len_a], 8));
+
+    // Truncated buffer
+    assert!(!packed_values_eq(&buf_a[..len_a], inputs.len(), &buf_a[..len_a - 3], inputs.len()));
+
+    // Identical but invalid buffers are still invalid
+    assert!(!packed_values_eq(&buf_a[..len_a - 3], inputs.len(), &buf_a[..len_a - 3], inputs.len()));
+    assert!(!packed_values_eq(&[0xf2u8, 0, 0], 8, &[0xf2u8, 0, 0], 8));
+}
+
+#[test]
+fn pack_unpack_f64_lossy() {
+    // Noisy series across a range of magnitudes and signs
+    let inputs: Vec<f64> = (0..200).map(|i| ((i as f64 * 0.37).sin() * 1000.0 + (i as f64 * 7.1).cos()) / 3.0)
+                                   .chain(vec![0.0, -0.0, 1e-310, 12345678.9, f64::INFINITY].into_iter())
+                                   .collect();
+    let max_abs_error = 0.01;
+    let mut buf = [0u8; 4096];
+    let written = pack_f64_lossy(&inputs[..], max_abs_error, &mut buf).unwrap();
+
+    let mut sink = DoubleXorSink::new(Vec::with_capacity(256));
+    unpack_f64_xor(&buf[..written], &mut sink, inputs.len()).unwrap();
+    for (out, orig) in sink.vec.iter().zip(inputs.iter()) {
+        if orig.is_finite() {
+            assert!((out - orig).abs() < max_abs_error, "{} vs {}", out, orig);
+        } else {
+            assert_eq!(out, orig);
+        }
+    }
+
+    let mut exact_buf = [0u8; 4096];
+    let exact_written = pack_f64_xor(inputs.iter().cloned(), &mut exact_buf).unwrap();
+    assert!(written < exact_written, "Lossy: {} bytes, exact XOR: {} bytes", written, exact_written);
+
+    assert!(pack_f64_lossy(&inputs[..], 0.0, &mut buf).is_err());
+    assert!(pack_f64_lossy(&inputs[..], f64::NAN, &mut buf).is_err());
+}
+
+#[test]
+fn unpack_to_stack_array() {
+    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+    let mut buf = [0u8; 512];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let (values, rest) = unpack_array::<12>(&buf[..written]).unwrap();
+    assert_eq!(values, inputs);
+    assert_eq!(rest.len(), 0);
+
+    // Exactly one block, leaving the rest of the buffer
+    let (values, rest) = unpack_array::<8>(&buf[..written]).unwrap();
+    assert_eq!(values[..], inputs[..8]);
+    assert!(rest.len() > 0);
+
+    assert!(unpack_array::<20>(&buf[..written]).is_err());
+}
+
+#[test]
+fn delta_packer_chunks_match_one_shot() {
+    let inputs: Vec<u64> = (0..50).map(|i| 1_000_000 + i * i * 13).collect();
+    let mut buf = [0u8; 1024];
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+
+    let mut packer = DeltaPacker::new(Vec::new());
+    let mut start = 0;
+    for &chunk_len in [1, 3, 7, 0, 11, 5, 23].iter() {
+        packer.extend(&inputs[start..start + chunk_len]).unwrap();
+        start += chunk_len;
+    }
+    let packed = packer.finish().unwrap();
+    assert_eq!(packed[..], buf[..written]);
+
+    let mut sink = DeltaSink::new();
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+}
+
+#[test]
+fn unpack_f64_xor_drops_padding() {
+    let inputs: Vec<f64> = (0..11).map(|i| i as f64 * 1.5).collect();
+    let mut buf = [0u8; 256];
+    let written = pack_f64_xor(inputs.iter().cloned(), &mut buf).unwrap();
+
+    // 10 values after the initial one take 2 blocks, but the 6 padding values are not kept
+    let mut sink = DoubleXorSink::new(Vec::new());
+    unpack_f64_xor(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.output_vec(), &inputs);
+
+    // Fewer values than were packed: everything past num_values is dropped, not just the padding
+    unpack_f64_xor(&buf[..written], &mut sink, 4).unwrap();
+    assert_eq!(sink.output_vec()[..], inputs[..4]);
+}
+
+#[test]
+fn unpack_f64_xor_append_columns() {
+    let col1 = [0f64, 0.5, 2.5, 10., 25., 100.];
+    let col2 = [-1.25f64, -1.5, 3.0, 3.0, 3.0, 7.75, 8.0, 8.25, 9.0, 1e10, -3.5];
+    let mut buf1 = [0u8; 512];
+    let mut buf2 = [0u8; 512];
+    let written1 = pack_f64_xor(col1.iter().cloned(), &mut buf1).unwrap();
+    let written2 = pack_f64_xor(col2.iter().cloned(), &mut buf2).unwrap();
+
+    let mut out = Vec::with_capacity(64);
+    out.push(42.0);     // existing contents must be kept
+    {
+        let mut sink = DoubleXorSink::new(&mut out).with_append_mode();
+        let offset1 = sink.output_vec().len();
+        unpack_f64_xor(&buf1[..written1], &mut sink, col1.len()).unwrap();
+        let offset2 = sink.output_vec().len();
+        unpack_f64_xor(&buf2[..written2], &mut sink, col2.len()).unwrap();
+        assert_eq!(offset1, 1);
+        assert_eq!(offset2, 1 + col1.len());
+    }
+    assert_eq!(out.len(), 1 + col1.len() + col2.len());
+    assert_eq!(out[0], 42.0);
+    assert_eq!(out[1..1 + col1.len()], col1[..]);
+    assert_eq!(out[1 + col1.len()..], col2[..]);
+    assert_eq!(out.capacity(), 64);
+}
+
+#[test]
+fn pack_unpack_u64_f64_pairs() {
+    let pairs: Vec<(u64, f64)> = (0..21).map(|i| (1_600_000_000_000 + i * 15_000, 100.25 + (i % 4) as f64 * 0.5))
+                                        .collect();
+    let mut ts_buf = [0u8; 512];
+    let mut val_buf = [0u8; 512];
+    let (ts_len, val_len) = pack_u64_f64_pairs(&pairs[..], &mut ts_buf, &mut val_buf).unwrap();
+    assert!(ts_len + val_len < pairs.len() * 16,
+            "Packed {} pairs into {} + {} bytes", pairs.len(), ts_len, val_len);
+
+    let out = unpack_u64_f64_pairs(&ts_buf[..ts_len], &val_buf[..val_len], pairs.len()).unwrap();
+    assert_eq!(out, pairs);
+
+    assert_eq!(pack_u64_f64_pairs(&[], &mut ts_buf, &mut val_buf), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn pack_unpack_ip_addrs() {
+    let mut buf = [0u8; 1024];
+
+    // Increasing addresses in one subnet, then a few scattered ones
+    let mut v4: Vec<Ipv4Addr> = (0..20u32).map(|i| Ipv4Addr::new(10, 1, 2, (i * 7) as u8)).collect();
+    v4.extend_from_slice(&[Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(8, 8, 8, 8),
+                           Ipv4Addr::new(255, 255, 255, 255), Ipv4Addr::new(0, 0, 0, 0)]);
+    let written = pack_ipv4(&v4[..], &mut buf).unwrap();
+    assert_eq!(unpack_ipv4(&buf[..written], v4.len()).unwrap(), v4);
+
+    let mut v6: Vec<Ipv6Addr> = (0..20u16).map(|i| Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i * 3)).collect();
+    v6.extend_from_slice(&[Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1ff, 0xfe23, 0x4567, 0x890a),
+                           Ipv6Addr::LOCALHOST, Ipv6Addr::UNSPECIFIED,
+                           Ipv6Addr::new(0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff)]);
+    let written = pack_ipv6(&v6[..], &mut buf).unwrap();
+    assert_eq!(unpack_ipv6(&buf[..written], v6.len()).unwrap(), v6);
+
+    // A corrupt count fails before anything is allocated for it
+    assert_eq!(unpack_ipv6(&buf[..written], usize::MAX), Err(CodingError::InputTooShort));
+    assert_eq!(unpack_ipv6(&buf[..written], written * 8 + 1), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn reset_sinks_and_redecode() {
+    // Generic reuse of any Reusable sink between decodes, which must not lose any capacity
+    fn decode_twice<S: Reusable<u64x8>>(encoded: &[u8], sink: &mut S, num_values: usize) {
+        unpack(encoded, sink, num_values).unwrap();
+        let capacity = sink.capacity();
+        sink.reset();
+        assert_eq!(sink.capacity(), capacity);
+        unpack(encoded, sink, num_values).unwrap();
+        assert_eq!(sink.capacity(), capacity);
+    }
+
+    let inputs: Vec<u64> = (0..100).map(|i| 1000 + i * i).collect();
+    let mut buf = [0u8; 1024];
+
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let mut sink = VecSink::<u64>::new();
+    decode_twice(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sink = DeltaSink::new();
+    decode_twice(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // A based DeltaSink goes back to its base, not 0, when reset
+    let deltas: Vec<u64> = inputs.iter().map(|&n| n - 1000).collect();
+    let written = pack_u64_delta(&deltas[..], &mut buf).unwrap();
+    let mut sink = DeltaSink::with_base(1000);
+    decode_twice(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    let written = pack_gauge(&inputs[..], &mut buf).unwrap();
+    let mut sink = ZigZagDeltaSink::new();
+    decode_twice(&buf[..written], &mut sink, inputs.len());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // The sinks which keep what they decode in sink.rs, and U128Sink
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let encoded = &buf[..written];
+    let old: Vec<u64> = inputs.iter().map(|&n| n - 1).collect();
+    let mut sink = SubtractSink::new(&old[..]);
+    decode_twice(encoded, &mut sink, inputs.len());
+    assert_eq!(sink.diffs(), &[1u64; 100][..]);
+
+    let baseline: Vec<i64> = inputs.iter().map(|&n| n as i64 + 2).collect();
+    let mut sink = BaselineDiffSink::new(&baseline[..]);
+    decode_twice(encoded, &mut sink, inputs.len());
+    assert_eq!(sink.diffs(), &[-2i64; 100][..]);
+
+    let mut sink = RingBufferSink::new(10);
+    decode_twice(encoded, &mut sink, inputs.len());
+    assert_eq!(sink.window(), &inputs[90..]);
+    assert_eq!(sink.total(), inputs.len());
+
+    let mut sink = ReservoirSink::new(200, 7);
+    decode_twice(encoded, &mut sink, inputs.len());
+    assert_eq!(sink.sample(), &inputs[..]);
+
+    let mut sink = FoldSink::new(0u64, |acc, n| acc + n);
+    decode_twice(encoded, &mut sink, inputs.len());
+    assert_eq!(sink.into_acc(), inputs.iter().sum::<u64>());
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut sink = ChannelSink::new(sender, 1000);
+    decode_twice(encoded, &mut sink, inputs.len());
+    sink.finish().unwrap();
+    assert_eq!(receiver.iter().flatten().collect::<Vec<u64>>(), inputs);
+
+    let mut sink = U128Sink::new();
+    decode_twice(encoded, &mut sink, inputs.len());
+    assert_eq!(sink.as_slice().len(), inputs.len());
+
+    // unpack_f64_xor resets the sink itself with the initial value, so reuse it directly
+    let floats: Vec<f64> = inputs.iter().map(|&n| n as f64 / 4.0).collect();
+    let written = pack_f64_xor(floats.iter().cloned(), &mut buf).unwrap();
+    let mut sink = DoubleXorSink::new(Vec::with_capacity(128));
+    unpack_f64_xor(&buf[..written], &mut sink, floats.len()).unwrap();
+    sink.reset();
+    unpack_f64_xor(&buf[..written], &mut sink, floats.len()).unwrap();
+    assert_eq!(sink.output_vec()[..], floats[..]);
+    assert_eq!(sink.capacity(), 128);
+}
+
+#[test]
+fn subtract_sink_diffs_columns() {
+    // Two increasing columns, where a few positions in this minute are a bit below last minute
+    let last_minute: Vec<u64> = (0..45).map(|i| 10_000 + i * 37).collect();
+    let this_minute: Vec<u64> = last_minute.iter().enumerate()
+                                           .map(|(i, &n)| if i % 11 == 5 { n - 10 } else { n + (i as u64 % 4) * 3 })
+                                           .collect();
+    assert!(this_minute.windows(2).all(|w| w[0] <= w[1]));
+    let mut buf = [0u8; 1024];
+    let written = pack_u64_delta(&this_minute[..], &mut buf).unwrap();
+
+    let mut sink = SubtractSink::new(&last_minute[..]);
+    range_decode_delta(&buf[..written], 0, this_minute.len(), &mut sink).unwrap();
+
+    let expected: Vec<i64> = this_minute.iter().zip(last_minute.iter()).map(|(&a, &b)| a as i64 - b as i64).collect();
+    let diffs: Vec<i64> = sink.diffs().iter().map(|&d| d as i64).collect();
+    assert_eq!(diffs, expected);
+    assert!(sink.any_decreased());
+    for i in 0..this_minute.len() {
+        assert_eq!(sink.is_decreased(i), this_minute[i] < last_minute[i], "position {}", i);
+    }
+    // Padding past the end of the last octet is never marked as decreased
+    assert_eq!(sink.decreased().len(), 6);
+    assert_eq!(sink.decreased()[5] & 0xe0, 0);
+
+    // Decoding the column in two parts without a reset gives the same result, as the padding of the first part
+    // is rolled back out of the position in the old column
+    sink.reset();
+    let (first, second) = this_minute.split_at(21);
+    let written = pack_u64(first.iter().cloned(), &mut buf, 0).unwrap();
+    let written2 = pack_u64(second.iter().cloned(), &mut buf, written).unwrap();
+    let rest = unpack(&buf[..written2], &mut sink, first.len()).unwrap();
+    unpack(rest, &mut sink, second.len()).unwrap();
+    let diffs: Vec<i64> = sink.diffs().iter().map(|&d| d as i64).collect();
+    assert_eq!(diffs, expected);
+    for i in 0..this_minute.len() {
+        assert_eq!(sink.is_decreased(i), this_minute[i] < last_minute[i], "position {}", i);
+    }
+    assert_eq!(sink.decreased().len(), 6);
+
+    // Diffing a column against itself gives all zeroes
+    sink.reset();
+    let written = pack_u64(last_minute.iter().cloned(), &mut buf, 0).unwrap();
+    unpack(&buf[..written], &mut sink, last_minute.len()).unwrap();
+    assert!(sink.diffs().iter().all(|&d| d == 0));
+    assert_eq!(sink.diffs().len(), last_minute.len());
+    assert!(!sink.any_decreased());
+}
+
+#[test]
+fn sinks_with_capacity_never_grow() {
+    let inputs: Vec<u64> = (0..1000).map(|i| i * 3 + (i % 2)).collect();
+    let mut buf = [0u8; 8192];
+
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let mut sink = VecSink::<u64>::with_capacity(1000);
+    let capacity = sink.vec.capacity();
+    unpack(&buf[..written], &mut sink, 1000).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+    assert_eq!(sink.vec.capacity(), capacity);
+
+    // 995 values still decode 1000 including padding
+    let mut sink = VecSink::<u64>::with_capacity(995);
+    let capacity = sink.vec.capacity();
+    unpack(&buf[..written], &mut sink, 995).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..995]);
+    assert_eq!(sink.vec.capacity(), capacity);
+
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sink = DeltaSink::with_capacity(1000);
+    let capacity = sink.output_vec().capacity();
+    unpack(&buf[..written], &mut sink, 1000).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+    assert_eq!(sink.output_vec().capacity(), capacity);
+
+    let floats: Vec<f64> = inputs.iter().map(|&n| n as f64 * 0.5).collect();
+    let written = pack_f64_xor(floats.iter().cloned(), &mut buf).unwrap();
+    let mut sink = DoubleXorSink::with_capacity(1000);
+    let capacity = sink.output_vec().capacity();
+    unpack_f64_xor(&buf[..written], &mut sink, 1000).unwrap();
+    assert_eq!(sink.output_vec()[..], floats[..]);
+    assert_eq!(sink.output_vec().capacity(), capacity);
+}
+
+#[test]
+fn pack_timestamps_out_of_order() {
+    // Timestamps 10ms apart, with some neighbours swapped, plus one big jump back
+    let mut inputs: Vec<u64> = (0..60).map(|i| 1_600_000_000_000 + i * 10).collect();
+    for &i in [3usize, 17, 40].iter() {
+        inputs.swap(i, i + 1);
+    }
+    let mut buf = [0u8; 1024];
+    let stats = pack_timestamps(&inputs[..], 20, &mut buf).unwrap();
+    assert_eq!(stats.max_backward_jump, 10);
+    assert_eq!(stats.num_outside_window, 0);
+
+    let mut sink = ZigZagDeltaSink::new();
+    unpack(&buf[..stats.bytes_written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // pack_u64_delta would have clipped the swaps
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sink = DeltaSink::new();
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_ne!(sink.as_slice(), &inputs[..]);
+
+    inputs[50] -= 10_000;
+    let stats = pack_timestamps(&inputs[..], 20, &mut buf).unwrap();
+    assert_eq!(stats.max_backward_jump, 10_000 - 10);
+    assert_eq!(stats.num_outside_window, 1);
+    let mut sink = ZigZagDeltaSink::new();
+    unpack(&buf[..stats.bytes_written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+}
+
+#[test]
+fn pack_u64_stepped_regular_and_jittery() {
+    let step = 15_000;
+    let mut inputs: Vec<u64> = (0..200).map(|i| 1_600_000_000_000 + i * step).collect();
+    let mut buf = [0u8; 2048];
+
+    // Only the first value is nonzero, every other block is a single zero byte
+    let written = pack_u64_stepped(&inputs[..], step, &mut buf).unwrap();
+    assert_eq!(written, block_len(&buf).unwrap() + 24);
+    let mut sink = ZigZagDeltaSink::with_step(step);
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // Jitter in both directions, including an early sample before the one it follows would be due
+    for &(i, jitter) in [(7usize, 3i64), (50, -2), (51, 1), (120, -14_999), (199, 40)].iter() {
+        inputs[i] = (inputs[i] as i64 + jitter) as u64;
+    }
+    let written = pack_u64_stepped(&inputs[..], step, &mut buf).unwrap();
+    assert!(written < pack_gauge(&inputs[..], &mut [0u8; 2048]).unwrap());
+    sink.reset();
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+}
+
+#[test]
+fn packed_appender_extends_buffer() {
+    let inputs: Vec<u64> = (0..45).map(|i| if i % 6 == 0 { 0 } else { i * 40_503 }).collect();
+    for &(first_len, second_len) in [(13usize, 20usize), (16, 5), (0, 9), (3, 4), (45, 0)].iter() {
+        let first = &inputs[..first_len];
+        let second = &inputs[first_len..first_len + second_len];
+        let packed = pack_u64_to_vec(first.iter().cloned());
+
+        let mut appender = PackedAppender::try_new(packed, first_len).unwrap();
+        appender.extend(second).unwrap();
+        assert_eq!(appender.num_values(), first_len + second_len);
+        let packed = appender.finish().unwrap();
+
+        // Appending gives the same bytes as packing everything at once
+        let all = &inputs[..first_len + second_len];
+        assert_eq!(packed, pack_u64_to_vec(all.iter().cloned()));
+        let mut sink = VecSink::<u64>::new();
+        unpack(&packed[..], &mut sink, all.len()).unwrap();
+        assert_eq!(sink.as_slice(), all);
+    }
+
+    let packed = pack_u64_to_vec(inputs[..13].iter().cloned());
+    assert_eq!(PackedAppender::try_new(packed, 17).err(), Some(CodingError::InputTooShort));
+
+    // A corrupt last block is reported as such, not as a truncated buffer
+    let mut packed = pack_u64_to_vec(inputs[..13].iter().cloned());
+    let last_block = walk_blocks(&packed[..], 1, |_, _| {}).unwrap();
+    packed[last_block + 1] = 0xff;
+    assert_eq!(PackedAppender::try_new(packed, 13).err(), Some(CodingError::CorruptHeader(0xff)));
+}
+
+#[test]
+fn pack_unpack_sparse_u64() {
+    // 95% null, with a few clustered and a few leading and trailing nulls
+    let inputs: Vec<Option<u64>> = (0..1000u64).map(|i| {
+        if i % 20 == 7 || (500..503).contains(&i) { Some(i * 1_000_003) } else { None }
+    }).collect();
+    let mut buf = [0u8; 2048];
+    let written = pack_sparse_u64(&inputs[..], &mut buf).unwrap();
+    assert_eq!(unpack_sparse_u64(&buf[..written], inputs.len()).unwrap(), inputs);
+
+    // Smaller than a null bitmap plus the present values packed on their own
+    let mut values_buf = [0u8; 2048];
+    let values_len = pack_u64(inputs.iter().filter_map(|&v| v), &mut values_buf, 0).unwrap();
+    assert!(written < values_len + inputs.len() / 8, "{} vs {}", written, values_len + inputs.len() / 8);
+
+    for inputs in [vec![None; 13], vec![Some(0u64), None, Some(5)], vec![]].iter() {
+        let written = pack_sparse_u64(&inputs[..], &mut buf).unwrap();
+        assert_eq!(&unpack_sparse_u64(&buf[..written], inputs.len()).unwrap(), inputs);
+    }
+
+    let written = pack_sparse_u64(&inputs[..], &mut buf).unwrap();
+    // The last value present is at 987
+    assert!(unpack_sparse_u64(&buf[..written], 980).is_err());
+
+    // A corrupt count of present values fails before anything is allocated for them
+    let corrupt = [0xffu8, 0xff, 0xff, 0x7f, 0, 0];
+    assert!(matches!(unpack_sparse_u64(&corrupt, 1000), Err(CodingError::InvalidFormat(_))));
+    assert_eq!(unpack_sparse_u64(&corrupt, usize::MAX), Err(CodingError::InputTooShort));
+
+    // A corrupt run of nulls which would overflow the count of values decoded so far
+    let mut corrupt_run = [0u8; 64];
+    corrupt_run[0] = 2;
+    let off = pack_u64([1u64, u64::MAX].iter().cloned(), &mut corrupt_run, 4).unwrap();
+    let off = pack_u64([5u64, 6].iter().cloned(), &mut corrupt_run, off).unwrap();
+    assert!(matches!(unpack_sparse_u64(&corrupt_run[..off], 1000), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn vec_sink_process_without_reserve() {
+    // Sinks may be driven directly by other sinks, without any capacity reserved up front
+    let mut sink = VecSink::<u64>::with_capacity(0);
+    for i in 0..100u64 {
+        sink.process(u64x8::splat(i));
+        sink.process_zeroes();
+    }
+    assert_eq!(sink.vec.len(), 1600);
+    assert_eq!(sink.vec[1584..1592], [99u64; 8]);
+    assert_eq!(sink.vec[1592..], [0u64; 8]);
+}
+
+#[test]
+fn delta_sink_last_value_chains_chunks() {
+    let inputs: Vec<u64> = (0..53).map(|i| 5000 + i * i * 3).collect();
+    let mut buf = [0u8; 1024];
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sink = DeltaSink::new();
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.last_value(), inputs[inputs.len() - 1]);
+
+    // Pack the second chunk as deltas continuing from the first chunk, and decode it starting from the first
+    // chunk's last value
+    let (first, second) = inputs.split_at(20);
+    let second_deltas: Vec<u64> = second.iter().zip(inputs[19..].iter()).map(|(b, a)| b - a).collect();
+    let mut sink = DeltaSink::new();
+    let written = pack_u64_delta(first, &mut buf).unwrap();
+    unpack(&buf[..written], &mut sink, first.len()).unwrap();
+
+    let written = pack_u64(second_deltas.iter().cloned(), &mut buf, 0).unwrap();
+    let mut second_sink = DeltaSink::with_base(sink.last_value());
+    assert_eq!(second_sink.last_value(), first[first.len() - 1]);
+    unpack(&buf[..written], &mut second_sink, second.len()).unwrap();
+    assert_eq!(second_sink.as_slice(), second);
+    assert_eq!(second_sink.last_value(), inputs[inputs.len() - 1]);
+}
+
+#[test]
+fn delta_split_roundtrip() {
+    let inputs: Vec<u64> = (0..50).map(|i| 1_600_000_000_000 + i * 15_000 + (i % 4)).collect();
+    let (base, body) = pack_u64_delta_split(&inputs[..]);
+    assert_eq!(base, inputs[0]);
+    // The body only holds small deltas, so it is the same as packing them on their own
+    let deltas: Vec<u64> = inputs.windows(2).map(|w| w[1] - w[0]).collect();
+    assert_eq!(body, pack_u64_to_vec(deltas.iter().cloned()));
+
+    assert_eq!(unpack_u64_delta_split(base, &body[..], inputs.len()).unwrap(), inputs);
+
+    let (base, body) = pack_u64_delta_split(&[42]);
+    assert!(body.is_empty());
+    assert_eq!(unpack_u64_delta_split(base, &body[..], 1).unwrap(), vec![42]);
+    let (base, body) = pack_u64_delta_split(&[]);
+    assert_eq!(unpack_u64_delta_split(base, &body[..], 0).unwrap(), Vec::<u64>::new());
+}
+
+#[test]
+fn pack_u64_slice_matches_iterator() {
+    let fixtures: Vec<Vec<u64>> = vec![
+        vec![],
+        vec![0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078],
+        (0..64).map(|i| if i % 3 == 0 { 0 } else { 1 << i }).collect(),
+        (0..1003).map(|i| i * 7919 % 65_537).collect(),
+    ];
+    for inputs in fixtures.iter() {
+        let mut buf = [0u8; 8192];
+        let written = pack_u64(inputs.iter().cloned(), &mut buf, 3).unwrap();
+        let mut slice_buf = [0u8; 8192];
+        let slice_written = pack_u64_slice(&inputs[..], &mut slice_buf, 3).unwrap();
+        assert_eq!(slice_written, written);
+        assert_eq!(slice_buf[..slice_written], buf[..written]);
+        assert_eq!(pack_u64_to(inputs.iter().cloned(), Vec::new()).unwrap(), &buf[3..written]);
+    }
+    assert_eq!(pack_u64_slice(&fixtures[3][..], &mut [0u8; 64], 0), Err(CodingError::NotEnoughSpace));
+}
+
+#[test]
+fn unpack_into_output_vs_input_too_short() {
+    let inputs: Vec<u64> = (0..21).map(|i| i * 100_003).collect();
+    let mut buf = [0u8; 512];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let mut out = [0u64; 21];
+    let rest = unpack_into(&buf[..written], &mut out, 21).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(out[..], inputs[..]);
+
+    // Larger output slices are fine, and are only written up to num_values
+    let mut out = [42u64; 30];
+    unpack_into(&buf[..written], &mut out, 21).unwrap();
+    assert_eq!(out[..21], inputs[..]);
+    assert_eq!(out[21..], [42u64; 9]);
+
+    let mut out = [0u64; 20];
+    assert_eq!(unpack_into(&buf[..written], &mut out, 21), Err(CodingError::OutputTooShort(21, 20)));
+
+    let mut out = [0u64; 21];
+    assert_eq!(unpack_into(&buf[..written - 3], &mut out, 21), Err(CodingError::InputTooShort));
+    assert_eq!(unpack_into(&buf[..0], &mut out, 21), Err(CodingError::InputTooShort));
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn unpack_bytes_returns_remainder() {
+    let first: Vec<u64> = (0..20).map(|i| i * 1000).collect();
+    let mut packed = pack_u64_to_vec(first.iter().cloned());
+    let first_len = packed.len();
+    packed.extend_from_slice(&pack_u64_to_vec([7u64, 8, 9].iter().cloned())[..]);
+    let buf = Bytes::from(packed);
+
+    let mut sink = VecSink::<u64>::new();
+    let rest = unpack_bytes(buf.clone(), &mut sink, first.len()).unwrap();
+    assert_eq!(sink.as_slice(), &first[..]);
+    assert_eq!(rest, buf.slice(first_len..));
+    // The remainder points into the same memory rather than a copy
+    assert_eq!(rest.as_ptr(), buf[first_len..].as_ptr());
+
+    let mut sink = VecSink::<u64>::new();
+    let rest = unpack_bytes(rest, &mut sink, 3).unwrap();
+    assert_eq!(sink.as_slice(), &[7, 8, 9]);
+    assert!(rest.is_empty());
+    assert_eq!(unpack_bytes(buf.slice(..5), &mut sink, 20), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn unpack_to_vec_drops_padding() {
+    let inputs: Vec<u64> = (1..=10).map(|i| i * 7777).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+
+    let values = unpack_to_vec(&packed[..], 10).unwrap();
+    assert_eq!(values.len(), 10);
+    assert_eq!(values, inputs);
+
+    assert_eq!(unpack_to_vec(&packed[..], 0).unwrap(), Vec::<u64>::new());
+    assert_eq!(unpack_to_vec(&packed[..packed.len() - 2], 10), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn validate_accepts_valid_and_rejects_corrupt() {
+    let inputs: Vec<u64> = (0..45).map(|i| if i < 16 { 0 } else { i * 0x1234_5678_9abc }).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+    assert_eq!(validate(&packed[..], inputs.len()), Ok(()));
+    assert_eq!(validate(&[], 0), Ok(()));
+    let mut buf = [0u8; 1024];
+    let written = pack_u64((0..20u64).map(|i| u64::MAX - i), &mut buf, 0).unwrap();
+    assert_eq!(validate(&buf[..written], 20), Ok(()));
+
+    assert_eq!(validate(&packed[..packed.len() - 1], inputs.len()), Err(CodingError::InputTooShort));
+    assert_eq!(validate(&packed[..], inputs.len() + 8), Err(CodingError::InputTooShort));
+    let mut longer = packed.clone();
+    longer.push(0);
+    assert!(matches!(validate(&longer[..], inputs.len()), Err(CodingError::InvalidFormat(_))));
+
+    // The third block is the first with nonzero values: 16 nibbles with 2 trailing ones is too wide
+    let mut garbage = packed.clone();
+    garbage[3] = 0xf2;
+    assert_eq!(validate(&garbage[..], inputs.len()), Err(CodingError::CorruptHeader(0xf2)));
+
+    // 45 values leave 3 padding values in the last block, which must be zero
+    assert!(matches!(validate(&packed[..], 43), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn tiering_hint_suggestions() {
+    // A constant counter, delta packed: after the first block every block is all zero
+    let constant = vec![123_456u64; 800];
+    let mut buf = [0u8; 8192];
+    let written = pack_u64_delta(&constant[..], &mut buf).unwrap();
+    let hint = tiering_hint(&buf[..written], constant.len()).unwrap();
+    assert_eq!((hint.num_blocks, hint.nonzero_blocks), (100, 1));
+    assert_eq!(hint.header_bytes + hint.payload_bytes, written);
+    assert!(hint.header_fraction() > 0.9);
+    assert_eq!(hint.suggested_reencoding(), Reencoding::RunLength);
+
+    // Millisecond timestamps packed as is are all 11 nibbles wide
+    let timestamps: Vec<u64> = (0..800).map(|i| 1_600_000_000_000 + i * 1000 + (i % 7)).collect();
+    let packed = pack_u64_to_vec(timestamps.iter().cloned());
+    let hint = tiering_hint(&packed[..], timestamps.len()).unwrap();
+    assert_eq!(hint.most_common_nibbles, Some(11));
+    assert_eq!(hint.suggested_reencoding(), Reencoding::Delta);
+    // Delta packing them fixes that
+    let written = pack_u64_delta(&timestamps[..], &mut buf).unwrap();
+    assert_eq!(tiering_hint(&buf[..written], timestamps.len()).unwrap().suggested_reencoding(), Reencoding::Keep);
+
+    // Random 64 bit values use every bit, and no re-encoding helps
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    let random: Vec<u64> = (0..800).map(|_| { x ^= x << 13; x ^= x >> 7; x ^= x << 17; x }).collect();
+    let packed = pack_u64_to_vec(random.iter().cloned());
+    let hint = tiering_hint(&packed[..], random.len()).unwrap();
+    assert_eq!(hint.most_common_nibbles, Some(16));
+    assert_eq!(hint.suggested_reencoding(), Reencoding::Keep);
+
+    let hint = tiering_hint(&[], 0).unwrap();
+    assert_eq!((hint.most_common_nibbles, hint.header_fraction()), (None, 0.0));
+    assert_eq!(tiering_hint(&packed[..10], random.len()), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn walk_blocks_lengths() {
+    let inputs: Vec<u64> = (0..100).map(|i| if i % 3 == 0 { 0 } else { i * i * 1001 }).chain(vec![0; 16]).collect();
+    let mut buf = [0u8; 2048];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let num_blocks = (inputs.len() + 7) / 8;
+
+    let mut offsets = Vec::new();
+    let mut total_len = 0;
+    let walked = walk_blocks(&buf[..written], num_blocks, |off, len| {
+        offsets.push(off);
+        total_len += len;
+    }).unwrap();
+    assert_eq!(walked, written);
+    assert_eq!(total_len, written);
+    assert_eq!(offsets.len(), num_blocks);
+    assert_eq!(offsets[0], 0);
+
+    assert_eq!(walk_blocks(&buf[..written - 1], num_blocks, |_, _| {}), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn value_byte_offset_matches_block_lens() {
+    let inputs: Vec<u64> = (0..100).map(|i| if i % 20 < 8 { 0 } else { i * 0x10_0001 }).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+
+    let mut expected = 0;
+    for block in 0..13 {
+        for index in block * 8..block * 8 + 8 {
+            if index < inputs.len() {
+                assert_eq!(value_byte_offset(&packed[..], inputs.len(), index), Ok(expected));
+            }
+        }
+        // Decoding from the offset gives the block holding the value
+        let mut sink = VecSink::<u64>::new();
+        nibble_unpack8(&packed[expected..], &mut sink).unwrap();
+        let end = (block * 8 + 8).min(inputs.len());
+        assert_eq!(sink.vec[..end - block * 8], inputs[block * 8..end]);
+        expected += block_len(&packed[expected..]).unwrap();
+    }
+    assert_eq!(expected, packed.len());
+    // In the padding of the last block, and past it
+    assert!(matches!(value_byte_offset(&packed[..], inputs.len(), 100), Err(CodingError::InvalidFormat(_))));
+    assert!(matches!(value_byte_offset(&packed[..], inputs.len(), 104), Err(CodingError::InvalidFormat(_))));
+    assert_eq!(value_byte_offset(&packed[..], 110, 104), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn truncate_packed_prefix() {
+    let inputs: Vec<u64> = (0..50).map(|i| if i % 4 == 0 { 0 } else { i * 1_000_003 }).collect();
+    let mut buf = [0u8; 1024];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    for &keep in [0usize, 1, 8, 13, 16, 49, 50].iter() {
+        let (prefix, num_values) = truncate_packed(&buf[..written], keep).unwrap();
+        assert_eq!(num_values, (keep + 7) / 8 * 8);
+        let mut sink = VecSink::<u64>::new();
+        let rest = unpack(prefix, &mut sink, num_values).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(sink.vec[..keep], inputs[..keep]);
+    }
+    let (prefix, _) = truncate_packed(&buf[..written], 50).unwrap();
+    assert_eq!(prefix.len(), written);
+
+    assert_eq!(truncate_packed(&buf[..written], 57), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn pack_u64_to_vec_matches_slice() {
+    for &len in [0usize, 1, 8, 13, 100].iter() {
+        let inputs: Vec<u64> = (0..len as u64).map(|i| if i % 5 == 0 { 0 } else { i * i * 7919 }).collect();
+        let mut buf = [0u8; 1024];
+        let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+        let packed = pack_u64_to_vec(inputs.iter().cloned());
+        assert_eq!(packed[..], buf[..written]);
+        assert!(packed.capacity() >= estimate_packed_size(len));
+        assert!(written <= estimate_packed_size(len));
+
+        let mut sink = VecSink::<u64>::new();
+        unpack(&packed[..], &mut sink, len).unwrap();
+        assert_eq!(sink.as_slice(), &inputs[..]);
+    }
+}
+
+#[test]
+fn unpack8_corrupt_nibbles_header() {
+    let mut sink = VecSink::<u64>::new();
+    // 16 nibbles plus 1 trailing nibble is 68 bits
+    let mut buf = [0xffu8; 128];
+    buf[1] = 0xf1;
+    assert_eq!(nibble_unpack8(&buf[..], &mut sink), Err(CodingError::CorruptHeader(0xf1)));
+    // 9 nibbles plus 8 trailing nibbles
+    buf[1] = 0x88;
+    assert_eq!(nibble_unpack8(&buf[..], &mut sink), Err(CodingError::CorruptHeader(0x88)));
+    assert!(sink.vec.is_empty());
+
+    // 9 nibbles plus 7 trailing nibbles is exactly 64 bits, which is fine
+    buf[0] = 0x01;
+    buf[1] = 0x87;
+    assert!(nibble_unpack8(&buf[..], &mut sink).is_ok());
+    assert_eq!(sink.vec[0], 0xffff_ffff_f000_0000);
+}
+
+#[test]
+fn zero_block_bitmap() {
+    // 70 blocks alternating between all zeroes and one nonzero value, plus a final partial block of zeroes
+    let inputs: Vec<u64> = (0..563u64).map(|i| if (i / 8) % 2 == 1 && i % 8 == 3 { i } else { 0 }).collect();
+    let mut buf = [0u8; 1024];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let bitmap = all_zero_block_bitmap(&buf[..written], inputs.len()).unwrap();
+    assert_eq!(bitmap, vec![0x5555_5555_5555_5555u64, 0b101_0101]);
+    for block in 0..71 {
+        let is_zero = inputs[block * 8..(block * 8 + 8).min(inputs.len())].iter().all(|&n| n == 0);
+        assert_eq!(bitmap[block / 64] & (1 << (block % 64)) != 0, is_zero, "block {}", block);
+    }
+
+    assert_eq!(all_zero_block_bitmap(&buf[..written - 1], inputs.len()), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn decoder_resumed_matches_unpack() {
+    let inputs: Vec<u64> = (0..203u64).map(|i| 50_000 + i * 7 + (i % 5)).collect();
+    let mut buf = [0u8; 2048];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let encoded = &buf[..written + 9];    // Trailing bytes which should not be touched
+
+    let mut oneshot = VecSink::<u64>::new();
+    let oneshot_rest = unpack(encoded, &mut oneshot, inputs.len()).unwrap();
+
+    for &max_blocks in &[1, 3, 26] {
+        let mut decoder = Decoder::new(encoded, VecSink::<u64>::new(), inputs.len());
+        let mut steps = 0;
+        while decoder.decode_blocks(max_blocks).unwrap() == DecodeProgress::More {
+            steps += 1;
+            assert_eq!(decoder.values_left(), inputs.len() - steps * max_blocks * 8);
+        }
+        assert_eq!(steps, (26 + max_blocks - 1) / max_blocks - 1);
+        assert_eq!(decoder.remaining(), oneshot_rest);
+        assert_eq!(decoder.decode_blocks(1), Ok(DecodeProgress::Done));
+        assert_eq!(decoder.into_sink().as_slice(), oneshot.as_slice());
+    }
+
+    // Delta state carries over between steps
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut decoder = Decoder::new(&buf[..written], DeltaSink::new(), inputs.len());
+    while decoder.decode_blocks(2).unwrap() == DecodeProgress::More {}
+    assert_eq!(decoder.sink().as_slice(), &inputs[..]);
+
+    // A truncated input fails at the block which is cut off, and the blocks before it are kept
+    let mut decoder = Decoder::new(&buf[..written - 1], DeltaSink::new(), inputs.len());
+    assert_eq!(decoder.decode_blocks(25), Ok(DecodeProgress::More));
+    let offset = decoder.offset();
+    assert_eq!(decoder.decode_blocks(1), Err(CodingError::InputTooShort));
+    assert_eq!(decoder.offset(), offset);
+    assert_eq!(decoder.sink().output_vec()[..200], inputs[..200]);
+}
+
+#[test]
+fn pack_u128_small_values_high_halves_compress() {
+    // Mostly small values, so almost every high half is zero
+    let inputs: Vec<u128> = (0..100u128).map(|i| if i == 50 { u128::MAX - i } else { i * 1000 }).collect();
+    let mut buf = [0u8; 2048];
+    let written = pack_u128(inputs.iter().cloned(), &mut buf).unwrap();
+
+    let mut low_buf = [0u8; 2048];
+    let low_written = pack_u64(inputs.iter().map(|&n| n as u64), &mut low_buf, 0).unwrap();
+    // 13 blocks of high halves: 12 zero blocks of 1 byte, and the one holding u128::MAX - 50
+    assert_eq!(written - low_written, 12 + 2 + 8);
+
+    let mut sink = U128Sink::new();
+    unpack_u128(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // The sink can be reused
+    let written = pack_u128(inputs[..3].iter().cloned(), &mut buf).unwrap();
+    unpack_u128(&buf[..written], &mut sink, 3).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..3]);
+}
+
+#[test]
+fn channel_sink_streams_to_consumer_thread() {
+    use std::sync::mpsc::channel;
+
+    let inputs: Vec<u64> = (0..1003u64).map(|i| (i * 7919) % 10_000).collect();
+    let mut buf = [0u8; 4096];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let (sender, receiver) = channel();
+    let consumer = std::thread::spawn(move || receiver.iter().collect::<Vec<Vec<u64>>>());
+    let mut sink = ChannelSink::new(sender, 100);
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    sink.finish().unwrap();
+
+    let batches = consumer.join().unwrap();
+    // Batches of 100 rounded up to 104, then the last 67 values without the padding
+    assert_eq!(batches.len(), 10);
+    assert!(batches[..9].iter().all(|batch| batch.len() == 104));
+    assert_eq!(batches[9].len(), 67);
+    assert_eq!(batches.concat(), inputs);
+
+    // A hung up receiver is reported by finish()
+    let (sender, receiver) = channel();
+    drop(receiver);
+    let mut sink = ChannelSink::new(sender, 16);
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.finish().unwrap_err().0, inputs[..16]);
+
+    // reset() forgets the failed batch, so finish() reports the first batch lost after it
+    let (sender, receiver) = channel();
+    drop(receiver);
+    let mut sink = ChannelSink::new(sender, 16);
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    sink.reset();
+    unpack(&buf[..written], &mut sink, 3).unwrap();
+    assert_eq!(sink.finish().unwrap_err().0, inputs[..3]);
+}
+
+#[test]
+fn versioned_pack_rejects_unknown_versions() {
+    let inputs: Vec<u64> = (0..21u64).map(|i| i * 1000).collect();
+    let mut buf = [0u8; 512];
+    let written = pack_versioned(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    assert_eq!(buf[0], 1);
+
+    let mut sink = VecSink::<u64>::new();
+    let rest = unpack_versioned(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    for &version in &[0u8, 2, 0xff] {
+        buf[0] = version;
+        sink.reset();
+        assert_eq!(unpack_versioned(&buf[..written], &mut sink, inputs.len()),
+                   Err(CodingError::UnsupportedVersion(version)));
+        assert!(sink.vec.is_empty());
+    }
+    assert_eq!(unpack_versioned(&buf[..0], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
+}
+
+#[test]
+fn baseline_diff_sink_constant_baseline() {
+    let inputs: Vec<u64> = (0..45u64).map(|i| 990 + (i * 13) % 25).collect();
+    let mut buf = [0u8; 512];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+    let baseline = vec![1000i64; inputs.len()];
+    let mut sink = BaselineDiffSink::new(&baseline[..]);
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    sink.check().unwrap();
+    let expected: Vec<i64> = inputs.iter().map(|&n| n as i64 - 1000).collect();
+    assert_eq!(sink.diffs(), &expected[..]);
+    assert!(sink.diffs().iter().any(|&d| d < 0));
+
+    // Only the padding goes past the end of a baseline of 41 values, which is fine
+    let mut sink = BaselineDiffSink::new(&baseline[..41]);
+    unpack(&buf[..written], &mut sink, 41).unwrap();
+    assert!(sink.check().is_ok());
+
+    // A column longer than the baseline
+    let mut sink = BaselineDiffSink::new(&baseline[..40]);
+    unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert!(matches!(sink.check(), Err(CodingError::InvalidFormat(_))));
+}
+
+#[test]
+fn pack_u64_scaled_shared_trailing_zeros() {
+    // Multiples of 8: 3 trailing zero bits, which the per-block trailing nibbles cannot drop
+    let inputs: Vec<u64> = (0..200u64).map(|i| ((i * 7919) % 4096) * 8).collect();
+    let mut buf = [0u8; 4096];
+    let written = pack_u64_scaled(&inputs[..], &mut buf).unwrap();
+    assert_eq!(buf[0], 3);
+    let mut plain_buf = [0u8; 4096];
+    let plain_written = pack_u64(inputs.iter().cloned(), &mut plain_buf, 0).unwrap();
+    assert!(written < plain_written, "{} vs {}", written, plain_written);
+
+    let mut sink = VecSink::<u64>::new();
+    let rest = unpack_u64_scaled(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // Multiples of 2^20 stack the global shift with trailing nibbles
+    let inputs: Vec<u64> = (1..50u64).map(|i| (i * 5) << 20).collect();
+    let written = pack_u64_scaled(&inputs[..], &mut buf).unwrap();
+    assert_eq!(buf[0], 20);
+    let mut sink = VecSink::<u64>::new();
+    unpack_u64_scaled(&buf[..written], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.as_slice(), &inputs[..]);
+
+    // All zeroes, and a corrupt shift
+    let written = pack_u64_scaled(&[0u64; 10], &mut buf).unwrap();
+    assert_eq!(buf[0], 0);
+    let mut sink = VecSink::<u64>::new();
+    unpack_u64_scaled(&buf[..written], &mut sink, 10).unwrap();
+    assert_eq!(sink.as_slice(), &[0u64; 10]);
+    buf[0] = 64;
+    assert!(matches!(unpack_u64_scaled(&buf[..written], &mut sink, 10), Err(CodingError::InvalidFormat(_))));
 }
 
-// Even nibbles with different combos of partial
-#[rustfmt::skip]   // We format the arrays specially to help visually see input vs output.  Don't reformat.
 #[test]
-fn nibblepack8_partial_evennibbles() {
-    // All 8 are nonzero, even # nibbles
-    let mut buf = [0u8; 512];
-    let inputs = [
-        0u64,
-        0x0000_0033_2211_0000u64, 0x0000_0044_3322_0000u64,
-        0x0000_0055_4433_0000u64, 0x0000_0066_5544_0000u64,
-        0u64,
-        0u64,
-        0u64,
-    ];
-    let res = nibble_pack8(&inputs, &mut buf, 0);
+fn progress_sink_reports_increasing_counts() {
+    let inputs: Vec<u64> = (0..1001u64).map(|i| i * 3).collect();
+    let mut buf = [0u8; 4096];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
 
-    // Expected result:
-    let expected_buf = [
-        0b0001_1110u8, // only some bits on
-        0x54u8,        // six nibbles wide, four zero nibbles trailing
-        0x11u8, 0x22u8, 0x33u8, 0x22u8, 0x33u8, 0x44u8,
-        0x33u8, 0x44u8, 0x55u8, 0x44u8, 0x55u8, 0x66u8,
-    ];
-    assert_eq!(res, Ok(2 + 3 * 4));
-    assert_eq!(buf[..expected_buf.len()], expected_buf);
+    let mut reports = vec![];
+    let mut out = VecSink::<u64>::new();
+    {
+        let mut sink = ProgressSink::new(&mut out, inputs.len(), 100, |n| reports.push(n));
+        unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+        assert_eq!(sink.processed(), inputs.len());
+    }
+    assert_eq!(out.as_slice(), &inputs[..]);
+    // Values arrive 8 at a time, so each report is at the first block reaching the next multiple of 100
+    assert_eq!(reports, vec![104, 200, 304, 400, 504, 600, 704, 800, 904, 1000, 1001]);
 }
 
-// Odd nibbles with different combos of partial
-#[rustfmt::skip]
 #[test]
-fn nibblepack8_partial_oddnibbles() {
-    // All 8 are nonzero, even # nibbles
-    let mut buf = [0u8; 512];
-    let inputs = [
-        0u64,
-        0x0000_0033_2210_0000u64, 0x0000_0044_3320_0000u64,
-        0x0000_0055_4430_0000u64, 0x0000_0066_5540_0000u64,
-        0x0000_0076_5430_0000u64, 0u64, 0u64,
-    ];
-    let res = nibble_pack8(&inputs, &mut buf, 0);
+fn ring_buffer_sink_keeps_last_window() {
+    let inputs: Vec<u64> = (0..5003u64).map(|i| (i * 7919) % 1000).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
 
-    // Expected result:
-    let expected_buf = [
-        0b0011_1110u8, // only some bits on
-        0x45u8,        // five nibbles wide, five zero nibbles trailing
-        0x21u8, 0x32u8, 0x23u8, 0x33u8, 0x44u8, // First two values
-        0x43u8, 0x54u8, 0x45u8, 0x55u8, 0x66u8,
-        0x43u8, 0x65u8, 0x07u8,
-    ];
-    assert_eq!(res, Ok(expected_buf.len()));
-    assert_eq!(buf[..expected_buf.len()], expected_buf);
+    let mut sink = RingBufferSink::new(100);
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.total(), inputs.len());
+    assert_eq!(sink.window(), &inputs[inputs.len() - 100..]);
+
+    // Fewer values than the window holds
+    sink.reset();
+    unpack(&packed[..], &mut sink, 13).unwrap();
+    assert_eq!(sink.window(), &inputs[..13]);
 }
 
-// Odd nibbles > 8 nibbles
-#[rustfmt::skip]
 #[test]
-fn nibblepack8_partial_oddnibbles_large() {
-    // All 8 are nonzero, even # nibbles
-    let mut buf = [0u8; 512];
-    let inputs = [
-        0u64,
-        0x0005_4433_2211_0000u64, 0x0000_0044_3320_0000u64,
-        0x0007_6655_4433_0000u64, 0x0000_0066_5540_0000u64,
-        0x0001_9876_5430_0000u64, 0u64, 0u64,
-    ];
-    let res = nibble_pack8(&inputs, &mut buf, 0);
+fn reverse_block_sink_reverses_each_group() {
+    let inputs: Vec<u64> = (1..=20u64).map(|i| i * 111).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+    let mut out = VecSink::<u64>::new();
+    let mut sink = ReverseBlockSink::new(&mut out);
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
 
-    // Expected result:
-    let expected_buf = [
-        0b0011_1110u8, // only some bits on
-        0x84u8,        // nine nibbles wide, four zero nibbles trailing
-        0x11u8, 0x22u8, 0x33u8, 0x44u8, 0x05u8, 0x32u8, 0x43u8, 0x04u8, 0,
-        0x33u8, 0x44u8, 0x55u8, 0x66u8, 0x07u8, 0x54u8, 0x65u8, 0x06u8, 0,
-        0x30u8, 0x54u8, 0x76u8, 0x98u8, 0x01u8,
-    ];
-    assert_eq!(res, Ok(expected_buf.len()));
-    assert_eq!(buf[..expected_buf.len()], expected_buf);
+    let mut expected = inputs.clone();
+    expected.resize(24, 0);
+    expected.chunks_mut(8).for_each(|group| group.reverse());
+    // The padding of the last group comes first, and is kept
+    assert_eq!(out.as_slice(), &expected[..]);
+    assert_eq!(out.as_slice()[16..20], [0, 0, 0, 0]);
 }
 
-#[rustfmt::skip]
 #[test]
-fn nibblepack8_64bit_numbers() {
-    let mut buf = [0u8; 512];
-    let inputs = [0, 0, -1i32 as u64, -2i32 as u64, 0, -100234i32 as u64, 0, 0];
-    let res = nibble_pack8(&inputs, &mut buf, 0);
+fn limit_sink_stops_at_max_values() {
+    let inputs: Vec<u64> = (1..=12u64).map(|i| i * 1000).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
 
-    let expected_buf = [
-        0b0010_1100u8,
-        0xf0u8, // all 16 nibbles wide, zero nibbles trailing
-        0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8,
-        0xfeu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8,
-        0x76u8, 0x78u8, 0xfeu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8,
-    ];
-    assert_eq!(res, Ok(expected_buf.len()));
-    assert_eq!(buf[..expected_buf.len()], expected_buf);
+    // The limit falls partway through the first block; the second block is dropped entirely
+    let mut out = VecSink::<u64>::new();
+    let mut sink = LimitSink::new(&mut out, 5);
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert!(sink.truncated());
+    assert_eq!(out.as_slice(), &inputs[..5]);
+
+    // Limits in the padding of the last block, or past the end, do not truncate
+    for &max in [12usize, 14, 16, 1000].iter() {
+        let mut out = VecSink::<u64>::new();
+        let mut sink = LimitSink::new(&mut out, max);
+        unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+        assert!(!sink.truncated(), "max {}", max);
+        assert_eq!(out.as_slice(), &inputs[..]);
+    }
+
+    // A limit on a block boundary, and a limit of zero
+    for &max in [8usize, 0].iter() {
+        let mut out = VecSink::<u64>::new();
+        let mut sink = LimitSink::new(&mut out, max);
+        unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+        assert!(sink.truncated());
+        assert_eq!(out.as_slice(), &inputs[..max]);
+    }
+
+    // Sinks which drop padding from what they already hold see exactly one mark_padding() with all of it
+    for &(max, total) in [(14usize, 12usize), (5, 5), (8, 8), (1000, 12)].iter() {
+        let mut ring = RingBufferSink::new(32);
+        let mut sink = LimitSink::new(&mut ring, max);
+        unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+        assert_eq!(ring.total(), total, "max {}", max);
+        assert_eq!(ring.window(), &inputs[..total]);
+    }
 }
 
 #[test]
-fn unpack8_all_zeroes() {
-    let compressed_array = [0x00u8];
-    let mut sink = VecSink::<u64>::new();
-    let res = nibble_unpack8(&compressed_array, &mut sink);
-    assert_eq!(res.unwrap().is_empty(), true);
-    assert_eq!(sink.vec.len(), 8);
-    assert_eq!(sink.vec[..], [0u64; 8]);
+fn verify_sink_reports_first_mismatch() {
+    let inputs: Vec<u64> = (0..50u64).map(|i| i * i * 31).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+
+    let mut sink = VerifySink::new(&inputs);
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.first_mismatch(), None);
+    assert_eq!(sink.num_values(), 50);
+    assert_eq!(sink.check(), Ok(()));
+
+    // Decoding fewer or more values than expected is a length mismatch, even though every value matches
+    let mut sink = VerifySink::new(&inputs);
+    unpack(&packed[..], &mut sink, 43).unwrap();
+    assert_eq!(sink.first_mismatch(), None);
+    assert_eq!(sink.check(), Err(VerifyMismatch::Length(50, 43)));
+    let mut sink = VerifySink::new(&inputs[..40]);
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.check(), Err(VerifyMismatch::Length(40, 50)));
+
+    // Only the first of several differences is reported, including one in a zero block or the last block
+    for &(first, second) in [(0usize, 5usize), (13, 14), (17, 49), (49, 49)].iter() {
+        let mut wrong = inputs.clone();
+        wrong[first] += 1;
+        wrong[second] ^= 0x100;
+        let mut sink = VerifySink::new(&wrong);
+        unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+        assert_eq!(sink.first_mismatch(), Some((first, wrong[first], inputs[first])), "first {}", first);
+        assert_eq!(sink.check(), Err(VerifyMismatch::Value(first, wrong[first], inputs[first])));
+
+        sink.reset();
+        unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+        assert_eq!(sink.first_mismatch().map(|(i, _, _)| i), Some(first));
+    }
+
+    let zeroes = pack_u64_to_vec(std::iter::repeat(0u64).take(16));
+    let mut wrong = vec![0u64; 16];
+    wrong[11] = 7;
+    let mut sink = VerifySink::new(&wrong);
+    unpack(&zeroes[..], &mut sink, 16).unwrap();
+    assert_eq!(sink.first_mismatch(), Some((11, 7, 0)));
 }
 
-#[rustfmt::skip]
 #[test]
-fn unpack8_input_too_short() {
-    let compressed = [
-        0b0011_1110u8, // only some bits on
-        0x84u8,        // nine nibbles wide, four zero nibbles trailing
-        0x11u8, 0x22u8, 0x33u8, 0x44u8, 0x05u8, 0x32u8, 0x43u8, 0x04u8, 0,
-        0x33u8, 0x44u8, 0x55u8, 0x66u8, 0x07u8,
-    ]; // too short!!
-    let mut sink = VecSink::<u64>::new();
-    let res = nibble_unpack8(&compressed, &mut sink);
-    assert_eq!(res, Err(CodingError::NotEnoughSpace));
+fn reservoir_sink_samples_uniformly() {
+    let inputs: Vec<u64> = (1..=10_000u64).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+
+    // Fewer values than k are all kept, in order, and the padding is never sampled
+    let mut sink = ReservoirSink::new(100, 7);
+    unpack(&packed[..], &mut sink, 37).unwrap();
+    assert_eq!(sink.sample(), &inputs[..37]);
+
+    let mut sink = ReservoirSink::new(1000, 7);
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    let mut sample = sink.sample().to_vec();
+    assert_eq!(sample.len(), 1000);
+    assert_eq!(sink.seen(), 10_000);
+    sample.sort_unstable();
+    sample.dedup();
+    assert_eq!(sample.len(), 1000);
+    assert!(sample.iter().all(|&n| n >= 1 && n <= 10_000));
+
+    // The mean of 1000 uniform picks from 1..=10_000 has a standard deviation of about 90, and each tenth of the
+    // range should get about 100 of them
+    let mean = sample.iter().sum::<u64>() as f64 / 1000.0;
+    assert!((mean - 5000.5).abs() < 450.0, "mean {}", mean);
+    for tenth in 0..10u64 {
+        let count = sample.iter().filter(|&&n| (n - 1) / 1000 == tenth).count();
+        assert!(count > 50 && count < 150, "tenth {} count {}", tenth, count);
+    }
+
+    // The same seed gives the same sample after a reset, and another seed a different one
+    let first = sink.sample().to_vec();
+    sink.reset();
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.sample(), &first[..]);
+    let mut other = ReservoirSink::new(1000, 8);
+    unpack(&packed[..], &mut other, inputs.len()).unwrap();
+    assert_ne!(other.sample(), &first[..]);
 }
 
-// Tests the case where nibbles lines up with 64-bit boundaries - edge case
 #[test]
-fn unpack8_4nibbles_allfull() {
-    // 4 nibbles = 2^16, so values < 65536
-    let inputs = [65535u64; 8];
-    let mut buf = [0u8; 512];
-    let written = nibble_pack8(&inputs, &mut buf, 0).unwrap();
+fn fold_sink_sum_and_max_match_dedicated_decoders() {
+    let inputs: Vec<u64> = (0..1003u64).map(|i| 5_000 + i * 17 + (i * i) % 13).collect();
+    let mut buf = [0u8; 8192];
 
-    let mut sink = VecSink::<u64>::new();
-    let res = nibble_unpack8(&buf[..written], &mut sink);
-    assert_eq!(res.unwrap().len(), 0);
-    assert_eq!(sink.vec[..], inputs);
+    // The sum of the deltas is what decode_delta_sum adds up lane by lane
+    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
+    let mut sum = FoldSink::new(0u64, |acc, n| acc.wrapping_add(n));
+    unpack(&buf[..written], &mut sum, inputs.len()).unwrap();
+    assert_eq!(sum.into_acc(), decode_delta_sum(&buf[..written], inputs.len()).unwrap());
+    let (_, _, max, _) = decode_delta_stats(&buf[..written], inputs.len()).unwrap();
+
+    // Folding the plain values gives the same max, and padding is not counted
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+    let mut max_sink = FoldSink::new(0u64, |acc, n| acc.max(n));
+    unpack(&packed[..], &mut max_sink, inputs.len()).unwrap();
+    assert_eq!(max_sink.into_acc(), max);
+    let mut min_count = FoldSink::new((u64::MAX, 0usize), |(min, count), n| (min.min(n), count + 1));
+    unpack(&packed[..], &mut min_count, inputs.len()).unwrap();
+    assert_eq!(min_count.into_acc(), (5_000, inputs.len()));
+    // Deltas which wrap past u64::MAX, which pack_u64_delta never writes, give a max other than the last value
+    let deltas = [u64::MAX - 5, 10, 1, 0, 7, 2, 3, 4, 9, u64::MAX - 100];
+    let packed = pack_u64_to_vec(deltas.iter().cloned());
+    let mut total = 0u64;
+    let values: Vec<u64> = deltas.iter().map(|&d| { total = total.wrapping_add(d); total }).collect();
+    let expected = (values[0], values[9], *values.iter().max().unwrap(), deltas.len());
+    assert_eq!(decode_delta_stats(&packed[..], deltas.len()).unwrap(), expected);
+    assert!(expected.2 != expected.1);
 }
 
 #[test]
-fn unpack8_partial_oddnibbles() {
-    let compressed = [
-        0b0011_1110u8, // only some bits on
-        0x84u8,        // nine nibbles wide, four zero nibbles trailing
-        0x11u8, 0x22u8, 0x33u8, 0x44u8, 0x05u8, 0x32u8, 0x43u8, 0x04u8, 0,
-        0x33u8, 0x44u8, 0x55u8, 0x66u8, 0x07u8, 0x54u8, 0x65u8, 0x06u8, 0,
-        0x30u8, 0x54u8, 0x76u8, 0x98u8, 0x01u8,
-        0x00u8, ]; // extra padding... just to test the return value
-    let mut sink = VecSink::<u64>::new();
-    let res = nibble_unpack8(&compressed, &mut sink);
-    assert_eq!(res.unwrap().len(), 1);
-    assert_eq!(sink.vec.len(), 8);
+fn per_value_sink_passes_only_real_values() {
+    struct Collect(Vec<u64>);
+    impl ValueConsumer for Collect {
+        fn consume(&mut self, value: u64) { self.0.push(value) }
+        fn reset(&mut self) { self.0.clear() }
+    }
 
-    let orig = [
-        0u64,
-        0x0005_4433_2211_0000u64, 0x0000_0044_3320_0000u64,
-        0x0007_6655_4433_0000u64, 0x0000_0066_5540_0000u64,
-        0x0001_9876_5430_0000u64, 0u64, 0u64,
-    ];
+    let inputs: Vec<u64> = (1..=21u64).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+    let mut sink = PerValueSink::with_consumer(Collect(Vec::new()));
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.flush().0, inputs);
 
-    assert_eq!(sink.vec[..], orig);
+    sink.reset();
+    unpack(&packed[..], &mut sink, 3).unwrap();
+    assert_eq!(sink.flush().0, &inputs[..3]);
 }
 
 #[test]
-fn pack_unpack_u64_plain() {
-    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
-    let mut buf = [0u8; 512];
-    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
-    println!("Packed {} u64 inputs (plain) into {} bytes", inputs.len(), written);
+fn hash_sink_matches_hash_of_original() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
 
-    let mut sink = VecSink::<u64>::new();
-    let res = unpack(&buf[..written], &mut sink, inputs.len());
-    assert_eq!(res.unwrap().len(), 0);
-    assert_eq!(sink.vec[..inputs.len()], inputs);
+    let inputs: Vec<u64> = (0..1001u64).map(|i| if i % 9 == 0 { 0 } else { i * i * 31 }).collect();
+    let packed = pack_u64_to_vec(inputs.iter().cloned());
+    let mut expected = DefaultHasher::new();
+    inputs.iter().for_each(|&n| expected.write_u64(n));
+
+    let mut sink = HashSink::new(DefaultHasher::new());
+    unpack(&packed[..], &mut sink, inputs.len()).unwrap();
+    assert_eq!(sink.finish(), expected.finish());
+
+    // Values past num_values are padding, and are not hashed
+    let mut expected = DefaultHasher::new();
+    inputs[..995].iter().for_each(|&n| expected.write_u64(n));
+    let mut sink = HashSink::new(DefaultHasher::new());
+    unpack(&packed[..], &mut sink, 995).unwrap();
+    assert_eq!(sink.finish(), expected.finish());
 }
 
 #[test]
-fn test_unpack_u64_plain_iter() {
-    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
-    let mut buf = [0u8; 512];
-    // NOTE: into_iter() of an array returns an Iterator<Item = &u64>, cloned() is needed to convert back to u64
-    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+fn empty_and_single_value_columns() {
+    let mut buf = [0u8; 256];
+    for num_values in 0..2usize {
+        let ints = vec![12345u64; num_values];
+        let floats = vec![-1.5f64; num_values];
 
-    let mut sink = U64_256Sink::new();
-    unpack(&buf[0..written], &mut sink, inputs.len()).unwrap();
-    assert_eq!(sink.values[0..inputs.len()], inputs);
+        // Integer encodings write nothing for no values, and one block for one value
+        let written = pack_u64(ints.iter().cloned(), &mut buf, 0).unwrap();
+        assert_eq!(written, num_values * 4);
+        assert_eq!(pack_u64_slice(&ints[..], &mut buf, 0), Ok(written));
+        assert_eq!(pack_u64_to_vec(ints.iter().cloned()).len(), written);
+        let trailing = &buf[..written + 3];
+        let mut sink = VecSink::<u64>::new();
+        assert_eq!(unpack(trailing, &mut sink, num_values).unwrap().len(), 3);
+        assert_eq!(sink.as_slice(), &ints[..]);
+        let mut out = [0u64; 1];
+        unpack_into(trailing, &mut out[..num_values], num_values).unwrap();
+        assert_eq!(out[..num_values], ints[..]);
+
+        let written = pack_u64_delta(&ints[..], &mut buf).unwrap();
+        let mut sink = DeltaSink::new();
+        assert!(unpack(&buf[..written], &mut sink, num_values).unwrap().is_empty());
+        assert_eq!(sink.as_slice(), &ints[..]);
+        assert_eq!(decode_delta_stats(&buf[..written], num_values).unwrap().3, num_values);
+
+        let written = pack_gauge(&ints[..], &mut buf).unwrap();
+        let mut sink = ZigZagDeltaSink::new();
+        unpack(&buf[..written], &mut sink, num_values).unwrap();
+        assert_eq!(sink.as_slice(), &ints[..]);
+
+        let written = pack_u64_scaled(&ints[..], &mut buf).unwrap();
+        let mut sink = VecSink::<u64>::new();
+        unpack_u64_scaled(&buf[..written], &mut sink, num_values).unwrap();
+        assert_eq!(sink.as_slice(), &ints[..]);
+
+        let written = pack_u128(ints.iter().map(|&n| (n as u128) << 64), &mut buf).unwrap();
+        let mut sink = U128Sink::new();
+        unpack_u128(&buf[..written], &mut sink, num_values).unwrap();
+        assert_eq!(sink.as_slice().len(), num_values);
+
+        let sparse = vec![Some(7u64); num_values];
+        let written = pack_sparse_u64(&sparse[..], &mut buf).unwrap();
+        assert_eq!(unpack_sparse_u64(&buf[..written], num_values).unwrap(), sparse);
+
+        let written = pack_f64_fixed(&floats[..], 10.0, &mut buf).unwrap();
+        assert_eq!(unpack_f64_fixed(&buf[..written], num_values).unwrap(), floats);
+
+        let mut decoder = Decoder::new(&buf[..0], VecSink::<u64>::new(), 0);
+        assert_eq!(decoder.decode_blocks(1), Ok(DecodeProgress::Done));
+    }
+
+    // XOR encodings need an initial value, so no values is an error, and one value is only the initial value
+    assert_eq!(pack_f64_xor(std::iter::empty(), &mut buf), Err(CodingError::InputTooShort));
+    let written = pack_f64_xor(std::iter::once(-1.5f64), &mut buf).unwrap();
+    assert_eq!(written, 8);
+    let mut sink = DoubleXorSink::new(Vec::new());
+    assert_eq!(unpack_f64_xor(&buf[..written + 2], &mut sink, 1).unwrap().len(), 2);
+    assert_eq!(sink.output_vec(), &vec![-1.5]);
+    // Unpacking no values reads nothing, and a truncated initial value is an error rather than a panic
+    assert_eq!(unpack_f64_xor(&buf[..5], &mut sink, 0).unwrap().len(), 5);
+    assert!(sink.output_vec().is_empty());
+    assert_eq!(unpack_f64_xor(&buf[..5], &mut sink, 1), Err(CodingError::InputTooShort));
+    assert_eq!(unpack_f64_fixed(&buf[..5], 1), Err(CodingError::InputTooShort));
+    assert_eq!(unpack_u64_f64_pairs(&[], &[], 0).unwrap(), vec![]);
 }
 
 #[test]
-fn pack_unpack_u64_deltas() {
-    let inputs = [0u64, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078];
+fn dump_blocks() {
+    // The nibblepack8_all_evennibbles fixture, then a block of zeroes
+    let inputs = [ 0x0000_00fe_dcba_0000u64, 0x0000_0033_2211_0000u64,
+                   0x0000_0044_3322_0000u64, 0x0000_0055_4433_0000u64,
+                   0x0000_0066_5544_0000u64, 0x0000_0076_5432_0000u64,
+                   0x0000_0087_6543_0000u64, 0x0000_0098_7654_0000u64,
+                   0, 0, 0, 0, 0, 0, 0, 0 ];
     let mut buf = [0u8; 512];
-    // NOTE: into_iter() of an array returns an Iterator<Item = &u64>, cloned() is needed to convert back to u64
-    let written = pack_u64_delta(&inputs[..], &mut buf).unwrap();
-    println!("Packed {} u64 inputs (delta) into {} bytes", inputs.len(), written);
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    assert_eq!(block_len(&buf[..written]).unwrap(), written - 1);
 
-    let mut sink = DeltaSink::new();
-    let res = unpack(&buf[..written], &mut sink, inputs.len());
-    assert_eq!(res.unwrap().len(), 0);
-    assert_eq!(sink.sink.vec[..inputs.len()], inputs);
+    let out = dump(&buf[..written]);
+    assert_eq!(out.lines().count(), 2);
+    assert!(out.contains("block 0 bitmask=0b11111111 6 nibbles, 4 trailing nibbles, bytes 0..26"), "{}", out);
+    assert!(out.contains("block 1 bitmask=0b00000000 empty, bytes 26..27"), "{}", out);
+
+    // Cut off the first block partway through its nibbles
+    let out = dump(&buf[..5]);
+    assert_eq!(out.lines().count(), 1);
+    assert!(out.contains("truncated"));
+    assert_eq!(block_len(&buf[..1]), Err(CodingError::NotEnoughSpace));
 }
 
 #[test]
-fn pack_unpack_f64_xor() {
-    let inputs = [0f64, 0.5, 2.5, 10., 25., 100.];
-    let mut buf = [0u8; 512];
-    let written = pack_f64_xor(inputs.iter().cloned(), &mut buf).unwrap();
-    println!("Packed {} f64 inputs (XOR) into {} bytes", inputs.len(), written);
+fn unpack_scattered_slices() {
+    let inputs: Vec<u64> = (0..100u64).map(|i| if i % 16 < 8 { i * 1000 } else { i % 7 }).collect();
+    let mut buf = [0u8; 1024];
+    let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+    let packed = &buf[..written];
+    let mut offsets = vec![0];
+    while *offsets.last().unwrap() < packed.len() {
+        let off = *offsets.last().unwrap();
+        offsets.push(off + block_len(&packed[off..]).unwrap());
+    }
 
-    let out = Vec::<f64>::with_capacity(64);
-    let mut sink = DoubleXorSink::new(out);
-    let res = unpack_f64_xor(&buf[..written], &mut sink, inputs.len());
-    assert_eq!(res.unwrap().len(), 0);
-    assert_eq!(sink.vec[..inputs.len()], inputs);
+    // Split after 5 blocks, ie 40 values, and again after 11, with an empty slice in between
+    let (first, rest) = packed.split_at(offsets[5]);
+    let (second, third) = rest.split_at(offsets[11] - offsets[5]);
+    for slices in [vec![first, rest], vec![first, &[], second, third]].iter() {
+        let mut sink = VecSink::<u64>::new();
+        unpack_scattered(&slices[..], &mut sink, inputs.len()).unwrap();
+        assert_eq!(sink.as_slice(), &inputs[..]);
+    }
+
+    // A split inside a block, and slices holding too few values
+    let mut sink = VecSink::<u64>::new();
+    let (first, rest) = packed.split_at(offsets[5] - 1);
+    assert_eq!(unpack_scattered(&[first, rest], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
+    let (first, _) = packed.split_at(offsets[5]);
+    assert_eq!(unpack_scattered(&[first, second], &mut sink, inputs.len()), Err(CodingError::InputTooShort));
 }
 
 // NOTE: cfg(test) is needed so that proptest can just be a "dev-dependency" and not linked for final library
@@ -764,6 +6465,15 @@ mod props {
         }
     }
 
+    // Generate gauge values which jitter up and down around some level
+    prop_compose! {
+        fn arb_gauge_values()
+                           (level in 0u64..(1 << 48), jitter in 1i64..5000)
+                           (v in proptest::collection::vec(-jitter..=jitter, 1..64), level in Just(level)) -> Vec<u64> {
+            v.iter().map(|&j| (level as i64 + j) as u64).collect()
+        }
+    }
+
     proptest! {
         #[test]
         fn prop_pack_unpack_identity(input in arb_8longs_nbits()) {
@@ -783,5 +6493,97 @@ mod props {
             let _res = unpack(&buf, &mut sink, input.len());
             assert_eq!(sink.sink.vec[..input.len()], input[..]);
         }
+
+        #[test]
+        fn prop_delta_stats_match_decode(input in arb_varlen_deltas()) {
+            let mut buf = [0u8; 1024];
+            let written = pack_u64_delta(&input[..], &mut buf).unwrap();
+            let mut sink = DeltaSink::new();
+            unpack(&buf[..written], &mut sink, input.len()).unwrap();
+            let decoded = sink.as_slice();
+
+            let expected = (decoded[0], decoded[decoded.len() - 1], *decoded.iter().max().unwrap(), decoded.len());
+            assert_eq!(decode_delta_stats(&buf[..written], input.len()).unwrap(), expected);
+            assert_eq!(decode_delta_sum(&buf[..written], input.len()).unwrap(), expected.1);
+        }
+
+        #[test]
+        fn prop_prefix_xor_simd_matches_serial(floats in prop::array::uniform8(any::<f64>()), start in any::<f64>()) {
+            let mut bits = [0u64; 8];
+            for i in 0..8 { bits[i] = floats[i].to_bits() }
+            let data = u64x8::from_slice_unaligned(&bits);
+            assert_eq!(prefix_xor_simd(start.to_bits(), data), prefix_xor_serial(start.to_bits(), data));
+        }
+
+        #[test]
+        fn prop_f64_xor_any_bits_roundtrip(bits in proptest::collection::vec(any::<u64>(), 1..100)) {
+            // Every bit pattern, including all NaN payloads, must come back exactly
+            let mut buf = [0u8; 1024];
+            let written = pack_f64_xor(bits.iter().map(|&b| f64::from_bits(b)), &mut buf).unwrap();
+            let mut sink = DoubleXorSink::new(Vec::new());
+            unpack_f64_xor(&buf[..written], &mut sink, bits.len()).unwrap();
+            let out_bits: Vec<u64> = sink.output_vec().iter().map(|f| f.to_bits()).collect();
+            assert_eq!(out_bits, bits);
+        }
+
+        #[test]
+        fn prop_f64_xor_windowed_roundtrip(bits in proptest::collection::vec(any::<u64>(), 1..100),
+                                           steps in proptest::collection::vec(0..16u64, 1..100)) {
+            // Random bits rarely fit the last window, while small steps on a level mostly do
+            let smooth: Vec<u64> = steps.iter().map(|&n| (1000.0 + n as f64 * 0.25).to_bits()).collect();
+            for input in [bits, smooth].iter() {
+                let mut buf = [0u8; 1024];
+                let written = pack_f64_xor_windowed(input.iter().map(|&b| f64::from_bits(b)), &mut buf).unwrap();
+                let mut sink = DoubleXorSink::new(Vec::new());
+                unpack_f64_xor_windowed(&buf[..written], &mut sink, input.len()).unwrap();
+                let out_bits: Vec<u64> = sink.output_vec().iter().map(|f| f.to_bits()).collect();
+                assert_eq!(&out_bits, input);
+            }
+        }
+
+        #[test]
+        fn prop_validate_accepts_packed(input in proptest::collection::vec(any::<u64>(), 0..100)) {
+            let packed = pack_u64_to_vec(input.iter().cloned());
+            assert_eq!(validate(&packed[..], input.len()), Ok(()));
+        }
+
+        #[test]
+        fn prop_u128_packing_roundtrip(input in proptest::collection::vec(
+                                           prop_oneof![any::<u64>().prop_map(u128::from), any::<u128>()], 1..100)) {
+            let mut buf = [0u8; 2048];
+            let written = pack_u128(input.iter().cloned(), &mut buf).unwrap();
+            let mut sink = U128Sink::new();
+            let rest = unpack_u128(&buf[..written], &mut sink, input.len()).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(sink.as_slice(), &input[..]);
+        }
+
+        #[test]
+        fn prop_packed_size_within_bits_bound(max_bits in 0u32..=64,
+                                             input in proptest::collection::vec(any::<u64>(), 1..100)) {
+            let input: Vec<u64> = input.iter().map(|&n| n & nibble_mask(max_bits)).collect();
+            let bound = max_packed_size_for_bits(input.len(), max_bits);
+            assert!(bound <= estimate_packed_size(input.len()));
+            let mut buf = [0u8; 1024];
+            let written = pack_u64(input.iter().cloned(), &mut buf, 0).unwrap();
+            assert!(written <= bound, "{} bytes for {} bits, bound {}", written, max_bits, bound);
+        }
+
+        #[test]
+        fn prop_packed_size_matches_pack(input in proptest::collection::vec(
+                                             prop_oneof![0u64..16, any::<u64>(), Just(0u64)], 0..100)) {
+            let mut buf = [0u8; 1024];
+            let written = pack_u64(input.iter().cloned(), &mut buf, 0).unwrap();
+            assert_eq!(packed_size(input.iter().cloned()), written);
+        }
+
+        #[test]
+        fn prop_gauge_packing(input in arb_gauge_values()) {
+            let mut buf = [0u8; 1024];
+            let written = pack_gauge(&input[..], &mut buf).unwrap();
+            let mut sink = ZigZagDeltaSink::new();
+            unpack(&buf[..written], &mut sink, input.len()).unwrap();
+            assert_eq!(sink.as_slice(), &input[..]);
+        }
     }
 }
@@ -0,0 +1,381 @@
+/// A compressed `Vec<u64>` which can be pushed to and iterated over like a `Vec`.
+/// Values are pushed into a small uncompressed tail, and every time the tail fills up with 8 values it is
+/// NibblePacked as one block onto the end of the compressed bytes.  Iterating decodes one block at a time as it
+/// goes, so the values are never all decompressed at once.
+///
+/// ```
+/// # use compressed_vec::CompressedVec;
+///     let mut vec = CompressedVec::new();
+///     for i in 0..100u64 {
+///         vec.push(i * 10);
+///     }
+///     assert_eq!(vec.len(), 100);
+///     assert_eq!(vec.get(42), Some(420));
+///     assert_eq!(vec.iter().sum::<u64>(), 49500);
+/// ```
+use crate::byteutils::{read_varint, write_varint};
+use crate::error::CodingError;
+use crate::nibblepacking::{estimate_packed_size, nibble_pack8_to, nibble_unpack8, pack_u64, truncated_input, validate,
+                           walk_blocks, OctetSink, ZERO_U64OCTET};
+
+/// A `Vec<u64>` stored as NibblePacked blocks, plus an uncompressed tail of fewer than 8 values
+#[derive(Clone, Debug, Default)]
+pub struct CompressedVec {
+    bytes: Vec<u8>,       // NibblePacked blocks of 8 values each
+    tail: [u64; 8],
+    tail_len: usize,
+    num_values: usize,
+}
+
+// Decodes the block at the start of encoded, returning its values and the bytes after it
+fn unpack_block(encoded: &[u8]) -> Result<([u64; 8], &[u8]), CodingError> {
+    let mut sink = OctetSink { values: ZERO_U64OCTET };
+    let rest = nibble_unpack8(encoded, &mut sink).map_err(truncated_input)?;
+    let mut block = [0u64; 8];
+    sink.values.write_to_slice_unaligned(&mut block);
+    Ok((block, rest))
+}
+
+impl CompressedVec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a value.  Packs the tail as a block once it holds 8 values.
+    #[inline]
+    pub fn push(&mut self, value: u64) {
+        self.tail[self.tail_len] = value;
+        self.tail_len += 1;
+        self.num_values += 1;
+        if self.tail_len == 8 {
+            nibble_pack8_to(&self.tail, &mut self.bytes).expect("Writing to a Vec cannot fail");
+            self.tail_len = 0;
+        }
+    }
+
+    /// Appends all of values.  The tail is topped up to a full block first, then every whole block of 8 left in
+    /// values is packed straight into the compressed bytes with [`pack_u64`](../nibblepacking/fn.pack_u64.html),
+    /// and only the last few values are copied into the tail.
+    pub fn extend_from_slice(&mut self, values: &[u64]) {
+        let mut values = values;
+        if self.tail_len > 0 {
+            let fill = values.len().min(8 - self.tail_len);
+            let (head, rest) = values.split_at(fill);
+            head.iter().for_each(|&value| self.push(value));
+            values = rest;
+        }
+
+        let whole = values.len() - values.len() % 8;
+        if whole > 0 {
+            let offset = self.bytes.len();
+            self.bytes.resize(offset + estimate_packed_size(whole), 0);
+            let written = pack_u64(values[..whole].iter().cloned(), &mut self.bytes[..], offset)
+                              .expect("Buffer is sized for the worst case");
+            self.bytes.truncate(written);
+            self.num_values += whole;
+        }
+        values[whole..].iter().for_each(|&value| self.push(value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_values
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_values == 0
+    }
+
+    /// The number of compressed bytes, not counting the uncompressed tail
+    pub fn packed_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the value at index, or None if index is out of bounds.  Blocks vary in length, so finding the
+    /// block holding index walks the headers of every block before it; use [`iter`](#method.iter) to read
+    /// many values.
+    pub fn get(&self, index: usize) -> Option<u64> {
+        if index >= self.num_values { return None }
+        let num_blocks = self.num_values / 8;
+        if index >= num_blocks * 8 { return Some(self.tail[index % 8]) }
+
+        let offset = walk_blocks(&self.bytes[..], index / 8, |_, _| {}).expect("Blocks are packed by CompressedVec");
+        let (block, _) = unpack_block(&self.bytes[offset..]).expect("Blocks are packed by CompressedVec");
+        Some(block[index % 8])
+    }
+
+    /// Serializes into one buffer: a varint number of values, then the packed blocks, then the tail packed as one
+    /// more block padded with zeroes if there is a tail.  This is the same as the values NibblePacked with
+    /// [`pack_u64`](../nibblepacking/fn.pack_u64.html) after their count.  Read back with
+    /// [`from_bytes`](#method.from_bytes).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(10 + self.bytes.len() + estimate_packed_size(self.tail_len));
+        write_varint(&mut out, self.num_values as u64).expect("Writing to a Vec cannot fail");
+        out.extend_from_slice(&self.bytes[..]);
+        if self.tail_len > 0 {
+            let mut block = [0u64; 8];
+            block[..self.tail_len].copy_from_slice(&self.tail[..self.tail_len]);
+            nibble_pack8_to(&block, &mut out).expect("Writing to a Vec cannot fail");
+        }
+        out
+    }
+
+    /// Reads a buffer written by [`to_bytes`](#method.to_bytes), checking every block header with
+    /// [`validate`](../nibblepacking/fn.validate.html) first so that `get` and `iter` never meet a corrupt block.
+    /// Returns InputTooShort if it ends before the last block, CorruptHeader if a block header is invalid, or
+    /// InvalidFormat if there are bytes after the last block.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, CodingError> {
+        let (num_values, pos) = read_varint(buf, 0)?;
+        let encoded = &buf[pos..];
+        // Every block of 8 values takes at least one byte
+        if num_values > encoded.len() as u64 * 8 { return Err(CodingError::InputTooShort) }
+        let num_values = num_values as usize;
+        validate(encoded, num_values)?;
+        let num_blocks = num_values / 8;
+        let tail_len = num_values % 8;
+
+        let blocks_len = walk_blocks(encoded, num_blocks, |_, _| {})?;
+        let mut tail = [0u64; 8];
+        if tail_len > 0 {
+            let (block, _) = unpack_block(&encoded[blocks_len..])?;
+            tail[..tail_len].copy_from_slice(&block[..tail_len]);
+        }
+        Ok(Self { bytes: encoded[..blocks_len].to_vec(), tail, tail_len, num_values })
+    }
+
+    /// Iterates over the values, decoding one block at a time.  The iterator is double ended, so `iter().rev()`
+    /// decodes from the last block back to the first.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            encoded: &self.bytes[..],
+            tail: &self.tail[..self.tail_len],
+            num_blocks: self.num_values / 8,
+            front: 0,
+            back: self.num_values,
+            front_offset: 0,
+            front_block: [0u64; 8],
+            front_block_num: usize::MAX,
+            back_block: [0u64; 8],
+            back_block_num: usize::MAX,
+            offsets: None,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a CompressedVec {
+    type Item = u64;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over the values of a [`CompressedVec`](struct.CompressedVec.html).
+///
+/// Going forwards, each block starts where the one before it ends.  Blocks vary in length though, so to go
+/// backwards the first call to `next_back()` walks the block headers once to build an index of block offsets,
+/// after which any block can be decoded directly.  This only works because every block of a CompressedVec decodes
+/// on its own.  A delta encoded buffer, such as one from
+/// [`pack_u64_delta`](../nibblepacking/fn.pack_u64_delta.html), cannot be reversed cheaply: each value is the sum of
+/// every delta before it, so the last block can only be decoded after all of the others.
+#[derive(Debug)]
+pub struct Iter<'a> {
+    encoded: &'a [u8],
+    tail: &'a [u64],
+    num_blocks: usize,
+    front: usize,                 // values front..back have not been returned yet
+    back: usize,
+    front_offset: usize,          // byte offset of the block after front_block
+    front_block: [u64; 8],
+    front_block_num: usize,       // usize::MAX until a block is decoded
+    back_block: [u64; 8],
+    back_block_num: usize,
+    offsets: Option<Vec<usize>>,  // byte offset of every block, built by the first next_back()
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.front == self.back { return None }
+        let index = self.front;
+        self.front += 1;
+        let block_num = index / 8;
+        if block_num == self.num_blocks { return Some(self.tail[index % 8]) }
+
+        if block_num != self.front_block_num {
+            let (block, rest) = unpack_block(&self.encoded[self.front_offset..])
+                                     .expect("Blocks are packed by CompressedVec");
+            self.front_block = block;
+            self.front_block_num = block_num;
+            self.front_offset = self.encoded.len() - rest.len();
+        }
+        Some(self.front_block[index % 8])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.back - self.front;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<u64> {
+        if self.front == self.back { return None }
+        self.back -= 1;
+        let index = self.back;
+        let block_num = index / 8;
+        if block_num == self.num_blocks { return Some(self.tail[index % 8]) }
+
+        if block_num != self.back_block_num {
+            let (encoded, num_blocks) = (self.encoded, self.num_blocks);
+            let offsets = self.offsets.get_or_insert_with(|| {
+                let mut offsets = Vec::with_capacity(num_blocks);
+                walk_blocks(encoded, num_blocks, |off, _| offsets.push(off))
+                    .expect("Blocks are packed by CompressedVec");
+                offsets
+            });
+            let (block, _) = unpack_block(&self.encoded[offsets[block_num]..])
+                                 .expect("Blocks are packed by CompressedVec");
+            self.back_block = block;
+            self.back_block_num = block_num;
+        }
+        Some(self.back_block[index % 8])
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_iter_get_match_vec() {
+        // A mix of small, large and zero values, so blocks vary in length and some are all zeroes
+        let reference: Vec<u64> = (0..1003u64).map(|i| match i % 40 {
+            0..=7   => 0,
+            8..=15  => i * 1_000_003,
+            _       => i % 17,
+        }).collect();
+
+        for &len in [0, 1, 7, 8, 9, 64, 1003].iter() {
+            let mut vec = CompressedVec::new();
+            for &value in reference[..len].iter() {
+                vec.push(value);
+            }
+            assert_eq!(vec.len(), len);
+            assert_eq!(vec.is_empty(), len == 0);
+            assert_eq!(vec.iter().len(), len);
+            assert_eq!(vec.iter().collect::<Vec<u64>>(), &reference[..len]);
+            for (i, &value) in reference[..len].iter().enumerate() {
+                assert_eq!(vec.get(i), Some(value), "index {} of {}", i, len);
+            }
+            assert_eq!(vec.get(len), None);
+        }
+
+        let mut vec = CompressedVec::new();
+        reference.iter().for_each(|&value| vec.push(value));
+        assert!(vec.packed_len() < reference.len() * 8 / 2);
+        assert_eq!((&vec).into_iter().sum::<u64>(), reference.iter().sum::<u64>());
+    }
+
+    #[test]
+    fn test_extend_from_slice_mixed_with_push() {
+        let source: Vec<u64> = (0..500u64).map(|i| (i * 7919) % 1000 + if i % 50 < 8 { 1 << 40 } else { 0 }).collect();
+        let mut vec = CompressedVec::new();
+        let mut reference = Vec::new();
+        let mut pos = 0;
+        // Slices of every length from empty to several blocks, starting at every tail length
+        for (step, len) in [0usize, 3, 8, 1, 16, 5, 0, 7, 21, 2, 8, 40].iter().cycle().take(60).enumerate() {
+            let len = (*len).min(source.len() - pos);
+            let values = &source[pos..pos + len];
+            if step % 3 == 0 {
+                values.iter().for_each(|&value| vec.push(value));
+            } else {
+                vec.extend_from_slice(values);
+            }
+            reference.extend_from_slice(values);
+            pos += len;
+            assert_eq!(vec.len(), reference.len());
+        }
+        assert_eq!(vec.iter().collect::<Vec<u64>>(), reference);
+        assert_eq!(vec.get(reference.len() - 1), reference.last().cloned());
+
+        // Bulk and one at a time packing give the same blocks
+        let mut pushed = CompressedVec::new();
+        reference.iter().for_each(|&value| pushed.push(value));
+        assert_eq!(pushed.packed_len(), vec.packed_len());
+    }
+
+    #[test]
+    fn test_to_from_bytes_roundtrip() {
+        let reference: Vec<u64> = (0..203u64).map(|i| if i % 30 < 8 { 0 } else { i * i * 31 }).collect();
+        for &len in [0, 5, 8, 200, 203].iter() {
+            let mut vec = CompressedVec::new();
+            vec.extend_from_slice(&reference[..len]);
+            let bytes = vec.to_bytes();
+
+            let read = CompressedVec::from_bytes(&bytes).unwrap();
+            assert_eq!(read.len(), len);
+            assert_eq!(read.iter().collect::<Vec<u64>>(), &reference[..len]);
+            assert_eq!(read.to_bytes(), bytes);
+
+            // Pushing after reading back carries on from the restored tail
+            let mut read = read;
+            read.push(99);
+            assert_eq!(read.get(len), Some(99));
+            assert_eq!(read.iter().take(len).collect::<Vec<u64>>(), &reference[..len]);
+        }
+
+        let mut vec = CompressedVec::new();
+        vec.extend_from_slice(&reference);
+        let mut bytes = vec.to_bytes();
+        assert_eq!(CompressedVec::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), CodingError::InputTooShort);
+        assert_eq!(CompressedVec::from_bytes(&[]).unwrap_err(), CodingError::InputTooShort);
+        bytes.push(0);
+        assert!(matches!(CompressedVec::from_bytes(&bytes), Err(CodingError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_from_bytes_corrupt_header() {
+        // One block whose nibbles byte 0xf2 describes 16 nibbles plus 2 trailing zero nibbles, ie 72 bits
+        let mut bytes = vec![1u8, 0x01, 0xf2, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(CompressedVec::from_bytes(&bytes).unwrap_err(), CodingError::CorruptHeader(0xf2));
+        bytes[0] = 8;
+        assert_eq!(CompressedVec::from_bytes(&bytes).unwrap_err(), CodingError::CorruptHeader(0xf2));
+
+        // A count far beyond the bytes given
+        assert_eq!(CompressedVec::from_bytes(&[0xff, 0xff, 0xff, 0xff, 0x0f, 0]).unwrap_err(),
+                   CodingError::InputTooShort);
+    }
+
+    #[test]
+    fn test_rev_matches_reversed_vec() {
+        let reference: Vec<u64> = (0..1005u64).map(|i| if i % 24 < 8 { 0 } else { (i * 2_654_435_761) % 100_000 })
+                                              .collect();
+        for &len in [0, 3, 8, 16, 1005].iter() {
+            let mut vec = CompressedVec::new();
+            vec.extend_from_slice(&reference[..len]);
+            let reversed: Vec<u64> = reference[..len].iter().rev().cloned().collect();
+            assert_eq!(vec.iter().rev().collect::<Vec<u64>>(), reversed);
+
+            // Taking from both ends meets in the middle, even within one block
+            let mut iter = vec.iter();
+            let mut front = Vec::new();
+            let mut back = Vec::new();
+            for step in 0.. {
+                let taken = if step % 3 == 0 {
+                    iter.next_back().map(|value| back.push(value))
+                } else {
+                    iter.next().map(|value| front.push(value))
+                };
+                if taken.is_none() { break }
+                assert_eq!(iter.len(), len - front.len() - back.len());
+            }
+            front.extend(back.iter().rev());
+            assert_eq!(front, &reference[..len]);
+            assert_eq!(iter.next(), None);
+        }
+    }
+}
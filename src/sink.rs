@@ -6,12 +6,16 @@
 ///     regular unpack8_u32_simd -> u32 to f32 XOR sink -> MultiplySink -> VecSink
 /// TODO: examples
 use core::marker::PhantomData;
+use std::hash::Hasher;
 use std::ops::{Add, BitXor};
+use std::sync::mpsc::{SendError, Sender};
 
+use crate::error::CodingError;
 use crate::section::VectBase;
+use crate::sketch::QuantileSketch;
 
 use num::{Zero, Unsigned, Float};
-use packed_simd::{u32x8, u64x8, f32x8, FromCast, FromBits, IntoBits};
+use packed_simd::{shuffle, u32x8, u64x8, f32x8, FromCast, FromBits, IntoBits};
 
 /// An input to a sink.  Sinks take a type which represents 8 values of an int, such as [u64; 8].
 /// Item type represents the underlying type of each individual item in the 8 item SinkInput.
@@ -141,28 +145,75 @@ pub trait Sink<Input: SinkInput> {
 
     /// Resets state in the sink; exact meaning depends on the sink itself.  Many sinks operate on more than
     /// 8 items; for example 256 items or entire sections.
+    /// After a reset the sink must be ready to decode a new stream from scratch, and should hold on to any
+    /// memory it has allocated, so that one sink can be recycled across many decodes without allocating.
     fn reset(&mut self);
+
+    /// Called by unpack() once decoding is done, with the number of padding values at the end of the output.
+    /// Values are always decoded 8 at a time, so unless the number of values requested is a multiple of 8
+    /// the final group has up to 7 padding values.  Sinks which keep their output can use this to hide them.
+    #[inline]
+    fn mark_padding(&mut self, _num_padding: usize) {}
+}
+
+/// A Sink whose reset() keeps the memory it has allocated for its output, so that generic code can recycle one
+/// sink across many decodes, such as when decoding many columns in a hot loop, without allocating for each.
+pub trait Reusable<Input: SinkInput>: Sink<Input> {
+    /// The number of values the sink can hold before it needs to allocate more memory
+    fn capacity(&self) -> usize;
 }
 
 
 /// A Sink which writes all values to a Vec.  A good choice as the final Sink in a chain of Sink processors!
 /// Important!  This Sink will decode entire sections at a time, so the result will have up to 255 extra values.
+/// Use as_slice() to get only the values up to the logical length, without the padding decoded by unpack().
 #[derive(Debug)]
 pub struct VecSink<T: VectBase> {
     pub vec: Vec<T>,
+    logical_len: Option<usize>,
 }
 
-const DEFAULT_CAPACITY: usize = 64;
+/// The initial capacity of sinks created with new().  Use with_capacity() to size them for the workload instead.
+pub const DEFAULT_CAPACITY: usize = 64;
 
 impl<T: VectBase> VecSink<T> {
     pub fn new() -> Self {
-        VecSink { vec: Vec::with_capacity(DEFAULT_CAPACITY) }
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates a VecSink with room for num_values values.  Since values are decoded 8 at a time, the capacity is
+    /// rounded up to the next multiple of 8, so that decoding num_values values never needs to grow the Vec.
+    pub fn with_capacity(num_values: usize) -> Self {
+        VecSink { vec: Vec::with_capacity((num_values + 7) / 8 * 8), logical_len: None }
+    }
+
+    /// Returns the decoded values without the trailing padding marked by unpack().  If no padding has been
+    /// marked, for example when decoding whole sections, all the values in the Vec are returned.
+    /// Unpacking again without a reset() appends after the values, not after the padding, so as_slice() then
+    /// returns the values of every unpack() in order.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        match self.logical_len {
+            Some(len) if len <= self.vec.len() => &self.vec[..len],
+            _                                  => &self.vec[..],
+        }
+    }
+
+    // The padding is left in the Vec until more values arrive, then dropped so they follow the real values
+    #[inline]
+    fn drop_marked_padding(&mut self) {
+        if let Some(len) = self.logical_len.take() {
+            self.vec.truncate(len);
+        }
     }
 }
 
 impl<T: VectBase> Sink<T::SI> for VecSink<T> {
+    // No unsafe or unchecked writes here: the Vec is grown as needed and written through a bounds checked
+    // slice, so callers never need to reserve capacity before process(), eg when forwarding from other sinks.
     #[inline]
     fn process(&mut self, data: T::SI) {
+        self.drop_marked_padding();
         // So first we need to resize the Vec, then we write in values using write_to_slice
         let new_len = self.vec.len() + 8;
         self.vec.resize(new_len, T::zero());
@@ -171,13 +222,26 @@ impl<T: VectBase> Sink<T::SI> for VecSink<T> {
 
     #[inline]
     fn process_zeroes(&mut self) {
+        self.drop_marked_padding();
         for _ in 0..8 {
             self.vec.push(T::zero());
         }
     }
 
     fn reset(&mut self) {
-        self.vec.clear()
+        self.vec.clear();
+        self.logical_len = None;
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.logical_len = Some(self.vec.len().saturating_sub(num_padding));
+    }
+}
+
+impl<T: VectBase> Reusable<T::SI> for VecSink<T> {
+    fn capacity(&self) -> usize {
+        self.vec.capacity()
     }
 }
 
@@ -231,6 +295,13 @@ where T: VectBase {
     }
 }
 
+impl<T> Reusable<T::SI> for Section256Sink<T>
+where T: VectBase {
+    fn capacity(&self) -> usize {
+        self.values.len()
+    }
+}
+
 pub type U32_256Sink = Section256Sink<u32>;
 pub type U64_256Sink = Section256Sink<u64>;
 
@@ -280,7 +351,9 @@ where F: VectBase + Float,      // Output floating point type
         self.inner_sink.process(self.last_bits.into_bits());
     }
 
-    fn reset(&mut self) {}
+    fn reset(&mut self) {
+        self.last_bits = I::SI::ZERO;
+    }
 }
 
 /// A Sink for adding a constant value to all output elements.  Note that all SIMD types we use also support Add :)
@@ -317,4 +390,1192 @@ where T: VectBase,
     }
 
     fn reset(&mut self) {}
-}
\ No newline at end of file
+}
+
+/// A Sink which maps each value, a dictionary index, through a lookup table and forwards the dictionary values
+/// to an inner sink.  Useful for dictionary encoded columns, fusing dictionary lookup with unpacking.
+/// Indices beyond the end of the dictionary are forwarded as 0 and recorded; call check() after decoding
+/// to find out if any index was out of range.
+#[derive(Debug)]
+pub struct MapSink<'a, S: Sink<u64x8>> {
+    lookup: DictLookup<'a>,
+    inner_sink: &'a mut S,
+}
+
+impl<'a, S: Sink<u64x8>> MapSink<'a, S> {
+    pub fn new(dict: &'a [u64], inner_sink: &'a mut S) -> Self {
+        Self { lookup: DictLookup::new(dict), inner_sink }
+    }
+
+    /// Returns an error describing the first out of range index seen since the last reset, if any
+    pub fn check(&self) -> Result<(), CodingError> {
+        self.lookup.check()
+    }
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u64x8> for MapSink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let mapped = self.lookup.lookup(data);
+        self.inner_sink.process(mapped);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        let mapped = self.lookup.lookup(u64x8::splat(0));
+        self.inner_sink.process(mapped);
+    }
+
+    fn reset(&mut self) {
+        self.lookup.reset();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.inner_sink.mark_padding(num_padding);
+    }
+}
+
+/// A Sink which forwards each block of 8 values to an inner sink in reverse order, for downstream SIMD kernels
+/// which want a reversed layout within each block.  Every process() call is already exactly one block, so this
+/// is a single shuffle with no buffering.
+/// The padding of the last block ends up at the start of its 8 values rather than the end, so it is not marked
+/// on the inner sink, which would otherwise drop real values; the inner sink gets whole blocks.
+#[derive(Debug)]
+pub struct ReverseBlockSink<'a, S: Sink<u64x8>> {
+    inner_sink: &'a mut S,
+}
+
+impl<'a, S: Sink<u64x8>> ReverseBlockSink<'a, S> {
+    pub fn new(inner_sink: &'a mut S) -> Self {
+        Self { inner_sink }
+    }
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u64x8> for ReverseBlockSink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.inner_sink.process(shuffle!(data, [7, 6, 5, 4, 3, 2, 1, 0]));
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.inner_sink.process_zeroes();
+    }
+
+    fn reset(&mut self) {
+        self.inner_sink.reset();
+    }
+}
+
+/// A Sink which forwards at most max values to an inner sink and drops the rest, as a guard when decoding
+/// untrusted buffers whose claimed number of values could be huge.  The block which crosses the limit is
+/// forwarded with the values past the limit zeroed, and the inner sink sees nothing more until the decode marks
+/// its padding.  The inner sink then gets a single mark_padding() covering both the values past the limit and
+/// any padding it was given, so an inner sink such as a VecSink ends up with exactly max values.
+/// After decoding, [`truncated`]: #method.truncated tells whether any real values were dropped; padding values
+/// of the last block do not count.
+#[derive(Debug)]
+pub struct LimitSink<'a, S: Sink<u64x8>> {
+    inner_sink: &'a mut S,
+    max: usize,
+    forwarded: usize,
+    dropped: usize,
+    zeroed: usize,      // Values past the limit zeroed in the last block forwarded, to be marked as padding
+}
+
+impl<'a, S: Sink<u64x8>> LimitSink<'a, S> {
+    pub fn new(inner_sink: &'a mut S, max: usize) -> Self {
+        Self { inner_sink, max, forwarded: 0, dropped: 0, zeroed: 0 }
+    }
+
+    /// Whether values past the limit were dropped.  Only final once the decode has marked its padding.
+    pub fn truncated(&self) -> bool {
+        self.dropped > 0
+    }
+}
+
+impl<'a, S: Sink<u64x8>> Sink<u64x8> for LimitSink<'a, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let room = self.max - self.forwarded;
+        if room >= 8 {
+            self.inner_sink.process(data);
+            self.forwarded += 8;
+        } else if room > 0 {
+            let keep = u64x8::new(0, 1, 2, 3, 4, 5, 6, 7).lt(u64x8::splat(room as u64));
+            self.inner_sink.process(keep.select(data, u64x8::splat(0)));
+            self.forwarded = self.max;
+            self.dropped += 8 - room;
+            self.zeroed = 8 - room;
+        } else {
+            self.dropped += 8;
+        }
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.forwarded = 0;
+        self.dropped = 0;
+        self.zeroed = 0;
+        self.inner_sink.reset();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        // The last values dropped were padding rather than real values.  If padding was forwarded too, the limit
+        // was reached in the last block if at all, so the inner sink's padding is that plus the zeroed values.
+        let padding_dropped = num_padding.min(self.dropped);
+        self.dropped -= padding_dropped;
+        self.inner_sink.mark_padding(num_padding - padding_dropped + self.zeroed);
+    }
+}
+
+/// A Sink which checks decoded values against a known good reference, recording the first position where they
+/// differ, for tracking down corruption or shrinking a failing property test.  Nothing is kept of the decoded
+/// values.  Only positions within expected are compared, so the padding of the last block is never a mismatch.
+/// Use [`check`]: #method.check to also catch a decode of more or fewer values than expected.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::{VerifySink, VerifyMismatch};
+///     let packed = nibblepacking::pack_u64_to_vec([3u64, 9, 4].iter().cloned());
+///     let mut sink = VerifySink::new(&[3, 9, 5]);
+///     nibblepacking::unpack(&packed[..], &mut sink, 3).unwrap();
+///     assert_eq!(sink.first_mismatch(), Some((2, 5, 4)));
+///     assert_eq!(sink.check(), Err(VerifyMismatch::Value(2, 5, 4)));
+/// ```
+#[derive(Debug)]
+pub struct VerifySink<'a> {
+    expected: &'a [u64],
+    pos: usize,
+    padding: usize,
+    mismatch: Option<(usize, u64, u64)>,
+}
+
+/// A difference found by [`VerifySink::check`]: struct.VerifySink.html#method.check
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VerifyMismatch {
+    /// The first position where the decoded value differs, as (index, expected, decoded)
+    Value(usize, u64, u64),
+    /// Every value compared matched, but the number of values decoded differs, as (expected, decoded)
+    Length(usize, usize),
+}
+
+impl<'a> VerifySink<'a> {
+    pub fn new(expected: &'a [u64]) -> Self {
+        Self { expected, pos: 0, padding: 0, mismatch: None }
+    }
+
+    /// The first position where the decoded value differs from expected, as (index, expected, decoded)
+    pub fn first_mismatch(&self) -> Option<(usize, u64, u64)> {
+        self.mismatch
+    }
+
+    /// Number of values decoded so far, not counting the padding marked by unpack()
+    pub fn num_values(&self) -> usize {
+        self.pos - self.padding
+    }
+
+    /// Ok if exactly expected.len() values were decoded and all of them matched.  Otherwise returns the first
+    /// differing value, or failing that the difference in length.
+    pub fn check(&self) -> Result<(), VerifyMismatch> {
+        if let Some((index, expected, decoded)) = self.mismatch {
+            Err(VerifyMismatch::Value(index, expected, decoded))
+        } else if self.num_values() != self.expected.len() {
+            Err(VerifyMismatch::Length(self.expected.len(), self.num_values()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Sink<u64x8> for VerifySink<'a> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        // Values from another unpack() follow the real values, not the padding
+        self.pos -= self.padding;
+        self.padding = 0;
+        let start = self.pos;
+        self.pos += 8;
+        if self.mismatch.is_some() || start >= self.expected.len() { return }
+
+        if start + 8 <= self.expected.len() &&
+           u64x8::from_slice_unaligned(&self.expected[start..start + 8]).eq(data).all() {
+            return
+        }
+        let mut values = [0u64; 8];
+        data.write_to_slice_unaligned(&mut values);
+        self.mismatch = self.expected[start..].iter().zip(values.iter()).enumerate()
+                            .find(|(_, (expected, decoded))| expected != decoded)
+                            .map(|(i, (&expected, &decoded))| (start + i, expected, decoded));
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.padding = 0;
+        self.mismatch = None;
+    }
+
+    // Added to rather than replaced, so that an unpack() of 0 values keeps the padding before it
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.padding += num_padding;
+    }
+}
+
+/// A Sink which diffs a column being decoded against another, already decoded column of the same length,
+/// such as this minute's counters against last minute's.  For each position it records new - old,
+/// wrapping on underflow so that `diff as i64` is the signed difference, and sets a bit in the decreased
+/// bitmap where the new value is less than the old one.  This is a general version of the histogram
+/// DeltaDiffPackSink, for any two columns.  Positions past the end of old are diffed against 0.
+#[derive(Debug)]
+pub struct SubtractSink<'a> {
+    old: &'a [u64],
+    pos: usize,
+    diffs: Vec<u64>,
+    decreased: Vec<u8>,
+}
+
+impl<'a> SubtractSink<'a> {
+    pub fn new(old: &'a [u64]) -> Self {
+        Self { old, pos: 0, diffs: Vec::with_capacity(old.len() + 8), decreased: Vec::new() }
+    }
+
+    /// The wrapping differences new - old, one per position decoded, not including padding
+    pub fn diffs(&self) -> &[u64] {
+        &self.diffs[..]
+    }
+
+    /// Bitmap of positions where the new value is less than the old one.  Bit n of byte i is position
+    /// i * 8 + n, the same layout as a NibblePack nonzero bitmask.
+    pub fn decreased(&self) -> &[u8] {
+        &self.decreased[..]
+    }
+
+    #[inline]
+    pub fn is_decreased(&self, pos: usize) -> bool {
+        self.decreased.get(pos / 8).map_or(false, |&mask| mask & (1 << (pos % 8)) != 0)
+    }
+
+    /// True if any value decreased, ie some diffs underflowed
+    pub fn any_decreased(&self) -> bool {
+        self.decreased.iter().any(|&mask| mask != 0)
+    }
+}
+
+impl<'a> Sink<u64x8> for SubtractSink<'a> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let old = if self.pos + 8 <= self.old.len() {
+            u64x8::from_slice_unaligned(&self.old[self.pos..self.pos + 8])
+        } else {
+            let mut buf = [0u64; 8];
+            let remaining = &self.old[self.pos.min(self.old.len())..];
+            buf[..remaining.len()].copy_from_slice(remaining);
+            u64x8::from_slice_unaligned(&buf)
+        };
+        // After padding is dropped pos need not be a multiple of 8, so the mask can straddle two bytes
+        let mask = (data.lt(old).bitmask() as u16) << (self.pos % 8);
+        self.decreased.resize((self.pos + 15) / 8, 0);
+        self.decreased[self.pos / 8] |= mask as u8;
+        if mask > 0xff { self.decreased[self.pos / 8 + 1] |= (mask >> 8) as u8; }
+        let new_len = self.diffs.len() + 8;
+        self.diffs.resize(new_len, 0);
+        (data - old).write_to_slice_unaligned(&mut self.diffs[new_len - 8..]);
+        self.pos += 8;
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.diffs.clear();
+        self.decreased.clear();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        // Rolled back so that the next values decoded line up with the old column again
+        self.pos = self.pos.saturating_sub(num_padding);
+        self.diffs.truncate(self.pos);
+        // Padding positions are not real, so never count as decreased
+        self.decreased.truncate((self.pos + 7) / 8);
+        let partial = self.pos % 8;
+        if partial > 0 {
+            if let Some(last_mask) = self.decreased.last_mut() { *last_mask &= (1u8 << partial) - 1; }
+        }
+    }
+}
+
+impl<'a> Reusable<u64x8> for SubtractSink<'a> {
+    fn capacity(&self) -> usize {
+        self.diffs.capacity()
+    }
+}
+
+/// A Sink which diffs a column being decoded against a fixed baseline, such as the expected values used for
+/// anomaly detection, recording value - baseline[i] as a signed i64 for each position.  The subtraction wraps,
+/// so values above i64::MAX give wrapped differences.  A decoded column longer than the baseline has nothing to
+/// diff against: those values are diffed against 0 and check() returns an error afterwards.
+#[derive(Debug)]
+pub struct BaselineDiffSink<'a> {
+    baseline: &'a [i64],
+    diffs: Vec<i64>,
+}
+
+impl<'a> BaselineDiffSink<'a> {
+    pub fn new(baseline: &'a [i64]) -> Self {
+        Self { baseline, diffs: Vec::with_capacity(baseline.len() + 8) }
+    }
+
+    /// The signed differences value - baseline, one per position decoded, not including padding
+    pub fn diffs(&self) -> &[i64] {
+        &self.diffs[..]
+    }
+
+    /// Returns an error if more values were decoded than the baseline has, not counting padding
+    pub fn check(&self) -> Result<(), CodingError> {
+        if self.diffs.len() > self.baseline.len() {
+            Err(CodingError::InvalidFormat(format!("Decoded {} values but the baseline only has {}",
+                                                   self.diffs.len(), self.baseline.len())))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Sink<u64x8> for BaselineDiffSink<'a> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let pos = self.diffs.len();
+        for i in 0..8 {
+            let base = self.baseline.get(pos + i).cloned().unwrap_or(0);
+            self.diffs.push((data.extract(i) as i64).wrapping_sub(base));
+        }
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.diffs.clear();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        let len = self.diffs.len().saturating_sub(num_padding);
+        self.diffs.truncate(len);
+    }
+}
+
+impl<'a> Reusable<u64x8> for BaselineDiffSink<'a> {
+    fn capacity(&self) -> usize {
+        self.diffs.capacity()
+    }
+}
+
+/// A Sink which inserts every decoded value into a [`QuantileSketch`](../sketch/trait.QuantileSketch.html),
+/// for computing approximate percentiles over a compressed column without materializing it.
+/// Like the other PerValueSinks it holds back the last block until unpack() marks its padding, so that the
+/// padding does not skew the sketch; call flush() if decoding ends without mark_padding().  Reset clears the
+/// sketch.  The sketch may be a trait object, eg `&mut dyn QuantileSketch`.
+pub type SketchSink<'a, S> = PerValueSink<Sketched<'a, S>>;
+
+/// The ValueConsumer behind SketchSink
+#[derive(Debug)]
+pub struct Sketched<'a, S: QuantileSketch + ?Sized>(&'a mut S);
+
+impl<'a, S: QuantileSketch + ?Sized> ValueConsumer for Sketched<'a, S> {
+    #[inline]
+    fn consume(&mut self, value: u64) {
+        self.0.insert(value);
+    }
+
+    fn reset(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<'a, S: QuantileSketch + ?Sized> SketchSink<'a, S> {
+    pub fn new(sketch: &'a mut S) -> Self {
+        Self::with_consumer(Sketched(sketch))
+    }
+}
+
+/// A Sink which streams decoded values across a channel to another thread, in batches to keep the per-value
+/// overhead low, so decoding and processing can run in a pipeline.  Each batch is batch_size values, rounded
+/// up to a multiple of 8, except the last which is sent by finish() and holds only the values before the padding.
+/// If the receiver hangs up, the rest of the values are dropped and finish() returns the first unsent batch.
+#[derive(Debug)]
+pub struct ChannelSink {
+    sender: Sender<Vec<u64>>,
+    batch_size: usize,
+    batch: Vec<u64>,
+    failed: Option<SendError<Vec<u64>>>,
+}
+
+impl ChannelSink {
+    pub fn new(sender: Sender<Vec<u64>>, batch_size: usize) -> Self {
+        let batch_size = ((batch_size.max(1) + 7) / 8) * 8;
+        Self { sender, batch_size, batch: Vec::with_capacity(batch_size), failed: None }
+    }
+
+    /// Sends any values left in the current batch, then drops the sender so that the receiver sees the
+    /// end of the stream.  Returns the first batch which could not be sent if the receiver hung up.
+    pub fn finish(mut self) -> Result<(), SendError<Vec<u64>>> {
+        self.flush();
+        match self.failed {
+            Some(err) => Err(err),
+            None      => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.batch.is_empty() || self.failed.is_some() { return }
+        let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size));
+        if let Err(err) = self.sender.send(batch) {
+            self.failed = Some(err);
+        }
+    }
+}
+
+impl Sink<u64x8> for ChannelSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        // Send a full batch only when more values arrive, so the last block is still here for mark_padding()
+        if self.batch.len() >= self.batch_size {
+            self.flush();
+        }
+        if self.failed.is_none() {
+            let new_len = self.batch.len() + 8;
+            self.batch.resize(new_len, 0);
+            data.write_to_slice_unaligned(&mut self.batch[new_len - 8..]);
+        }
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    /// Drops any values not sent yet, and forgets any failed send, so that sending is tried again
+    fn reset(&mut self) {
+        self.batch.clear();
+        self.failed = None;
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.batch.truncate(self.batch.len().saturating_sub(num_padding));
+    }
+}
+
+// Every batch sent is handed over to the receiver, so only the batch being filled is kept across a reset
+impl Reusable<u64x8> for ChannelSink {
+    fn capacity(&self) -> usize {
+        self.batch.capacity()
+    }
+}
+
+/// A Sink which keeps only the last capacity values decoded, overwriting the oldest, so that windowed aggregates
+/// such as a moving average can be computed over a stream of any length in O(capacity) memory.
+/// Each value is stored twice, half a buffer apart, so that the window is always one contiguous slice.  The
+/// buffer also holds 8 values more than the window so that padding can be dropped without losing real values.
+#[derive(Debug)]
+pub struct RingBufferSink {
+    buf: Vec<u64>,
+    capacity: usize,
+    total: usize,
+}
+
+impl RingBufferSink {
+    pub fn new(capacity: usize) -> Self {
+        Self { buf: vec![0; 2 * (capacity + 8)], capacity, total: 0 }
+    }
+
+    /// The most recent values, at most capacity of them, oldest first
+    #[inline]
+    pub fn window(&self) -> &[u64] {
+        let len = self.total.min(self.capacity);
+        let start = (self.total - len) % (self.capacity + 8);
+        &self.buf[start..start + len]
+    }
+
+    /// The number of values decoded since the last reset, including ones which have left the window
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+impl Sink<u64x8> for RingBufferSink {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        let ring_len = self.capacity + 8;
+        let mut values = [0u64; 8];
+        data.write_to_slice_unaligned(&mut values);
+        for (i, &value) in values.iter().enumerate() {
+            let pos = (self.total + i) % ring_len;
+            self.buf[pos] = value;
+            self.buf[pos + ring_len] = value;
+        }
+        self.total += 8;
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.total = 0;
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.total = self.total.saturating_sub(num_padding);
+    }
+}
+
+// The ring never grows, as older values are overwritten instead
+impl Reusable<u64x8> for RingBufferSink {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Takes decoded values one at a time, for sinks which only care about each real value and not about blocks of 8.
+/// Wrap one in a PerValueSink to decode into it.
+pub trait ValueConsumer {
+    /// Takes the next decoded value
+    fn consume(&mut self, value: u64);
+
+    /// Called when the PerValueSink is reset, as in Sink::reset()
+    fn reset(&mut self);
+
+    /// The number of values the consumer can keep before it needs to allocate more memory, as in
+    /// Reusable::capacity().  Consumers which keep no values never allocate, so by default there is no limit.
+    fn capacity(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// A Sink which passes each decoded value on to a ValueConsumer.  The most recent block is held back until the
+/// next one arrives, so that the padding marked by unpack() is never passed on.  HashSink, ReservoirSink,
+/// FoldSink and SketchSink are all built on this.
+#[derive(Debug)]
+pub struct PerValueSink<C: ValueConsumer> {
+    consumer: C,
+    pending: Option<[u64; 8]>,
+}
+
+impl<C: ValueConsumer> PerValueSink<C> {
+    pub fn with_consumer(consumer: C) -> Self {
+        Self { consumer, pending: None }
+    }
+
+    /// Passes on any values still held back, then returns the consumer
+    pub fn flush(&mut self) -> &mut C {
+        self.consume_pending(8);
+        &mut self.consumer
+    }
+
+    fn consume_pending(&mut self, num_values: usize) {
+        if let Some(values) = self.pending.take() {
+            values[..num_values].iter().for_each(|&value| self.consumer.consume(value));
+        }
+    }
+}
+
+impl<C: ValueConsumer> Sink<u64x8> for PerValueSink<C> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.consume_pending(8);
+        let mut values = [0u64; 8];
+        data.write_to_slice_unaligned(&mut values);
+        self.pending = Some(values);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    /// Forgets any values held back and resets the consumer
+    fn reset(&mut self) {
+        self.pending = None;
+        self.consumer.reset();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.consume_pending(8 - num_padding.min(8));
+    }
+}
+
+impl<C: ValueConsumer> Reusable<u64x8> for PerValueSink<C> {
+    fn capacity(&self) -> usize {
+        self.consumer.capacity()
+    }
+}
+
+/// A Sink which feeds every decoded value into a Hasher with write_u64(), for checking that a round trip through
+/// packing gave back the original values without keeping them.  Hash the originals the same way and compare.
+pub type HashSink<H> = PerValueSink<Hashed<H>>;
+
+/// The ValueConsumer behind HashSink
+#[derive(Debug)]
+pub struct Hashed<H: Hasher>(H);
+
+impl<H: Hasher> ValueConsumer for Hashed<H> {
+    #[inline]
+    fn consume(&mut self, value: u64) {
+        self.0.write_u64(value);
+    }
+
+    /// The hasher itself cannot be reset, so values already hashed stay hashed
+    fn reset(&mut self) {}
+}
+
+impl<H: Hasher> HashSink<H> {
+    pub fn new(hasher: H) -> Self {
+        Self::with_consumer(Hashed(hasher))
+    }
+
+    /// Hashes any values still held back, then returns the hash of all values so far
+    pub fn finish(&mut self) -> u64 {
+        self.flush().0.finish()
+    }
+}
+
+/// A Sink which keeps a uniform random sample of up to k of the values decoded, using reservoir sampling
+/// (Algorithm R), so that approximate statistics over a huge column take one decode pass and O(k) memory.  The
+/// first k values fill the sample; after that the nth value replaces a random sample with probability k/n.
+/// The random numbers come from a SplitMix64 generator started from seed, so the same seed and column always give
+/// the same sample.  Reset empties the sample and restarts the random numbers from the seed.
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::ReservoirSink;
+///     let values: Vec<u64> = (1..=10_000).collect();
+///     let packed = nibblepacking::pack_u64_to_vec(values.iter().cloned());
+///     let mut sink = ReservoirSink::new(100, 42);
+///     nibblepacking::unpack(&packed[..], &mut sink, values.len()).unwrap();
+///     assert_eq!(sink.sample().len(), 100);
+///     assert_eq!(sink.seen(), 10_000);
+/// ```
+pub type ReservoirSink = PerValueSink<Reservoir>;
+
+/// The ValueConsumer behind ReservoirSink
+#[derive(Debug)]
+pub struct Reservoir {
+    k: usize,
+    seed: u64,
+    rng_state: u64,
+    seen: u64,
+    sample: Vec<u64>,
+}
+
+impl Reservoir {
+    // SplitMix64, which unlike xorshift has no bad seeds
+    #[inline]
+    fn next_random(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl ValueConsumer for Reservoir {
+    #[inline]
+    fn consume(&mut self, value: u64) {
+        self.seen += 1;
+        if self.sample.len() < self.k {
+            self.sample.push(value);
+        } else {
+            // A random index below seen, by taking the high bits of a 64 x 64 bit product
+            let index = ((self.next_random() as u128 * self.seen as u128) >> 64) as usize;
+            if index < self.k { self.sample[index] = value; }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rng_state = self.seed;
+        self.seen = 0;
+        self.sample.clear();
+    }
+
+    fn capacity(&self) -> usize {
+        self.sample.capacity()
+    }
+}
+
+impl ReservoirSink {
+    pub fn new(k: usize, seed: u64) -> Self {
+        Self::with_consumer(Reservoir { k, seed, rng_state: seed, seen: 0, sample: Vec::with_capacity(k) })
+    }
+
+    /// Samples any values still held back, then returns the sample: every value if there were at most k of them,
+    /// in the order decoded, otherwise k of them in no particular order
+    pub fn sample(&mut self) -> &[u64] {
+        &self.flush().sample[..]
+    }
+
+    /// Number of values sampled from so far, not counting any held back
+    pub fn seen(&self) -> u64 {
+        self.consumer.seen
+    }
+}
+
+/// A Sink which folds every decoded value into an accumulator, so that any reduction such as a sum, min, max or
+/// count is a one liner:
+///
+/// ```
+/// # use compressed_vec::nibblepacking;
+/// # use compressed_vec::FoldSink;
+///     let packed = nibblepacking::pack_u64_to_vec([3u64, 9, 4].iter().cloned());
+///     let mut sink = FoldSink::new(0u64, |acc, n| acc.max(n));
+///     nibblepacking::unpack(&packed[..], &mut sink, 3).unwrap();
+///     assert_eq!(sink.into_acc(), 9);
+/// ```
+///
+/// Reset starts over from the initial accumulator.
+pub type FoldSink<A, F> = PerValueSink<Fold<A, F>>;
+
+/// The ValueConsumer behind FoldSink
+pub struct Fold<A: Clone, F: FnMut(A, u64) -> A> {
+    init: A,
+    acc: Option<A>,
+    f: F,
+}
+
+impl<A: Clone + core::fmt::Debug, F: FnMut(A, u64) -> A> core::fmt::Debug for Fold<A, F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Fold").field("acc", &self.acc).finish()
+    }
+}
+
+impl<A: Clone, F: FnMut(A, u64) -> A> ValueConsumer for Fold<A, F> {
+    #[inline]
+    fn consume(&mut self, value: u64) {
+        let acc = self.acc.take().expect("The accumulator is only taken while folding");
+        self.acc = Some((self.f)(acc, value));
+    }
+
+    fn reset(&mut self) {
+        self.acc = Some(self.init.clone());
+    }
+}
+
+impl<A: Clone, F: FnMut(A, u64) -> A> FoldSink<A, F> {
+    pub fn new(init: A, f: F) -> Self {
+        Self::with_consumer(Fold { acc: Some(init.clone()), init, f })
+    }
+
+    /// Folds in any values still held back and returns the accumulator
+    pub fn into_acc(mut self) -> A {
+        self.flush().acc.take().expect("The accumulator is only taken while folding")
+    }
+}
+
+/// A Sink which forwards everything to an inner sink, calling a callback with the number of values processed so
+/// far every `every` values and once more when all total values are done, eg to drive a progress bar for a long
+/// decode.  Counts never go past total, so the padding in the last block of 8 is not counted.
+pub struct ProgressSink<'a, S: Sink<u64x8>, F: FnMut(usize)> {
+    inner_sink: &'a mut S,
+    total: usize,
+    every: usize,
+    processed: usize,
+    next_report: usize,
+    callback: F,
+}
+
+impl<'a, S: Sink<u64x8>, F: FnMut(usize)> ProgressSink<'a, S, F> {
+    pub fn new(inner_sink: &'a mut S, total: usize, every: usize, callback: F) -> Self {
+        let every = every.max(1);
+        Self { inner_sink, total, every, processed: 0, next_report: every.min(total), callback }
+    }
+
+    /// The number of values processed so far
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+}
+
+impl<'a, S: Sink<u64x8>, F: FnMut(usize)> core::fmt::Debug for ProgressSink<'a, S, F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ProgressSink").field("total", &self.total).field("processed", &self.processed).finish()
+    }
+}
+
+impl<'a, S: Sink<u64x8>, F: FnMut(usize)> Sink<u64x8> for ProgressSink<'a, S, F> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.inner_sink.process(data);
+        self.processed = (self.processed + 8).min(self.total);
+        if self.processed >= self.next_report && self.next_report > 0 {
+            (self.callback)(self.processed);
+            // Report again at the next multiple of every, or at the end
+            self.next_report = if self.processed == self.total { 0 }
+                               else { ((self.processed / self.every + 1) * self.every).min(self.total) };
+        }
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.process(u64x8::splat(0));
+    }
+
+    fn reset(&mut self) {
+        self.processed = 0;
+        self.next_report = self.every.min(self.total);
+        self.inner_sink.reset();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.inner_sink.mark_padding(num_padding);
+    }
+}
+
+/// A transformation of 8 values at a time, which can be chained with other stages in front of a Sink
+/// using a [`SinkBuilder`]: struct.SinkBuilder.html.  Each stage passes its output on to whatever follows it,
+/// so a stage may pass on fewer blocks than it is given, as Downsample does, or copy them elsewhere, as Tee does.
+pub trait Stage {
+    /// Transforms 8 values and passes the result on to next
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S);
+
+    /// Passes on the number of padding values at the end of the last block pushed, as in Sink::mark_padding().
+    /// Stages which buffer values flush them here.
+    #[inline]
+    fn mark_padding<S: Sink<u64x8>>(&mut self, num_padding: usize, next: &mut S) {
+        next.mark_padding(num_padding);
+    }
+
+    /// Resets any state in the stage, as in Sink::reset()
+    fn reset(&mut self) {}
+
+    /// Returns any error recorded while transforming values since the last reset
+    fn check(&self) -> Result<(), CodingError> { Ok(()) }
+}
+
+/// The empty Stage, which passes values through unchanged
+impl Stage for () {
+    #[inline]
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S) {
+        next.process(data);
+    }
+}
+
+/// Two Stages applied one after the other
+#[derive(Debug)]
+pub struct Chain<A: Stage, B: Stage>(A, B);
+
+impl<A: Stage, B: Stage> Stage for Chain<A, B> {
+    #[inline]
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S) {
+        self.0.push(data, &mut StageSink { stage: &mut self.1, next });
+    }
+
+    #[inline]
+    fn mark_padding<S: Sink<u64x8>>(&mut self, num_padding: usize, next: &mut S) {
+        self.0.mark_padding(num_padding, &mut StageSink { stage: &mut self.1, next });
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+        self.1.reset();
+    }
+
+    fn check(&self) -> Result<(), CodingError> {
+        self.0.check()?;
+        self.1.check()
+    }
+}
+
+/// A stage together with whatever follows it, as a Sink for the stage in front of it to push to
+struct StageSink<'a, T: Stage, S: Sink<u64x8>> {
+    stage: &'a mut T,
+    next: &'a mut S,
+}
+
+impl<'a, T: Stage, S: Sink<u64x8>> Sink<u64x8> for StageSink<'a, T, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.stage.push(data, self.next);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.stage.push(u64x8::splat(0), self.next);
+    }
+
+    // PipelineSink resets every stage and the output sink itself
+    fn reset(&mut self) {}
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.stage.mark_padding(num_padding, self.next);
+    }
+}
+
+/// Maps each value through a dictionary; the Stage used by MapSink and SinkBuilder::map().
+/// Indices beyond the end of the dictionary become 0 and are recorded for check().
+#[derive(Debug)]
+pub struct DictLookup<'a> {
+    dict: &'a [u64],
+    bad_index: Option<u64>,
+}
+
+impl<'a> DictLookup<'a> {
+    pub fn new(dict: &'a [u64]) -> Self {
+        Self { dict, bad_index: None }
+    }
+
+    #[inline]
+    fn lookup(&mut self, data: u64x8) -> u64x8 {
+        let mut mapped = [0u64; 8];
+        for (i, value) in mapped.iter_mut().enumerate() {
+            let index = data.extract(i);
+            *value = match self.dict.get(index as usize) {
+                Some(&value) => value,
+                None         => {
+                    self.bad_index.get_or_insert(index);
+                    0
+                }
+            };
+        }
+        u64x8::from_slice_unaligned(&mapped)
+    }
+}
+
+impl<'a> Stage for DictLookup<'a> {
+    #[inline]
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S) {
+        let mapped = self.lookup(data);
+        next.process(mapped);
+    }
+
+    fn reset(&mut self) {
+        self.bad_index = None;
+    }
+
+    fn check(&self) -> Result<(), CodingError> {
+        match self.bad_index {
+            Some(index) => Err(CodingError::InvalidFormat(
+                               format!("Index {} out of range for dictionary of {} entries", index, self.dict.len()))),
+            None        => Ok(()),
+        }
+    }
+}
+
+/// Adds a constant to each value, wrapping on overflow; the Stage used by SinkBuilder::add_const()
+#[derive(Debug)]
+pub struct AddConst(u64x8);
+
+impl Stage for AddConst {
+    #[inline]
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S) {
+        next.process(data + self.0);
+    }
+}
+
+/// Keeps every nth value, starting with the first, and drops the rest; the Stage used by SinkBuilder::downsample().
+/// The values kept are passed on 8 at a time, and the last few are flushed by mark_padding(), so that padding
+/// is only passed on in the final block.  Padding values do not count towards n, so decoding several streams
+/// without a reset downsamples them as if they were one.
+#[derive(Debug)]
+pub struct Downsample {
+    every: usize,
+    pos: usize,         // Position of the next value within a run of every values; the value is kept at 0
+    block_start: usize, // pos at the start of the last block pushed
+    pushed: bool,       // Whether a block was pushed since the last mark_padding()
+    kept: [u64; 8],
+    num_kept: usize,
+}
+
+impl Downsample {
+    /// Panics if every is 0
+    pub fn new(every: usize) -> Self {
+        assert!(every > 0, "Cannot keep every 0th value");
+        Self { every, pos: 0, block_start: 0, pushed: false, kept: [0u64; 8], num_kept: 0 }
+    }
+}
+
+impl Stage for Downsample {
+    #[inline]
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S) {
+        self.block_start = self.pos;
+        self.pushed = true;
+        for i in 0..8 {
+            if self.pos == 0 {
+                self.kept[self.num_kept] = data.extract(i);
+                self.num_kept += 1;
+                if self.num_kept == 8 {
+                    next.process(u64x8::from_slice_unaligned(&self.kept));
+                    self.num_kept = 0;
+                }
+            }
+            self.pos = (self.pos + 1) % self.every;
+        }
+    }
+
+    fn mark_padding<S: Sink<u64x8>>(&mut self, num_padding: usize, next: &mut S) {
+        // Nothing new to pad, eg after an unpack() of 0 values, and block_start is left from an earlier block
+        if !self.pushed { return }
+        self.pushed = false;
+        // The padding values which were kept are the last ones kept, but may have been passed on already
+        let real_values = 8 - num_padding;
+        let kept_padding = (real_values..8).filter(|i| (self.block_start + i) % self.every == 0).count();
+        if kept_padding >= self.num_kept {
+            next.mark_padding(kept_padding - self.num_kept);
+        } else {
+            self.kept[self.num_kept..].iter_mut().for_each(|value| *value = 0);
+            next.process(u64x8::from_slice_unaligned(&self.kept));
+            next.mark_padding(8 - self.num_kept + kept_padding);
+        }
+        self.num_kept = 0;
+        self.pos = (self.block_start + real_values) % self.every;
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.block_start = 0;
+        self.pushed = false;
+        self.num_kept = 0;
+    }
+}
+
+/// Copies each block to a second sink as well as passing it on; the Stage used by SinkBuilder::tee().
+/// The second sink gets the padding marked and is reset along with the pipeline.
+#[derive(Debug)]
+pub struct Tee<'a, O: Sink<u64x8>> {
+    side_sink: &'a mut O,
+}
+
+impl<'a, O: Sink<u64x8>> Stage for Tee<'a, O> {
+    #[inline]
+    fn push<S: Sink<u64x8>>(&mut self, data: u64x8, next: &mut S) {
+        self.side_sink.process(data);
+        next.process(data);
+    }
+
+    #[inline]
+    fn mark_padding<S: Sink<u64x8>>(&mut self, num_padding: usize, next: &mut S) {
+        self.side_sink.mark_padding(num_padding);
+        next.mark_padding(num_padding);
+    }
+
+    fn reset(&mut self) {
+        self.side_sink.reset();
+    }
+}
+
+/// Builds a single owned Sink out of a chain of Stages feeding a final Sink, instead of stacking sinks which
+/// borrow each other by hand.  Values flow through the stages in the order they are added, then to the sink:
+///
+/// ```
+/// # use compressed_vec::sink::{Sink, SinkBuilder, VecSink};
+/// # use compressed_vec::nibblepacking;
+///     let dict = [100u64, 200, 300];
+///     let mut buf = [0u8; 64];
+///     let written = nibblepacking::pack_u64([2u64, 0, 1].iter().cloned(), &mut buf, 0).unwrap();
+///     let mut sink = SinkBuilder::new(VecSink::<u64>::new()).map(&dict).add_const(5).build();
+///     nibblepacking::unpack(&buf[..written], &mut sink, 3).unwrap();
+///     assert_eq!(sink.sink.as_slice(), &[305u64, 105, 205]);
+/// ```
+#[derive(Debug)]
+pub struct SinkBuilder<T: Stage, S: Sink<u64x8>> {
+    stages: T,
+    sink: S,
+}
+
+impl<S: Sink<u64x8>> SinkBuilder<(), S> {
+    /// Starts a builder with no stages in front of the given output sink
+    pub fn new(sink: S) -> Self {
+        Self { stages: (), sink }
+    }
+}
+
+impl<T: Stage, S: Sink<u64x8>> SinkBuilder<T, S> {
+    /// Adds any Stage after the existing stages
+    pub fn stage<N: Stage>(self, next: N) -> SinkBuilder<Chain<T, N>, S> {
+        SinkBuilder { stages: Chain(self.stages, next), sink: self.sink }
+    }
+
+    /// Adds a dictionary lookup, as in MapSink
+    pub fn map(self, dict: &[u64]) -> SinkBuilder<Chain<T, DictLookup<'_>>, S> {
+        self.stage(DictLookup::new(dict))
+    }
+
+    /// Adds a constant to every value, as in AddConstSink
+    pub fn add_const(self, base: u64) -> SinkBuilder<Chain<T, AddConst>, S> {
+        self.stage(AddConst(u64x8::splat(base)))
+    }
+
+    /// Keeps only every nth value, starting with the first.  Panics if every is 0.
+    pub fn downsample(self, every: usize) -> SinkBuilder<Chain<T, Downsample>, S> {
+        self.stage(Downsample::new(every))
+    }
+
+    /// Also sends the values coming out of the existing stages to side_sink
+    pub fn tee<O: Sink<u64x8>>(self, side_sink: &mut O) -> SinkBuilder<Chain<T, Tee<'_, O>>, S> {
+        self.stage(Tee { side_sink })
+    }
+
+    pub fn build(self) -> PipelineSink<T, S> {
+        PipelineSink { stages: self.stages, sink: self.sink }
+    }
+}
+
+/// The Sink produced by SinkBuilder.  The output sink is available as the sink field.
+#[derive(Debug)]
+pub struct PipelineSink<T: Stage, S: Sink<u64x8>> {
+    stages: T,
+    pub sink: S,
+}
+
+impl<T: Stage, S: Sink<u64x8>> PipelineSink<T, S> {
+    /// Returns the first error recorded by any stage since the last reset, such as an out of range dictionary index
+    pub fn check(&self) -> Result<(), CodingError> {
+        self.stages.check()
+    }
+}
+
+impl<T: Stage, S: Sink<u64x8>> Sink<u64x8> for PipelineSink<T, S> {
+    #[inline]
+    fn process(&mut self, data: u64x8) {
+        self.stages.push(data, &mut self.sink);
+    }
+
+    #[inline]
+    fn process_zeroes(&mut self) {
+        self.stages.push(u64x8::splat(0), &mut self.sink);
+    }
+
+    fn reset(&mut self) {
+        self.stages.reset();
+        self.sink.reset();
+    }
+
+    #[inline]
+    fn mark_padding(&mut self, num_padding: usize) {
+        self.stages.mark_padding(num_padding, &mut self.sink);
+    }
+}
@@ -0,0 +1,140 @@
+/// Approximate quantile sketches, which can be fed values during decoding through a
+/// [`SketchSink`](../sink/struct.SketchSink.html) to compute percentiles over a compressed column in one pass,
+/// without materializing it.
+use std::collections::BTreeMap;
+
+/// A summary of a stream of u64 values which can answer approximate quantile queries.
+/// Methods take no generics so that sketches can be used as trait objects.
+pub trait QuantileSketch {
+    /// Adds one value to the sketch
+    fn insert(&mut self, value: u64);
+
+    /// Returns the approximate value at quantile q, where 0.0 is the minimum, 0.5 the median and 1.0 the maximum.
+    /// Returns None if nothing has been inserted.
+    fn quantile(&self, q: f64) -> Option<u64>;
+
+    /// The number of values inserted
+    fn count(&self) -> u64;
+
+    /// Forgets every value inserted, as if the sketch were new
+    fn clear(&mut self);
+}
+
+/// A simple DDSketch style sketch (https://arxiv.org/abs/1908.10693), which counts values in logarithmically
+/// sized buckets.  Every quantile returned is within relative_accuracy of a value at that rank, for example
+/// within 1% for a relative_accuracy of 0.01, and memory grows only with the log of the range of values.
+#[derive(Debug, Clone)]
+pub struct LogBucketSketch {
+    gamma: f64,
+    ln_gamma: f64,
+    zero_count: u64,
+    count: u64,
+    buckets: BTreeMap<i32, u64>,    // Bucket i counts values in (gamma^(i-1), gamma^i]
+}
+
+impl LogBucketSketch {
+    /// Creates a new sketch.  relative_accuracy must be between 0 and 1, exclusive.
+    pub fn new(relative_accuracy: f64) -> Self {
+        assert!(relative_accuracy > 0.0 && relative_accuracy < 1.0,
+                "relative_accuracy {} must be between 0 and 1", relative_accuracy);
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Self { gamma, ln_gamma: gamma.ln(), zero_count: 0, count: 0, buckets: BTreeMap::new() }
+    }
+
+    // The value in the middle of bucket i, in relative terms
+    fn bucket_value(&self, index: i32) -> u64 {
+        (2.0 * self.gamma.powi(index) / (self.gamma + 1.0)).round() as u64
+    }
+}
+
+impl QuantileSketch for LogBucketSketch {
+    #[inline]
+    fn insert(&mut self, value: u64) {
+        self.count += 1;
+        if value == 0 {
+            self.zero_count += 1;
+        } else {
+            let index = ((value as f64).ln() / self.ln_gamma).ceil() as i32;
+            *self.buckets.entry(index).or_insert(0) += 1;
+        }
+    }
+
+    fn quantile(&self, q: f64) -> Option<u64> {
+        if self.count == 0 { return None }
+        let rank = (q.clamp(0.0, 1.0) * (self.count - 1) as f64) as u64;
+        if rank < self.zero_count { return Some(0) }
+        let mut seen = self.zero_count;
+        for (&index, &bucket_count) in self.buckets.iter() {
+            seen += bucket_count;
+            if seen > rank { return Some(self.bucket_value(index)) }
+        }
+        // Should never get here, since the bucket counts add up to count
+        self.buckets.keys().next_back().map(|&index| self.bucket_value(index))
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn clear(&mut self) {
+        self.zero_count = 0;
+        self.count = 0;
+        self.buckets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nibblepacking::{pack_u64, unpack};
+    use crate::sink::{Sink, SketchSink};
+
+    #[test]
+    fn test_log_bucket_sketch_quantiles() {
+        let mut sketch = LogBucketSketch::new(0.01);
+        assert_eq!(sketch.quantile(0.5), None);
+        for n in 1..=1000u64 {
+            sketch.insert(n * 10);
+        }
+        sketch.insert(0);
+        assert_eq!(sketch.count(), 1001);
+        assert_eq!(sketch.quantile(0.0), Some(0));
+
+        let median = sketch.quantile(0.5).unwrap() as f64;
+        assert!((median - 5000.0).abs() <= 5000.0 * 0.01, "median {}", median);
+        let p99 = sketch.quantile(0.99).unwrap() as f64;
+        assert!((p99 - 9900.0).abs() <= 9900.0 * 0.01, "p99 {}", p99);
+        let max = sketch.quantile(1.0).unwrap() as f64;
+        assert!((max - 10000.0).abs() <= 10000.0 * 0.01, "max {}", max);
+    }
+
+    #[test]
+    fn test_sketch_sink_decodes_into_sketch() {
+        // Latencies which are mostly small, with a long tail
+        let inputs: Vec<u64> = (0..77u64).map(|i| if i % 10 == 9 { 100_000 + i } else { 1000 + i * 5 }).collect();
+        let mut sorted = inputs.clone();
+        sorted.sort_unstable();
+        let true_median = sorted[sorted.len() / 2] as f64;
+
+        let mut buf = [0u8; 1024];
+        let written = pack_u64(inputs.iter().cloned(), &mut buf, 0).unwrap();
+
+        let mut sketch = LogBucketSketch::new(0.02);
+        {
+            // Decode through a trait object, so the sketch implementation can be chosen at runtime
+            let dyn_sketch: &mut dyn QuantileSketch = &mut sketch;
+            let mut sink = SketchSink::new(dyn_sketch);
+            unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+
+            // Reset clears the sketch, so decoding again does not count any value twice
+            sink.reset();
+            unpack(&buf[..written], &mut sink, inputs.len()).unwrap();
+        }
+        // The padding zeroes in the last block are not inserted
+        assert_eq!(sketch.count(), inputs.len() as u64);
+        let min = sketch.quantile(0.0).unwrap() as f64;
+        assert!((min - 1000.0).abs() <= 1000.0 * 0.02, "min {}", min);
+        let median = sketch.quantile(0.5).unwrap() as f64;
+        assert!((median - true_median).abs() <= true_median * 0.02, "median {} vs {}", median, true_median);
+    }
+}